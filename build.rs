@@ -29,8 +29,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         Ok(())
     });
 
+    let compute = thread::spawn(|| -> Result<_, Box<dyn std::error::Error + Send + Sync>> {
+        let mut b = SpirvBuilder::new("shaders/compute", "spirv-unknown-vulkan1.3");
+        b.build_script.defaults = true;
+        b.build_script.forward_rustc_warnings = Some(true);
+        b.build_script.env_shader_spv_path = Some(true);
+        b.build()?;
+        Ok(())
+    });
+
     fragment.join().unwrap().map_err(|e| e.to_string())?;
     vertex.join().unwrap().map_err(|e| e.to_string())?;
+    compute.join().unwrap().map_err(|e| e.to_string())?;
 
     Ok(())
 }
@@ -0,0 +1,20 @@
+use crate::{PushConstants, PushStages};
+use bytemuck::{Pod, Zeroable};
+
+/// Has to match `threads` of the `fill` kernel
+pub const FILL_WORKGROUP_SIZE: u32 = 64;
+
+/// Push constants of the `fill` kernel
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct FillParams {
+    pub value: u32,
+    pub count: u32,
+}
+
+unsafe impl Pod for FillParams {}
+unsafe impl Zeroable for FillParams {}
+
+impl PushConstants for FillParams {
+    const STAGES: PushStages = PushStages::COMPUTE;
+}
@@ -0,0 +1,24 @@
+use crate::{PushConstants, PushStages};
+use bytemuck::{Pod, Zeroable};
+
+/// Push constants of debug line drawing
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct DebugParams {
+    pub view_proj: [[f32; 4]; 4],
+}
+
+unsafe impl Pod for DebugParams {}
+unsafe impl Zeroable for DebugParams {}
+
+impl PushConstants for DebugParams {
+    const STAGES: PushStages = PushStages::VERTEX;
+}
+
+/// Values of the `debug_view` fragment entry mode constant, specialization constant 0
+pub const DEBUG_VIEW_WIREFRAME: u32 = 1;
+pub const DEBUG_VIEW_NORMALS: u32 = 2;
+pub const DEBUG_VIEW_UV_CHECKER: u32 = 3;
+pub const DEBUG_VIEW_DEPTH: u32 = 4;
+pub const DEBUG_VIEW_MATERIAL_ID: u32 = 5;
+pub const DEBUG_VIEW_OVERDRAW: u32 = 6;
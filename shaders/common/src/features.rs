@@ -0,0 +1,8 @@
+/// Optional paths of the scene shaders. Each bit is a 32-bit boolean specialization constant whose id is the bit index,
+/// materials request a set and the renderer creates one pipeline per distinct set
+pub const FEATURE_ALPHA_TEST: u32 = 1 << 0;
+pub const FEATURE_NORMAL_MAP: u32 = 1 << 1;
+pub const FEATURE_COUNT: u32 = 2;
+
+/// glTF default of `alphaCutoff`
+pub const DEFAULT_ALPHA_CUTOFF: f32 = 0.5;
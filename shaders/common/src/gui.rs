@@ -0,0 +1,24 @@
+use crate::{PushConstants, PushStages};
+use bytemuck::{Pod, Zeroable};
+
+/// Target is an sRGB format, blended result has to be linearized before write
+pub const GUI_FLAG_LINEAR_OUTPUT: u32 = 1;
+/// Target is HDR10, colors are linearized and PQ encoded at paper white
+pub const GUI_FLAG_PQ_OUTPUT: u32 = 1 << 1;
+
+/// Push constants of the egui pipeline, visible to both stages
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct GuiParams {
+    /// In points, egui vertex positions are in the same space
+    pub screen_size: [f32; 2],
+    pub flags: u32,
+    pub _pad: u32,
+}
+
+unsafe impl Pod for GuiParams {}
+unsafe impl Zeroable for GuiParams {}
+
+impl PushConstants for GuiParams {
+    const STAGES: PushStages = PushStages::VERTEX_FRAGMENT;
+}
@@ -0,0 +1,19 @@
+use crate::{PushConstants, PushStages};
+use bytemuck::{Pod, Zeroable};
+
+/// Push constants of IBL precomputation kernels
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct IblParams {
+    pub face: u32,
+    pub roughness: f32,
+    pub sample_count: u32,
+    pub _pad: u32,
+}
+
+unsafe impl Pod for IblParams {}
+unsafe impl Zeroable for IblParams {}
+
+impl PushConstants for IblParams {
+    const STAGES: PushStages = PushStages::FRAGMENT;
+}
@@ -1,8 +1,28 @@
 #![no_std]
 #![allow(unexpected_cfgs)]
 #![allow(unused_imports)]
+mod compute;
+mod debug;
+mod features;
+mod gui;
+mod ibl;
+mod lod;
 mod material;
+mod particles;
+mod post;
+mod push;
+mod skybox;
+pub use compute::*;
+pub use debug::*;
+pub use features::*;
+pub use gui::*;
+pub use ibl::*;
+pub use lod::*;
 pub use material::*;
+pub use particles::*;
+pub use post::*;
+pub use push::*;
+pub use skybox::*;
 
 use cfg_if::cfg_if;
 
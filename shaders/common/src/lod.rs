@@ -0,0 +1,79 @@
+use crate::{PushConstants, PushStages};
+use bytemuck::{Pod, Zeroable};
+
+/// Has to match `threads` of the `lod_select` kernel
+pub const LOD_WORKGROUP_SIZE: u32 = 64;
+/// Levels per draw, the base mesh included
+pub const MAX_LODS: usize = 4;
+/// Words of one `VkDrawIndexedIndirectCommand` written by `lod_select`
+pub const DRAW_INDEXED_WORDS: usize = 5;
+/// `DrawLods::texture` of draws without a texture material, `LodParams::excluded_texture` of cameras drawing everything
+pub const NO_TEXTURE: u32 = u32::MAX;
+
+/// Index range of one detail level
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
+pub struct LodLevel {
+    pub first_index: u32,
+    pub index_count: u32,
+    pub vertex_offset: i32,
+    /// Smallest screen coverage the level is drawn at, levels are ordered from the most detailed one
+    pub screen_coverage: f32,
+}
+
+/// Element of the scene LOD storage buffer, one per indirect draw in draw order.
+/// The bounding sphere is in world space
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct DrawLods {
+    pub center: [f32; 3],
+    pub radius: f32,
+    pub instance_count: u32,
+    pub first_instance: u32,
+    pub level_count: u32,
+    /// Bindless slot the material of the draw samples, `NO_TEXTURE` if it samples none
+    pub texture: u32,
+    pub levels: [LodLevel; MAX_LODS],
+}
+
+impl DrawLods {
+    /// Level drawn at `coverage`, `None` once the draw is smaller than its last level allows
+    pub fn select(&self, coverage: f32) -> Option<&LodLevel> {
+        let mut level = 0;
+        while level < self.level_count as usize {
+            if coverage >= self.levels[level].screen_coverage {
+                return Some(&self.levels[level]);
+            }
+            level += 1;
+        }
+        None
+    }
+}
+
+/// Push constants of `lod_select`, one dispatch per camera
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct LodParams {
+    pub camera_position: [f32; 3],
+    /// Cotangent of half the vertical field of view
+    pub projection_scale: f32,
+    pub draw_count: u32,
+    /// First draw command of the camera's slice of the indirect buffer
+    pub first_output: u32,
+    /// Multiplies the screen coverage, above one keeps detail longer. Coverage is the bounding sphere radius
+    /// times `projection_scale` over its distance, the share of the screen height the sphere covers
+    pub bias: f32,
+    /// Draws sampling this slot are skipped, an offscreen camera passes its own image. `NO_TEXTURE` skips nothing
+    pub excluded_texture: u32,
+}
+
+unsafe impl Pod for LodLevel {}
+unsafe impl Zeroable for LodLevel {}
+unsafe impl Pod for DrawLods {}
+unsafe impl Zeroable for DrawLods {}
+unsafe impl Pod for LodParams {}
+unsafe impl Zeroable for LodParams {}
+
+impl PushConstants for LodParams {
+    const STAGES: PushStages = PushStages::COMPUTE;
+}
@@ -1,35 +1,39 @@
 #![allow(unused)]
 use bytemuck::{bytes_of, Pod, Zeroable};
 
+pub const MATERIAL_DIFFUSE: u32 = 0;
+pub const MATERIAL_SPECULAR: u32 = 1;
+pub const MATERIAL_TEXTURE: u32 = 2;
+
+/// Parameters of any variant as raw words, read back by the variant's `parse`
 #[repr(C)]
 #[derive(Copy, Clone)]
-pub union MaterialData {
-    pub diffuse: DiffuseMaterial,
-    pub specular: SpecularMaterial,
-    pub texture: TextureMaterial,
-    pub bytes: [u8; MATERIAL_DATA_SIZE],
+pub struct MaterialData {
+    pub words: [u32; MATERIAL_DATA_WORDS],
 }
 
+/// Element of the material storage buffer, `mat_type` selects how `data` is read
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct MaterialBinary {
     pub mat_type: u32,
+    pub _pad: [u32; 3],
     pub data: MaterialData,
 }
 
 #[repr(u32)]
 pub enum Material {
-    Diffuse(DiffuseMaterial) = 0,
-    Specular(SpecularMaterial) = 1,
-    Texture(TextureMaterial) = 2,
+    Diffuse(DiffuseMaterial) = MATERIAL_DIFFUSE,
+    Specular(SpecularMaterial) = MATERIAL_SPECULAR,
+    Texture(TextureMaterial) = MATERIAL_TEXTURE,
 }
 
 impl Material {
     pub fn discriminant(&self) -> u32 {
         match self {
-            Material::Diffuse(_) => 0,
-            Material::Specular(_) => 1,
-            Material::Texture(_) => 2,
+            Material::Diffuse(_) => MATERIAL_DIFFUSE,
+            Material::Specular(_) => MATERIAL_SPECULAR,
+            Material::Texture(_) => MATERIAL_TEXTURE,
         }
     }
 
@@ -40,6 +44,24 @@ impl Material {
             Material::Texture(texture) => texture.bytes(),
         }
     }
+
+    /// Layout uploaded into the material storage buffer, unused words are zero
+    pub fn binary(&self) -> MaterialBinary {
+        let mut data = MaterialData::zeroed();
+        let bytes = self.bytes();
+        bytemuck::bytes_of_mut(&mut data)[..bytes.len()].copy_from_slice(bytes);
+        MaterialBinary {
+            mat_type: self.discriminant(),
+            _pad: [0; 3],
+            data,
+        }
+    }
+}
+
+impl From<Material> for MaterialBinary {
+    fn from(material: Material) -> Self {
+        material.binary()
+    }
 }
 
 const fn max_size(a: usize, b: usize) -> usize {
@@ -53,15 +75,28 @@ const MATERIAL_DATA_SIZE: usize = max_size(
         size_of::<TextureMaterial>()
     )
 );
+const MATERIAL_DATA_WORDS: usize = MATERIAL_DATA_SIZE / size_of::<u32>();
+
+fn word_f32(data: &MaterialData, index: usize) -> f32 {
+    f32::from_bits(data.words[index])
+}
+
+fn word_vec4(data: &MaterialData, index: usize) -> [f32; 4] {
+    [word_f32(data, index), word_f32(data, index + 1), word_f32(data, index + 2), word_f32(data, index + 3)]
+}
+
+/// Lambert only, `base_color` is linear RGBA
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct DiffuseMaterial {
-
+    pub base_color: [f32; 4],
 }
 
 impl DiffuseMaterial {
-    pub fn parse(bytes: &[u8]) -> DiffuseMaterial {
-        *bytemuck::from_bytes(&bytes[0..size_of::<DiffuseMaterial>()])
+    pub fn parse(data: &MaterialData) -> DiffuseMaterial {
+        DiffuseMaterial {
+            base_color: word_vec4(data, 0),
+        }
     }
 
     pub fn bytes(&self) -> &[u8] {
@@ -69,15 +104,23 @@ impl DiffuseMaterial {
     }
 }
 
+/// Lambert with a Blinn-Phong highlight of `specular_color`
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct SpecularMaterial {
-
+    pub base_color: [f32; 4],
+    pub specular_color: [f32; 3],
+    pub shininess: f32,
 }
 
 impl SpecularMaterial {
-    pub fn parse(bytes: &[u8]) -> SpecularMaterial {
-        *bytemuck::from_bytes(&bytes[0..size_of::<SpecularMaterial>()])
+    pub fn parse(data: &MaterialData) -> SpecularMaterial {
+        let specular = word_vec4(data, 4);
+        SpecularMaterial {
+            base_color: word_vec4(data, 0),
+            specular_color: [specular[0], specular[1], specular[2]],
+            shininess: specular[3],
+        }
     }
 
     pub fn bytes(&self) -> &[u8] {
@@ -85,16 +128,27 @@ impl SpecularMaterial {
     }
 }
 
+/// Lambert over a sampled base color, multiplied by `base_color`. Ids are slots of the bindless texture and sampler arrays,
+/// `normal_id` and `alpha_cutoff` are only read by the `FEATURE_NORMAL_MAP` and `FEATURE_ALPHA_TEST` permutations
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct TextureMaterial {
+    pub base_color: [f32; 4],
     pub source_id: u32,
     pub sampler_id: u32,
+    pub normal_id: u32,
+    pub alpha_cutoff: f32,
 }
 
 impl TextureMaterial {
-    pub fn parse(bytes: &[u8]) -> TextureMaterial {
-        *bytemuck::from_bytes(&bytes[0..size_of::<TextureMaterial>()])
+    pub fn parse(data: &MaterialData) -> TextureMaterial {
+        TextureMaterial {
+            base_color: word_vec4(data, 0),
+            source_id: data.words[4],
+            sampler_id: data.words[5],
+            normal_id: data.words[6],
+            alpha_cutoff: word_f32(data, 7),
+        }
     }
 
     pub fn bytes(&self) -> &[u8] {
@@ -110,4 +164,6 @@ unsafe impl Zeroable for DiffuseMaterial {}
 unsafe impl Pod for TextureMaterial {}
 unsafe impl Zeroable for TextureMaterial {}
 unsafe impl Pod for MaterialData {}
-unsafe impl Zeroable for MaterialData {}
\ No newline at end of file
+unsafe impl Zeroable for MaterialData {}
+unsafe impl Pod for MaterialBinary {}
+unsafe impl Zeroable for MaterialBinary {}
@@ -0,0 +1,81 @@
+use crate::{PushConstants, PushStages};
+use bytemuck::{Pod, Zeroable};
+
+/// Has to match `threads` of the `particles_simulate` and `particles_reset` kernels
+pub const PARTICLE_WORKGROUP_SIZE: u32 = 64;
+/// Two triangles per billboard, written into the indirect command on reset
+pub const PARTICLE_VERTICES: u32 = 6;
+/// Words in front of the per emitter spawn counters, laid out as `VkDrawIndirectCommand`
+pub const PARTICLE_DRAW_WORDS: u32 = 4;
+
+/// One element of the particle storage buffer, dead once `age` reaches `lifetime`
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Particle {
+    pub position: [f32; 3],
+    pub age: f32,
+    pub velocity: [f32; 3],
+    pub lifetime: f32,
+    pub color_start: [f32; 4],
+    pub color_end: [f32; 4],
+    pub size_start: f32,
+    pub size_end: f32,
+    /// 1.0 adds onto the target, 0.0 alpha blends
+    pub additive: f32,
+    pub _pad: f32,
+}
+
+unsafe impl Pod for Particle {}
+unsafe impl Zeroable for Particle {}
+
+/// Push constants of `particles_simulate`, one dispatch per emitter over its slice of the pool
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct EmitterParams {
+    pub position: [f32; 3],
+    pub spawn_count: u32,
+    pub velocity: [f32; 3],
+    /// Random cone around `velocity`, in the same units
+    pub spread: f32,
+    pub gravity: [f32; 3],
+    pub delta_time: f32,
+    pub color_start: [f32; 4],
+    pub color_end: [f32; 4],
+    pub size_start: f32,
+    pub size_end: f32,
+    pub lifetime: f32,
+    pub seed: u32,
+    pub first: u32,
+    pub capacity: u32,
+    /// Index of the spawn counter after the draw command
+    pub emitter: u32,
+    pub additive: u32,
+}
+
+unsafe impl Pod for EmitterParams {}
+unsafe impl Zeroable for EmitterParams {}
+
+impl PushConstants for EmitterParams {
+    const STAGES: PushStages = PushStages::COMPUTE;
+}
+
+/// Push constants of the billboard pass, `depth_params` linearizes the scene depth
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct ParticleDrawParams {
+    pub view_proj: [[f32; 4]; 4],
+    pub camera_right: [f32; 3],
+    /// View space fade distance in front of geometry, zero turns soft particles off
+    pub softness: f32,
+    pub camera_up: [f32; 3],
+    pub _pad: f32,
+    /// Projection z scale and offset followed by the viewport depth range
+    pub depth_params: [f32; 4],
+}
+
+unsafe impl Pod for ParticleDrawParams {}
+unsafe impl Zeroable for ParticleDrawParams {}
+
+impl PushConstants for ParticleDrawParams {
+    const STAGES: PushStages = PushStages::VERTEX_FRAGMENT;
+}
@@ -0,0 +1,26 @@
+use crate::{PushConstants, PushStages};
+use bytemuck::{Pod, Zeroable};
+
+pub const POST_FLAG_AGX: u32 = 1;
+pub const POST_FLAG_ENCODE_SRGB: u32 = 1 << 1;
+/// HDR10 target, linear Rec.709 is converted to Rec.2020 and encoded with the ST 2084 curve
+pub const POST_FLAG_ENCODE_PQ: u32 = 1 << 2;
+
+/// Push constants shared by every post-processing kernel
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct PostParams {
+    pub texel_size: [f32; 2],
+    pub direction: [f32; 2],
+    pub exposure: f32,
+    pub threshold: f32,
+    pub intensity: f32,
+    pub flags: u32,
+}
+
+unsafe impl Pod for PostParams {}
+unsafe impl Zeroable for PostParams {}
+
+impl PushConstants for PostParams {
+    const STAGES: PushStages = PushStages::FRAGMENT;
+}
@@ -0,0 +1,22 @@
+use bytemuck::Pod;
+
+/// Shader stages reading a push constant block
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+pub struct PushStages {
+    pub vertex: bool,
+    pub fragment: bool,
+    pub compute: bool,
+}
+
+impl PushStages {
+    pub const VERTEX: PushStages = PushStages { vertex: true, fragment: false, compute: false };
+    pub const FRAGMENT: PushStages = PushStages { vertex: false, fragment: true, compute: false };
+    pub const VERTEX_FRAGMENT: PushStages = PushStages { vertex: true, fragment: true, compute: false };
+    pub const COMPUTE: PushStages = PushStages { vertex: false, fragment: false, compute: true };
+}
+
+/// A `#[spirv(push_constant)]` block starting at offset 0.
+/// The host builds the pipeline layout range and the push stage flags from it, size has to be a multiple of 4
+pub trait PushConstants: Pod {
+    const STAGES: PushStages;
+}
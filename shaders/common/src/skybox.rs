@@ -0,0 +1,19 @@
+use crate::{PushConstants, PushStages};
+use bytemuck::{Pod, Zeroable};
+
+/// Push constants of the skybox pass, `inv_view_proj` ignores camera translation
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct SkyboxParams {
+    pub inv_view_proj: [[f32; 4]; 4],
+    pub intensity: f32,
+    pub lod: f32,
+    pub _pad: [f32; 2],
+}
+
+unsafe impl Pod for SkyboxParams {}
+unsafe impl Zeroable for SkyboxParams {}
+
+impl PushConstants for SkyboxParams {
+    const STAGES: PushStages = PushStages::FRAGMENT;
+}
@@ -0,0 +1,149 @@
+#![no_std]
+#![allow(unexpected_cfgs)]
+
+use common::{DrawLods, EmitterParams, FillParams, LodParams, Particle, DRAW_INDEXED_WORDS, NO_TEXTURE, PARTICLE_DRAW_WORDS, PARTICLE_VERTICES};
+use spirv_std::arch::atomic_i_add;
+use spirv_std::glam::{UVec3, Vec3};
+use spirv_std::memory::{Scope, Semantics};
+use spirv_std::spirv;
+
+/// Writes `params.value` into the first `params.count` words, used to reset counters. Workgroup size is `FILL_WORKGROUP_SIZE`
+#[spirv(compute(threads(64)))]
+pub fn fill(
+    #[spirv(global_invocation_id)] id: UVec3,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] buffer: &mut [u32],
+    #[spirv(push_constant)] params: &FillParams,
+) {
+    let index = id.x as usize;
+    if index < params.count as usize {
+        buffer[index] = params.value;
+    }
+}
+
+/// Empty draw command with `PARTICLE_VERTICES` per instance and zeroed spawn counters, `params.count` covers both
+#[spirv(compute(threads(64)))]
+pub fn particles_reset(
+    #[spirv(global_invocation_id)] id: UVec3,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] counters: &mut [u32],
+    #[spirv(push_constant)] params: &FillParams,
+) {
+    let index = id.x as usize;
+    if index < params.count as usize {
+        counters[index] = if index == 0 { PARTICLE_VERTICES } else { 0 };
+    }
+}
+
+/// Ages and integrates the emitter's slice, dead slots respawn while spawn tickets last.
+/// Survivors are appended to `alive` and counted as instances of the indirect draw
+#[spirv(compute(threads(64)))]
+pub fn particles_simulate(
+    #[spirv(global_invocation_id)] id: UVec3,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] particles: &mut [Particle],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] alive: &mut [u32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] counters: &mut [u32],
+    #[spirv(push_constant)] params: &EmitterParams,
+) {
+    if id.x >= params.capacity {
+        return;
+    }
+    let index = (params.first + id.x) as usize;
+    let mut particle = particles[index];
+
+    if particle.age < particle.lifetime {
+        let gravity = Vec3::from_array(params.gravity);
+        let velocity = Vec3::from_array(particle.velocity) + gravity * params.delta_time;
+        let position = Vec3::from_array(particle.position) + velocity * params.delta_time;
+        particle.velocity = velocity.to_array();
+        particle.position = position.to_array();
+        particle.age += params.delta_time;
+    } else if params.spawn_count > 0 {
+        let ticket = unsafe {
+            atomic_i_add::<u32, { Scope::Device as u32 }, { Semantics::NONE.bits() }>(&mut counters[(PARTICLE_DRAW_WORDS + params.emitter) as usize], 1)
+        };
+        if ticket < params.spawn_count {
+            particle = spawn(params, index as u32);
+        }
+    }
+
+    if particle.age < particle.lifetime {
+        let slot = unsafe {
+            atomic_i_add::<u32, { Scope::Device as u32 }, { Semantics::NONE.bits() }>(&mut counters[1], 1)
+        };
+        alive[slot as usize] = index as u32;
+    }
+    particles[index] = particle;
+}
+
+/// Picks the detail level of every draw for one camera and writes its indexed indirect command.
+/// Draws below the coverage of their last level get no instances. Workgroup size is `LOD_WORKGROUP_SIZE`
+#[spirv(compute(threads(64)))]
+pub fn lod_select(
+    #[spirv(global_invocation_id)] id: UVec3,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] draws: &[DrawLods],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] commands: &mut [u32],
+    #[spirv(push_constant)] params: &LodParams,
+) {
+    if id.x >= params.draw_count {
+        return;
+    }
+    let draw = &draws[id.x as usize];
+    let distance = Vec3::from_array(draw.center).distance(Vec3::from_array(params.camera_position));
+    // inside the sphere it covers the whole screen
+    let coverage = if distance > draw.radius {
+        draw.radius * params.projection_scale / distance * params.bias
+    } else {
+        f32::MAX
+    };
+
+    let base = (params.first_output + id.x) as usize * DRAW_INDEXED_WORDS;
+    // sampling the image the camera renders into would read its own color attachment
+    let excluded = params.excluded_texture != NO_TEXTURE && draw.texture == params.excluded_texture;
+    match draw.select(coverage) {
+        Some(level) if !excluded => {
+            commands[base] = level.index_count;
+            commands[base + 1] = draw.instance_count;
+            commands[base + 2] = level.first_index;
+            commands[base + 3] = level.vertex_offset as u32;
+        }
+        _ => {
+            commands[base] = 0;
+            commands[base + 1] = 0;
+            commands[base + 2] = 0;
+            commands[base + 3] = 0;
+        }
+    }
+    commands[base + 4] = draw.first_instance;
+}
+
+fn spawn(params: &EmitterParams, index: u32) -> Particle {
+    let mut seed = hash(index ^ hash(params.seed));
+    let jitter = Vec3::new(random(&mut seed), random(&mut seed), random(&mut seed)) * 2.0 - Vec3::ONE;
+    let velocity = Vec3::from_array(params.velocity) + jitter * params.spread;
+
+    Particle {
+        position: params.position,
+        age: 0.0,
+        velocity: velocity.to_array(),
+        // up to a quarter shorter so particles of one burst do not die on the same frame
+        lifetime: params.lifetime * (1.0 - 0.25 * random(&mut seed)),
+        color_start: params.color_start,
+        color_end: params.color_end,
+        size_start: params.size_start,
+        size_end: params.size_end,
+        additive: params.additive as f32,
+        _pad: 0.0,
+    }
+}
+
+/// PCG hash
+fn hash(value: u32) -> u32 {
+    let state = value.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+/// Uniform in [0, 1), advances `seed`
+fn random(seed: &mut u32) -> f32 {
+    *seed = hash(*seed);
+    (*seed >> 8) as f32 / 16777216.0
+}
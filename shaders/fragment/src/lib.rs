@@ -1,28 +1,538 @@
 #![no_std]
 #![allow(unexpected_cfgs)]
 
-use spirv_std::glam::{Vec2, Vec4};
-use spirv_std::{spirv, RuntimeArray, Sampler};
-use spirv_std::image::Image2d;
+use common::{DiffuseMaterial, GuiParams, IblParams, MaterialBinary, ParticleDrawParams, PostParams, SkyboxParams, SpecularMaterial, TextureMaterial, DEBUG_VIEW_DEPTH, DEBUG_VIEW_MATERIAL_ID, DEBUG_VIEW_NORMALS, DEBUG_VIEW_OVERDRAW, DEBUG_VIEW_UV_CHECKER, DEBUG_VIEW_WIREFRAME, GUI_FLAG_LINEAR_OUTPUT, GUI_FLAG_PQ_OUTPUT, MATERIAL_DIFFUSE, MATERIAL_SPECULAR, POST_FLAG_AGX, POST_FLAG_ENCODE_PQ, POST_FLAG_ENCODE_SRGB};
+use spirv_std::glam::{IVec2, Mat3, Mat4, Vec2, Vec3, Vec4};
+use spirv_std::arch::{kill, Derivative};
+use spirv_std::{spirv, Image, RuntimeArray, Sampler};
+use spirv_std::image::{sample_with, Cubemap, Image2d, SampledImage};
+#[cfg(target_arch = "spirv")]
+use spirv_std::num_traits::Float;
 
-#[repr(C)]
-pub struct Material {
-    pub source_id: u32,
-    pub sampler_id: u32,
-}
+/// Light not coming from the sun
+const AMBIENT: f32 = 0.25;
 
 #[spirv(fragment)]
 pub fn main(
     output: &mut Vec4,
     in_tex_coords: Vec2,
-    #[spirv(descriptor_set = 1, binding = 0)] textures: &RuntimeArray<Image2d>,
-    #[spirv(descriptor_set = 1, binding = 1)] samplers: &RuntimeArray<Sampler>,
+    #[spirv(descriptor_set = 2, binding = 0)] textures: &RuntimeArray<Image2d>,
+    #[spirv(descriptor_set = 2, binding = 1)] samplers: &RuntimeArray<Sampler>,
     #[spirv(flat)] in_instance_index: usize,
-    #[spirv(storage_buffer, descriptor_set = 1, binding = 4)] materials: &[Material]
+    in_normal: Vec3,
+    in_view_position: Vec3,
+    in_light_direction: Vec3,
+    #[spirv(storage_buffer, descriptor_set = 1, binding = 4)] materials: &[MaterialBinary],
+    #[spirv(spec_constant(id = 0, default = 0))] alpha_test: u32,
+    #[spirv(spec_constant(id = 1, default = 0))] normal_map: u32,
 ) {
     let material = &materials[in_instance_index];
-    unsafe {
-        let color: Vec4 = textures.index(material.source_id as usize).sample(*samplers.index(material.sampler_id as usize), in_tex_coords);
-        *output = color;
+    let normal = in_normal.normalize();
+    let light = in_light_direction.normalize();
+    let lambert = AMBIENT + normal.dot(light).max(0.0);
+
+    *output = match material.mat_type {
+        MATERIAL_DIFFUSE => {
+            let diffuse = DiffuseMaterial::parse(&material.data);
+            let base = Vec4::from_array(diffuse.base_color);
+            (base.truncate() * lambert).extend(base.w)
+        }
+        MATERIAL_SPECULAR => {
+            let specular = SpecularMaterial::parse(&material.data);
+            let base = Vec4::from_array(specular.base_color);
+            // camera sits at the view space origin
+            let half = (light - in_view_position.normalize()).normalize();
+            let highlight = normal.dot(half).max(0.0).powf(specular.shininess);
+            (base.truncate() * lambert + Vec3::from_array(specular.specular_color) * highlight).extend(base.w)
+        }
+        _ => {
+            let texture = TextureMaterial::parse(&material.data);
+            let sampler = unsafe { *samplers.index(texture.sampler_id as usize) };
+            let texel: Vec4 = unsafe { textures.index(texture.source_id as usize).sample(sampler, in_tex_coords) };
+            let color = texel * Vec4::from_array(texture.base_color);
+            if alpha_test != 0 && color.w < texture.alpha_cutoff {
+                kill();
+            }
+            let lambert = if normal_map != 0 {
+                let encoded: Vec4 = unsafe { textures.index(texture.normal_id as usize).sample(sampler, in_tex_coords) };
+                let mapped = perturb_normal(normal, in_view_position, in_tex_coords, encoded.truncate() * 2.0 - Vec3::ONE);
+                AMBIENT + mapped.dot(light).max(0.0)
+            } else {
+                lambert
+            };
+            (color.truncate() * lambert).extend(color.w)
+        }
+    };
+}
+
+/// Tangent space normal into view space without vertex tangents, the frame comes from screen space derivatives
+fn perturb_normal(normal: Vec3, view_position: Vec3, tex_coords: Vec2, mapped: Vec3) -> Vec3 {
+    let (dp1, dp2) = (view_position.dfdx(), view_position.dfdy());
+    let (duv1, duv2) = (tex_coords.dfdx(), tex_coords.dfdy());
+    let dp2_perp = dp2.cross(normal);
+    let dp1_perp = normal.cross(dp1);
+    let tangent = dp2_perp * duv1.x + dp1_perp * duv2.x;
+    let bitangent = dp2_perp * duv1.y + dp1_perp * duv2.y;
+    let scale = tangent.length_squared().max(bitangent.length_squared());
+    if scale <= 0.0 {
+        return normal;
+    }
+    let scale = scale.sqrt().recip();
+    (tangent * (mapped.x * scale) + bitangent * (mapped.y * scale) + normal * mapped.z).normalize()
+}
+
+#[spirv(fragment)]
+pub fn skybox(
+    output: &mut Vec4,
+    in_ndc: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] environment: &SampledImage<Cubemap>,
+    #[spirv(push_constant)] params: &SkyboxParams,
+) {
+    let inv_view_proj = Mat4::from_cols_array_2d(&params.inv_view_proj);
+    let far = inv_view_proj * Vec4::new(in_ndc.x, in_ndc.y, 1.0, 1.0);
+    let direction = (far.truncate() / far.w).normalize();
+    let color: Vec4 = unsafe { environment.sample_by_lod(direction, params.lod) };
+    *output = (color.truncate() * params.intensity).extend(1.0);
+}
+
+// Post-processing kernels, all drawn with the `fullscreen` vertex shader
+
+fn luma(color: Vec3) -> f32 {
+    color.dot(Vec3::new(0.299, 0.587, 0.114))
+}
+
+fn fetch(image: &SampledImage<Image2d>, uv: Vec2) -> Vec3 {
+    let color: Vec4 = unsafe { image.sample_by_lod(uv, 0.0) };
+    color.truncate()
+}
+
+/// Luminance of 1.0 in nits when writing HDR10
+const PAPER_WHITE: f32 = 200.0;
+
+/// Linear Rec.709 to Rec.2020 primaries, PQ encoded relative to 10000 nits
+fn encode_pq(color: Vec3) -> Vec3 {
+    let rec709_to_rec2020 = Mat3::from_cols(
+        Vec3::new(0.6274, 0.0691, 0.0164),
+        Vec3::new(0.3293, 0.9195, 0.0880),
+        Vec3::new(0.0433, 0.0114, 0.8956),
+    );
+    let m1 = 0.1593017578125;
+    let m2 = 78.84375;
+    let c1 = 0.8359375;
+    let c2 = 18.8515625;
+    let c3 = 18.6875;
+
+    let luminance = (rec709_to_rec2020 * color.max(Vec3::ZERO) * (PAPER_WHITE / 10000.0)).min(Vec3::ONE);
+    let powered = luminance.powf(m1);
+    ((Vec3::splat(c1) + powered * c2) / (Vec3::ONE + powered * c3)).powf(m2)
+}
+
+/// Only the pass writing into the swapchain gets an encoding flag
+fn finish(color: Vec3, params: &PostParams) -> Vec4 {
+    let color = if params.flags & POST_FLAG_ENCODE_PQ != 0 {
+        encode_pq(color)
+    } else if params.flags & POST_FLAG_ENCODE_SRGB != 0 {
+        color.max(Vec3::ZERO).powf(1.0 / 2.2)
+    } else {
+        color
+    };
+    color.extend(1.0)
+}
+
+#[spirv(fragment)]
+pub fn bloom_prefilter(
+    output: &mut Vec4,
+    in_uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] input: &SampledImage<Image2d>,
+    #[spirv(push_constant)] params: &PostParams,
+) {
+    let texel = Vec2::from(params.texel_size);
+    let color = (fetch(input, in_uv + texel * Vec2::new(-0.5, -0.5))
+        + fetch(input, in_uv + texel * Vec2::new(0.5, -0.5))
+        + fetch(input, in_uv + texel * Vec2::new(-0.5, 0.5))
+        + fetch(input, in_uv + texel * Vec2::new(0.5, 0.5))) * 0.25;
+
+    let brightness = color.x.max(color.y).max(color.z);
+    let contribution = (brightness - params.threshold).max(0.0) / brightness.max(1e-4);
+    *output = finish(color * contribution, params);
+}
+
+#[spirv(fragment)]
+pub fn bloom_blur(
+    output: &mut Vec4,
+    in_uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] input: &SampledImage<Image2d>,
+    #[spirv(push_constant)] params: &PostParams,
+) {
+    let weights = [0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216];
+    let step = Vec2::from(params.texel_size) * Vec2::from(params.direction);
+
+    let mut color = fetch(input, in_uv) * weights[0];
+    let mut i = 1;
+    while i < 5 {
+        let offset = step * i as f32;
+        color += (fetch(input, in_uv + offset) + fetch(input, in_uv - offset)) * weights[i];
+        i += 1;
+    }
+    *output = finish(color, params);
+}
+
+#[spirv(fragment)]
+pub fn bloom_composite(
+    output: &mut Vec4,
+    in_uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] input: &SampledImage<Image2d>,
+    #[spirv(descriptor_set = 0, binding = 1)] bloom: &SampledImage<Image2d>,
+    #[spirv(push_constant)] params: &PostParams,
+) {
+    let color = fetch(input, in_uv) + fetch(bloom, in_uv) * params.intensity;
+    *output = finish(color, params);
+}
+
+fn aces(color: Vec3) -> Vec3 {
+    let a = color * (color * 2.51 + 0.03);
+    let b = color * (color * 2.43 + 0.59) + 0.14;
+    (a / b).clamp(Vec3::ZERO, Vec3::ONE)
+}
+
+fn agx(color: Vec3) -> Vec3 {
+    let inset = Mat3::from_cols_array(&[
+        0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+        0.0784335999999992, 0.878468636469772, 0.0784336,
+        0.0792237451477643, 0.0791661274605434, 0.879142973793104,
+    ]);
+    let outset = Mat3::from_cols_array(&[
+        1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+        -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+        -0.0990297440797205, -0.0989611768448433, 1.15107367264116,
+    ]);
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    let log = (inset * color).max(Vec3::splat(1e-10));
+    let log = Vec3::new(log.x.log2(), log.y.log2(), log.z.log2()).clamp(Vec3::splat(min_ev), Vec3::splat(max_ev));
+    let x = (log - min_ev) / (max_ev - min_ev);
+
+    // default contrast curve approximation
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    let curve = x4 * x2 * 15.5 - x4 * x * 40.14 + x4 * 31.96 - x2 * x * 6.868 + x2 * 0.4298 + x * 0.1191 - 0.00232;
+
+    (outset * curve).max(Vec3::ZERO).powf(2.2)
+}
+
+#[spirv(fragment)]
+pub fn tonemap(
+    output: &mut Vec4,
+    in_uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] input: &SampledImage<Image2d>,
+    #[spirv(push_constant)] params: &PostParams,
+) {
+    let color = fetch(input, in_uv) * params.exposure;
+    let mapped = if params.flags & POST_FLAG_AGX != 0 {
+        agx(color)
+    } else {
+        aces(color)
+    };
+    *output = finish(mapped, params);
+}
+
+#[spirv(fragment)]
+pub fn fxaa(
+    output: &mut Vec4,
+    in_uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] input: &SampledImage<Image2d>,
+    #[spirv(push_constant)] params: &PostParams,
+) {
+    let reduce_min = 1.0 / 128.0;
+    let reduce_mul = 1.0 / 8.0;
+    let span_max = 8.0;
+    let texel = Vec2::from(params.texel_size);
+
+    let rgb_m = fetch(input, in_uv);
+    let luma_nw = luma(fetch(input, in_uv + Vec2::new(-1.0, -1.0) * texel));
+    let luma_ne = luma(fetch(input, in_uv + Vec2::new(1.0, -1.0) * texel));
+    let luma_sw = luma(fetch(input, in_uv + Vec2::new(-1.0, 1.0) * texel));
+    let luma_se = luma(fetch(input, in_uv + Vec2::new(1.0, 1.0) * texel));
+    let luma_m = luma(rgb_m);
+    let luma_min = luma_m.min(luma_nw.min(luma_ne).min(luma_sw.min(luma_se)));
+    let luma_max = luma_m.max(luma_nw.max(luma_ne).max(luma_sw.max(luma_se)));
+
+    let direction = Vec2::new(-((luma_nw + luma_ne) - (luma_sw + luma_se)), (luma_nw + luma_sw) - (luma_ne + luma_se));
+    let direction_reduce = ((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * reduce_mul).max(reduce_min);
+    let inverse_min = 1.0 / (direction.x.abs().min(direction.y.abs()) + direction_reduce);
+    let direction = (direction * inverse_min).clamp(Vec2::splat(-span_max), Vec2::splat(span_max)) * texel;
+
+    let rgb_a = (fetch(input, in_uv + direction * (1.0 / 3.0 - 0.5)) + fetch(input, in_uv + direction * (2.0 / 3.0 - 0.5))) * 0.5;
+    let rgb_b = rgb_a * 0.5 + (fetch(input, in_uv - direction * 0.5) + fetch(input, in_uv + direction * 0.5)) * 0.25;
+    let luma_b = luma(rgb_b);
+
+    let color = if luma_b < luma_min || luma_b > luma_max { rgb_a } else { rgb_b };
+    *output = finish(color, params);
+}
+
+#[spirv(fragment)]
+pub fn blit(
+    output: &mut Vec4,
+    in_uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] input: &SampledImage<Image2d>,
+    #[spirv(push_constant)] params: &PostParams,
+) {
+    *output = finish(fetch(input, in_uv), params);
+}
+
+/// egui texels and vertex colors are premultiplied gamma space values, linearized for sRGB targets
+#[spirv(fragment)]
+pub fn gui(
+    output: &mut Vec4,
+    in_uv: Vec2,
+    in_color: Vec4,
+    #[spirv(descriptor_set = 0, binding = 0)] texture: &SampledImage<Image2d>,
+    #[spirv(push_constant)] params: &GuiParams,
+) {
+    let texel: Vec4 = unsafe { texture.sample(in_uv) };
+    let color = in_color * texel;
+    *output = if params.flags & GUI_FLAG_PQ_OUTPUT != 0 {
+        encode_pq(color.truncate().max(Vec3::ZERO).powf(2.2)).extend(color.w)
+    } else if params.flags & GUI_FLAG_LINEAR_OUTPUT != 0 {
+        color.truncate().max(Vec3::ZERO).powf(2.2).extend(color.w)
+    } else {
+        color
+    };
+}
+
+#[spirv(fragment)]
+pub fn debug_lines(output: &mut Vec4, in_color: Vec4) {
+    *output = in_color;
+}
+
+/// Scene inputs shown as colors instead of shading, `mode` is one of the `DEBUG_VIEW_*` constants.
+/// Depth is logarithmic up to `far` view space units
+#[spirv(fragment)]
+pub fn debug_view(
+    output: &mut Vec4,
+    in_tex_coords: Vec2,
+    #[spirv(flat)] in_instance_index: usize,
+    _in_normal: Vec3,
+    in_view_position: Vec3,
+    _in_light_direction: Vec3,
+    in_world_normal: Vec3,
+    #[spirv(spec_constant(id = 0, default = 0))] mode: u32,
+    // default is the bit pattern of 1000.0
+    #[spirv(spec_constant(id = 1, default = 1148846080))] far: f32,
+) {
+    let color = match mode {
+        DEBUG_VIEW_WIREFRAME => Vec3::new(0.1, 1.0, 0.3),
+        DEBUG_VIEW_NORMALS => in_world_normal.normalize() * 0.5 + Vec3::splat(0.5),
+        DEBUG_VIEW_UV_CHECKER => {
+            let cell = (in_tex_coords * 8.0).floor();
+            let sum = cell.x + cell.y;
+            let parity = sum - 2.0 * (sum * 0.5).floor();
+            Vec3::new(in_tex_coords.x, in_tex_coords.y, 1.0).clamp(Vec3::ZERO, Vec3::ONE) * (0.25 + 0.75 * parity)
+        }
+        DEBUG_VIEW_DEPTH => {
+            let distance = (-in_view_position.z).max(0.0);
+            Vec3::splat(1.0 - (distance + 1.0).log2() / (far + 1.0).log2())
+        }
+        DEBUG_VIEW_MATERIAL_ID => id_color(in_instance_index as u32),
+        // additive, every covering layer adds up
+        DEBUG_VIEW_OVERDRAW => Vec3::new(0.08, 0.03, 0.01),
+        _ => Vec3::new(1.0, 0.0, 1.0),
+    };
+    *output = color.extend(1.0);
+}
+
+/// Stable, well spread color per id
+fn id_color(id: u32) -> Vec3 {
+    let mut hash = id.wrapping_mul(0x9E37_79B1);
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(0x85EB_CA6B);
+    hash ^= hash >> 13;
+    Vec3::new((hash & 0xFF) as f32, ((hash >> 8) & 0xFF) as f32, ((hash >> 16) & 0xFF) as f32) / 255.0
+}
+
+/// Round billboard, hidden behind scene depth and faded near it when soft particles are on
+#[spirv(fragment)]
+pub fn particles(
+    #[spirv(frag_coord)] frag_coord: Vec4,
+    output: &mut Vec4,
+    in_uv: Vec2,
+    in_color: Vec4,
+    in_view_depth: f32,
+    #[spirv(descriptor_set = 0, binding = 3)] depth: &Image!(2D, type=f32, sampled),
+    #[spirv(push_constant)] params: &ParticleDrawParams,
+) {
+    let texel: Vec4 = depth.fetch(IVec2::new(frag_coord.x as i32, frag_coord.y as i32));
+    *output = shade_particle(texel.x, in_uv, in_color, in_view_depth, params);
+}
+
+/// Same as `particles` for a multisampled depth attachment, compares against sample 0
+#[spirv(fragment)]
+pub fn particles_msaa(
+    #[spirv(frag_coord)] frag_coord: Vec4,
+    output: &mut Vec4,
+    in_uv: Vec2,
+    in_color: Vec4,
+    in_view_depth: f32,
+    #[spirv(descriptor_set = 0, binding = 3)] depth: &Image!(2D, type=f32, sampled, multisampled),
+    #[spirv(push_constant)] params: &ParticleDrawParams,
+) {
+    let texel: Vec4 = depth.fetch_with(IVec2::new(frag_coord.x as i32, frag_coord.y as i32), sample_with::sample_index(0));
+    *output = shade_particle(texel.x, in_uv, in_color, in_view_depth, params);
+}
+
+/// `in_color` is premultiplied, scaling all channels fades both blend modes
+fn shade_particle(depth: f32, in_uv: Vec2, in_color: Vec4, in_view_depth: f32, params: &ParticleDrawParams) -> Vec4 {
+    let [z_scale, z_offset, min_depth, max_depth] = params.depth_params;
+    let ndc_depth = (depth - min_depth) / (max_depth - min_depth);
+    let scene_view_depth = z_offset / (ndc_depth + z_scale);
+
+    let distance = scene_view_depth - in_view_depth;
+    let fade = if params.softness > 0.0 {
+        (distance / params.softness).clamp(0.0, 1.0)
+    } else if distance > 0.0 {
+        1.0
+    } else {
+        0.0
+    };
+    let falloff = (1.0 - (in_uv * 2.0 - Vec2::ONE).length()).clamp(0.0, 1.0);
+    in_color * (falloff * fade)
+}
+
+// IBL precomputation, drawn once per cubemap face and mip with the `fullscreen` vertex shader
+
+const PI: f32 = core::f32::consts::PI;
+
+/// Same face order and orientation as the host side equirect conversion
+fn cube_direction(face: u32, uv: Vec2) -> Vec3 {
+    let s = uv.x * 2.0 - 1.0;
+    let t = uv.y * 2.0 - 1.0;
+    let direction = match face {
+        0 => Vec3::new(1.0, -t, -s),
+        1 => Vec3::new(-1.0, -t, s),
+        2 => Vec3::new(s, 1.0, t),
+        3 => Vec3::new(s, -1.0, -t),
+        4 => Vec3::new(s, -t, 1.0),
+        _ => Vec3::new(-s, -t, -1.0),
+    };
+    direction.normalize()
+}
+
+fn tangent_frame(normal: Vec3) -> (Vec3, Vec3) {
+    let up = if normal.y.abs() < 0.999 { Vec3::Y } else { Vec3::Z };
+    let tangent = up.cross(normal).normalize();
+    (tangent, normal.cross(tangent))
+}
+
+fn hammersley(i: u32, count: u32) -> Vec2 {
+    Vec2::new(i as f32 / count as f32, i.reverse_bits() as f32 * 2.328_306_4e-10)
+}
+
+fn importance_sample_ggx(xi: Vec2, normal: Vec3, roughness: f32) -> Vec3 {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = ((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y)).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+
+    let (tangent, bitangent) = tangent_frame(normal);
+    (tangent * (phi.cos() * sin_theta) + bitangent * (phi.sin() * sin_theta) + normal * cos_theta).normalize()
+}
+
+fn geometry_smith_ibl(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let k = roughness * roughness * 0.5;
+    let g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    g_v * g_l
+}
+
+/// Cosine weighted hemisphere convolution, `sample_count` steps around the normal
+#[spirv(fragment)]
+pub fn ibl_irradiance(
+    output: &mut Vec4,
+    in_uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] environment: &SampledImage<Cubemap>,
+    #[spirv(push_constant)] params: &IblParams,
+) {
+    let normal = cube_direction(params.face, in_uv);
+    let (tangent, bitangent) = tangent_frame(normal);
+
+    let phi_steps = params.sample_count.max(4);
+    let theta_steps = (phi_steps / 4).max(1);
+    let mut irradiance = Vec3::ZERO;
+    let mut i = 0;
+    while i < phi_steps {
+        let phi = 2.0 * PI * i as f32 / phi_steps as f32;
+        let mut j = 0;
+        while j < theta_steps {
+            let theta = 0.5 * PI * (j as f32 + 0.5) / theta_steps as f32;
+            let local = Vec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos());
+            let direction = tangent * local.x + bitangent * local.y + normal * local.z;
+            let color: Vec4 = unsafe { environment.sample_by_lod(direction, 0.0) };
+            irradiance += color.truncate() * theta.cos() * theta.sin();
+            j += 1;
+        }
+        i += 1;
     }
+
+    *output = (irradiance * PI / (phi_steps * theta_steps) as f32).extend(1.0);
+}
+
+/// GGX prefiltered environment for `params.roughness`, one mip level per roughness step
+#[spirv(fragment)]
+pub fn ibl_prefilter(
+    output: &mut Vec4,
+    in_uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] environment: &SampledImage<Cubemap>,
+    #[spirv(push_constant)] params: &IblParams,
+) {
+    let normal = cube_direction(params.face, in_uv);
+    let mut color = Vec3::ZERO;
+    let mut weight = 0.0;
+    let mut i = 0;
+    while i < params.sample_count {
+        let half = importance_sample_ggx(hammersley(i, params.sample_count), normal, params.roughness);
+        let light = (half * 2.0 * normal.dot(half) - normal).normalize();
+        let n_dot_l = normal.dot(light);
+        if n_dot_l > 0.0 {
+            let sample: Vec4 = unsafe { environment.sample_by_lod(light, 0.0) };
+            color += sample.truncate() * n_dot_l;
+            weight += n_dot_l;
+        }
+        i += 1;
+    }
+
+    *output = (color / weight.max(1e-4)).extend(1.0);
+}
+
+/// Split-sum scale and bias for F0, x is N.V and y is roughness
+#[spirv(fragment)]
+pub fn ibl_brdf_lut(
+    output: &mut Vec4,
+    in_uv: Vec2,
+    #[spirv(push_constant)] params: &IblParams,
+) {
+    let n_dot_v = in_uv.x.max(1e-3);
+    let roughness = in_uv.y;
+    let view = Vec3::new((1.0 - n_dot_v * n_dot_v).sqrt(), 0.0, n_dot_v);
+
+    let mut scale = 0.0;
+    let mut bias = 0.0;
+    let mut i = 0;
+    while i < params.sample_count {
+        let half = importance_sample_ggx(hammersley(i, params.sample_count), Vec3::Z, roughness);
+        let light = (half * 2.0 * view.dot(half) - view).normalize();
+        let n_dot_l = light.z.max(0.0);
+        let n_dot_h = half.z.max(0.0);
+        let v_dot_h = view.dot(half).max(0.0);
+        if n_dot_l > 0.0 {
+            let visibility = geometry_smith_ibl(n_dot_v, n_dot_l, roughness) * v_dot_h / (n_dot_h * n_dot_v).max(1e-4);
+            let fresnel = (1.0 - v_dot_h).powf(5.0);
+            scale += (1.0 - fresnel) * visibility;
+            bias += fresnel * visibility;
+        }
+        i += 1;
+    }
+
+    let count = params.sample_count.max(1) as f32;
+    *output = Vec4::new(scale / count, bias / count, 0.0, 1.0);
 }
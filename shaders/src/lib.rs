@@ -1 +1,2 @@
 pub use fragment::*;
+pub use common;
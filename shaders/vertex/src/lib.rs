@@ -2,6 +2,7 @@
 #![allow(unexpected_cfgs)]
 #![allow(clippy::too_many_arguments)]
 
+use common::{DebugParams, GuiParams, Particle, ParticleDrawParams};
 use spirv_std::glam::{Mat4, Vec2, Vec3, Vec4};
 use spirv_std::spirv;
 
@@ -10,20 +11,125 @@ pub struct UBO {
     proj: Mat4,
 }
 
+/// World space direction towards the sun lighting the scene materials
+const SUN_DIRECTION: Vec3 = Vec3::new(0.4, 1.0, 0.3);
+
+/// Lighting inputs of the material shading are passed on in view space
 #[spirv(vertex)]
 pub fn main(
     #[spirv(position)] out_position: &mut Vec4,
         in_position: Vec3,
-        _in_normals: Vec3,
+        in_normals: Vec3,
         in_tex_coords: Vec2,
         out_tex_coords: &mut Vec2,
         #[spirv(flat)] out_instance_index: &mut usize,
+        out_normal: &mut Vec3,
+        out_view_position: &mut Vec3,
+        out_light_direction: &mut Vec3,
+        out_world_normal: &mut Vec3,
         #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UBO,
         #[spirv(storage_buffer, descriptor_set = 1, binding = 3)] models: &[Mat4],
         #[spirv(instance_index)] gl_instance_index: usize) {
     let model: Mat4 = models[gl_instance_index];
-    *out_position = ubo.proj * ubo.view * (model * in_position.extend(1.0));
+    let view_model = ubo.view * model;
+    let view_position = view_model * in_position.extend(1.0);
+    *out_position = ubo.proj * view_position;
 
     *out_tex_coords = in_tex_coords;
     *out_instance_index = gl_instance_index;
-}
\ No newline at end of file
+    // ignores non-uniform scale, good enough for the current scenes
+    *out_normal = (view_model * in_normals.extend(0.0)).truncate();
+    *out_view_position = view_position.truncate();
+    *out_light_direction = (ubo.view * SUN_DIRECTION.extend(0.0)).truncate();
+    *out_world_normal = (model * in_normals.extend(0.0)).truncate();
+}
+/// Single triangle covering the screen, no vertex buffers
+#[spirv(vertex)]
+pub fn fullscreen(
+    #[spirv(vertex_index)] vertex_index: i32,
+    #[spirv(position)] out_position: &mut Vec4,
+    out_uv: &mut Vec2,
+) {
+    let uv = Vec2::new(((vertex_index << 1) & 2) as f32, (vertex_index & 2) as f32);
+    *out_uv = uv;
+    *out_position = Vec4::new(uv.x * 2.0 - 1.0, uv.y * 2.0 - 1.0, 0.0, 1.0);
+}
+
+/// Fullscreen triangle on the far plane, passes NDC on to reconstruct view rays
+#[spirv(vertex)]
+pub fn skybox(
+    #[spirv(vertex_index)] vertex_index: i32,
+    #[spirv(position)] out_position: &mut Vec4,
+    out_ndc: &mut Vec2,
+) {
+    let uv = Vec2::new(((vertex_index << 1) & 2) as f32, (vertex_index & 2) as f32);
+    let ndc = uv * 2.0 - Vec2::ONE;
+    *out_ndc = ndc;
+    *out_position = Vec4::new(ndc.x, ndc.y, 1.0, 1.0);
+}
+
+/// egui meshes, positions in points with the origin at the top left
+#[spirv(vertex)]
+pub fn gui(
+    in_position: Vec2,
+    in_uv: Vec2,
+    in_color: Vec4,
+    #[spirv(position)] out_position: &mut Vec4,
+    out_uv: &mut Vec2,
+    out_color: &mut Vec4,
+    #[spirv(push_constant)] params: &GuiParams,
+) {
+    let screen_size = Vec2::new(params.screen_size[0], params.screen_size[1]);
+    let ndc = in_position / screen_size * 2.0 - Vec2::ONE;
+    *out_position = Vec4::new(ndc.x, ndc.y, 0.0, 1.0);
+    *out_uv = in_uv;
+    *out_color = in_color;
+}
+
+/// Debug lines in world space, color is linear RGBA
+#[spirv(vertex)]
+pub fn debug_lines(
+    in_position: Vec3,
+    in_color: Vec4,
+    #[spirv(position)] out_position: &mut Vec4,
+    out_color: &mut Vec4,
+    #[spirv(push_constant)] params: &DebugParams,
+) {
+    let view_proj = Mat4::from_cols_array_2d(&params.view_proj);
+    *out_position = view_proj * in_position.extend(1.0);
+    *out_color = in_color;
+}
+
+/// Camera facing quad per live particle, `instance_index` goes through the alive list of the simulation
+#[spirv(vertex)]
+pub fn particles(
+    #[spirv(vertex_index)] vertex_index: i32,
+    #[spirv(instance_index)] instance_index: i32,
+    #[spirv(position)] out_position: &mut Vec4,
+    out_uv: &mut Vec2,
+    out_color: &mut Vec4,
+    out_view_depth: &mut f32,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] particles: &[Particle],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] alive: &[u32],
+    #[spirv(push_constant)] params: &ParticleDrawParams,
+) {
+    let particle = particles[alive[instance_index as usize] as usize];
+    let t = (particle.age / particle.lifetime).clamp(0.0, 1.0);
+    let size = particle.size_start + (particle.size_end - particle.size_start) * t;
+    let color = Vec4::from_array(particle.color_start).lerp(Vec4::from_array(particle.color_end), t);
+
+    // two triangles, 0 1 2 and 2 3 0 of the quad corners
+    let corner = [0, 1, 2, 2, 3, 0][vertex_index as usize];
+    let uv = Vec2::new((corner & 1 ^ corner >> 1) as f32, (corner >> 1) as f32);
+    let offset = (uv - Vec2::splat(0.5)) * size;
+    let position = Vec3::from_array(particle.position)
+        + Vec3::from_array(params.camera_right) * offset.x
+        + Vec3::from_array(params.camera_up) * offset.y;
+
+    let clip = Mat4::from_cols_array_2d(&params.view_proj) * position.extend(1.0);
+    *out_position = clip;
+    *out_uv = uv;
+    // alpha blended particles carry coverage in alpha, additive ones must not darken the target
+    *out_color = (color.truncate() * color.w).extend(color.w * (1.0 - particle.additive));
+    *out_view_depth = clip.w;
+}
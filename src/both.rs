@@ -1,60 +1,119 @@
-use crate::engine::camera::Camera;
+use crate::engine::bindless::{BindlessRegistry, TextureSlot};
+use crate::engine::cameras::{CameraTarget, Cameras};
+use crate::engine::debug_draw::{DebugDraw, DebugStyle};
+use crate::engine::debug_view::DebugViews;
 use crate::engine::fps::GpuTimer;
 use crate::engine::gui_renderer::FastRenderer;
-use crate::engine::pipelines::create_pipelines_multithreaded;
+use crate::engine::lod::LodSelector;
+#[cfg(feature = "hot_reload")]
+use crate::engine::hot_reload::{ShaderCrate, ShaderWatcher};
+use crate::engine::ibl::Ibl;
+use crate::engine::particles::ParticleSystem;
+use crate::engine::permutations::{PermutationCache, ShaderFeatures};
+use crate::engine::pipeline_registry::PipelineRegistry;
+use crate::engine::post::{PostChain, HDR_FORMAT};
+use crate::engine::render_graph::{BufferHandle, GraphExtent, GraphImage, ImageDesc, ImageHandle, PassContext, PassDesc, RenderGraph, ResourceUsage};
 use crate::engine::shapes::AABB::{SimpleAABox, AABB4};
-use crate::engine::{FrameInfo, PerFrameResource, PerImageResource, Settings, WinitHandler};
+use crate::engine::skybox::{Cubemap, Skybox};
+use crate::engine::{FrameInfo, PerFrameResource, PerImageResource, RenderPath, Settings, WinitHandler};
+use crate::prelude::pool_alloc::Image;
 use crate::prelude::*;
 use crate::vulkan::func::Vulkan;
 use crate::vulkan::gltf::scene::Scene;
 use crate::vulkan::gltf::utils::StagingBuffer;
-use egui::Context;
+use crate::vulkan::utils::ImageUsage;
+use egui::{ClippedPrimitive, Context};
+use std::path::Path;
 use ultraviolet::Vec3;
 use winit::keyboard::KeyCode;
 
 const MAX_FRAMES_IN_FLIGHT: usize = 3;
+const MAX_PARTICLES: u32 = 1 << 16;
+/// View distance shown as black by the depth debug view
+const DEBUG_DEPTH_RANGE: f32 = 100.0;
 #[derive(Default)]
 pub struct RenderLoop {
     pub scene: Scene,
+    pub bindless: BindlessRegistry,
     pub settings: Settings,
 
     pub current_frame: usize,
 
     pub samples: VkSampleCountFlags,
+    pub render_path: RenderPath,
+    /// Dropped before `graph_pipeline_layout`, it waits for background compiles using its modules
+    pub pipeline_registry: PipelineRegistry,
     pub graph_pipeline_layout: PipelineContainer,
-    pub graph_pipeline: VkDestroy<VkPipeline>,
+    pub scene_pipelines: PermutationCache,
     pub render_pass: VkDestroy<VkRenderPass>,
+    pub scene_framebuffer: VkDestroy<VkFramebuffer>,
     pub descriptor_set: VkDescriptorSet,
+    pub post_chain: PostChain,
+    pub skybox: Skybox,
+    pub debug_draw: DebugDraw,
+    pub debug_views: DebugViews,
+    pub particles: ParticleSystem,
+    pub lod: LodSelector,
+    pub ibl: Option<Ibl>,
     fast_renderer: FastRenderer,
+    gui_primitives: Vec<ClippedPrimitive>,
+    gui_pixels_per_point: f32,
 
-    pub camera: Camera,
+    pub cameras: Cameras,
+    offscreen: Vec<OffscreenCamera>,
     pub test_box: Vec<AABB4>,
 
     pub graphic_queue: VkQueue,
     pub present_queue: VkQueue,
     pub extent: VkExtent2D,
+    pub swapchain_format: SurfaceFormat,
 
     pub fps: GpuTimer,
     pub prepared: bool,
 
+    pub graph: RenderGraph<RenderLoop>,
+    swapchain_target: ImageHandle,
+    hdr_target: ImageHandle,
+    depth_target: ImageHandle,
+    color_msaa_target: Option<ImageHandle>,
+    ubo_target: BufferHandle,
+    draws_target: BufferHandle,
+    particle_targets: [BufferHandle; 3],
+
+    #[cfg(feature = "hot_reload")]
+    pub shader_watcher: Option<ShaderWatcher>,
+    /// Last failed build per shader crate, pipelines keep the previous modules meanwhile
+    #[cfg(feature = "hot_reload")]
+    shader_errors: Vec<(ShaderCrate, String)>,
+
     pub command_pool: VkDestroy<VkCommandPool>,
     pub per_image_resources: Vec<PerImageResource>,
     pub per_frame_resources: Vec<PerFrameResource>,
 }
 pub const RAW: &[u8] = include_bytes!("../../Untitled.glb");
 
+/// Color image an offscreen camera renders into, kept across graph rebuilds while its extent stays the same.
+/// It is registered in the bindless registry so materials can sample it
+struct OffscreenCamera {
+    camera: usize,
+    extent: VkExtent2D,
+    image: Image,
+    view: VkDestroy<VkImageView>,
+    texture: TextureSlot,
+    target: ImageHandle,
+    depth_target: ImageHandle,
+    msaa_target: Option<ImageHandle>,
+    framebuffer: VkDestroy<VkFramebuffer>,
+    pass_index: usize,
+}
+
 impl RenderLoop {
     pub fn recreate_framebuffers(&mut self, vulkan: &Vulkan, swapchain: &mut SwapchainInfo) {
-        self.camera.set_aspect_ratio(swapchain.width as f32 / swapchain.height as f32);
         self.extent = VkExtent2D {
             width: swapchain.width,
             height: swapchain.height,
         };
-        let extent = VkExtent3D {
-            width: swapchain.width,
-            height: swapchain.height,
-            depth: 1,
-        };
+        self.swapchain_format = swapchain.format;
 
         let swapchain_images = vulkan.get_images(swapchain);
         if self.per_image_resources.len() != 0 {
@@ -62,21 +121,346 @@ impl RenderLoop {
         }
 
         swapchain_images.into_iter().for_each(|image| {
-            self.per_image_resources.push(PerImageResource::new(vulkan, image, swapchain.format.format, extent, self.samples, *self.render_pass.get()));
+            self.per_image_resources.push(PerImageResource::new(vulkan, image, swapchain.format.format));
+        });
+
+        self.rebuild_graph(vulkan);
+    }
+
+    /// Declares frame passes again, needed on resize and when post chain changes. Device must be idle
+    pub fn rebuild_graph(&mut self, vulkan: &Vulkan) {
+        self.scene_framebuffer = VkDestroy::default();
+        let extent = self.extent;
+        for view in self.cameras.iter_mut() {
+            let camera_extent = view.extent(extent);
+            view.camera.set_aspect_ratio(camera_extent.width as f32 / camera_extent.height as f32);
+        }
+        self.build_graph(vulkan);
+        self.graph.compile(vulkan, self.extent);
+        self.post_chain.update_descriptors(vulkan, &self.graph);
+        self.particles.update_depth(vulkan, self.graph.image(self.depth_target).view);
+
+        if self.render_path == RenderPath::RenderPass {
+            let hdr_view = self.graph.image(self.hdr_target).view;
+            let depth_view = self.graph.image(self.depth_target).view;
+            let attachments = match self.color_msaa_target {
+                Some(msaa) => vec![self.graph.image(msaa).view, depth_view, hdr_view],
+                None => vec![hdr_view, depth_view],
+            };
+            let framebuffer = vulkan.create_framebuffer(*self.render_pass, &attachments, self.extent.width, self.extent.height, 1);
+            self.scene_framebuffer = VkDestroy::new(framebuffer, vulkan);
+
+            for offscreen in &mut self.offscreen {
+                let depth_view = self.graph.image(offscreen.depth_target).view;
+                let attachments = match offscreen.msaa_target {
+                    Some(msaa) => vec![self.graph.image(msaa).view, depth_view, *offscreen.view],
+                    None => vec![*offscreen.view, depth_view],
+                };
+                let framebuffer = vulkan.create_framebuffer(*self.render_pass, &attachments, offscreen.extent.width, offscreen.extent.height, 1);
+                offscreen.framebuffer = VkDestroy::new(framebuffer, vulkan);
+            }
+        }
+    }
+
+    /// Color image of an offscreen camera, valid until the next graph rebuild
+    pub fn camera_output(&self, camera: usize) -> Option<GraphImage> {
+        self.offscreen.iter()
+            .find(|offscreen| offscreen.camera == camera)
+            .map(|offscreen| *self.graph.image(offscreen.target))
+    }
+
+    /// Bindless slot of an offscreen camera's image, what a `TextureMaterial` stores as `source_id`.
+    /// Stays the same across graph rebuilds unless the camera's extent changes
+    pub fn camera_texture(&self, camera: usize) -> Option<TextureSlot> {
+        self.offscreen.iter()
+            .find(|offscreen| offscreen.camera == camera)
+            .map(|offscreen| offscreen.texture)
+    }
+
+    fn build_graph(&mut self, vulkan: &Vulkan) {
+        let mut graph: RenderGraph<RenderLoop> = RenderGraph::new();
+        self.swapchain_target = graph.import_image("swapchain", VkImageLayout::UNDEFINED);
+        self.ubo_target = graph.import_buffer("ubo");
+        self.hdr_target = graph.create_image("hdr", ImageDesc::attachment(HDR_FORMAT, VkImageAspectFlags::COLOR_BIT, VkSampleCountFlags::SC_1_BIT));
+        self.depth_target = graph.create_image("depth", ImageDesc::attachment(VkFormat::D32_SFLOAT, VkImageAspectFlags::DEPTH_BIT, self.samples));
+        self.color_msaa_target = if self.samples != VkSampleCountFlags::SC_1_BIT {
+            Some(graph.create_image("color_msaa", ImageDesc::attachment(HDR_FORMAT, VkImageAspectFlags::COLOR_BIT, self.samples)))
+        } else {
+            None
+        };
+
+        self.particle_targets = ["particles", "particle_alive", "particle_counters"].map(|name| graph.import_buffer(name));
+        for (handle, buffer) in self.particle_targets.into_iter().zip(self.particles.buffers()) {
+            graph.set_buffer(handle, buffer);
+        }
+
+        graph.add_pass("ubo_upload", PassDesc::new().buffer(self.ubo_target, ResourceUsage::TransferWrite), |render_loop, vulkan, pass| {
+            render_loop.scene.ubo.sync_with_buffer(pass.command_buffer, vulkan);
         });
+
+        let color_clear = VkClearValue { color: VkClearColorValue { float32: [0.0, 0.0, 0.0, 1.0] } };
+        let depth_clear = VkClearValue { depthStencil: VkClearDepthStencilValue { depth: 1.0, stencil: 0 } };
+        self.draws_target = graph.import_buffer("scene_draws");
+        graph.set_buffer(self.draws_target, *self.scene.indirect_buffer.get());
+        graph.add_pass("scene_lod", PassDesc::new()
+                           .buffer(self.draws_target, ResourceUsage::StorageWrite(VkPipelineStageFlags2::COMPUTE_SHADER_BIT)),
+                       |render_loop, vulkan, pass| {
+                           for (slice, view) in render_loop.cameras.iter().enumerate().filter(|(_, view)| view.enabled) {
+                               let target = render_loop.offscreen.iter()
+                                   .find(|offscreen| offscreen.camera == slice)
+                                   .map(|offscreen| offscreen.texture);
+                               render_loop.lod.record(vulkan, pass.command_buffer, &render_loop.scene, slice, &view.camera, target);
+                           }
+                       });
+
+        let mut scene_pass = PassDesc::new()
+            .buffer(self.ubo_target, ResourceUsage::UniformRead(VkPipelineStageFlags2::VERTEX_SHADER_BIT))
+            .buffer(self.draws_target, ResourceUsage::IndirectBuffer)
+            .depth_attachment(self.depth_target, VkAttachmentLoadOp::CLEAR, depth_clear);
+        scene_pass = match self.color_msaa_target {
+            Some(msaa) => scene_pass.resolved_color_attachment(msaa, self.hdr_target, VkAttachmentLoadOp::CLEAR, color_clear),
+            None => scene_pass.color_attachment(self.hdr_target, VkAttachmentLoadOp::CLEAR, color_clear),
+        };
+        if self.render_path == RenderPath::RenderPass {
+            scene_pass = scene_pass.manual_rendering();
+        }
+        // materials may show any camera texture
+        self.build_offscreen_passes(vulkan, &mut graph, color_clear, depth_clear);
+        for offscreen in &self.offscreen {
+            scene_pass = scene_pass.image(offscreen.target, ResourceUsage::Sampled(VkPipelineStageFlags2::FRAGMENT_SHADER_BIT));
+        }
+        graph.add_pass("scene", scene_pass, RenderLoop::scene_pass);
+
+        let [particles, alive, counters] = self.particle_targets;
+        let compute_write = ResourceUsage::StorageWrite(VkPipelineStageFlags2::COMPUTE_SHADER_BIT);
+        graph.add_pass("particles_simulate", PassDesc::new()
+                           .buffer(particles, compute_write)
+                           .buffer(alive, compute_write)
+                           .buffer(counters, compute_write),
+                       |render_loop, vulkan, pass| render_loop.particles.record_simulation(vulkan, pass.command_buffer));
+        // after resolve, depth is sampled instead of attached so the pass works with any sample count
+        graph.add_pass("particles", PassDesc::new()
+                           .buffer(particles, ResourceUsage::StorageRead(VkPipelineStageFlags2::VERTEX_SHADER_BIT))
+                           .buffer(alive, ResourceUsage::StorageRead(VkPipelineStageFlags2::VERTEX_SHADER_BIT))
+                           .buffer(counters, ResourceUsage::IndirectBuffer)
+                           .image(self.depth_target, ResourceUsage::Sampled(VkPipelineStageFlags2::FRAGMENT_SHADER_BIT))
+                           .color_attachment(self.hdr_target, VkAttachmentLoadOp::LOAD, VkClearValue::default()),
+                       |render_loop, vulkan, pass| {
+                           let extent = render_loop.extent;
+                           for view in render_loop.cameras.iter_mut().filter(|view| view.enabled) {
+                               let CameraTarget::Viewport(rect) = view.target else {
+                                   continue;
+                               };
+                               let area = rect.to_pixels(extent);
+                               render_loop.particles.record_draw(vulkan, pass.command_buffer, &mut view.camera, camera_viewport(area), area);
+                           }
+                       });
+
+        self.post_chain.build(vulkan, &mut graph, &self.settings.post, self.hdr_target, self.swapchain_target, self.swapchain_format,
+                              |render_loop, vulkan, pass| render_loop.post_chain.record(vulkan, pass, &render_loop.settings.post));
+
+        graph.add_pass("gui", PassDesc::new().color_attachment(self.swapchain_target, VkAttachmentLoadOp::LOAD, VkClearValue::default()),
+                       |render_loop, vulkan, pass| {
+                           render_loop.fast_renderer.render_primitives(vulkan, render_loop.current_frame, pass.command_buffer, pass.render_area.extent,
+                                                                       render_loop.gui_pixels_per_point, &render_loop.gui_primitives);
+                       });
+
+        graph.export_image(self.swapchain_target, ResourceUsage::Present);
+        self.graph = graph;
+    }
+
+    fn scene_pass(&mut self, vulkan: &Vulkan, pass: &PassContext) {
+        let command_buffer = pass.command_buffer;
+        if self.render_path == RenderPath::RenderPass {
+            let clear_values = vec![
+                VkClearValue { color: VkClearColorValue { float32: [0.0, 0.0, 0.0, 1.0] } },
+                VkClearValue { depthStencil: VkClearDepthStencilValue { depth: 1.0, stencil: 0 } },
+            ];
+            vulkan.begin_render_pass(command_buffer, *self.render_pass, *self.scene_framebuffer,
+                                     pass.render_area, clear_values.as_slice(), VkSubpassContents::INLINE);
+        }
+        for index in 0..self.cameras.len() {
+            let view = self.cameras.get(index);
+            let CameraTarget::Viewport(rect) = view.target else {
+                continue;
+            };
+            if view.enabled {
+                self.record_camera(vulkan, command_buffer, index, rect.to_pixels(self.extent));
+            }
+        }
+
+        if self.render_path == RenderPath::RenderPass {
+            vulkan.end_render_pass(command_buffer);
+        }
+    }
+
+    /// Only the base permutation is created right away, draws use it until their own permutation finished compiling
+    fn prepare_scene_pipelines(&mut self, vulkan: &Vulkan) {
+        self.scene_pipelines.prepare(vulkan, &mut self.pipeline_registry, [ShaderFeatures::NONE]);
+        self.scene_pipelines.prepare_async(vulkan, &mut self.pipeline_registry, self.scene.features());
+    }
+
+    /// Scene pipeline info for the current render path and sample count, subsystems derive their pipelines from it
+    fn scene_template(&self) -> GraphicsPipelineCreateInfo {
+        let mut create_info = preset_multisample(self.graph_pipeline_layout.info.clone(), self.samples, self.samples);
+        if self.render_path == RenderPath::DynamicRendering {
+            create_info = preset_dynamic_rendering(create_info, &[HDR_FORMAT], VkFormat::D32_SFLOAT);
+        }
+        create_info
+    }
+
+    /// Scene, skybox and debug shapes seen by camera `index`, drawn into `area` of the bound attachments
+    fn record_camera(&mut self, vulkan: &Vulkan, command_buffer: VkCommandBuffer, index: usize, area: VkRect2D) {
+        let viewports = [camera_viewport(area)];
+        unsafe { vkCmdSetViewport(command_buffer, 0, 1, viewports.as_ptr()); };
+        let scissors = [area];
+        unsafe { vkCmdSetScissor(command_buffer, 0, 1, scissors.as_ptr()); };
+
+        match self.debug_views.pipeline(vulkan, &mut self.pipeline_registry) {
+            Some(pipeline) => self.scene.render_scene_with(vulkan, command_buffer, self.graph_pipeline_layout.layout, pipeline, index),
+            None => self.scene.render_scene(vulkan, command_buffer, self.graph_pipeline_layout.layout, &self.scene_pipelines, index),
+        }
+        let camera = &mut self.cameras.get_mut(index).camera;
+        self.skybox.record(vulkan, command_buffer, camera);
+        self.debug_draw.record(vulkan, self.current_frame, command_buffer, camera);
+    }
+
+    /// One scene pass per offscreen camera, their images and texture slots are reused while camera and extent stay the same.
+    /// A camera sees the textures of cameras added before it, later ones are not ready yet
+    fn build_offscreen_passes(&mut self, vulkan: &Vulkan, graph: &mut RenderGraph<RenderLoop>, color_clear: VkClearValue, depth_clear: VkClearValue) {
+        let mut previous = std::mem::take(&mut self.offscreen);
+        for index in 0..self.cameras.len() {
+            let view = self.cameras.get(index);
+            let CameraTarget::Offscreen(extent) = view.target else {
+                continue;
+            };
+            if !view.enabled {
+                continue;
+            }
+            let reused = previous.iter()
+                .position(|offscreen| offscreen.camera == index && offscreen.extent == extent)
+                .map(|position| previous.remove(position));
+            let (image, image_view, texture) = match reused {
+                Some(offscreen) => (offscreen.image, offscreen.view, offscreen.texture),
+                None => {
+                    let image = vulkan.pool().allocate_image(HDR_FORMAT, VkImageType::IT_2D, false, 1, 1,
+                                                             VkExtent3D { width: extent.width, height: extent.height, depth: 1 }, VkSampleCountFlags::SC_1_BIT,
+                                                             ImageUsage::default().color_attachment(true).sampled(true));
+                    let image_view = vulkan.create_image_view(&image.image, VkImageViewType::IVT_2D, HDR_FORMAT, VkImageAspectFlags::COLOR_BIT);
+                    // registered textures have to be readable at all times
+                    vulkan.immediate_submit(|command_buffer| {
+                        vulkan.transition_images2(vec![ImageTransition2 {
+                            image: image.image,
+                            src_stage: VkPipelineStageFlags2::NONE,
+                            dst_stage: VkPipelineStageFlags2::FRAGMENT_SHADER_BIT,
+                            src_access: VkAccessFlags2::NONE,
+                            dst_access: VkAccessFlags2::SHADER_SAMPLED_READ_BIT,
+                            old_layout: VkImageLayout::UNDEFINED,
+                            new_layout: VkImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                            ..Default::default()
+                        }], command_buffer);
+                    });
+                    let texture = self.bindless.register_texture(vulkan, image_view);
+                    self.cameras.get_mut(index).request_update();
+                    (image, VkDestroy::new(image_view, vulkan), texture)
+                }
+            };
+
+            // skipped updates keep the last image, so it starts every frame sampled instead of discarded
+            let target = graph.import_image("camera_output", VkImageLayout::SHADER_READ_ONLY_OPTIMAL);
+            graph.set_image(target, GraphImage {
+                image: image.image,
+                view: *image_view,
+                format: HDR_FORMAT,
+                extent,
+                aspect: VkImageAspectFlags::COLOR_BIT,
+            });
+            let fixed = |format: VkFormat, aspect: VkImageAspectFlags| ImageDesc {
+                format,
+                extent: GraphExtent::Fixed(extent),
+                samples: self.samples,
+                aspect,
+            };
+            let depth_target = graph.create_image("camera_depth", fixed(VkFormat::D32_SFLOAT, VkImageAspectFlags::DEPTH_BIT));
+            let msaa_target = (self.samples != VkSampleCountFlags::SC_1_BIT)
+                .then(|| graph.create_image("camera_color_msaa", fixed(HDR_FORMAT, VkImageAspectFlags::COLOR_BIT)));
+
+            let mut pass = PassDesc::new()
+                .buffer(self.ubo_target, ResourceUsage::UniformRead(VkPipelineStageFlags2::VERTEX_SHADER_BIT))
+                .buffer(self.draws_target, ResourceUsage::IndirectBuffer)
+                .depth_attachment(depth_target, VkAttachmentLoadOp::CLEAR, depth_clear);
+            for earlier in &self.offscreen {
+                pass = pass.image(earlier.target, ResourceUsage::Sampled(VkPipelineStageFlags2::FRAGMENT_SHADER_BIT));
+            }
+            pass = match msaa_target {
+                Some(msaa) => pass.resolved_color_attachment(msaa, target, VkAttachmentLoadOp::CLEAR, color_clear),
+                None => pass.color_attachment(target, VkAttachmentLoadOp::CLEAR, color_clear),
+            };
+            if self.render_path == RenderPath::RenderPass {
+                pass = pass.manual_rendering();
+            }
+            let pass_index = graph.add_pass("camera_scene", pass, RenderLoop::offscreen_pass);
+            graph.export_image(target, ResourceUsage::Sampled(VkPipelineStageFlags2::FRAGMENT_SHADER_BIT));
+
+            self.offscreen.push(OffscreenCamera {
+                camera: index,
+                extent,
+                image,
+                view: image_view,
+                texture,
+                target,
+                depth_target,
+                msaa_target,
+                framebuffer: VkDestroy::default(),
+                pass_index,
+            });
+        }
+        // the device is idle while the graph is rebuilt, so dropped images can go right away
+        for offscreen in previous {
+            self.bindless.free_texture(offscreen.texture);
+        }
+    }
+
+    fn offscreen_pass(&mut self, vulkan: &Vulkan, pass: &PassContext) {
+        let command_buffer = pass.command_buffer;
+        let Some(offscreen) = self.offscreen.iter().find(|offscreen| offscreen.pass_index == pass.pass_index) else {
+            return;
+        };
+        let camera = offscreen.camera;
+        if self.render_path == RenderPath::RenderPass {
+            let clear_values = vec![
+                VkClearValue { color: VkClearColorValue { float32: [0.0, 0.0, 0.0, 1.0] } },
+                VkClearValue { depthStencil: VkClearDepthStencilValue { depth: 1.0, stencil: 0 } },
+            ];
+            vulkan.begin_render_pass(command_buffer, *self.render_pass, *offscreen.framebuffer,
+                                     pass.render_area, clear_values.as_slice(), VkSubpassContents::INLINE);
+        }
+        self.record_camera(vulkan, command_buffer, camera, pass.render_area);
+        if self.render_path == RenderPath::RenderPass {
+            vulkan.end_render_pass(command_buffer);
+        }
     }
+
     pub fn init(&mut self, vulkan: &Vulkan, swapchain: &mut SwapchainInfo, settings: &mut Settings) {
         let mut staging = StagingBuffer::new();
-        self.scene = Scene::from_glb(RAW, vulkan.clone(), &mut staging);
+        self.bindless = BindlessRegistry::new(vulkan, MAX_FRAMES_IN_FLIGHT);
+        self.scene = Scene::from_glb_with(RAW, vulkan.clone(), &mut staging, &mut self.bindless, &settings.lod);
+        self.lod = LodSelector::new(vulkan);
+        self.lod.bind_scene(vulkan, &self.scene);
 
         let limits = &vulkan.get_loaded_device().device_info.properties.limits;
         let supported_samples = limits.framebufferColorSampleCounts & limits.framebufferDepthSampleCounts;
         self.samples = resolve_highest_multisampling(supported_samples, settings.msaa);
-        let render_pass = vulkan.preset_renderpass_color_depth(self.samples, self.settings.render_format.format, VkImageLayout::UNDEFINED, VkImageLayout::PRESENT_SRC_KHR);
+        self.render_path = settings.render_path;
+        self.settings.post = settings.post.clone();
+        self.settings.present_mode = swapchain.present_mode;
+        self.settings.surface_formats = swapchain.preferred_formats.clone();
+        let render_pass = match self.render_path {
+            RenderPath::RenderPass => vulkan.preset_renderpass_color_depth(self.samples, HDR_FORMAT, VkImageLayout::UNDEFINED, VkImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+            RenderPath::DynamicRendering => VkRenderPass::none(),
+        };
         self.render_pass = VkDestroy::new(render_pass, vulkan);
 
-        self.recreate_framebuffers(vulkan, swapchain);
-
         let command_pool = vulkan.create_command_pool(vulkan.get_loaded_device().queue_info[0].family_index, VkCommandPoolCreateFlags::RESET_COMMAND_BUFFER_BIT);
         self.per_frame_resources = vulkan.alloc_command_buffers(command_pool, VkCommandBufferLevel::PRIMARY, MAX_FRAMES_IN_FLIGHT as u32).into_iter()
             .map(|command_buffer| {
@@ -84,18 +468,28 @@ impl RenderLoop {
             }).collect::<Vec<_>>();
         self.command_pool = VkDestroy::new(command_pool, vulkan);
 
-        self.graph_pipeline_layout = preset_graphic_pipeline(vulkan, swapchain.width, swapchain.height, render_pass, 0, &self.scene.descriptors.descriptor_layouts);
+        let mut descriptor_layouts = self.scene.descriptors.descriptor_layouts.clone();
+        descriptor_layouts.push(self.bindless.descriptor_layout());
+        self.graph_pipeline_layout = preset_graphic_pipeline(vulkan, swapchain.width, swapchain.height, render_pass, 0, &descriptor_layouts);
+
+        let create_info = self.scene_template();
+        self.skybox = Skybox::new(vulkan, create_info.clone());
+        self.debug_draw = DebugDraw::new(vulkan, create_info.clone(), MAX_FRAMES_IN_FLIGHT);
+        self.debug_views = DebugViews::new(vulkan, create_info.clone(), settings.debug_view, DEBUG_DEPTH_RANGE);
+        self.pipeline_registry = PipelineRegistry::new(vulkan);
+        self.scene_pipelines = PermutationCache::new(create_info);
+        self.prepare_scene_pipelines(vulkan);
+        self.post_chain = PostChain::new(vulkan, self.graph_pipeline_layout.info.clone());
+        self.fast_renderer = FastRenderer::new(vulkan, self.graph_pipeline_layout.info.clone(), swapchain.format, MAX_FRAMES_IN_FLIGHT);
+        self.particles = ParticleSystem::new(vulkan, self.graph_pipeline_layout.info.clone(), self.samples, MAX_PARTICLES);
 
-        let create_info = preset_multisample(self.graph_pipeline_layout.info.clone(), supported_samples, settings.msaa);
-        let graph_pipeline = create_pipelines_multithreaded(true, vec![create_info], vulkan)[0];
-        self.graph_pipeline = VkDestroy::new(graph_pipeline, vulkan);
+        self.recreate_framebuffers(vulkan, swapchain);
 
         //TODO: check for queues
         self.graphic_queue = vulkan.get_queues()[0];
         self.present_queue = vulkan.get_queues()[0];
 
-        self.scene.ubo.set_view(self.camera.view_matrix());
-        self.scene.ubo.set_proj(self.camera.projection_matrix());
+        self.update_camera_slices();
 
         let mut rng = rand::rng();
         self.test_box = Vec::with_capacity(2);
@@ -105,6 +499,11 @@ impl RenderLoop {
 
         self.fps = GpuTimer::new(vulkan.get_loaded_device().logical_device, vulkan.get_loaded_device().device_info.properties.limits.timestampPeriod);
 
+        #[cfg(feature = "hot_reload")]
+        {
+            self.shader_watcher = Some(ShaderWatcher::new());
+        }
+
         //allocate
 
         //prepare
@@ -112,20 +511,103 @@ impl RenderLoop {
         self.prepared = true;
     }
 
+    /// Shows `environment` as skybox and binds its IBL maps, read from or written to `ibl_cache` when given.
+    /// The scene shading does not use them yet, the bindings are reserved for PBR
+    pub fn set_environment(&mut self, vulkan: &Vulkan, environment: Cubemap, ibl_cache: Option<&Path>) {
+        let ibl = Ibl::load_or_generate(vulkan, &environment, self.graph_pipeline_layout.info.clone(), ibl_cache);
+        self.scene.bind_environment(vulkan, &ibl);
+        self.skybox.set_environment(vulkan, Some(environment));
+        self.ibl = Some(ibl);
+    }
+
+    /// Swaps in shader crates rebuilt by the watcher. A failed build keeps the old pipelines and is shown in the stats window
+    #[cfg(feature = "hot_reload")]
+    fn poll_shader_reload(&mut self, vulkan: &Vulkan) {
+        let Some(watcher) = &self.shader_watcher else {
+            return;
+        };
+        let updates = watcher.poll();
+        if updates.iter().any(|update| update.result.is_ok()) {
+            vulkan.device_wait();
+            // background compiles still read the modules replaced below
+            self.pipeline_registry.wait_pending();
+        }
+
+        let mut graphics_changed = false;
+        for update in updates {
+            self.shader_errors.retain(|(shader, _)| *shader != update.shader);
+            let spirv = match update.result {
+                Ok(spirv) => spirv,
+                Err(message) => {
+                    eprintln!("{message}");
+                    self.shader_errors.push((update.shader, message));
+                    continue;
+                }
+            };
+            match update.shader {
+                ShaderCrate::Vertex => {
+                    self.graph_pipeline_layout.replace_shader(vulkan, VkShaderStageFlags::VERTEX_BIT, &spirv);
+                    graphics_changed = true;
+                }
+                ShaderCrate::Fragment => {
+                    self.graph_pipeline_layout.replace_shader(vulkan, VkShaderStageFlags::FRAGMENT_BIT, &spirv);
+                    graphics_changed = true;
+                }
+                ShaderCrate::Compute => {
+                    self.particles.reload_compute(vulkan, &spirv);
+                    self.lod.reload_compute(vulkan, &spirv);
+                }
+            }
+        }
+
+        // the IBL maps are baked once and keep the shaders they were generated with
+        if graphics_changed {
+            let template = self.scene_template();
+            let base = self.graph_pipeline_layout.info.clone();
+            self.scene_pipelines.set_template(template.clone());
+            self.debug_views.set_template(template.clone());
+            // before new requests, a new module may get the handle of a destroyed one and match its stale keys
+            self.pipeline_registry.evict_unused();
+            self.prepare_scene_pipelines(vulkan);
+            self.skybox.rebuild_pipeline(vulkan, template.clone());
+            self.debug_draw.rebuild_pipelines(vulkan, template);
+            self.fast_renderer.rebuild_pipeline(vulkan, base.clone());
+            self.particles.rebuild_pipeline(vulkan, base.clone());
+            self.post_chain.set_template(base);
+            self.rebuild_graph(vulkan);
+        }
+    }
+
     pub fn render_loop(&mut self, vulkan: &Vulkan, swapchain: &mut SwapchainInfo, ctx: &mut Context, handler: &mut WinitHandler, frame_info: FrameInfo) {
         if !self.prepared {
             return;
         }
-        if self.extent.width != swapchain.width || self.extent.height != swapchain.height {
+        #[cfg(feature = "hot_reload")]
+        self.poll_shader_reload(vulkan);
+        if self.pipeline_registry.poll() > 0 {
+            self.scene_pipelines.poll(&self.pipeline_registry);
+        }
+        let resized = self.extent.width != swapchain.width || self.extent.height != swapchain.height;
+        let presentation_changed = self.settings.present_mode != swapchain.present_mode || self.settings.surface_formats != swapchain.preferred_formats;
+        if resized || presentation_changed {
             vulkan.device_wait();
             self.per_image_resources.clear();
 
             self.extent.height = swapchain.height;
             self.extent.width = swapchain.width;
+            swapchain
+                .set_present_mode(self.settings.present_mode)
+                .set_formats(self.settings.surface_formats.clone());
             vulkan.create_swapchain(swapchain);
+            if swapchain.format != self.swapchain_format {
+                self.fast_renderer.set_format(vulkan, self.graph_pipeline_layout.info.clone(), swapchain.format);
+            }
 
             self.recreate_framebuffers(vulkan, swapchain);
-            self.scene.ubo.set_proj(self.camera.projection_matrix());
+        }
+        if self.settings.post.active() != self.post_chain.effects() {
+            vulkan.device_wait();
+            self.rebuild_graph(vulkan);
         }
 
         let current_frame = self.current_frame;
@@ -133,53 +615,79 @@ impl RenderLoop {
 
         vulkan.wait_for_fences(&[frame_resource.fence()], true, u64::MAX);
         vulkan.reset_fences(&[frame_resource.fence()]);
+        self.bindless.begin_frame();
 
         let image_index = vulkan.get_next_image_index(swapchain, frame_resource.image_available_semaphore(), VkFence::none()) as usize;
         let image_resource = self.per_image_resources.get(image_index).unwrap();
+        let command_buffer = frame_resource.command_buffer();
 
-        if self.camera.tick_speed(frame_info.delta_time) {
-            self.scene.ubo.set_view(self.camera.view_matrix());
-        };
+        for view in self.cameras.iter_mut() {
+            view.camera.tick_speed(frame_info.delta_time);
+        }
+        self.update_camera_slices();
+        for offscreen in &self.offscreen {
+            let due = self.cameras.get_mut(offscreen.camera).tick_update(frame_info.delta_time);
+            self.graph.set_pass_enabled(offscreen.pass_index, due);
+        }
+
+        self.particles.update(frame_info.delta_time as f32, &self.scene);
+        self.debug_draw.tick(frame_info.delta_time as f32);
+        for aabb in &self.test_box {
+            self.debug_draw.aabb4(aabb, DebugStyle::default().color([1.0, 0.8, 0.0, 1.0]));
+        }
+
+        // TODO: Move to another thread
+        let frame_time = frame_info.delta_time * 1000.0;
+        let presentation = format!("Present: {:?} as {:?} (F4), {:?} {:?} (F5)", self.settings.present_mode, swapchain.active_present_mode,
+                                   swapchain.format.format, swapchain.format.colorSpace);
+        let full_output = ctx.run(frame_info.raw_input, |ctx| {
+            egui::Window::new("Stats").show(ctx, |ui| {
+                ui.label(format!("Frame: {:.2} ms", frame_time));
+                ui.label(format!("View: {} (F3)", self.debug_views.current.label()));
+                ui.label(&presentation);
+                let pipelines = self.pipeline_registry.stats();
+                ui.label(format!("Pipelines: {} live, {} compiling, {} hits, {} misses, {} evicted",
+                                 pipelines.live, pipelines.pending, pipelines.hits, pipelines.misses, pipelines.evictions));
+                #[cfg(feature = "hot_reload")]
+                for (_, message) in &self.shader_errors {
+                    ui.colored_label(egui::Color32::RED, message);
+                }
+            });
+        });
+        handler.handle_output(full_output.platform_output);
+        self.gui_pixels_per_point = full_output.pixels_per_point;
+        self.gui_primitives = ctx.tessellate(full_output.shapes, full_output.pixels_per_point);
 
         let recording_info = RecordingInfo {
             renderPass: *self.render_pass.get(),
             subpass: 0,
-            framebuffer: image_resource.framebuffer(),
+            framebuffer: *self.scene_framebuffer,
             occlusionQueryEnable: false,
             queryFlags: Default::default(),
             pipelineStatistics: Default::default(),
         };
-        vulkan.reset_buffer(frame_resource.command_buffer(), false);
-        vulkan.start_recording(frame_resource.command_buffer(), VkCommandBufferUsageFlags::ONE_TIME_SUBMIT_BIT, recording_info);
-        self.fps.begin(frame_resource.command_buffer());
-        self.scene.ubo.sync_with_buffer(frame_resource.command_buffer(), vulkan);
-        let clear_values = vec![
-            VkClearValue { color: VkClearColorValue { float32: [0.0, 0.0, 0.0, 1.0] } },
-            VkClearValue { depthStencil: VkClearDepthStencilValue { depth: 1.0, stencil: 0 } },
-        ];
-        vulkan.begin_render_pass(frame_resource.command_buffer(), *self.render_pass, image_resource.framebuffer(),
-                                  VkRect2D { offset: Default::default(), extent: self.extent }, clear_values.as_slice(), VkSubpassContents::INLINE);
-        vulkan.bind_pipeline(frame_resource.command_buffer(), VkPipelineBindPoint::GRAPHICS, *self.graph_pipeline);
-
-        let viewports = [VkViewport {
-            x: 0.0,
-            y: 0.0,
-            width: swapchain.width as f32,
-            height: swapchain.height as f32,
-            minDepth: 0.1,
-            maxDepth: 1.0,
-        }];
-        unsafe { vkCmdSetViewport(frame_resource.command_buffer(), 0, 1, viewports.as_ptr()); };
-
-        let scissors = [VkRect2D {
-            offset: Default::default(),
+        self.graph.set_image(self.swapchain_target, GraphImage {
+            image: image_resource.swapchain_image(),
+            view: image_resource.swapchain_image_view(),
+            format: swapchain.format.format,
             extent: self.extent,
-        }];
-        unsafe { vkCmdSetScissor(frame_resource.command_buffer(), 0, 1, scissors.as_ptr()); };
+            aspect: VkImageAspectFlags::COLOR_BIT,
+        });
+        self.graph.set_buffer(self.ubo_target, self.scene.ubo.provide_buffer());
+
+        vulkan.reset_buffer(command_buffer, false);
+        vulkan.start_recording(command_buffer, VkCommandBufferUsageFlags::ONE_TIME_SUBMIT_BIT, recording_info);
+        self.fps.begin(command_buffer);
+        self.fast_renderer.update_textures(vulkan, current_frame, command_buffer, &full_output.textures_delta);
+
+        self.debug_draw.upload(vulkan, current_frame);
 
-        self.scene.render_scene(vulkan, frame_resource.command_buffer(), self.graph_pipeline_layout.layout);
+        let graph = std::mem::take(&mut self.graph);
+        graph.execute(vulkan, command_buffer, self);
+        self.graph = graph;
 
-        vulkan.end_render_pass(frame_resource.command_buffer());
+        let frame_resource = &self.per_frame_resources[current_frame];
+        let image_resource = &self.per_image_resources[image_index];
         self.fps.end(frame_resource.command_buffer());
         vulkan.end_recording(frame_resource.command_buffer());
 
@@ -199,64 +707,70 @@ impl RenderLoop {
         };
         unsafe { vkQueuePresentKHR(self.present_queue, &present_info) };
 
-        //GUI
-        // TODO: Move to another thread
-        // let raw_input = frame_info.raw_input;
-        // let full_output = ctx.run(raw_input, |ctx| {
-        //     egui::CentralPanel::default().show(&ctx, |ui| {
-        //         ui.label("Hello world!");
-        //         if ui.button("Click me").clicked() {
-        //             dbg!("Clicked!");
-        //         }
-        //     });
-        // });
-        // handler.handle_output(full_output.platform_output);
-        // let clipped_primitives = ctx.tessellate(full_output.shapes, full_output.pixels_per_point);
-        //self.gui_renderer.render_primitives(vulkan, clipped_primitives);
+        self.fast_renderer.free_textures(&full_output.textures_delta);
 
         self.current_frame = (current_frame + 1) % MAX_FRAMES_IN_FLIGHT;
     }
 
+    /// Writes every camera into its UBO slice
+    fn update_camera_slices(&mut self) {
+        for (index, view) in self.cameras.iter_mut().enumerate() {
+            self.scene.ubo.set_slice(index, view.camera.view_matrix(), view.camera.projection_matrix());
+        }
+    }
+
     pub fn handle_mouse_input(&mut self, delta: (f64, f64)) {
         let pitch = delta.1;
         let yaw = delta.0;
 
-        self.camera.rotate(yaw as f32, pitch as f32);
-
-        self.scene.ubo.set_view(self.camera.view_matrix());
+        for (_, view) in self.cameras.routed_mut().filter(|(_, view)| view.mouse) {
+            view.camera.rotate(yaw as f32, pitch as f32);
+        }
     }
 
     pub fn key_pressed(&mut self, key: KeyCode) {
-        let speed_vec = key_map(key);
-        self.camera.add_speed(speed_vec);
+        if key == KeyCode::Tab {
+            self.cameras.focused_mut().camera.set_speed(Vec3::zero());
+            self.cameras.focus_next();
+            return;
+        }
+        if key == KeyCode::F3 {
+            self.debug_views.cycle();
+            return;
+        }
+        if key == KeyCode::F4 {
+            self.settings.present_mode = self.settings.present_mode.next();
+            return;
+        }
+        if key == KeyCode::F5 {
+            self.settings.surface_formats = if self.settings.surface_formats.iter().any(SurfaceFormat::is_hdr) {
+                SurfaceFormat::sdr_preferences()
+            } else {
+                SurfaceFormat::hdr_preferences()
+            };
+            return;
+        }
+        for (_, view) in self.cameras.routed_mut() {
+            let speed_vec = view.keys.direction(key);
+            view.camera.add_speed(speed_vec);
+        }
     }
 
     pub fn key_released(&mut self, key: KeyCode) {
-        let speed_vec = key_map(key);
-        self.camera.remove_speed(speed_vec);
+        for (_, view) in self.cameras.routed_mut() {
+            let speed_vec = view.keys.direction(key);
+            view.camera.remove_speed(speed_vec);
+        }
     }
 }
-//TODO: write keymap api with serialization
-fn key_map(key_code: KeyCode) -> Vec3 {
-    match key_code {
-        KeyCode::KeyW => {
-            -Vec3::unit_z()
-        },
-        KeyCode::KeyS => {
-            Vec3::unit_z()
-        },
-        KeyCode::KeyD => {
-            Vec3::unit_x()
-        },
-        KeyCode::KeyA => {
-            -Vec3::unit_x()
-        },
-        KeyCode::Space => {
-            Vec3::unit_y()
-        },
-        KeyCode::ShiftLeft => {
-            -Vec3::unit_y()
-        },
-        _ => Vec3::zero(),
+
+fn camera_viewport(area: VkRect2D) -> VkViewport {
+    VkViewport {
+        x: area.offset.x as f32,
+        y: area.offset.y as f32,
+        width: area.extent.width as f32,
+        height: area.extent.height as f32,
+        minDepth: 0.1,
+        maxDepth: 1.0,
     }
-}
\ No newline at end of file
+}
@@ -61,7 +61,8 @@ impl ApplicationHandler for App {
         self.swapchain_info
             .set_width(self.settings.width)
             .set_height(self.settings.height)
-            .set_format(self.settings.render_format)
+            .set_formats(self.settings.surface_formats.clone())
+            .set_present_mode(self.settings.present_mode)
             .set_surface(surface);
         self.vulkan.create_swapchain(&mut self.swapchain_info);
 
@@ -0,0 +1,227 @@
+use crate::prelude::*;
+use crate::vulkan::func::Vulkan;
+use crate::vulkan::utils::build_pool_size;
+use std::ptr::null_mut;
+
+/// Set index of the registry in every pipeline layout reading it, matches `descriptor_set` in the shaders
+pub const BINDLESS_SET: u32 = 2;
+pub const BINDLESS_TEXTURE_BINDING: u32 = 0;
+pub const BINDLESS_SAMPLER_BINDING: u32 = 1;
+pub const BINDLESS_BUFFER_BINDING: u32 = 2;
+
+pub const MAX_BINDLESS_TEXTURES: u32 = 4096;
+pub const MAX_BINDLESS_SAMPLERS: u32 = 64;
+pub const MAX_BINDLESS_BUFFERS: u32 = 1024;
+
+/// Index into the registry's texture array, what materials store as `source_id`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TextureSlot(pub u32);
+
+/// Index into the registry's sampler array, what materials store as `sampler_id`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SamplerSlot(pub u32);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BufferSlot(pub u32);
+
+/// Hands out array elements, freed ones come back only after `frames_in_flight` frames
+#[derive(Default)]
+struct SlotAllocator {
+    kind: &'static str,
+    capacity: u32,
+    next: u32,
+    free: Vec<u32>,
+    retired: Vec<(u64, u32)>,
+}
+
+impl SlotAllocator {
+    fn new(kind: &'static str, capacity: u32) -> Self {
+        SlotAllocator {
+            kind,
+            capacity,
+            ..Default::default()
+        }
+    }
+
+    fn allocate(&mut self) -> u32 {
+        if let Some(slot) = self.free.pop() {
+            return slot;
+        }
+        assert!(self.next < self.capacity, "All {} bindless {} slots are in use", self.capacity, self.kind);
+        self.next += 1;
+        self.next - 1
+    }
+
+    fn retire(&mut self, slot: u32, frame: u64) {
+        assert!(slot < self.next && !self.free.contains(&slot), "Freeing {} slot {slot} which is not allocated", self.kind);
+        self.retired.push((frame, slot));
+    }
+
+    fn reclaim(&mut self, completed_frame: u64) {
+        let free = &mut self.free;
+        self.retired.retain(|&(frame, slot)| {
+            let reusable = frame <= completed_frame;
+            if reusable {
+                free.push(slot);
+            }
+            !reusable
+        });
+    }
+
+    fn in_use(&self) -> u32 {
+        self.next - self.free.len() as u32 - self.retired.len() as u32
+    }
+}
+
+/// One partially bound, update after bind descriptor set with texture, sampler and storage buffer arrays shared by every scene.
+/// Slots can be written while the set is bound, freed ones are reused once no frame in flight can still read them
+#[derive(Default)]
+pub struct BindlessRegistry {
+    descriptor_set: VkDescriptorSet,
+    bindings: Vec<VkDescriptorSetLayoutBinding>,
+
+    textures: SlotAllocator,
+    samplers: SlotAllocator,
+    buffers: SlotAllocator,
+
+    frame: u64,
+    frames_in_flight: u64,
+
+    descriptor_pool: VkDestroy<VkDescriptorPool>,
+    descriptor_layout: VkDestroy<VkDescriptorSetLayout>,
+}
+
+impl BindlessRegistry {
+    pub fn new(vulkan: &Vulkan, frames_in_flight: usize) -> Self {
+        let stages = VkShaderStageFlags::VERTEX_BIT | VkShaderStageFlags::FRAGMENT_BIT | VkShaderStageFlags::COMPUTE_BIT;
+        let binding = |binding: u32, descriptor_type: VkDescriptorType, count: u32| VkDescriptorSetLayoutBinding {
+            binding,
+            descriptorType: descriptor_type,
+            descriptorCount: count,
+            stageFlags: stages,
+            pImmutableSamplers: null_mut(),
+        };
+        let bindings = vec![
+            binding(BINDLESS_TEXTURE_BINDING, VkDescriptorType::SAMPLED_IMAGE, MAX_BINDLESS_TEXTURES),
+            binding(BINDLESS_SAMPLER_BINDING, VkDescriptorType::SAMPLER, MAX_BINDLESS_SAMPLERS),
+            binding(BINDLESS_BUFFER_BINDING, VkDescriptorType::STORAGE_BUFFER, MAX_BINDLESS_BUFFERS),
+        ];
+        let flags = VkDescriptorBindingFlags::PARTIALLY_BOUND_BIT
+            | VkDescriptorBindingFlags::UPDATE_AFTER_BIND_BIT
+            | VkDescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING_BIT;
+        let descriptor_layout = vulkan.create_descriptor_set_layout_with_flags(&bindings, &[flags; 3],
+                                                                               VkDescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL_BIT);
+        let descriptor_pool = vulkan.create_update_after_bind_descriptor_pool(&build_pool_size(&bindings), 1);
+        let descriptor_set = vulkan.allocate_descriptor_sets(descriptor_pool, &[descriptor_layout])[0];
+
+        BindlessRegistry {
+            descriptor_set,
+            bindings,
+            textures: SlotAllocator::new("texture", MAX_BINDLESS_TEXTURES),
+            samplers: SlotAllocator::new("sampler", MAX_BINDLESS_SAMPLERS),
+            buffers: SlotAllocator::new("buffer", MAX_BINDLESS_BUFFERS),
+            frame: 0,
+            frames_in_flight: frames_in_flight as u64,
+            descriptor_pool: VkDestroy::new(descriptor_pool, vulkan),
+            descriptor_layout: VkDestroy::new(descriptor_layout, vulkan),
+        }
+    }
+
+    pub fn descriptor_set(&self) -> VkDescriptorSet {
+        self.descriptor_set
+    }
+
+    pub fn descriptor_layout(&self) -> VkDescriptorSetLayout {
+        *self.descriptor_layout
+    }
+
+    /// Layout bindings, for checking pipelines against their shaders
+    pub fn bindings(&self) -> &[VkDescriptorSetLayoutBinding] {
+        &self.bindings
+    }
+
+    /// Call once per frame after waiting for the fence of the frame about to be recorded
+    pub fn begin_frame(&mut self) {
+        self.frame += 1;
+        if self.frame > self.frames_in_flight {
+            let completed = self.frame - self.frames_in_flight;
+            self.textures.reclaim(completed);
+            self.samplers.reclaim(completed);
+            self.buffers.reclaim(completed);
+        }
+    }
+
+    /// `view` has to stay in `SHADER_READ_ONLY_OPTIMAL` while the slot is registered
+    pub fn register_texture(&mut self, vulkan: &Vulkan, view: VkImageView) -> TextureSlot {
+        let slot = self.textures.allocate();
+        self.write_image(vulkan, BINDLESS_TEXTURE_BINDING, slot, VkDescriptorType::SAMPLED_IMAGE, VkDescriptorImageInfo {
+            sampler: VkSampler::none(),
+            imageView: view,
+            imageLayout: VkImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        });
+        TextureSlot(slot)
+    }
+
+    pub fn register_sampler(&mut self, vulkan: &Vulkan, sampler: VkSampler) -> SamplerSlot {
+        let slot = self.samplers.allocate();
+        self.write_image(vulkan, BINDLESS_SAMPLER_BINDING, slot, VkDescriptorType::SAMPLER, VkDescriptorImageInfo {
+            sampler,
+            imageView: VkImageView::none(),
+            imageLayout: VkImageLayout::UNDEFINED,
+        });
+        SamplerSlot(slot)
+    }
+
+    pub fn register_buffer(&mut self, vulkan: &Vulkan, buffer: VkBuffer, offset: VkDeviceSize, range: VkDeviceSize) -> BufferSlot {
+        let slot = self.buffers.allocate();
+        vulkan.update_descriptor_sets(vec![], vec![BufferDescriptorInfo {
+            target_descriptor: DescriptorSetInfo {
+                descriptor_set: self.descriptor_set,
+                descriptor_binding: BINDLESS_BUFFER_BINDING,
+                array_element: slot,
+            },
+            target_descriptor_type: VkDescriptorType::STORAGE_BUFFER,
+            buffer_infos: vec![VkDescriptorBufferInfo {
+                buffer,
+                offset,
+                range,
+            }],
+        }], vec![], vec![]);
+        BufferSlot(slot)
+    }
+
+    /// The image may be destroyed once no frame in flight samples it, the slot is handed out again after that
+    pub fn free_texture(&mut self, slot: TextureSlot) {
+        self.textures.retire(slot.0, self.frame);
+    }
+
+    pub fn free_sampler(&mut self, slot: SamplerSlot) {
+        self.samplers.retire(slot.0, self.frame);
+    }
+
+    pub fn free_buffer(&mut self, slot: BufferSlot) {
+        self.buffers.retire(slot.0, self.frame);
+    }
+
+    /// Registered textures, samplers and buffers
+    pub fn in_use(&self) -> [u32; 3] {
+        [self.textures.in_use(), self.samplers.in_use(), self.buffers.in_use()]
+    }
+
+    /// Binds the registry at `BINDLESS_SET` of `layout`
+    pub fn bind(&self, vulkan: &Vulkan, command_buffer: VkCommandBuffer, bind_point: VkPipelineBindPoint, layout: VkPipelineLayout) {
+        vulkan.bind_descriptor_sets(command_buffer, bind_point, layout, BINDLESS_SET, &[self.descriptor_set], &[]);
+    }
+
+    fn write_image(&self, vulkan: &Vulkan, binding: u32, slot: u32, descriptor_type: VkDescriptorType, info: VkDescriptorImageInfo) {
+        vulkan.update_descriptor_sets(vec![ImageDescriptorInfo {
+            target_descriptor: DescriptorSetInfo {
+                descriptor_set: self.descriptor_set,
+                descriptor_binding: binding,
+                array_element: slot,
+            },
+            target_descriptor_type: descriptor_type,
+            image_infos: vec![info],
+        }], vec![], vec![], vec![]);
+    }
+}
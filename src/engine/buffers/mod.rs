@@ -1,2 +1,3 @@
 pub mod vbo;
-pub mod ubo;
\ No newline at end of file
+pub mod ubo;
+pub mod stream;
@@ -0,0 +1,33 @@
+use crate::prelude::pool_alloc::Buffer;
+use crate::prelude::*;
+use crate::vulkan::func::Vulkan;
+use crate::vulkan::utils::BufferUsage;
+use std::ffi::c_void;
+
+/// Host visible buffer rewritten every frame, grows to the largest frame seen
+#[derive(Default)]
+pub struct StreamBuffer {
+    pub buffer: Buffer,
+    pub pointer: *mut c_void,
+    capacity: u64,
+}
+
+impl StreamBuffer {
+    /// Reallocates when `size` does not fit, previous contents are lost. The buffer must not be in use by the GPU
+    pub fn reserve(&mut self, vulkan: &Vulkan, size: u64, usage: BufferUsage, initial_capacity: u64) {
+        if size <= self.capacity && self.capacity != 0 {
+            return;
+        }
+        let capacity = size.max(initial_capacity).next_power_of_two();
+        let alloc_info = VmaAllocationCreateInfo {
+            usage: VmaMemoryUsage::AUTO_PREFER_DEVICE,
+            flags: VmaAllocationCreateFlagBits::HOST_ACCESS_SEQUENTIAL_WRITE_BIT,
+            requiredFlags: VkMemoryPropertyFlagBits::HOST_VISIBLE_BIT
+                | VkMemoryPropertyFlagBits::HOST_COHERENT_BIT,
+            ..Default::default()
+        };
+        self.buffer = vulkan.pool().allocate_buffer(capacity, usage, alloc_info);
+        self.pointer = self.buffer.map_memory(vulkan);
+        self.capacity = capacity;
+    }
+}
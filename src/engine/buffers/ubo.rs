@@ -7,8 +7,10 @@ use ultraviolet::Mat4;
 use vulkan_raw::{VkBufferCopy, VkCommandBuffer, VkDeviceSize};
 
 pub const MATRICES_SIZE: usize = size_of::<Matrices>();
+/// Slices of the buffer, one per camera. Shaders see one slice through a dynamic offset
+pub const MAX_CAMERAS: usize = 8;
 #[repr(C)]
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone, Copy)]
 pub struct Matrices {
     view: Mat4,
     proj: Mat4,
@@ -16,7 +18,9 @@ pub struct Matrices {
 
 #[derive(Default)]
 pub struct UniformBuffer {
-    matrices: Matrices,
+    matrices: [Matrices; MAX_CAMERAS],
+    /// Slice size rounded up to `minUniformBufferOffsetAlignment`
+    stride: usize,
     host_pointer: *mut c_void,
     host_buffer: Buffer,
     device_buffer: Option<Buffer>,
@@ -34,7 +38,11 @@ impl UniformBuffer {
             ..Default::default()
         };
 
-        let mut host_buffer = vulkan.pool().allocate_buffer(MATRICES_SIZE as u64, BufferUsage::preset_staging().uniform_buffer(true), alloc_info);
+        let alignment = vulkan.get_loaded_device().device_info.properties.limits.minUniformBufferOffsetAlignment as usize;
+        let stride = MATRICES_SIZE.next_multiple_of(alignment.max(1));
+        let size = (stride * MAX_CAMERAS) as u64;
+
+        let mut host_buffer = vulkan.pool().allocate_buffer(size, BufferUsage::preset_staging().uniform_buffer(true), alloc_info);
         let flags = vulkan.get_loaded_device().memory_properties.memoryTypes[host_buffer.info.alloc_info.memoryType as usize].propertyFlags;
         let device_buffer = if flags.contains(VkMemoryPropertyFlagBits::DEVICE_LOCAL_BIT) {
             None
@@ -43,13 +51,11 @@ impl UniformBuffer {
                 usage: VmaMemoryUsage::AUTO_PREFER_DEVICE,
                 ..Default::default()
             };
-            Some(vulkan.pool().allocate_buffer(MATRICES_SIZE as u64, BufferUsage::preset_uniform_storage(), alloc_info))
+            Some(vulkan.pool().allocate_buffer(size, BufferUsage::preset_uniform_storage(), alloc_info))
         };
         Self {
-            matrices: Matrices {
-                 view,
-                 proj,
-            },
+            matrices: [Matrices { view, proj }; MAX_CAMERAS],
+            stride,
             host_pointer: host_buffer.map_memory(vulkan),
             host_buffer,
             device_buffer,
@@ -57,50 +63,51 @@ impl UniformBuffer {
         }
     }
 
+    /// Matrices of the first slice
     pub fn view(&self) -> Mat4 {
-        self.matrices.view
+        self.matrices[0].view
     }
 
     pub fn proj(&self) -> Mat4 {
-        self.matrices.proj
+        self.matrices[0].proj
     }
 
     pub fn set_proj(&mut self, proj: Mat4) {
-        self.matrices.proj = proj;
+        self.matrices[0].proj = proj;
         self.dirty = true;
     }
 
     pub fn set_view(&mut self, view: Mat4) {
-        self.matrices.view = view;
+        self.matrices[0].view = view;
+        self.dirty = true;
+    }
+
+    pub fn set_slice(&mut self, slice: usize, view: Mat4, proj: Mat4) {
+        self.matrices[slice] = Matrices { view, proj };
         self.dirty = true;
     }
 
+    /// Dynamic offset selecting `slice` when binding set 0
+    pub fn slice_offset(&self, slice: usize) -> u32 {
+        assert!(slice < MAX_CAMERAS, "UBO has {MAX_CAMERAS} slices, asked for slice {slice}");
+        (slice * self.stride) as u32
+    }
+
     pub fn sync_with_buffer(&mut self, command_buffer: VkCommandBuffer, vulkan: &Vulkan) {
         if self.dirty {
-            Vulkan::copy_info(self.host_pointer, &self.matrices as *const _ as *const u8, MATRICES_SIZE);
+            for (slice, matrices) in self.matrices.iter().enumerate() {
+                unsafe { Vulkan::copy_info(self.host_pointer.add(slice * self.stride), matrices as *const _ as *const u8, MATRICES_SIZE); }
+            }
 
             if let Some(device_buffer) = self.device_buffer.as_ref() {
                 let regions = [VkBufferCopy {
                     srcOffset: 0,
                     dstOffset: 0,
-                    size: MATRICES_SIZE as VkDeviceSize,
+                    size: (self.stride * MAX_CAMERAS) as VkDeviceSize,
                 }];
                 vulkan.buffer_to_buffer(&regions, command_buffer, *self.host_buffer, **device_buffer);
 
-                // vulkan.transition_buffers(
-                //     vec![BufferTransition {
-                //         buffer: **device_buffer,
-                //         offset: 0,
-                //         size: VK_WHOLE_SIZE,
-                //         src_stage: VkPipelineStageFlags2::TRANSFER_BIT,
-                //         dst_stage: VkPipelineStageFlags2::VERTEX_SHADER_BIT,
-                //         src_access: VkAccessFlags2::TRANSFER_WRITE_BIT,
-                //         dst_access: VkAccessFlags2::UNIFORM_READ_BIT,
-                //         src_queue_family: VK_QUEUE_FAMILY_IGNORED,
-                //         dst_queue_family: VK_QUEUE_FAMILY_IGNORED,
-                //     }],
-                //     command_buffer,
-                // );
+                // uniform read barrier is recorded by the render graph
             }
 
             self.dirty = false;
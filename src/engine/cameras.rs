@@ -0,0 +1,300 @@
+use crate::engine::buffers::ubo::MAX_CAMERAS;
+use crate::engine::camera::Camera;
+use crate::prelude::*;
+use ultraviolet::Vec3;
+use winit::keyboard::KeyCode;
+
+/// Part of the output a camera draws into, in fractions of the output size with the origin top left
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ViewportRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl ViewportRect {
+    pub const FULL: ViewportRect = ViewportRect { x: 0.0, y: 0.0, width: 1.0, height: 1.0 };
+
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        ViewportRect { x, y, width, height }
+    }
+
+    /// Pixel rectangle inside `extent`, never empty
+    pub fn to_pixels(self, extent: VkExtent2D) -> VkRect2D {
+        let x = (self.x * extent.width as f32).round() as u32;
+        let y = (self.y * extent.height as f32).round() as u32;
+        let right = ((self.x + self.width) * extent.width as f32).round() as u32;
+        let bottom = ((self.y + self.height) * extent.height as f32).round() as u32;
+        VkRect2D {
+            offset: VkOffset2D { x: x as i32, y: y as i32 },
+            extent: VkExtent2D {
+                width: right.min(extent.width).saturating_sub(x).max(1),
+                height: bottom.min(extent.height).saturating_sub(y).max(1),
+            },
+        }
+    }
+}
+
+/// Where a camera's image ends up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CameraTarget {
+    /// Rectangle of the swapchain output, drawn in the main scene pass
+    Viewport(ViewportRect),
+    /// Own HDR image of `extent`, left in `SHADER_READ_ONLY_OPTIMAL` for later passes
+    Offscreen(VkExtent2D),
+}
+
+/// Which input events move a camera
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InputRouting {
+    /// Only while it is the focused camera, `Cameras::focus_next` cycles focus
+    #[default]
+    Focused,
+    /// Always, e.g. for local co-op players with their own key layout
+    Always,
+    /// Never, for scripted or render-to-texture cameras
+    Never,
+}
+
+/// Movement keys of a camera, so two players can share a keyboard
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyLayout {
+    /// WASD, space and left shift
+    #[default]
+    Wasd,
+    /// Arrow keys, right control and right shift
+    Arrows,
+}
+
+impl KeyLayout {
+    /// Camera space movement direction of `key`, zero for keys outside the layout
+    pub fn direction(self, key: KeyCode) -> Vec3 {
+        match (self, key) {
+            (KeyLayout::Wasd, KeyCode::KeyW) | (KeyLayout::Arrows, KeyCode::ArrowUp) => -Vec3::unit_z(),
+            (KeyLayout::Wasd, KeyCode::KeyS) | (KeyLayout::Arrows, KeyCode::ArrowDown) => Vec3::unit_z(),
+            (KeyLayout::Wasd, KeyCode::KeyD) | (KeyLayout::Arrows, KeyCode::ArrowRight) => Vec3::unit_x(),
+            (KeyLayout::Wasd, KeyCode::KeyA) | (KeyLayout::Arrows, KeyCode::ArrowLeft) => -Vec3::unit_x(),
+            (KeyLayout::Wasd, KeyCode::Space) | (KeyLayout::Arrows, KeyCode::ControlRight) => Vec3::unit_y(),
+            (KeyLayout::Wasd, KeyCode::ShiftLeft) | (KeyLayout::Arrows, KeyCode::ShiftRight) => -Vec3::unit_y(),
+            _ => Vec3::zero(),
+        }
+    }
+}
+
+/// How often an offscreen camera renders, its texture keeps the last image in between.
+/// Viewport cameras render every frame regardless
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum UpdateRate {
+    #[default]
+    EveryFrame,
+    /// Once every `n` frames, e.g. 2 for a security monitor at half the frame rate
+    EveryNthFrame(u32),
+    /// At most once per interval in seconds
+    Interval(f64),
+    /// Only after `CameraView::request_update`, for static mirrors and portraits
+    OnRequest,
+}
+
+/// One camera of the render loop with its target and input settings
+#[derive(Clone, Debug)]
+pub struct CameraView {
+    pub camera: Camera,
+    pub target: CameraTarget,
+    pub routing: InputRouting,
+    pub keys: KeyLayout,
+    /// Whether mouse motion rotates the camera when input reaches it
+    pub mouse: bool,
+    pub enabled: bool,
+    pub update_rate: UpdateRate,
+
+    frames_since_update: u32,
+    time_since_update: f64,
+    update_requested: bool,
+}
+
+impl CameraView {
+    pub fn new(camera: Camera, target: CameraTarget) -> Self {
+        CameraView {
+            camera,
+            target,
+            routing: InputRouting::default(),
+            keys: KeyLayout::default(),
+            mouse: true,
+            enabled: true,
+            update_rate: UpdateRate::default(),
+            frames_since_update: 0,
+            time_since_update: 0.0,
+            update_requested: true,
+        }
+    }
+
+    pub fn routing(mut self, routing: InputRouting) -> Self {
+        self.routing = routing;
+        self
+    }
+
+    pub fn keys(mut self, keys: KeyLayout) -> Self {
+        self.keys = keys;
+        self
+    }
+
+    pub fn mouse(mut self, mouse: bool) -> Self {
+        self.mouse = mouse;
+        self
+    }
+
+    pub fn update_rate(mut self, update_rate: UpdateRate) -> Self {
+        self.update_rate = update_rate;
+        self
+    }
+
+    /// Renders the camera on the next frame whatever its update rate
+    pub fn request_update(&mut self) {
+        self.update_requested = true;
+    }
+
+    /// Advances the update timer by one frame, returns whether the camera renders this frame
+    pub fn tick_update(&mut self, delta_time: f64) -> bool {
+        self.frames_since_update += 1;
+        self.time_since_update += delta_time;
+        let due = self.update_requested || match self.update_rate {
+            UpdateRate::EveryFrame => true,
+            UpdateRate::EveryNthFrame(frames) => self.frames_since_update >= frames.max(1),
+            UpdateRate::Interval(seconds) => self.time_since_update >= seconds,
+            UpdateRate::OnRequest => false,
+        };
+        if due {
+            self.frames_since_update = 0;
+            self.time_since_update = 0.0;
+            self.update_requested = false;
+        }
+        due
+    }
+
+    /// Pixel extent of the camera image for an output of `extent`
+    pub fn extent(&self, extent: VkExtent2D) -> VkExtent2D {
+        match self.target {
+            CameraTarget::Viewport(rect) => rect.to_pixels(extent).extent,
+            CameraTarget::Offscreen(extent) => extent,
+        }
+    }
+}
+
+/// Cameras of the render loop. Index `i` renders with UBO slice `i`, so there are at most `MAX_CAMERAS`
+pub struct Cameras {
+    views: Vec<CameraView>,
+    focused: usize,
+}
+
+impl Default for Cameras {
+    fn default() -> Self {
+        Cameras::new(Camera::default())
+    }
+}
+
+impl Cameras {
+    /// Single camera covering the whole output
+    pub fn new(camera: Camera) -> Self {
+        Cameras {
+            views: vec![CameraView::new(camera, CameraTarget::Viewport(ViewportRect::FULL))],
+            focused: 0,
+        }
+    }
+
+    /// Returns the index of the camera, which is also its UBO slice
+    pub fn add(&mut self, view: CameraView) -> usize {
+        assert!(self.views.len() < MAX_CAMERAS, "At most {MAX_CAMERAS} cameras are supported");
+        self.views.push(view);
+        self.views.len() - 1
+    }
+
+    /// Indices of later cameras shift down by one, the render graph has to be rebuilt afterwards
+    pub fn remove(&mut self, index: usize) -> CameraView {
+        assert!(self.views.len() > 1, "The last camera cannot be removed");
+        let view = self.views.remove(index);
+        if self.focused >= self.views.len() || self.focused > index {
+            self.focused = self.focused.saturating_sub(1);
+        }
+        view
+    }
+
+    /// Replaces every viewport camera with `count` copies of the focused one, split into columns for two players
+    /// and a grid for up to four. Offscreen cameras are kept
+    pub fn split_screen(&mut self, count: usize) {
+        let rects: &[ViewportRect] = match count {
+            1 => &[ViewportRect::FULL],
+            2 => &[ViewportRect::new(0.0, 0.0, 0.5, 1.0), ViewportRect::new(0.5, 0.0, 0.5, 1.0)],
+            3 => &[ViewportRect::new(0.0, 0.0, 0.5, 0.5), ViewportRect::new(0.5, 0.0, 0.5, 0.5), ViewportRect::new(0.0, 0.5, 1.0, 0.5)],
+            4 => &[ViewportRect::new(0.0, 0.0, 0.5, 0.5), ViewportRect::new(0.5, 0.0, 0.5, 0.5),
+                   ViewportRect::new(0.0, 0.5, 0.5, 0.5), ViewportRect::new(0.5, 0.5, 0.5, 0.5)],
+            _ => panic!("Split screen supports one to four viewports, {count} requested"),
+        };
+        let template = self.views[self.focused].clone();
+        self.views.retain(|view| matches!(view.target, CameraTarget::Offscreen(_)));
+        assert!(self.views.len() + rects.len() <= MAX_CAMERAS, "At most {MAX_CAMERAS} cameras are supported");
+        let viewports = rects.iter().map(|rect| CameraView {
+            target: CameraTarget::Viewport(*rect),
+            ..template.clone()
+        });
+        self.views.splice(0..0, viewports);
+        self.focused = 0;
+    }
+
+    pub fn get(&self, index: usize) -> &CameraView {
+        &self.views[index]
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> &mut CameraView {
+        &mut self.views[index]
+    }
+
+    pub fn len(&self) -> usize {
+        self.views.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.views.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &CameraView> {
+        self.views.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut CameraView> {
+        self.views.iter_mut()
+    }
+
+    pub fn focused(&self) -> usize {
+        self.focused
+    }
+
+    pub fn focused_mut(&mut self) -> &mut CameraView {
+        &mut self.views[self.focused]
+    }
+
+    pub fn focus(&mut self, index: usize) {
+        assert!(index < self.views.len(), "No camera {index}");
+        self.focused = index;
+    }
+
+    /// Moves focus to the next enabled camera routed by focus
+    pub fn focus_next(&mut self) {
+        let count = self.views.len();
+        if let Some(next) = (1..=count)
+            .map(|offset| (self.focused + offset) % count)
+            .find(|&index| self.views[index].enabled && self.views[index].routing == InputRouting::Focused) {
+            self.focused = next;
+        }
+    }
+
+    /// Cameras the current input reaches with their index
+    pub fn routed_mut(&mut self) -> impl Iterator<Item = (usize, &mut CameraView)> {
+        let focused = self.focused;
+        self.views.iter_mut().enumerate().filter(move |(index, view)| view.enabled && match view.routing {
+            InputRouting::Focused => *index == focused,
+            InputRouting::Always => true,
+            InputRouting::Never => false,
+        })
+    }
+}
@@ -0,0 +1,147 @@
+use crate::engine::render_graph::ResourceUsage;
+use crate::prelude::*;
+use crate::vulkan::func::Vulkan;
+use shaders::common::PushConstants;
+use std::ffi::CString;
+use std::ptr::null;
+use std::sync::OnceLock;
+
+const COMPUTE_SHADER: &[u8] = include_bytes!(env!("compute.spv"));
+
+/// Interface of the embedded `compute` crate, parsed on first use
+pub fn compute_reflection() -> &'static ShaderReflection {
+    static REFLECTION: OnceLock<ShaderReflection> = OnceLock::new();
+    REFLECTION.get_or_init(|| ShaderReflection::parse(COMPUTE_SHADER))
+}
+
+/// Number of workgroups of `workgroup_size` covering `threads` invocations
+pub fn group_count(threads: u32, workgroup_size: u32) -> u32 {
+    threads.div_ceil(workgroup_size)
+}
+
+/// Barrier between two uses of a whole buffer, stages and accesses follow `ResourceUsage`
+pub fn buffer_barrier(buffer: VkBuffer, src: ResourceUsage, dst: ResourceUsage) -> BufferTransition {
+    BufferTransition {
+        buffer,
+        offset: 0,
+        size: VK_WHOLE_SIZE,
+        src_stage: src.stage(),
+        dst_stage: dst.stage(),
+        src_access: src.access(),
+        dst_access: dst.access(),
+        src_queue_family: VK_QUEUE_FAMILY_IGNORED,
+        dst_queue_family: VK_QUEUE_FAMILY_IGNORED,
+    }
+}
+
+/// Barrier between two uses of every subresource of an image, layouts follow `ResourceUsage`
+pub fn image_barrier(image: VkImage, aspect: VkImageAspectFlags, src: ResourceUsage, dst: ResourceUsage) -> ImageTransition2 {
+    ImageTransition2 {
+        image,
+        src_stage: src.stage(),
+        dst_stage: dst.stage(),
+        src_access: src.access(),
+        dst_access: dst.access(),
+        old_layout: src.layout(),
+        new_layout: dst.layout(),
+        aspect,
+        ..Default::default()
+    }
+}
+
+/// One kernel of the `compute` shader crate with its layout.
+/// Usable inside render graph passes, the graph then inserts barriers against other passes
+#[derive(Default)]
+pub struct ComputePass {
+    pipeline: VkDestroy<VkPipeline>,
+    layout: VkDestroy<VkPipelineLayout>,
+    entry: &'static str,
+    push_constant_size: u32,
+    workgroup_size: [u32; 3],
+}
+
+impl ComputePass {
+    /// `workgroup_size` has to match `threads` of `entry`, `T` is the push constant block of the kernel
+    pub fn new<T: PushConstants>(vulkan: &Vulkan, entry: &'static str, set_layouts: &[VkDescriptorSetLayout], workgroup_size: [u32; 3]) -> Self {
+        let push_constant_size = push_constant_range::<T>().size;
+        let interface = ShaderInterface::new(&[(compute_reflection(), entry)]);
+        let used_push_constants = interface.push_constants.map_or(0, |(_, size)| size);
+        if used_push_constants > push_constant_size {
+            eprintln!("{entry}: kernel reads {used_push_constants} bytes of push constants, the layout has {push_constant_size}");
+        }
+        if interface.set_count() > set_layouts.len() as u32 {
+            eprintln!("{entry}: kernel uses {} descriptor sets, {} given", interface.set_count(), set_layouts.len());
+        }
+
+        let layout = vulkan.create_pipeline_layout_with::<T>(set_layouts);
+        let pipeline = create_compute_pipeline(vulkan, COMPUTE_SHADER, entry, layout);
+
+        ComputePass {
+            pipeline: VkDestroy::new(pipeline, vulkan),
+            layout: VkDestroy::new(layout, vulkan),
+            entry,
+            push_constant_size,
+            workgroup_size,
+        }
+    }
+
+    /// Recreates the pipeline from a rebuilt `compute` crate, the layout is kept
+    pub fn reload(&mut self, vulkan: &Vulkan, spirv: &[u8]) {
+        let pipeline = create_compute_pipeline(vulkan, spirv, self.entry, *self.layout);
+        self.pipeline = VkDestroy::new(pipeline, vulkan);
+    }
+
+    pub fn layout(&self) -> VkPipelineLayout {
+        *self.layout
+    }
+
+    pub fn workgroup_size(&self) -> [u32; 3] {
+        self.workgroup_size
+    }
+
+    /// Binds the pipeline and `descriptor_sets` starting at set 0
+    pub fn bind(&self, vulkan: &Vulkan, command_buffer: VkCommandBuffer, descriptor_sets: &[VkDescriptorSet]) {
+        vulkan.bind_pipeline(command_buffer, VkPipelineBindPoint::COMPUTE, *self.pipeline);
+        if !descriptor_sets.is_empty() {
+            vulkan.bind_descriptor_sets(command_buffer, VkPipelineBindPoint::COMPUTE, *self.layout, 0, descriptor_sets, &[]);
+        }
+    }
+
+    pub fn push_constants<T: PushConstants>(&self, vulkan: &Vulkan, command_buffer: VkCommandBuffer, params: &T) {
+        assert_eq!(size_of::<T>() as u32, self.push_constant_size, "Push constant size does not match the compute layout");
+        vulkan.push_constants(command_buffer, *self.layout, params);
+    }
+
+    pub fn dispatch(&self, vulkan: &Vulkan, command_buffer: VkCommandBuffer, groups: [u32; 3]) {
+        vulkan.dispatch(command_buffer, groups[0], groups[1], groups[2]);
+    }
+
+    /// Dispatches enough workgroups to cover `threads` invocations, kernels have to bounds check
+    pub fn dispatch_threads(&self, vulkan: &Vulkan, command_buffer: VkCommandBuffer, threads: [u32; 3]) {
+        let groups = [0, 1, 2].map(|axis| group_count(threads[axis], self.workgroup_size[axis]));
+        self.dispatch(vulkan, command_buffer, groups);
+    }
+
+    /// Group counts are read from `buffer` as `VkDispatchIndirectCommand`
+    pub fn dispatch_indirect(&self, vulkan: &Vulkan, command_buffer: VkCommandBuffer, buffer: VkBuffer, offset: VkDeviceSize) {
+        vulkan.dispatch_indirect(command_buffer, buffer, offset);
+    }
+
+    /// Records `buffers` and `images` barriers, for dependencies between dispatches of the same pass
+    pub fn barrier(&self, vulkan: &Vulkan, command_buffer: VkCommandBuffer, buffers: Vec<BufferTransition>, images: Vec<ImageTransition2>) {
+        vulkan.transition_resources(buffers, images, command_buffer);
+    }
+}
+
+fn create_compute_pipeline(vulkan: &Vulkan, spirv: &[u8], entry: &str, layout: VkPipelineLayout) -> VkPipeline {
+    let module = VkDestroy::new(vulkan.create_shader_module(spirv), vulkan);
+    let name = CString::new(entry).unwrap();
+    let stage = VkPipelineShaderStageCreateInfo {
+        stage: VkShaderStageFlags::COMPUTE_BIT,
+        module: *module,
+        pName: name.as_ptr(),
+        pSpecializationInfo: null(),
+        ..Default::default()
+    };
+    vulkan.create_compute_pipeline(None, VkPipelineCreateFlags::empty(), stage, layout, VkPipeline::none())
+}
@@ -0,0 +1,311 @@
+use crate::engine::buffers::stream::StreamBuffer;
+use crate::engine::camera::Camera;
+use crate::engine::pipelines::create_pipelines_multithreaded;
+use crate::engine::shapes::ray::Ray;
+use crate::engine::shapes::AABB::AABB4;
+use crate::prelude::*;
+use crate::vulkan::func::Vulkan;
+use crate::vulkan::utils::BufferUsage;
+use shaders::common::DebugParams;
+use std::f32::consts::TAU;
+use ultraviolet::{Mat4, Rotor3, Vec3};
+
+const SPHERE_SEGMENTS: usize = 32;
+const INITIAL_CAPACITY: u64 = 1 << 16;
+
+/// How a debug shape is drawn, builder style like the usage flags
+#[derive(Clone, Copy, Debug)]
+pub struct DebugStyle {
+    color: [f32; 4],
+    depth_test: bool,
+    duration: f32,
+}
+
+impl Default for DebugStyle {
+    fn default() -> Self {
+        DebugStyle {
+            color: [1.0, 1.0, 1.0, 1.0],
+            depth_test: true,
+            duration: 0.0,
+        }
+    }
+}
+
+impl DebugStyle {
+    /// Linear RGBA, written into the HDR target before tonemapping
+    pub fn color(mut self, color: [f32; 4]) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn depth_test(mut self, depth_test: bool) -> Self {
+        self.depth_test = depth_test;
+        self
+    }
+
+    /// Seconds the shape stays visible, zero draws it for the current frame only
+    pub fn duration(mut self, seconds: f32) -> Self {
+        self.duration = seconds;
+        self
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct DebugVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+struct TimedLines {
+    vertices: Vec<DebugVertex>,
+    depth_test: bool,
+    remaining: f32,
+}
+
+/// Immediate mode line drawing for shapes and rays, recorded at the end of the scene pass
+#[derive(Default)]
+pub struct DebugDraw {
+    pub enabled: bool,
+
+    depth_tested: Vec<DebugVertex>,
+    overlay: Vec<DebugVertex>,
+    timed: Vec<TimedLines>,
+    /// Depth tested and total vertex count in the current frame's buffer
+    uploaded: (usize, usize),
+
+    depth_pipeline: VkDestroy<VkPipeline>,
+    overlay_pipeline: VkDestroy<VkPipeline>,
+    layout: VkDestroy<VkPipelineLayout>,
+    frames: Vec<StreamBuffer>,
+}
+
+impl DebugDraw {
+    /// `template` is the scene pipeline, its render targets and sample count are reused
+    pub fn new(vulkan: &Vulkan, template: GraphicsPipelineCreateInfo, frames_in_flight: usize) -> Self {
+        let push_constant_ranges = [push_constant_range::<DebugParams>()];
+        graphics_interface("debug_lines", "debug_lines").check_layout("debug lines", &[], &push_constant_ranges);
+        let layout = vulkan.create_pipeline_layout_with::<DebugParams>(&[]);
+        let pipelines = create_pipelines_multithreaded(true, vec![
+            preset_debug_lines(template.clone(), layout, true),
+            preset_debug_lines(template, layout, false),
+        ], vulkan);
+
+        DebugDraw {
+            enabled: true,
+            depth_tested: vec![],
+            overlay: vec![],
+            timed: vec![],
+            uploaded: (0, 0),
+            depth_pipeline: VkDestroy::new(pipelines[0], vulkan),
+            overlay_pipeline: VkDestroy::new(pipelines[1], vulkan),
+            layout: VkDestroy::new(layout, vulkan),
+            frames: (0..frames_in_flight).map(|_| StreamBuffer::default()).collect(),
+        }
+    }
+
+    /// Recreates both pipelines from a `template` with new shader modules
+    pub fn rebuild_pipelines(&mut self, vulkan: &Vulkan, template: GraphicsPipelineCreateInfo) {
+        let pipelines = create_pipelines_multithreaded(true, vec![
+            preset_debug_lines(template.clone(), *self.layout, true),
+            preset_debug_lines(template, *self.layout, false),
+        ], vulkan);
+        self.depth_pipeline = VkDestroy::new(pipelines[0], vulkan);
+        self.overlay_pipeline = VkDestroy::new(pipelines[1], vulkan);
+    }
+
+    pub fn line(&mut self, from: Vec3, to: Vec3, style: DebugStyle) {
+        self.push(style, &[(from, to)]);
+    }
+
+    pub fn aabb(&mut self, min: Vec3, max: Vec3, style: DebugStyle) {
+        let corner = |i: usize| Vec3::new(
+            if i & 1 == 0 { min.x } else { max.x },
+            if i & 2 == 0 { min.y } else { max.y },
+            if i & 4 == 0 { min.z } else { max.z },
+        );
+        // every pair of corners differing in exactly one axis bit is an edge
+        let mut edges = Vec::with_capacity(12);
+        for i in 0..8 {
+            for axis in [1, 2, 4] {
+                if i & axis == 0 {
+                    edges.push((corner(i), corner(i | axis)));
+                }
+            }
+        }
+        self.push(style, &edges);
+    }
+
+    /// All four boxes packed in `aabb`
+    pub fn aabb4(&mut self, aabb: &AABB4, style: DebugStyle) {
+        let points: [Vec3; 8] = aabb.0.into();
+        for pair in points.chunks(2) {
+            self.aabb(pair[0], pair[1], style);
+        }
+    }
+
+    /// Three great circles around `center`
+    pub fn sphere(&mut self, center: Vec3, radius: f32, style: DebugStyle) {
+        let mut edges = Vec::with_capacity(SPHERE_SEGMENTS * 3);
+        for (u, v) in [(Vec3::unit_x(), Vec3::unit_y()), (Vec3::unit_y(), Vec3::unit_z()), (Vec3::unit_z(), Vec3::unit_x())] {
+            let point = |i: usize| {
+                let angle = i as f32 / SPHERE_SEGMENTS as f32 * TAU;
+                center + (u * angle.cos() + v * angle.sin()) * radius
+            };
+            edges.extend((0..SPHERE_SEGMENTS).map(|i| (point(i), point(i + 1))));
+        }
+        self.push(style, &edges);
+    }
+
+    /// Segment from the ray origin with an arrow head at `length`
+    pub fn ray(&mut self, ray: &Ray, length: f32, style: DebugStyle) {
+        let direction = ray.direction.normalized();
+        let tip = ray.origin + direction * length;
+        let (right, up) = perpendicular(direction);
+        let head = length * 0.1;
+        let back = tip - direction * head;
+
+        self.push(style, &[
+            (ray.origin, tip),
+            (tip, back + right * head * 0.5),
+            (tip, back - right * head * 0.5),
+            (tip, back + up * head * 0.5),
+            (tip, back - up * head * 0.5),
+        ]);
+    }
+
+    /// X, Y and Z in red, green and blue, the style color is ignored
+    pub fn axes(&mut self, origin: Vec3, rotation: Rotor3, size: f32, style: DebugStyle) {
+        let axes = [
+            (Vec3::unit_x(), [1.0, 0.0, 0.0, 1.0]),
+            (Vec3::unit_y(), [0.0, 1.0, 0.0, 1.0]),
+            (Vec3::unit_z(), [0.0, 0.0, 1.0, 1.0]),
+        ];
+        for (axis, color) in axes {
+            self.push(style.color(color), &[(origin, origin + rotation * axis * size)]);
+        }
+    }
+
+    /// View volume of `camera` between its near and far planes
+    pub fn frustum(&mut self, camera: &Camera, style: DebugStyle) {
+        let mut camera = *camera;
+        let forward = camera.forward_direction();
+        let right = camera.right_direction();
+        let up = camera.up_direction();
+        let tan_half_fov = (camera.fov * 0.5).to_radians().tan();
+
+        let plane = |distance: f32| {
+            let center = camera.position + forward * distance;
+            let half_height = up * (distance * tan_half_fov);
+            let half_width = right * (distance * tan_half_fov * camera.aspect_ratio);
+            [
+                center - half_width - half_height,
+                center + half_width - half_height,
+                center + half_width + half_height,
+                center - half_width + half_height,
+            ]
+        };
+        let near = plane(camera.near_plane);
+        let far = plane(camera.far_plane);
+
+        let mut edges = Vec::with_capacity(12);
+        for i in 0..4 {
+            let next = (i + 1) % 4;
+            edges.push((near[i], near[next]));
+            edges.push((far[i], far[next]));
+            edges.push((near[i], far[i]));
+        }
+        self.push(style, &edges);
+    }
+
+    /// Ages timed shapes, the ones past their duration are dropped
+    pub fn tick(&mut self, delta_time: f32) {
+        self.timed.iter_mut().for_each(|lines| lines.remaining -= delta_time);
+        self.timed.retain(|lines| lines.remaining > 0.0);
+    }
+
+    /// Moves this frame's shapes into the frame's vertex buffer and clears them, once per frame before any `record`
+    pub fn upload(&mut self, vulkan: &Vulkan, frame_index: usize) {
+        let depth_tested = std::mem::take(&mut self.depth_tested);
+        let overlay = std::mem::take(&mut self.overlay);
+        self.uploaded = (0, 0);
+        if !self.enabled {
+            return;
+        }
+
+        let timed = |depth_test: bool| self.timed.iter()
+            .filter(move |lines| lines.depth_test == depth_test)
+            .flat_map(|lines| lines.vertices.iter().copied());
+        let vertices = depth_tested.into_iter().chain(timed(true))
+            .collect::<Vec<_>>();
+        let depth_count = vertices.len();
+        let vertices = vertices.into_iter().chain(overlay).chain(timed(false))
+            .collect::<Vec<_>>();
+        if vertices.is_empty() {
+            return;
+        }
+
+        let frame = &mut self.frames[frame_index];
+        frame.reserve(vulkan, (vertices.len() * size_of::<DebugVertex>()) as u64, BufferUsage::preset_vertex(), INITIAL_CAPACITY);
+        Vulkan::copy_info(frame.pointer, vertices.as_ptr(), vertices.len());
+        self.uploaded = (depth_count, vertices.len());
+    }
+
+    /// Draws the uploaded shapes from `camera`, expects viewport and scissor of the scene pass to be set
+    pub fn record(&self, vulkan: &Vulkan, frame_index: usize, command_buffer: VkCommandBuffer, camera: &mut Camera) {
+        let (depth_count, vertex_count) = self.uploaded;
+        if vertex_count == 0 {
+            return;
+        }
+        let frame = &self.frames[frame_index];
+
+        let view_proj: Mat4 = camera.projection_matrix() * camera.view_matrix();
+        let params = DebugParams {
+            view_proj: view_proj.cols.map(|col| [col.x, col.y, col.z, col.w]),
+        };
+        vulkan.bind_vertex_buffers(command_buffer, 0, vec![VertexBufferParameters {
+            buffer: *frame.buffer,
+            offset: 0,
+        }]);
+
+        let batches = [
+            (*self.depth_pipeline, 0, depth_count),
+            (*self.overlay_pipeline, depth_count, vertex_count - depth_count),
+        ];
+        for (pipeline, first, count) in batches {
+            if count == 0 {
+                continue;
+            }
+            vulkan.bind_pipeline(command_buffer, VkPipelineBindPoint::GRAPHICS, pipeline);
+            vulkan.push_constants(command_buffer, *self.layout, &params);
+            vulkan.draw(command_buffer, count as u32, 1, first as u32, 0);
+        }
+    }
+
+    fn push(&mut self, style: DebugStyle, edges: &[(Vec3, Vec3)]) {
+        let vertices = edges.iter()
+            .flat_map(|(from, to)| [*from, *to])
+            .map(|position| DebugVertex {
+                position: [position.x, position.y, position.z],
+                color: style.color,
+            });
+
+        if style.duration > 0.0 {
+            self.timed.push(TimedLines {
+                vertices: vertices.collect(),
+                depth_test: style.depth_test,
+                remaining: style.duration,
+            });
+        } else if style.depth_test {
+            self.depth_tested.extend(vertices);
+        } else {
+            self.overlay.extend(vertices);
+        }
+    }
+}
+
+fn perpendicular(direction: Vec3) -> (Vec3, Vec3) {
+    let up = if direction.y.abs() < 0.999 { Vec3::unit_y() } else { Vec3::unit_x() };
+    let right = direction.cross(up).normalized();
+    (right, right.cross(direction))
+}
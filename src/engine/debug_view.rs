@@ -0,0 +1,149 @@
+use crate::engine::pipeline_registry::{PipelineKey, PipelineRegistry, SharedPipeline};
+use crate::prelude::*;
+use crate::vulkan::func::Vulkan;
+use shaders::common::{DEBUG_VIEW_DEPTH, DEBUG_VIEW_MATERIAL_ID, DEBUG_VIEW_NORMALS, DEBUG_VIEW_OVERDRAW, DEBUG_VIEW_UV_CHECKER, DEBUG_VIEW_WIREFRAME};
+use std::collections::HashMap;
+
+/// What the scene pass shows instead of shaded materials
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DebugView {
+    #[default]
+    Off,
+    /// Triangle edges, needs `fillModeNonSolid`
+    Wireframe,
+    /// World space normals mapped to 0..1
+    Normals,
+    UvChecker,
+    /// Logarithmic view distance, bright is near
+    Depth,
+    /// Random color per material
+    MaterialId,
+    /// Additive heatmap of covering layers, ignores depth
+    Overdraw,
+}
+
+impl DebugView {
+    pub const ALL: [DebugView; 7] = [DebugView::Off, DebugView::Wireframe, DebugView::Normals, DebugView::UvChecker,
+        DebugView::Depth, DebugView::MaterialId, DebugView::Overdraw];
+
+    pub fn next(self) -> DebugView {
+        let index = DebugView::ALL.iter().position(|view| *view == self).unwrap();
+        DebugView::ALL[(index + 1) % DebugView::ALL.len()]
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            DebugView::Off => "off",
+            DebugView::Wireframe => "wireframe",
+            DebugView::Normals => "normals",
+            DebugView::UvChecker => "UV checker",
+            DebugView::Depth => "depth",
+            DebugView::MaterialId => "material id",
+            DebugView::Overdraw => "overdraw",
+        }
+    }
+
+    fn shader_mode(self) -> u32 {
+        match self {
+            DebugView::Off => 0,
+            DebugView::Wireframe => DEBUG_VIEW_WIREFRAME,
+            DebugView::Normals => DEBUG_VIEW_NORMALS,
+            DebugView::UvChecker => DEBUG_VIEW_UV_CHECKER,
+            DebugView::Depth => DEBUG_VIEW_DEPTH,
+            DebugView::MaterialId => DEBUG_VIEW_MATERIAL_ID,
+            DebugView::Overdraw => DEBUG_VIEW_OVERDRAW,
+        }
+    }
+}
+
+/// One pipeline per debug view, all derived from the scene template. They are compiled in the background on first use,
+/// the scene is shaded normally until the pipeline is ready
+#[derive(Default)]
+pub struct DebugViews {
+    pub current: DebugView,
+    /// View distance mapped to black in `DebugView::Depth`
+    pub depth_range: f32,
+    wireframe_supported: bool,
+    template: Option<GraphicsPipelineCreateInfo>,
+    pipelines: HashMap<DebugView, SharedPipeline>,
+    pending: HashMap<DebugView, PipelineKey>,
+}
+
+impl DebugViews {
+    pub fn new(vulkan: &Vulkan, template: GraphicsPipelineCreateInfo, current: DebugView, depth_range: f32) -> Self {
+        let wireframe_supported = vulkan.get_loaded_device().device_info.features.fillModeNonSolid == VkBool32::TRUE;
+        if current == DebugView::Wireframe && !wireframe_supported {
+            eprintln!("Device does not support fillModeNonSolid, wireframe view shows normals instead");
+        }
+        DebugViews {
+            current,
+            depth_range,
+            wireframe_supported,
+            template: Some(template),
+            pipelines: HashMap::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Drops every pipeline, the next `pipeline` call creates them from `template`
+    pub fn set_template(&mut self, template: GraphicsPipelineCreateInfo) {
+        self.pipelines.clear();
+        self.pending.clear();
+        self.template = Some(template);
+    }
+
+    pub fn set_depth_range(&mut self, depth_range: f32) {
+        self.pipelines.remove(&DebugView::Depth);
+        self.pending.remove(&DebugView::Depth);
+        self.depth_range = depth_range;
+    }
+
+    /// Switches to the next view, skipping wireframe when the device draws no lines
+    pub fn cycle(&mut self) -> DebugView {
+        self.current = self.current.next();
+        if self.current == DebugView::Wireframe && !self.wireframe_supported {
+            self.current = self.current.next();
+        }
+        self.current
+    }
+
+    /// Pipeline of the current view, `None` while it is off or still compiling
+    pub fn pipeline(&mut self, vulkan: &Vulkan, registry: &mut PipelineRegistry) -> Option<VkPipeline> {
+        let view = self.current;
+        if view == DebugView::Off {
+            return None;
+        }
+        if let Some(pipeline) = self.pipelines.get(&view) {
+            return Some(***pipeline);
+        }
+
+        let key = match self.pending.get(&view) {
+            Some(key) => key.clone(),
+            None => {
+                let key = registry.request(vulkan, vec![self.create_info(view)]).pop().unwrap();
+                self.pending.insert(view, key.clone());
+                key
+            }
+        };
+        let pipeline = registry.get(&key)?;
+        self.pending.remove(&view);
+        self.pipelines.insert(view, pipeline.clone());
+        Some(**pipeline)
+    }
+
+    fn create_info(&self, view: DebugView) -> GraphicsPipelineCreateInfo {
+        let template = self.template.clone().expect("Debug views have no template");
+        let (polygon_mode, mode) = match view {
+            DebugView::Wireframe if !self.wireframe_supported => (VkPolygonMode::FILL, DebugView::Normals.shader_mode()),
+            DebugView::Wireframe => (VkPolygonMode::LINE, view.shader_mode()),
+            _ => (VkPolygonMode::FILL, view.shader_mode()),
+        };
+        let mut info = preset_debug_view(template, polygon_mode, view == DebugView::Overdraw);
+
+        let specialization_info = SpecializationInfo::from_u32s(&[mode, self.depth_range.max(1.0).to_bits()]);
+        for stage in info.stages.iter_mut().filter(|stage| stage.stage == VkShaderStageFlags::FRAGMENT_BIT) {
+            stage.specialization_info = Some(specialization_info.clone());
+        }
+        info
+    }
+}
@@ -1,13 +1,24 @@
 use crate::both::RenderLoop;
+use crate::engine::debug_view::DebugView;
+use crate::engine::lod::LodGeneration;
+use crate::engine::pipelines::set_pipeline_cache_path;
+use crate::engine::post::PostSettings;
 use crate::engine::{App, Delta, WinitHandler};
 use crate::prelude::*;
 use crate::vulkan::func::Vulkan;
-use crate::vulkan::utils::ImageUsage;
 use egui::{Context, RawInput};
+use std::path::PathBuf;
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::keyboard::KeyCode;
 
 pub fn create_window(settings: Settings) -> Result<(), Box<dyn std::error::Error>> {
+    let cache_path = settings.pipeline_cache.clone();
+    #[cfg(target_os = "android")]
+    let cache_path = cache_path.or_else(|| {
+        settings.activity.as_ref().and_then(|activity| activity.internal_data_path()).map(|dir| dir.join("cache.storage"))
+    });
+    set_pipeline_cache_path(cache_path);
+
     let mut vulkan = Vulkan::default();
     vulkan.init();
     let mut event_loop = EventLoop::builder();
@@ -47,17 +58,39 @@ pub fn create_window(settings: Settings) -> Result<(), Box<dyn std::error::Error
 pub struct Settings {
     pub width: u32,
     pub height: u32,
-    pub render_format: SurfaceFormat,
+    /// Swapchain formats in order of preference, F5 switches between SDR and HDR at runtime
+    pub surface_formats: Vec<SurfaceFormat>,
+    /// F4 cycles through the modes at runtime
+    pub present_mode: PresentMode,
     pub target_fps: f64,
     pub min_fps: f64,
     pub smoothing_factor: f64,
+    /// Caps the CPU frame rate at `target_fps`, presentation is controlled by `present_mode`
     pub vsync: bool,
     pub sensitivity: (f64, f64),
     pub msaa: VkSampleCountFlags,
+    pub render_path: RenderPath,
+    pub post: PostSettings,
+    /// Simplified levels generated for meshes without `MSFT_lod` every time the scene is loaded, not written back to the file
+    pub lod: LodGeneration,
+    /// Scene view at startup, F3 cycles through them at runtime
+    pub debug_view: DebugView,
+    /// Pipeline cache file, `None` uses the platform cache directory
+    pub pipeline_cache: Option<PathBuf>,
     pub callbacks: Callbacks,
     #[cfg(target_os = "android")]
     pub activity: Option<android_activity::AndroidApp>,
 }
+
+/// How the scene pass is recorded, dynamic rendering skips render pass and framebuffer objects.
+/// Post-processing always uses dynamic rendering
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RenderPath {
+    #[default]
+    DynamicRendering,
+    RenderPass,
+}
+
 pub struct Callbacks {
     pub render_init: fn(&mut RenderLoop, &Vulkan, &mut SwapchainInfo, &mut Settings),
     pub render: fn(&mut RenderLoop, &Vulkan, &mut SwapchainInfo, &mut Context, &mut WinitHandler, FrameInfo),
@@ -83,13 +116,19 @@ impl Default for Settings {
         Settings {
             width: 640,
             height: 480,
-            render_format: Default::default(),
+            surface_formats: SurfaceFormat::sdr_preferences(),
+            present_mode: PresentMode::Immediate,
             target_fps: 144.0,
             min_fps: 24.0,
             smoothing_factor: 0.7,
             vsync: false,
             sensitivity: (1.0, 1.0),
             msaa: VkSampleCountFlags::SC_1_BIT,
+            render_path: Default::default(),
+            post: Default::default(),
+            lod: Default::default(),
+            debug_view: Default::default(),
+            pipeline_cache: None,
             callbacks: Default::default(),
             #[cfg(target_os = "android")]
             activity: None,
@@ -131,62 +170,18 @@ impl PerFrameResource {
 pub struct PerImageResource {
     swapchain_image: VkImage,
     swapchain_image_view: VkDestroy<VkImageView>,
-    depth_image: VkDestroy<VkImage>,
-    depth_image_view: VkDestroy<VkImageView>,
-    color_msaa_image: Option<VkDestroy<VkImage>>,
-    color_msaa_view: Option<VkDestroy<VkImageView>>,
-    framebuffer: VkDestroy<VkFramebuffer>,
     render_finished_semaphore: VkDestroy<VkSemaphore>,
-    _memory_storage: Vec<VkDestroy<VkDeviceMemory>>,
 }
 
 impl PerImageResource {
-    pub fn new(vulkan: &Vulkan, image: VkImage, format: VkFormat, extent: VkExtent3D, sample_rate: VkSampleCountFlags, render_pass: VkRenderPass) -> Self {
+    pub fn new(vulkan: &Vulkan, image: VkImage, format: VkFormat) -> Self {
         let swapchain_image_view = vulkan.create_image_view(&image, VkImageViewType::IVT_2D, format, VkImageAspectFlags::COLOR_BIT);
-        let depth_image = vulkan.create_image(VkFormat::D32_SFLOAT, VkImageType::IT_2D, false, 1, 1, extent, sample_rate, ImageUsage::default().depth_stencil_attachment(true));
-        let mut device_storage = BatchedStorage::new();
-        device_storage.add_image(depth_image);
-
-        let color_msaa_image = if sample_rate != VkSampleCountFlags::SC_1_BIT {
-            let color_msaa_image = vulkan.create_image(format, VkImageType::IT_2D, false, 1, 1, extent, sample_rate, ImageUsage::default().color_attachment(true));
-            device_storage.add_image(color_msaa_image);
-
-            Some(VkDestroy::new(color_msaa_image, vulkan))
-        } else {
-            None
-        };
-        let memory = vulkan.arena().device(device_storage, vulkan).get_all_memory_objects();
-        let memory_storage = memory.into_iter().map(|memory| {
-            VkDestroy::new(memory, vulkan)
-        }).collect();
-
-        let color_msaa_view = if sample_rate != VkSampleCountFlags::SC_1_BIT {
-            let color_msaa_view = vulkan.create_image_view(color_msaa_image.as_ref().unwrap().get(), VkImageViewType::IVT_2D, format, VkImageAspectFlags::COLOR_BIT);
-            Some(VkDestroy::new(color_msaa_view, vulkan))
-        } else {
-            None
-        };
-
-        let depth_image_view = vulkan.create_image_view(&depth_image, VkImageViewType::IVT_2D, VkFormat::D32_SFLOAT, VkImageAspectFlags::DEPTH_BIT);
-        let attachments = if sample_rate != VkSampleCountFlags::SC_1_BIT {
-            vec![*color_msaa_view.as_ref().unwrap().get(), depth_image_view, swapchain_image_view]
-        } else {
-            vec![swapchain_image_view, depth_image_view]
-        };
-
-        let framebuffer = vulkan.create_framebuffer(render_pass, &attachments, extent.width, extent.height, 1);
-
         let render_finished_semaphore = vulkan.create_semaphore();
+
         Self {
             swapchain_image: image,
             swapchain_image_view: VkDestroy::new(swapchain_image_view, vulkan),
-            depth_image: VkDestroy::new(depth_image, vulkan),
-            depth_image_view: VkDestroy::new(depth_image_view, vulkan),
-            color_msaa_image,
-            color_msaa_view,
-            framebuffer: VkDestroy::new(framebuffer, vulkan),
             render_finished_semaphore: VkDestroy::new(render_finished_semaphore, vulkan),
-            _memory_storage: memory_storage,
         }
     }
 
@@ -198,26 +193,6 @@ impl PerImageResource {
         *self.swapchain_image_view
     }
 
-    pub fn depth_image(&self) -> VkImage {
-        *self.depth_image
-    }
-
-    pub fn depth_image_view(&self) -> VkImageView {
-        *self.depth_image_view
-    }
-
-    pub fn color_msaa_image(&self) -> &Option<VkDestroy<VkImage>> {
-        &self.color_msaa_image
-    }
-
-    pub fn color_msaa_view(&self) -> &Option<VkDestroy<VkImageView>> {
-        &self.color_msaa_view
-    }
-
-    pub fn framebuffer(&self) -> VkFramebuffer {
-        *self.framebuffer
-    }
-
     pub fn render_finished_semaphore(&self) -> VkSemaphore {
         *self.render_finished_semaphore
     }
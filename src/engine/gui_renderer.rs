@@ -1,53 +1,333 @@
+use crate::engine::buffers::stream::StreamBuffer;
+use crate::engine::pipelines::create_pipelines_multithreaded;
+use crate::prelude::pool_alloc::Image;
 use crate::vulkan::func::Vulkan;
-use egui::ClippedPrimitive;
+use crate::vulkan::utils::{BufferUsage, ImageUsage};
+use egui::epaint::{Primitive, Vertex};
+use egui::{ClippedPrimitive, ImageData, TextureFilter, TextureId, TexturesDelta};
+use shaders::common::{GuiParams, GUI_FLAG_LINEAR_OUTPUT, GUI_FLAG_PQ_OUTPUT};
+use std::collections::HashMap;
+use std::ptr::null_mut;
 use std::time::Instant;
 
 use crate::prelude::*;
 
+const GUI_TEXTURE_FORMAT: VkFormat = VkFormat::R8G8B8A8_UNORM;
+const MAX_GUI_TEXTURES: u32 = 256;
+const INITIAL_VERTEX_CAPACITY: u64 = 1 << 16;
+const INITIAL_INDEX_CAPACITY: u64 = 1 << 17;
+const SRGB_FORMATS: &[VkFormat] = &[
+    VkFormat::B8G8R8A8_SRGB,
+    VkFormat::R8G8B8A8_SRGB,
+    VkFormat::A8B8G8R8_SRGB_PACK32,
+];
+
+struct GuiTexture {
+    image: Image,
+    _view: VkDestroy<VkImageView>,
+    descriptor_set: VkDescriptorSet,
+}
+
+/// Per frame in flight, only touched after the fence of that frame was waited on
+#[derive(Default)]
+struct GuiFrame {
+    vertices: StreamBuffer,
+    indices: StreamBuffer,
+    staging: StreamBuffer,
+}
+
+/// egui backend, composites tessellated primitives over the final image
 #[derive(Default)]
 pub struct FastRenderer {
-    render_pass: VkDestroy<VkRenderPass>,
-    //vbo: VBO,
-    //idx: 
-    //pipeline: VkPipeline,
+    pipeline: VkDestroy<VkPipeline>,
+    layout: VkDestroy<VkPipelineLayout>,
+    descriptor_layout: VkDestroy<VkDescriptorSetLayout>,
+    descriptor_pool: VkDestroy<VkDescriptorPool>,
+    linear_sampler: VkDestroy<VkSampler>,
+    nearest_sampler: VkDestroy<VkSampler>,
+    format: SurfaceFormat,
+
+    textures: HashMap<TextureId, GuiTexture>,
+    /// Freed textures with the frame number they were last used in
+    retired: Vec<(u64, GuiTexture)>,
+    frames: Vec<GuiFrame>,
+    frame_number: u64,
 }
 
 impl FastRenderer {
-    pub fn new(vulkan: &Vulkan, format: VkFormat) -> FastRenderer {
-        let render_pass = vulkan.preset_renderpass_color(format, VkImageLayout::COLOR_ATTACHMENT_OPTIMAL, VkImageLayout::PRESENT_SRC_KHR);
+    /// `template` provides shader modules and fixed states, `format` is the format of the image composited onto
+    pub fn new(vulkan: &Vulkan, template: GraphicsPipelineCreateInfo, format: SurfaceFormat, frames_in_flight: usize) -> FastRenderer {
+        let sampler_info = |filter: VkFilter| SamplerInfo {
+            min_filter: filter,
+            mag_filter: filter,
+            address_mode_u: VkSamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_v: VkSamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_w: VkSamplerAddressMode::CLAMP_TO_EDGE,
+            ..Default::default()
+        };
+        let linear_sampler = vulkan.create_sampler(sampler_info(VkFilter::LINEAR));
+        let nearest_sampler = vulkan.create_sampler(sampler_info(VkFilter::NEAREST));
+
+        let bindings = [VkDescriptorSetLayoutBinding {
+            binding: 0,
+            descriptorType: VkDescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptorCount: 1,
+            stageFlags: VkShaderStageFlags::FRAGMENT_BIT,
+            pImmutableSamplers: null_mut(),
+        }];
+        let descriptor_layout = vulkan.create_descriptor_set_layout(&bindings);
+        let descriptor_pool = vulkan.create_descriptor_pool(&[VkDescriptorPoolSize {
+            descriptorType: VkDescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptorCount: MAX_GUI_TEXTURES,
+        }], MAX_GUI_TEXTURES, true);
+
+        let push_constant_ranges = [push_constant_range::<GuiParams>()];
+        graphics_interface("gui", "gui").check_layout("gui", &[&bindings], &push_constant_ranges);
+        let layout = vulkan.create_pipeline_layout_with::<GuiParams>(&[descriptor_layout]);
+        let pipeline = create_pipelines_multithreaded(true, vec![preset_gui(template, layout, format.format)], vulkan)[0];
 
-        let render_pass = VkDestroy::new(render_pass, vulkan);
         FastRenderer {
-            render_pass,
-            //pipeline,
+            pipeline: VkDestroy::new(pipeline, vulkan),
+            layout: VkDestroy::new(layout, vulkan),
+            descriptor_layout: VkDestroy::new(descriptor_layout, vulkan),
+            descriptor_pool: VkDestroy::new(descriptor_pool, vulkan),
+            linear_sampler: VkDestroy::new(linear_sampler, vulkan),
+            nearest_sampler: VkDestroy::new(nearest_sampler, vulkan),
+            format,
+            textures: HashMap::new(),
+            retired: vec![],
+            frames: (0..frames_in_flight).map(|_| GuiFrame::default()).collect(),
+            frame_number: 0,
         }
     }
 
-    pub fn render_primitives(&self, vulkan: &Vulkan,
-                             frame_resource: PerFrameResource,
-                             image_resource: PerImageResource,
-                             command_buffer: VkCommandBuffer, extent: VkExtent2D,
-                             primitives: Vec<ClippedPrimitive>) -> f32 {
+    /// Recreates the pipeline from a `template` with new shader modules
+    pub fn rebuild_pipeline(&mut self, vulkan: &Vulkan, template: GraphicsPipelineCreateInfo) {
+        let pipeline = create_pipelines_multithreaded(true, vec![preset_gui(template, *self.layout, self.format.format)], vulkan)[0];
+        self.pipeline = VkDestroy::new(pipeline, vulkan);
+    }
+
+    /// Switches to another target format, the pipeline is recreated from `template`
+    pub fn set_format(&mut self, vulkan: &Vulkan, template: GraphicsPipelineCreateInfo, format: SurfaceFormat) {
+        self.format = format;
+        self.rebuild_pipeline(vulkan, template);
+    }
+
+    /// Records uploads of `delta.set`, must be called outside of rendering, before `render_primitives` of the same frame
+    pub fn update_textures(&mut self, vulkan: &Vulkan, frame_index: usize, command_buffer: VkCommandBuffer, delta: &TexturesDelta) {
+        self.frame_number += 1;
+        self.free_retired(vulkan);
+        if delta.set.is_empty() {
+            return;
+        }
+
+        // every upload of the frame shares one staging buffer
+        let mut pixels = vec![];
+        let mut uploads = vec![];
+        for (id, image_delta) in &delta.set {
+            let ImageData::Color(image) = &image_delta.image;
+            let size = [image.size[0] as u32, image.size[1] as u32];
+            let sampler = match image_delta.options.magnification {
+                TextureFilter::Nearest => *self.nearest_sampler,
+                TextureFilter::Linear => *self.linear_sampler,
+            };
+
+            let (offset, is_new) = match image_delta.pos {
+                Some([x, y]) => (VkOffset3D { x: x as i32, y: y as i32, z: 0 }, false),
+                None => {
+                    let texture = self.create_texture(vulkan, size, sampler);
+                    if let Some(old) = self.textures.insert(*id, texture) {
+                        self.retired.push((self.frame_number, old));
+                    }
+                    (VkOffset3D::default(), true)
+                }
+            };
+            let Some(texture) = self.textures.get(id) else {
+                eprintln!("Partial update of unknown egui texture {:?}", id);
+                continue;
+            };
+
+            uploads.push((texture.image.image, is_new, VkBufferImageCopy {
+                bufferOffset: pixels.len() as VkDeviceSize,
+                bufferRowLength: 0,
+                bufferImageHeight: 0,
+                imageSubresource: VkImageSubresourceLayers {
+                    aspectMask: VkImageAspectFlags::COLOR_BIT,
+                    mipLevel: 0,
+                    baseArrayLayer: 0,
+                    layerCount: 1,
+                },
+                imageOffset: offset,
+                imageExtent: VkExtent3D { width: size[0], height: size[1], depth: 1 },
+            }));
+            pixels.extend(image.pixels.iter().flat_map(|color| color.to_array()));
+        }
+
+        let staging = &mut self.frames[frame_index].staging;
+        staging.reserve(vulkan, pixels.len() as u64, BufferUsage::preset_staging(), 0);
+        Vulkan::copy_info(staging.pointer, pixels.as_ptr(), pixels.len());
+
+        vulkan.transition_images2(uploads.iter().map(|&(image, is_new, _)| ImageTransition2 {
+            image,
+            src_stage: if is_new { VkPipelineStageFlags2::NONE } else { VkPipelineStageFlags2::FRAGMENT_SHADER_BIT },
+            dst_stage: VkPipelineStageFlags2::COPY_BIT,
+            src_access: if is_new { VkAccessFlags2::NONE } else { VkAccessFlags2::SHADER_SAMPLED_READ_BIT },
+            dst_access: VkAccessFlags2::TRANSFER_WRITE_BIT,
+            old_layout: if is_new { VkImageLayout::UNDEFINED } else { VkImageLayout::SHADER_READ_ONLY_OPTIMAL },
+            new_layout: VkImageLayout::TRANSFER_DST_OPTIMAL,
+            ..Default::default()
+        }).collect(), command_buffer);
+
+        for &(image, _, region) in &uploads {
+            vulkan.buffer_to_image(vec![region], command_buffer, *staging.buffer, image, VkImageLayout::TRANSFER_DST_OPTIMAL);
+        }
+
+        vulkan.transition_images2(uploads.iter().map(|&(image, _, _)| ImageTransition2 {
+            image,
+            src_stage: VkPipelineStageFlags2::COPY_BIT,
+            dst_stage: VkPipelineStageFlags2::FRAGMENT_SHADER_BIT,
+            src_access: VkAccessFlags2::TRANSFER_WRITE_BIT,
+            dst_access: VkAccessFlags2::SHADER_SAMPLED_READ_BIT,
+            old_layout: VkImageLayout::TRANSFER_DST_OPTIMAL,
+            new_layout: VkImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ..Default::default()
+        }).collect(), command_buffer);
+    }
+
+    /// Applies `delta.free` once the current frame is submitted, textures are destroyed after all frames in flight finished
+    pub fn free_textures(&mut self, delta: &TexturesDelta) {
+        for id in &delta.free {
+            if let Some(texture) = self.textures.remove(id) {
+                self.retired.push((self.frame_number, texture));
+            }
+        }
+    }
+
+    /// Records draws into the currently begun rendering, `extent` is in pixels
+    pub fn render_primitives(&mut self, vulkan: &Vulkan, frame_index: usize, command_buffer: VkCommandBuffer, extent: VkExtent2D,
+                             pixels_per_point: f32, primitives: &[ClippedPrimitive]) -> f32 {
         let instant = Instant::now();
 
-        let clear_values = vec![
-            VkClearValue { color: VkClearColorValue { float32: [0.0, 0.0, 0.0, 1.0] } },
-            VkClearValue { depthStencil: VkClearDepthStencilValue { depth: 1.0, stencil: 0 } },
-        ];
-        vulkan.begin_render_pass(frame_resource.command_buffer(), *self.render_pass, image_resource.framebuffer(),
-                                 VkRect2D { offset: Default::default(), extent }, clear_values.as_slice(), VkSubpassContents::INLINE);
-
-        //Write into buffer
-        // for clipped in &primitives {
-        //     if let Primitive::Mesh(mesh) = &clipped.primitive {
-        //         // Upload vertices
-        //         vertex_buffer.write(&mesh.vertices);
-        // 
-        //         // Upload indices
-        //         index_buffer.write(&mesh.indices);
-        //     }
-        // }
+        let meshes = primitives.iter().filter_map(|clipped| match &clipped.primitive {
+            Primitive::Mesh(mesh) if !mesh.indices.is_empty() => Some((clipped.clip_rect, mesh)),
+            _ => None,
+        }).collect::<Vec<_>>();
+        if meshes.is_empty() {
+            return instant.elapsed().as_secs_f32();
+        }
+
+        let vertex_count = meshes.iter().map(|(_, mesh)| mesh.vertices.len()).sum::<usize>();
+        let index_count = meshes.iter().map(|(_, mesh)| mesh.indices.len()).sum::<usize>();
+        let frame = &mut self.frames[frame_index];
+        frame.vertices.reserve(vulkan, (vertex_count * size_of::<Vertex>()) as u64, BufferUsage::preset_vertex(), INITIAL_VERTEX_CAPACITY);
+        frame.indices.reserve(vulkan, (index_count * size_of::<u32>()) as u64, BufferUsage::default().index_buffer(true), INITIAL_INDEX_CAPACITY);
+
+        let mut vertex_offset = 0;
+        let mut index_offset = 0;
+        for (_, mesh) in &meshes {
+            unsafe {
+                Vulkan::copy_info(frame.vertices.pointer.add(vertex_offset * size_of::<Vertex>()), mesh.vertices.as_ptr(), mesh.vertices.len());
+                Vulkan::copy_info(frame.indices.pointer.add(index_offset * size_of::<u32>()), mesh.indices.as_ptr(), mesh.indices.len());
+            }
+            vertex_offset += mesh.vertices.len();
+            index_offset += mesh.indices.len();
+        }
+
+        let params = GuiParams {
+            screen_size: [extent.width as f32 / pixels_per_point, extent.height as f32 / pixels_per_point],
+            flags: match self.format.colorSpace {
+                VkColorSpaceKHR::HDR10_ST2084_EXT => GUI_FLAG_PQ_OUTPUT,
+                VkColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => GUI_FLAG_LINEAR_OUTPUT,
+                _ if SRGB_FORMATS.contains(&self.format.format) => GUI_FLAG_LINEAR_OUTPUT,
+                _ => 0,
+            },
+            _pad: 0,
+        };
+        vulkan.bind_pipeline(command_buffer, VkPipelineBindPoint::GRAPHICS, *self.pipeline);
+        vulkan.bind_vertex_buffers(command_buffer, 0, vec![VertexBufferParameters {
+            buffer: *frame.vertices.buffer,
+            offset: 0,
+        }]);
+        vulkan.bind_index_buffer(command_buffer, *frame.indices.buffer, 0, VkIndexType::UINT32);
+        vulkan.push_constants(command_buffer, *self.layout, &params);
+        vulkan.set_viewport(command_buffer, 0, &[VkViewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            minDepth: 0.0,
+            maxDepth: 1.0,
+        }]);
+
+        let mut vertex_offset = 0;
+        let mut index_offset = 0;
+        for (clip_rect, mesh) in meshes {
+            let first_vertex = vertex_offset;
+            let first_index = index_offset;
+            vertex_offset += mesh.vertices.len();
+            index_offset += mesh.indices.len();
+
+            let Some(texture) = self.textures.get(&mesh.texture_id) else {
+                continue;
+            };
+
+            // clip rect is in points, scissor in pixels clamped to the target
+            let min_x = (clip_rect.min.x * pixels_per_point).round().clamp(0.0, extent.width as f32) as u32;
+            let min_y = (clip_rect.min.y * pixels_per_point).round().clamp(0.0, extent.height as f32) as u32;
+            let max_x = (clip_rect.max.x * pixels_per_point).round().clamp(min_x as f32, extent.width as f32) as u32;
+            let max_y = (clip_rect.max.y * pixels_per_point).round().clamp(min_y as f32, extent.height as f32) as u32;
+            if max_x == min_x || max_y == min_y {
+                continue;
+            }
+            vulkan.set_scissors(command_buffer, 0, &[VkRect2D {
+                offset: VkOffset2D { x: min_x as i32, y: min_y as i32 },
+                extent: VkExtent2D { width: max_x - min_x, height: max_y - min_y },
+            }]);
+
+            vulkan.bind_descriptor_sets(command_buffer, VkPipelineBindPoint::GRAPHICS, *self.layout, 0, &[texture.descriptor_set], &[]);
+            vulkan.draw_indexed(command_buffer, mesh.indices.len() as u32, 1, first_index as u32, first_vertex as i32, 0);
+        }
 
         instant.elapsed().as_secs_f32()
     }
-}
\ No newline at end of file
+
+    fn create_texture(&self, vulkan: &Vulkan, size: [u32; 2], sampler: VkSampler) -> GuiTexture {
+        let image = vulkan.pool().allocate_image(GUI_TEXTURE_FORMAT, VkImageType::IT_2D, false, 1, 1,
+                                                 VkExtent3D { width: size[0], height: size[1], depth: 1 },
+                                                 VkSampleCountFlags::SC_1_BIT, ImageUsage::default().sampled(true).transfer_dst(true));
+        let view = vulkan.create_image_view(&image.image, VkImageViewType::IVT_2D, GUI_TEXTURE_FORMAT, VkImageAspectFlags::COLOR_BIT);
+        let descriptor_set = vulkan.allocate_descriptor_sets(*self.descriptor_pool, &[*self.descriptor_layout])[0];
+        vulkan.update_descriptor_sets(vec![ImageDescriptorInfo {
+            target_descriptor: DescriptorSetInfo {
+                descriptor_set,
+                descriptor_binding: 0,
+                array_element: 0,
+            },
+            target_descriptor_type: VkDescriptorType::COMBINED_IMAGE_SAMPLER,
+            image_infos: vec![VkDescriptorImageInfo {
+                sampler,
+                imageView: view,
+                imageLayout: VkImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            }],
+        }], vec![], vec![], vec![]);
+
+        GuiTexture {
+            image,
+            _view: VkDestroy::new(view, vulkan),
+            descriptor_set,
+        }
+    }
+
+    fn free_retired(&mut self, vulkan: &Vulkan) {
+        let frames_in_flight = self.frames.len() as u64;
+        let frame_number = self.frame_number;
+        let descriptor_pool = *self.descriptor_pool;
+        self.retired.retain(|(retired_at, texture)| {
+            let in_use = frame_number < retired_at + frames_in_flight;
+            if !in_use {
+                vulkan.free_descriptor_sets(descriptor_pool, &[texture.descriptor_set]);
+            }
+            in_use
+        });
+    }
+}
@@ -0,0 +1,262 @@
+use crate::engine::bindless::BindlessRegistry;
+use crate::engine::camera::Camera;
+use crate::engine::permutations::PermutationCache;
+use crate::engine::pipeline_registry::PipelineRegistry;
+use crate::engine::post::{PostChain, PostSettings, HDR_FORMAT};
+use crate::engine::render_graph::{BufferHandle, GraphImage, ImageDesc, ImageHandle, PassContext, PassDesc, RenderGraph, ResourceUsage};
+use crate::prelude::pool_alloc::{Buffer, Image};
+use crate::prelude::*;
+use crate::vulkan::func::Vulkan;
+use crate::vulkan::gltf::scene::Scene;
+use crate::vulkan::gltf::utils::StagingBuffer;
+use crate::vulkan::utils::{BufferUsage, ImageUsage};
+use std::ffi::c_void;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// Offscreen target format, `HeadlessRenderer::render` returns it as sRGB encoded RGBA8
+pub const HEADLESS_FORMAT: VkFormat = VkFormat::R8G8B8A8_SRGB;
+
+/// Renders a scene into an offscreen image and reads it back, no window or swapchain required.
+/// `Vulkan` has to be initialized with `init_headless` or `init`
+pub struct HeadlessRenderer {
+    pub scene: Scene,
+    pub bindless: BindlessRegistry,
+    pub post: PostSettings,
+    extent: VkExtent2D,
+
+    pipeline_layout: PipelineContainer,
+    pipelines: PermutationCache,
+    post_chain: PostChain,
+
+    graph: RenderGraph<HeadlessRenderer>,
+    output_target: ImageHandle,
+    hdr_target: ImageHandle,
+    depth_target: ImageHandle,
+    ubo_target: BufferHandle,
+    readback_target: BufferHandle,
+
+    output_view: VkDestroy<VkImageView>,
+    output: Image,
+    readback: Buffer,
+    readback_pointer: *mut c_void,
+
+    command_pool: VkDestroy<VkCommandPool>,
+    command_buffer: VkCommandBuffer,
+    fence: VkDestroy<VkFence>,
+    queue: VkQueue,
+}
+
+impl HeadlessRenderer {
+    pub fn new(vulkan: &Vulkan, glb: &[u8], width: u32, height: u32, post: PostSettings) -> Self {
+        let mut staging = StagingBuffer::new();
+        // every render waits for its fence, so slots are reusable after one frame
+        let mut bindless = BindlessRegistry::new(vulkan, 1);
+        let scene = Scene::from_glb(glb, vulkan.clone(), &mut staging, &mut bindless);
+        let extent = VkExtent2D { width, height };
+
+        let mut descriptor_layouts = scene.descriptors.descriptor_layouts.clone();
+        descriptor_layouts.push(bindless.descriptor_layout());
+        let pipeline_layout = preset_graphic_pipeline(vulkan, width, height, VkRenderPass::none(), 0, &descriptor_layouts);
+        let create_info = preset_dynamic_rendering(pipeline_layout.info.clone(), &[HDR_FORMAT], VkFormat::D32_SFLOAT);
+        let mut pipelines = PermutationCache::new(create_info);
+        // the cache holds the only handles, the registry is not needed afterwards
+        pipelines.prepare(vulkan, &mut PipelineRegistry::new(vulkan), scene.features());
+        let post_chain = PostChain::new(vulkan, pipeline_layout.info.clone());
+
+        let output = vulkan.pool().allocate_image(HEADLESS_FORMAT, VkImageType::IT_2D, false, 1, 1,
+                                                  VkExtent3D { width, height, depth: 1 }, VkSampleCountFlags::SC_1_BIT,
+                                                  ImageUsage::default().color_attachment(true).transfer_src(true));
+        let output_view = vulkan.create_image_view(&output.image, VkImageViewType::IVT_2D, HEADLESS_FORMAT, VkImageAspectFlags::COLOR_BIT);
+
+        let alloc_info = VmaAllocationCreateInfo {
+            usage: VmaMemoryUsage::AUTO_PREFER_HOST,
+            flags: VmaAllocationCreateFlagBits::HOST_ACCESS_RANDOM_BIT,
+            requiredFlags: VkMemoryPropertyFlagBits::HOST_VISIBLE_BIT
+                | VkMemoryPropertyFlagBits::HOST_COHERENT_BIT,
+            ..Default::default()
+        };
+        let mut readback = vulkan.pool().allocate_buffer((width * height * 4) as u64, BufferUsage::default().transfer_dst(true), alloc_info);
+        let readback_pointer = readback.map_memory(vulkan);
+
+        let command_pool = vulkan.create_command_pool(vulkan.get_loaded_device().queue_info[0].family_index, VkCommandPoolCreateFlags::RESET_COMMAND_BUFFER_BIT);
+        let command_buffer = vulkan.alloc_command_buffers(command_pool, VkCommandBufferLevel::PRIMARY, 1)[0];
+        let fence = vulkan.create_fence(false);
+
+        let mut renderer = HeadlessRenderer {
+            scene,
+            bindless,
+            post,
+            extent,
+            pipeline_layout,
+            pipelines,
+            post_chain,
+            graph: RenderGraph::new(),
+            output_target: Default::default(),
+            hdr_target: Default::default(),
+            depth_target: Default::default(),
+            ubo_target: Default::default(),
+            readback_target: Default::default(),
+            output_view: VkDestroy::new(output_view, vulkan),
+            output,
+            readback,
+            readback_pointer,
+            command_pool: VkDestroy::new(command_pool, vulkan),
+            command_buffer,
+            fence: VkDestroy::new(fence, vulkan),
+            queue: vulkan.get_queues()[0],
+        };
+        renderer.rebuild_graph(vulkan);
+        renderer
+    }
+
+    pub fn extent(&self) -> VkExtent2D {
+        self.extent
+    }
+
+    /// Needed after changing enabled post effects
+    pub fn rebuild_graph(&mut self, vulkan: &Vulkan) {
+        let mut graph: RenderGraph<HeadlessRenderer> = RenderGraph::new();
+        self.output_target = graph.import_image("output", VkImageLayout::UNDEFINED);
+        self.ubo_target = graph.import_buffer("ubo");
+        self.readback_target = graph.import_buffer("readback");
+        self.hdr_target = graph.create_image("hdr", ImageDesc::attachment(HDR_FORMAT, VkImageAspectFlags::COLOR_BIT, VkSampleCountFlags::SC_1_BIT));
+        self.depth_target = graph.create_image("depth", ImageDesc::attachment(VkFormat::D32_SFLOAT, VkImageAspectFlags::DEPTH_BIT, VkSampleCountFlags::SC_1_BIT));
+
+        graph.add_pass("ubo_upload", PassDesc::new().buffer(self.ubo_target, ResourceUsage::TransferWrite), |renderer, vulkan, pass| {
+            renderer.scene.ubo.sync_with_buffer(pass.command_buffer, vulkan);
+        });
+
+        let color_clear = VkClearValue { color: VkClearColorValue { float32: [0.0, 0.0, 0.0, 1.0] } };
+        let depth_clear = VkClearValue { depthStencil: VkClearDepthStencilValue { depth: 1.0, stencil: 0 } };
+        let scene_pass = PassDesc::new()
+            .buffer(self.ubo_target, ResourceUsage::UniformRead(VkPipelineStageFlags2::VERTEX_SHADER_BIT))
+            .depth_attachment(self.depth_target, VkAttachmentLoadOp::CLEAR, depth_clear)
+            .color_attachment(self.hdr_target, VkAttachmentLoadOp::CLEAR, color_clear);
+        graph.add_pass("scene", scene_pass, HeadlessRenderer::scene_pass);
+
+        self.post_chain.build(vulkan, &mut graph, &self.post, self.hdr_target, self.output_target, SurfaceFormat { format: HEADLESS_FORMAT, colorSpace: VkColorSpaceKHR::SRGB_NONLINEAR_KHR },
+                              |renderer, vulkan, pass| renderer.post_chain.record(vulkan, pass, &renderer.post));
+
+        let readback_pass = PassDesc::new()
+            .image(self.output_target, ResourceUsage::TransferRead)
+            .buffer(self.readback_target, ResourceUsage::TransferWrite);
+        graph.add_pass("readback", readback_pass, HeadlessRenderer::readback_pass);
+
+        graph.compile(vulkan, self.extent);
+        self.post_chain.update_descriptors(vulkan, &graph);
+        self.graph = graph;
+    }
+
+    fn scene_pass(&mut self, vulkan: &Vulkan, pass: &PassContext) {
+        let command_buffer = pass.command_buffer;
+        let viewports = [VkViewport {
+            x: 0.0,
+            y: 0.0,
+            width: self.extent.width as f32,
+            height: self.extent.height as f32,
+            minDepth: 0.1,
+            maxDepth: 1.0,
+        }];
+        unsafe { vkCmdSetViewport(command_buffer, 0, 1, viewports.as_ptr()); };
+
+        let scissors = [pass.render_area];
+        unsafe { vkCmdSetScissor(command_buffer, 0, 1, scissors.as_ptr()); };
+
+        self.scene.render_scene(vulkan, command_buffer, self.pipeline_layout.layout, &self.pipelines, 0);
+    }
+
+    fn readback_pass(&mut self, vulkan: &Vulkan, pass: &PassContext) {
+        let output = pass.image(self.output_target);
+        vulkan.image_to_buffer(vec![
+            VkBufferImageCopy {
+                bufferOffset: 0,
+                bufferRowLength: 0,
+                bufferImageHeight: 0,
+                imageSubresource: VkImageSubresourceLayers {
+                    aspectMask: VkImageAspectFlags::COLOR_BIT,
+                    mipLevel: 0,
+                    baseArrayLayer: 0,
+                    layerCount: 1,
+                },
+                imageOffset: Default::default(),
+                imageExtent: VkExtent3D { width: output.extent.width, height: output.extent.height, depth: 1 },
+            }
+        ], pass.command_buffer, output.image, pass.buffer(self.readback_target), VkImageLayout::TRANSFER_SRC_OPTIMAL);
+
+        // make the copy visible to host reads after the fence
+        vulkan.transition_resources(vec![BufferTransition {
+            buffer: pass.buffer(self.readback_target),
+            src_stage: VkPipelineStageFlags2::COPY_BIT,
+            dst_stage: VkPipelineStageFlags2::HOST_BIT,
+            src_access: VkAccessFlags2::TRANSFER_WRITE_BIT,
+            dst_access: VkAccessFlags2::HOST_READ_BIT,
+            src_queue_family: VK_QUEUE_FAMILY_IGNORED,
+            dst_queue_family: VK_QUEUE_FAMILY_IGNORED,
+            ..Default::default()
+        }], vec![], pass.command_buffer);
+    }
+
+    /// Renders one frame from `camera` and returns tightly packed RGBA8 rows, top to bottom
+    pub fn render(&mut self, vulkan: &Vulkan, camera: &Camera) -> Vec<u8> {
+        if self.post.active() != self.post_chain.effects() {
+            self.rebuild_graph(vulkan);
+        }
+        self.bindless.begin_frame();
+
+        let mut camera = *camera;
+        camera.set_aspect_ratio(self.extent.width as f32 / self.extent.height as f32);
+        self.scene.ubo.set_view(camera.view_matrix());
+        self.scene.ubo.set_proj(camera.projection_matrix());
+
+        self.graph.set_image(self.output_target, GraphImage {
+            image: self.output.image,
+            view: *self.output_view,
+            format: HEADLESS_FORMAT,
+            extent: self.extent,
+            aspect: VkImageAspectFlags::COLOR_BIT,
+        });
+        self.graph.set_buffer(self.ubo_target, self.scene.ubo.provide_buffer());
+        self.graph.set_buffer(self.readback_target, self.readback.buffer);
+
+        let command_buffer = self.command_buffer;
+        vulkan.reset_buffer(command_buffer, false);
+        vulkan.start_recording(command_buffer, VkCommandBufferUsageFlags::ONE_TIME_SUBMIT_BIT, RecordingInfo {
+            renderPass: Default::default(),
+            subpass: 0,
+            framebuffer: Default::default(),
+            occlusionQueryEnable: false,
+            queryFlags: Default::default(),
+            pipelineStatistics: Default::default(),
+        });
+
+        let graph = std::mem::take(&mut self.graph);
+        graph.execute(vulkan, command_buffer, self);
+        self.graph = graph;
+
+        vulkan.end_recording(command_buffer);
+        vulkan.submit_buffer(self.queue, *self.fence, &[command_buffer], &[], &[]);
+        vulkan.wait_for_fences(&[*self.fence], true, u64::MAX);
+        vulkan.reset_fences(&[*self.fence]);
+
+        let size = (self.extent.width * self.extent.height * 4) as usize;
+        let mut pixels = vec![0u8; size];
+        unsafe { std::ptr::copy_nonoverlapping(self.readback_pointer as *const u8, pixels.as_mut_ptr(), size) };
+        pixels
+    }
+}
+
+/// Writes tightly packed RGBA8 pixels as PNG
+pub fn save_png(path: impl AsRef<Path>, width: u32, height: u32, pixels: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    let file = File::create(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(pixels)?;
+    writer.finish()?;
+    Ok(())
+}
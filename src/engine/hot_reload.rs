@@ -0,0 +1,153 @@
+use spirv_builder::{Capability, SpirvBuilder};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Editors save in several writes, changes are collected for this long before building
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+/// Shader crates embedded by `build.rs`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ShaderCrate {
+    Vertex,
+    Fragment,
+    Compute,
+}
+
+impl ShaderCrate {
+    pub const ALL: [ShaderCrate; 3] = [ShaderCrate::Vertex, ShaderCrate::Fragment, ShaderCrate::Compute];
+
+    fn directory(&self) -> &'static str {
+        match self {
+            ShaderCrate::Vertex => "vertex",
+            ShaderCrate::Fragment => "fragment",
+            ShaderCrate::Compute => "compute",
+        }
+    }
+}
+
+/// Result of one background build, the error holds the builder message
+pub struct ShaderUpdate {
+    pub shader: ShaderCrate,
+    pub result: Result<Vec<u8>, String>,
+}
+
+/// Polls `shaders/*` for changes and rebuilds the affected crates on its own thread, `common` rebuilds all of them
+pub struct ShaderWatcher {
+    receiver: Receiver<ShaderUpdate>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ShaderWatcher {
+    /// Watches the shader crates next to the engine sources
+    pub fn new() -> Self {
+        Self::watch(Path::new(env!("CARGO_MANIFEST_DIR")).join("shaders"))
+    }
+
+    pub fn watch(shaders_dir: PathBuf) -> Self {
+        let (sender, receiver) = channel();
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        let thread = thread::Builder::new()
+            .name("shader watcher".into())
+            .spawn(move || watch_loop(&shaders_dir, &thread_running, &sender))
+            .expect("Unable to spawn shader watcher");
+
+        ShaderWatcher {
+            receiver,
+            running,
+            thread: Some(thread),
+        }
+    }
+
+    /// Finished builds since the last call, never blocks
+    pub fn poll(&self) -> Vec<ShaderUpdate> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+impl Drop for ShaderWatcher {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn watch_loop(shaders_dir: &Path, running: &AtomicBool, sender: &Sender<ShaderUpdate>) {
+    let mut snapshot = scan(shaders_dir);
+    while running.load(Ordering::Relaxed) {
+        thread::sleep(POLL_INTERVAL);
+        if scan(shaders_dir) == snapshot {
+            continue;
+        }
+        thread::sleep(SETTLE_TIME);
+        let current = scan(shaders_dir);
+
+        let changed = |name: &str| snapshot.get(name) != current.get(name);
+        let dirty = ShaderCrate::ALL.into_iter()
+            .filter(|shader| changed("common") || changed(shader.directory()))
+            .collect::<Vec<_>>();
+        snapshot = current;
+
+        let updates = thread::scope(|s| {
+            let handles = dirty.iter()
+                .map(|&shader| s.spawn(move || ShaderUpdate { shader, result: build(shaders_dir, shader) }))
+                .collect::<Vec<_>>();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect::<Vec<_>>()
+        });
+        for update in updates {
+            if sender.send(update).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Latest modification time per crate directory
+fn scan(shaders_dir: &Path) -> HashMap<String, SystemTime> {
+    let mut times = HashMap::new();
+    let Ok(entries) = fs::read_dir(shaders_dir) else {
+        return times;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            times.insert(name, latest_modification(&path));
+        }
+    }
+    times
+}
+
+fn latest_modification(path: &Path) -> SystemTime {
+    let own = fs::metadata(path).and_then(|metadata| metadata.modified()).unwrap_or(SystemTime::UNIX_EPOCH);
+    let Ok(entries) = fs::read_dir(path) else {
+        return own;
+    };
+    entries.flatten()
+        .filter(|entry| entry.file_name() != "target")
+        .map(|entry| latest_modification(&entry.path()))
+        .fold(own, SystemTime::max)
+}
+
+/// Same settings as `build.rs`, without the build script output
+fn build(shaders_dir: &Path, shader: ShaderCrate) -> Result<Vec<u8>, String> {
+    let mut builder = SpirvBuilder::new(shaders_dir.join(shader.directory()), "spirv-unknown-vulkan1.3");
+    if shader == ShaderCrate::Fragment {
+        builder.capabilities.push(Capability::RuntimeDescriptorArray);
+    }
+
+    let result = builder.build().map_err(|err| format!("{} shader failed to compile: {err}", shader.directory()))?;
+    let path = result.module.unwrap_single();
+    fs::read(path).map_err(|err| format!("Unable to read {}: {err}", path.display()))
+}
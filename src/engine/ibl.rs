@@ -0,0 +1,399 @@
+use crate::engine::pipelines::{compression_algo, create_pipelines_multithreaded};
+use crate::engine::skybox::{upload_image, Cubemap, CUBEMAP_HDR_FORMAT};
+use crate::prelude::pool_alloc::Image;
+use crate::prelude::*;
+use crate::vulkan::func::Vulkan;
+use crate::vulkan::utils::{BufferUsage, ImageUsage};
+use shaders::common::IblParams;
+use std::ffi::c_void;
+use std::fs;
+use std::path::Path;
+use std::ptr::null_mut;
+
+pub const IRRADIANCE_SIZE: u32 = 32;
+pub const PREFILTERED_SIZE: u32 = 128;
+/// Mip `n` of the prefiltered map holds roughness `n / (PREFILTERED_MIPS - 1)`
+pub const PREFILTERED_MIPS: u32 = 5;
+pub const BRDF_LUT_SIZE: u32 = 512;
+pub const BRDF_LUT_FORMAT: VkFormat = VkFormat::R16G16_SFLOAT;
+/// Material set bindings of the irradiance, prefiltered and BRDF LUT maps. Reserved for PBR shading,
+/// no shader samples them yet, so reflection does not report them and the scene layout adds them from here
+pub const SCENE_BINDINGS: [u32; 3] = [5, 6, 7];
+
+const IRRADIANCE_SAMPLES: u32 = 64;
+const PREFILTER_SAMPLES: u32 = 1024;
+const BRDF_LUT_SAMPLES: u32 = 512;
+
+const CACHE_MAGIC: &[u8; 4] = b"AIBL";
+const CACHE_VERSION: u32 = 2;
+const CACHE_HEADER_SIZE: usize = 4 + 4 * 5 + 8;
+
+/// Precomputed image based lighting of one environment: diffuse irradiance, GGX prefiltered specular and split-sum BRDF LUT
+pub struct Ibl {
+    pub irradiance: Cubemap,
+    pub prefiltered: Cubemap,
+    pub brdf_lut: Image,
+    pub brdf_lut_view: VkDestroy<VkImageView>,
+    /// Trilinear clamp sampler shared by all three maps
+    pub sampler: VkDestroy<VkSampler>,
+}
+
+struct Target {
+    pipeline: usize,
+    image: VkImage,
+    format: VkFormat,
+    size: u32,
+    mip: u32,
+    face: u32,
+    roughness: f32,
+    sample_count: u32,
+}
+
+impl Ibl {
+    /// Layout bindings for `SCENE_BINDINGS`, appended to the reflected material set
+    pub fn scene_layout_bindings() -> Vec<VkDescriptorSetLayoutBinding> {
+        SCENE_BINDINGS.iter().map(|&binding| VkDescriptorSetLayoutBinding {
+            binding,
+            descriptorType: VkDescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptorCount: 1,
+            stageFlags: VkShaderStageFlags::FRAGMENT_BIT,
+            pImmutableSamplers: null_mut(),
+        }).collect()
+    }
+
+    /// Renders all maps from `environment` on the GPU and waits for completion.
+    /// `template` provides shader modules and fixed states, see `preset_fullscreen`
+    pub fn generate(vulkan: &Vulkan, environment: &Cubemap, template: GraphicsPipelineCreateInfo) -> Ibl {
+        let usage = ImageUsage::default().sampled(true).color_attachment(true).transfer_src(true).transfer_dst(true);
+        let irradiance = Cubemap::allocate(vulkan, CUBEMAP_HDR_FORMAT, IRRADIANCE_SIZE, 1, usage);
+        let prefiltered = Cubemap::allocate(vulkan, CUBEMAP_HDR_FORMAT, PREFILTERED_SIZE, PREFILTERED_MIPS, usage);
+        let (brdf_lut, brdf_lut_view) = allocate_brdf_lut(vulkan, usage);
+
+        let environment_sampler = VkDestroy::new(vulkan.create_sampler(SamplerInfo {
+            min_filter: VkFilter::LINEAR,
+            mag_filter: VkFilter::LINEAR,
+            address_mode_u: VkSamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_v: VkSamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_w: VkSamplerAddressMode::CLAMP_TO_EDGE,
+            ..Default::default()
+        }), vulkan);
+
+        let bindings = [VkDescriptorSetLayoutBinding {
+            binding: 0,
+            descriptorType: VkDescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptorCount: 1,
+            stageFlags: VkShaderStageFlags::FRAGMENT_BIT,
+            pImmutableSamplers: null_mut(),
+        }];
+        let descriptor_layout = VkDestroy::new(vulkan.create_descriptor_set_layout(&bindings), vulkan);
+        let descriptor_pool = VkDestroy::new(vulkan.create_descriptor_pool(&[VkDescriptorPoolSize {
+            descriptorType: VkDescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptorCount: 1,
+        }], 1, false), vulkan);
+        let descriptor_set = vulkan.allocate_descriptor_sets(*descriptor_pool, &[*descriptor_layout])[0];
+        vulkan.update_descriptor_sets(vec![ImageDescriptorInfo {
+            target_descriptor: DescriptorSetInfo {
+                descriptor_set,
+                descriptor_binding: 0,
+                array_element: 0,
+            },
+            target_descriptor_type: VkDescriptorType::COMBINED_IMAGE_SAMPLER,
+            image_infos: vec![VkDescriptorImageInfo {
+                sampler: *environment_sampler,
+                imageView: *environment.view,
+                imageLayout: VkImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            }],
+        }], vec![], vec![], vec![]);
+
+        let push_constant_ranges = [push_constant_range::<IblParams>()];
+        for entry in ["ibl_irradiance", "ibl_prefilter", "ibl_brdf_lut"] {
+            graphics_interface("fullscreen", entry).check_layout(entry, &[&bindings], &push_constant_ranges);
+        }
+        let layout = VkDestroy::new(vulkan.create_pipeline_layout_with::<IblParams>(&[*descriptor_layout]), vulkan);
+        let pipelines = create_pipelines_multithreaded(true, vec![
+            preset_fullscreen(template.clone(), "ibl_irradiance", *layout, CUBEMAP_HDR_FORMAT),
+            preset_fullscreen(template.clone(), "ibl_prefilter", *layout, CUBEMAP_HDR_FORMAT),
+            preset_fullscreen(template, "ibl_brdf_lut", *layout, BRDF_LUT_FORMAT),
+        ], vulkan).into_iter().map(|pipeline| VkDestroy::new(pipeline, vulkan)).collect::<Vec<_>>();
+
+        let mut targets = vec![];
+        for face in 0..6 {
+            targets.push(Target {
+                pipeline: 0,
+                image: irradiance.image.image,
+                format: CUBEMAP_HDR_FORMAT,
+                size: IRRADIANCE_SIZE,
+                mip: 0,
+                face,
+                roughness: 0.0,
+                sample_count: IRRADIANCE_SAMPLES,
+            });
+        }
+        for mip in 0..PREFILTERED_MIPS {
+            for face in 0..6 {
+                targets.push(Target {
+                    pipeline: 1,
+                    image: prefiltered.image.image,
+                    format: CUBEMAP_HDR_FORMAT,
+                    size: (PREFILTERED_SIZE >> mip).max(1),
+                    mip,
+                    face,
+                    roughness: mip as f32 / (PREFILTERED_MIPS - 1) as f32,
+                    sample_count: PREFILTER_SAMPLES,
+                });
+            }
+        }
+        targets.push(Target {
+            pipeline: 2,
+            image: brdf_lut.image,
+            format: BRDF_LUT_FORMAT,
+            size: BRDF_LUT_SIZE,
+            mip: 0,
+            face: 0,
+            roughness: 0.0,
+            sample_count: BRDF_LUT_SAMPLES,
+        });
+
+        // single face, single mip views, alive until the submit finished
+        let views = targets.iter().map(|target| {
+            VkDestroy::new(vulkan.create_image_view_range(&target.image, VkImageViewType::IVT_2D, target.format, VkImageAspectFlags::COLOR_BIT,
+                                                          target.mip, 1, target.face, 1), vulkan)
+        }).collect::<Vec<_>>();
+
+        let images = [irradiance.image.image, prefiltered.image.image, brdf_lut.image];
+        vulkan.immediate_submit(|command_buffer| {
+            vulkan.transition_images2(images.iter().map(|&image| ImageTransition2 {
+                image,
+                src_stage: VkPipelineStageFlags2::NONE,
+                dst_stage: VkPipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT_BIT,
+                src_access: VkAccessFlags2::NONE,
+                dst_access: VkAccessFlags2::COLOR_ATTACHMENT_WRITE_BIT,
+                old_layout: VkImageLayout::UNDEFINED,
+                new_layout: VkImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                ..Default::default()
+            }).collect(), command_buffer);
+
+            for (target, view) in targets.iter().zip(&views) {
+                let render_area = VkRect2D {
+                    offset: Default::default(),
+                    extent: VkExtent2D { width: target.size, height: target.size },
+                };
+                let attachment = RenderingAttachment::color(**view, VkAttachmentLoadOp::DONT_CARE, VkClearValue::default());
+                vulkan.begin_rendering(command_buffer, render_area, 1, &[attachment], None);
+
+                let params = IblParams {
+                    face: target.face,
+                    roughness: target.roughness,
+                    sample_count: target.sample_count,
+                    _pad: 0,
+                };
+                vulkan.bind_pipeline(command_buffer, VkPipelineBindPoint::GRAPHICS, *pipelines[target.pipeline]);
+                vulkan.bind_descriptor_sets(command_buffer, VkPipelineBindPoint::GRAPHICS, *layout, 0, &[descriptor_set], &[]);
+                vulkan.push_constants(command_buffer, *layout, &params);
+                vulkan.set_viewport(command_buffer, 0, &[VkViewport {
+                    x: 0.0,
+                    y: 0.0,
+                    width: target.size as f32,
+                    height: target.size as f32,
+                    minDepth: 0.0,
+                    maxDepth: 1.0,
+                }]);
+                vulkan.set_scissors(command_buffer, 0, &[render_area]);
+                vulkan.draw(command_buffer, 3, 1, 0, 0);
+                vulkan.end_rendering(command_buffer);
+            }
+
+            vulkan.transition_images2(images.iter().map(|&image| ImageTransition2 {
+                image,
+                src_stage: VkPipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT_BIT,
+                dst_stage: VkPipelineStageFlags2::FRAGMENT_SHADER_BIT,
+                src_access: VkAccessFlags2::COLOR_ATTACHMENT_WRITE_BIT,
+                dst_access: VkAccessFlags2::SHADER_SAMPLED_READ_BIT,
+                old_layout: VkImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                new_layout: VkImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                ..Default::default()
+            }).collect(), command_buffer);
+        });
+
+        Ibl {
+            irradiance,
+            prefiltered,
+            brdf_lut,
+            brdf_lut_view,
+            sampler: ibl_sampler(vulkan),
+        }
+    }
+
+    /// Reads the maps from `cache_path` if it holds a valid cache of `environment`, otherwise generates and writes them there.
+    /// Environments without a `source_hash` are always generated
+    pub fn load_or_generate(vulkan: &Vulkan, environment: &Cubemap, template: GraphicsPipelineCreateInfo, cache_path: Option<&Path>) -> Ibl {
+        let (Some(path), Some(environment_hash)) = (cache_path, environment.source_hash) else {
+            return Ibl::generate(vulkan, environment, template);
+        };
+
+        if let Ok(bytes) = fs::read(path) {
+            match Ibl::decode(vulkan, &bytes, environment_hash) {
+                Some(ibl) => return ibl,
+                None => eprintln!("IBL cache {} is outdated, corrupt or of another environment, regenerating", path.display()),
+            }
+        }
+
+        let ibl = Ibl::generate(vulkan, environment, template);
+        if let Err(err) = fs::write(path, ibl.encode(vulkan, environment_hash)) {
+            eprintln!("Unable to write IBL cache {}: {}", path.display(), err);
+        }
+        ibl
+    }
+
+    /// Header followed by lz4 compressed irradiance, prefiltered and LUT texels, same compression as the pipeline cache.
+    /// `environment_hash` is the `source_hash` of the cubemap the maps were generated from
+    pub fn encode(&self, vulkan: &Vulkan, environment_hash: u64) -> Vec<u8> {
+        let mut payload = download_image(vulkan, self.irradiance.image.image, IRRADIANCE_SIZE, 6, 1, 8);
+        payload.extend(download_image(vulkan, self.prefiltered.image.image, PREFILTERED_SIZE, 6, PREFILTERED_MIPS, 8));
+        payload.extend(download_image(vulkan, self.brdf_lut.image, BRDF_LUT_SIZE, 1, 1, 4));
+
+        let mut bytes = Vec::with_capacity(CACHE_HEADER_SIZE);
+        bytes.extend_from_slice(CACHE_MAGIC);
+        for value in [CACHE_VERSION, IRRADIANCE_SIZE, PREFILTERED_SIZE, PREFILTERED_MIPS, BRDF_LUT_SIZE] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&environment_hash.to_le_bytes());
+        bytes.extend(compression_algo(&payload));
+        bytes
+    }
+
+    /// `None` if the header does not match the current constants or `environment_hash`, or the payload is damaged
+    pub fn decode(vulkan: &Vulkan, bytes: &[u8], environment_hash: u64) -> Option<Ibl> {
+        if bytes.len() < CACHE_HEADER_SIZE || &bytes[..4] != CACHE_MAGIC {
+            return None;
+        }
+        let cached_hash = u64::from_le_bytes(bytes[CACHE_HEADER_SIZE - 8..CACHE_HEADER_SIZE].try_into().unwrap());
+        if cached_hash != environment_hash {
+            return None;
+        }
+        let header = bytes[4..CACHE_HEADER_SIZE - 8].chunks(4)
+            .map(|value| u32::from_le_bytes(value.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        if header != [CACHE_VERSION, IRRADIANCE_SIZE, PREFILTERED_SIZE, PREFILTERED_MIPS, BRDF_LUT_SIZE] {
+            return None;
+        }
+
+        let payload = lz4_flex::decompress_size_prepended(&bytes[CACHE_HEADER_SIZE..]).ok()?;
+        let irradiance_size = image_size(IRRADIANCE_SIZE, 6, 1, 8);
+        let prefiltered_size = image_size(PREFILTERED_SIZE, 6, PREFILTERED_MIPS, 8);
+        if payload.len() != irradiance_size + prefiltered_size + image_size(BRDF_LUT_SIZE, 1, 1, 4) {
+            return None;
+        }
+        let (irradiance, rest) = payload.split_at(irradiance_size);
+        let (prefiltered, brdf_lut_data) = rest.split_at(prefiltered_size);
+
+        let (brdf_lut, brdf_lut_view) = allocate_brdf_lut(vulkan, ImageUsage::default().sampled(true).transfer_dst(true).transfer_src(true));
+        upload_image(vulkan, brdf_lut.image, BRDF_LUT_SIZE, 1, 1, brdf_lut_data);
+
+        Some(Ibl {
+            irradiance: Cubemap::from_raw(vulkan, CUBEMAP_HDR_FORMAT, IRRADIANCE_SIZE, 1, irradiance),
+            prefiltered: Cubemap::from_raw(vulkan, CUBEMAP_HDR_FORMAT, PREFILTERED_SIZE, PREFILTERED_MIPS, prefiltered),
+            brdf_lut,
+            brdf_lut_view,
+            sampler: ibl_sampler(vulkan),
+        })
+    }
+}
+
+fn allocate_brdf_lut(vulkan: &Vulkan, usage: ImageUsage) -> (Image, VkDestroy<VkImageView>) {
+    let image = vulkan.pool().allocate_image(BRDF_LUT_FORMAT, VkImageType::IT_2D, false, 1, 1,
+                                             VkExtent3D { width: BRDF_LUT_SIZE, height: BRDF_LUT_SIZE, depth: 1 },
+                                             VkSampleCountFlags::SC_1_BIT, usage);
+    let view = vulkan.create_image_view(&image.image, VkImageViewType::IVT_2D, BRDF_LUT_FORMAT, VkImageAspectFlags::COLOR_BIT);
+    (image, VkDestroy::new(view, vulkan))
+}
+
+fn ibl_sampler(vulkan: &Vulkan) -> VkDestroy<VkSampler> {
+    VkDestroy::new(vulkan.create_sampler(SamplerInfo {
+        min_filter: VkFilter::LINEAR,
+        mag_filter: VkFilter::LINEAR,
+        mipmap_mode: VkSamplerMipmapMode::LINEAR,
+        address_mode_u: VkSamplerAddressMode::CLAMP_TO_EDGE,
+        address_mode_v: VkSamplerAddressMode::CLAMP_TO_EDGE,
+        address_mode_w: VkSamplerAddressMode::CLAMP_TO_EDGE,
+        max_lod: PREFILTERED_MIPS as f32,
+        ..Default::default()
+    }), vulkan)
+}
+
+fn image_size(size: u32, layers: u32, mips: u32, texel_size: usize) -> usize {
+    (0..mips).map(|mip| ((size >> mip).max(1) as usize).pow(2) * layers as usize * texel_size).sum()
+}
+
+/// Inverse of `upload_image`, expects and leaves the image in SHADER_READ_ONLY_OPTIMAL
+fn download_image(vulkan: &Vulkan, image: VkImage, size: u32, layers: u32, mips: u32, texel_size: usize) -> Vec<u8> {
+    let total_size = image_size(size, layers, mips, texel_size);
+    let alloc_info = VmaAllocationCreateInfo {
+        usage: VmaMemoryUsage::AUTO_PREFER_HOST,
+        flags: VmaAllocationCreateFlagBits::HOST_ACCESS_RANDOM_BIT,
+        requiredFlags: VkMemoryPropertyFlagBits::HOST_VISIBLE_BIT
+            | VkMemoryPropertyFlagBits::HOST_COHERENT_BIT,
+        ..Default::default()
+    };
+    let mut readback = vulkan.pool().allocate_buffer(total_size as u64, BufferUsage::default().transfer_dst(true), alloc_info);
+    let pointer = readback.map_memory(vulkan);
+
+    let mut offset = 0;
+    let regions = (0..mips).map(|mip| {
+        let mip_size = (size >> mip).max(1);
+        let region = VkBufferImageCopy {
+            bufferOffset: offset as VkDeviceSize,
+            bufferRowLength: 0,
+            bufferImageHeight: 0,
+            imageSubresource: VkImageSubresourceLayers {
+                aspectMask: VkImageAspectFlags::COLOR_BIT,
+                mipLevel: mip,
+                baseArrayLayer: 0,
+                layerCount: layers,
+            },
+            imageOffset: Default::default(),
+            imageExtent: VkExtent3D { width: mip_size, height: mip_size, depth: 1 },
+        };
+        offset += image_size(mip_size, layers, 1, texel_size);
+        region
+    }).collect::<Vec<_>>();
+
+    vulkan.immediate_submit(|command_buffer| {
+        vulkan.transition_images2(vec![ImageTransition2 {
+            image,
+            src_stage: VkPipelineStageFlags2::FRAGMENT_SHADER_BIT,
+            dst_stage: VkPipelineStageFlags2::COPY_BIT,
+            src_access: VkAccessFlags2::SHADER_SAMPLED_READ_BIT,
+            dst_access: VkAccessFlags2::TRANSFER_READ_BIT,
+            old_layout: VkImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            new_layout: VkImageLayout::TRANSFER_SRC_OPTIMAL,
+            ..Default::default()
+        }], command_buffer);
+
+        vulkan.image_to_buffer(regions, command_buffer, image, *readback, VkImageLayout::TRANSFER_SRC_OPTIMAL);
+
+        vulkan.transition_resources(vec![BufferTransition {
+            buffer: *readback,
+            src_stage: VkPipelineStageFlags2::COPY_BIT,
+            dst_stage: VkPipelineStageFlags2::HOST_BIT,
+            src_access: VkAccessFlags2::TRANSFER_WRITE_BIT,
+            dst_access: VkAccessFlags2::HOST_READ_BIT,
+            src_queue_family: VK_QUEUE_FAMILY_IGNORED,
+            dst_queue_family: VK_QUEUE_FAMILY_IGNORED,
+            ..Default::default()
+        }], vec![ImageTransition2 {
+            image,
+            src_stage: VkPipelineStageFlags2::COPY_BIT,
+            dst_stage: VkPipelineStageFlags2::FRAGMENT_SHADER_BIT,
+            src_access: VkAccessFlags2::TRANSFER_READ_BIT,
+            dst_access: VkAccessFlags2::SHADER_SAMPLED_READ_BIT,
+            old_layout: VkImageLayout::TRANSFER_SRC_OPTIMAL,
+            new_layout: VkImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ..Default::default()
+        }], command_buffer);
+    });
+
+    let mut data = vec![0u8; total_size];
+    Vulkan::copy_info(data.as_mut_ptr() as *mut c_void, pointer as *const u8, total_size);
+    data
+}
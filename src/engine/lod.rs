@@ -0,0 +1,166 @@
+use crate::engine::bindless::TextureSlot;
+use crate::engine::camera::Camera;
+use crate::engine::compute::ComputePass;
+use crate::prelude::*;
+use crate::vulkan::func::Vulkan;
+use crate::vulkan::gltf::scene::Scene;
+use shaders::common::{LodParams, LOD_WORKGROUP_SIZE, NO_TEXTURE};
+use std::collections::{HashMap, HashSet};
+use std::ptr::null_mut;
+
+/// Simplified levels generated while a scene is imported, for meshes the file has no `MSFT_lod` levels for.
+/// Levels live only in memory and are generated again on every load, ship them as `MSFT_lod` to skip that
+#[derive(Clone, Copy, Debug)]
+pub struct LodGeneration {
+    /// Levels added below the base mesh, zero keeps meshes as they are
+    pub levels: u32,
+    /// Cells per axis of the clustering grid of the first generated level, halved for every further one
+    pub grid: u32,
+    /// Coverage below which the first generated level takes over, halved for every further one
+    pub screen_coverage: f32,
+}
+
+impl Default for LodGeneration {
+    fn default() -> Self {
+        LodGeneration {
+            levels: 0,
+            grid: 32,
+            screen_coverage: 0.25,
+        }
+    }
+}
+
+impl LodGeneration {
+    /// Smallest coverage of `level`, the base mesh is level zero and the last level is drawn at any distance
+    pub fn screen_coverage(&self, level: u32, level_count: u32) -> f32 {
+        if level + 1 >= level_count {
+            0.0
+        } else {
+            self.screen_coverage / (1 << level) as f32
+        }
+    }
+
+    pub fn grid(&self, level: u32) -> u32 {
+        (self.grid >> (level - 1)).max(2)
+    }
+}
+
+/// Vertex clustering: vertices inside one cell of a `grid`³ lattice over the bounds merge into the first of them.
+/// Returned indices point into the same vertices, triangles collapsing to a line or point are dropped
+pub fn simplify_clusters(positions: &[[f32; 3]], indices: &[u16], grid: u32) -> Vec<u16> {
+    let (min, max) = bounds(positions);
+    let cell = [0, 1, 2].map(|axis| ((max[axis] - min[axis]) / grid as f32).max(f32::EPSILON));
+    let mut representatives: HashMap<[u32; 3], u16> = HashMap::new();
+    let remap = positions.iter().enumerate().map(|(index, position)| {
+        let key = [0, 1, 2].map(|axis| (((position[axis] - min[axis]) / cell[axis]) as u32).min(grid - 1));
+        *representatives.entry(key).or_insert(index as u16)
+    }).collect::<Vec<_>>();
+
+    let mut seen = HashSet::new();
+    let mut simplified = Vec::with_capacity(indices.len() / 2);
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|corner| remap[triangle[corner] as usize]);
+        if a == b || b == c || a == c {
+            continue;
+        }
+        let mut key = [a, b, c];
+        key.sort_unstable();
+        if seen.insert(key) {
+            simplified.extend_from_slice(&[a, b, c]);
+        }
+    }
+    simplified
+}
+
+/// Axis aligned bounds of `positions`
+pub fn bounds(positions: &[[f32; 3]]) -> ([f32; 3], [f32; 3]) {
+    positions.iter().fold(([f32::MAX; 3], [f32::MIN; 3]), |(min, max), position| {
+        ([0, 1, 2].map(|axis| min[axis].min(position[axis])), [0, 1, 2].map(|axis| max[axis].max(position[axis])))
+    })
+}
+
+/// Picks the detail level of every scene draw per camera on the GPU. Each camera has its own slice of
+/// `Scene::indirect_buffer`, `Scene::render_scene` draws from the slice of the camera it is given
+#[derive(Default)]
+pub struct LodSelector {
+    /// Disabled selection draws every mesh at full detail
+    pub enabled: bool,
+    /// Multiplies screen coverage, above one keeps detail longer
+    pub bias: f32,
+
+    pass: ComputePass,
+    descriptor_set: VkDescriptorSet,
+    descriptor_pool: VkDestroy<VkDescriptorPool>,
+    descriptor_layout: VkDestroy<VkDescriptorSetLayout>,
+}
+
+impl LodSelector {
+    pub fn new(vulkan: &Vulkan) -> Self {
+        let storage = |binding: u32| VkDescriptorSetLayoutBinding {
+            binding,
+            descriptorType: VkDescriptorType::STORAGE_BUFFER,
+            descriptorCount: 1,
+            stageFlags: VkShaderStageFlags::COMPUTE_BIT,
+            pImmutableSamplers: null_mut(),
+        };
+        let descriptor_layout = vulkan.create_descriptor_set_layout(&[storage(0), storage(1)]);
+        let descriptor_pool = vulkan.create_descriptor_pool(&[VkDescriptorPoolSize {
+            descriptorType: VkDescriptorType::STORAGE_BUFFER,
+            descriptorCount: 2,
+        }], 1, false);
+        let descriptor_set = vulkan.allocate_descriptor_sets(descriptor_pool, &[descriptor_layout])[0];
+        let pass = ComputePass::new::<LodParams>(vulkan, "lod_select", &[descriptor_layout], [LOD_WORKGROUP_SIZE, 1, 1]);
+
+        LodSelector {
+            enabled: true,
+            bias: 1.0,
+            pass,
+            descriptor_set,
+            descriptor_pool: VkDestroy::new(descriptor_pool, vulkan),
+            descriptor_layout: VkDestroy::new(descriptor_layout, vulkan),
+        }
+    }
+
+    /// Points the kernel at the LOD records and draw commands of `scene`, needed again for every new scene
+    pub fn bind_scene(&self, vulkan: &Vulkan, scene: &Scene) {
+        let buffers = [(0, *scene.lod_ssbo.get()), (1, *scene.indirect_buffer.get())];
+        vulkan.update_descriptor_sets(vec![], buffers.into_iter().map(|(binding, buffer)| BufferDescriptorInfo {
+            target_descriptor: DescriptorSetInfo {
+                descriptor_set: self.descriptor_set,
+                descriptor_binding: binding,
+                array_element: 0,
+            },
+            target_descriptor_type: VkDescriptorType::STORAGE_BUFFER,
+            buffer_infos: vec![VkDescriptorBufferInfo {
+                buffer,
+                offset: 0,
+                range: VK_WHOLE_SIZE,
+            }],
+        }).collect(), vec![], vec![]);
+    }
+
+    /// Recreates the kernel from a rebuilt `compute` crate
+    pub fn reload_compute(&mut self, vulkan: &Vulkan, spirv: &[u8]) {
+        self.pass.reload(vulkan, spirv);
+    }
+
+    /// Writes the draw commands of camera slice `slice`, call once per camera before the scene is drawn.
+    /// Draws whose material samples `target` are skipped, offscreen cameras pass the slot of their own image
+    pub fn record(&self, vulkan: &Vulkan, command_buffer: VkCommandBuffer, scene: &Scene, slice: usize, camera: &Camera, target: Option<TextureSlot>) {
+        let draw_count = scene.draw_count();
+        if draw_count == 0 {
+            return;
+        }
+        let params = LodParams {
+            camera_position: camera.position.into(),
+            projection_scale: (camera.fov * 0.5).to_radians().tan().recip(),
+            draw_count,
+            first_output: slice as u32 * draw_count,
+            bias: if self.enabled { self.bias } else { f32::MAX },
+            excluded_texture: target.map_or(NO_TEXTURE, |slot| slot.0),
+        };
+        self.pass.bind(vulkan, command_buffer, &[self.descriptor_set]);
+        self.pass.push_constants(vulkan, command_buffer, &params);
+        self.pass.dispatch_threads(vulkan, command_buffer, [draw_count, 1, 1]);
+    }
+}
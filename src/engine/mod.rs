@@ -2,6 +2,7 @@ mod engine;
 mod delta;
 mod app;
 pub mod pipelines;
+pub mod pipeline_registry;
 pub mod caches;
 pub mod camera;
 pub mod shapes;
@@ -9,6 +10,21 @@ pub mod fps;
 pub mod utils;
 pub mod buffers;
 pub mod gui_renderer;
+pub mod render_graph;
+pub mod post;
+pub mod headless;
+pub mod skybox;
+pub mod ibl;
+pub mod debug_draw;
+pub mod debug_view;
+pub mod compute;
+pub mod particles;
+pub mod bindless;
+pub mod permutations;
+pub mod cameras;
+pub mod lod;
+#[cfg(feature = "hot_reload")]
+pub mod hot_reload;
 
 pub use app::*;
 pub use delta::*;
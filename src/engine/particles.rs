@@ -0,0 +1,427 @@
+use crate::engine::camera::Camera;
+use crate::engine::compute::{buffer_barrier, ComputePass};
+use crate::engine::pipelines::create_pipelines_multithreaded;
+use crate::engine::post::HDR_FORMAT;
+use crate::engine::render_graph::ResourceUsage;
+use crate::prelude::pool_alloc::Buffer;
+use crate::prelude::*;
+use crate::vulkan::func::Vulkan;
+use crate::vulkan::gltf::scene::Scene;
+use crate::vulkan::utils::BufferUsage;
+use shaders::common::{EmitterParams, FillParams, Particle, ParticleDrawParams, FILL_WORKGROUP_SIZE, PARTICLE_DRAW_WORDS, PARTICLE_WORKGROUP_SIZE};
+use std::ptr::null_mut;
+use ultraviolet::{Mat4, Vec3};
+
+pub const MAX_EMITTERS: usize = 64;
+
+/// Spawn and look parameters of one emitter. Position and velocity follow the attached node, gravity stays in world space
+#[derive(Clone, Copy, Debug)]
+pub struct Emitter {
+    /// Index into `Scene::node_matrices`, `None` places the emitter in world space
+    pub node: Option<usize>,
+    pub offset: Vec3,
+    /// Particles per second
+    pub spawn_rate: f32,
+    /// Seconds, each particle lives up to a quarter less
+    pub lifetime: f32,
+    pub velocity: Vec3,
+    /// Random offset added to `velocity` per axis
+    pub spread: f32,
+    pub gravity: Vec3,
+    /// Linear RGBA at spawn and at the end of the lifetime
+    pub color_start: [f32; 4],
+    pub color_end: [f32; 4],
+    pub size_start: f32,
+    pub size_end: f32,
+    /// Adds light instead of covering what is behind, for sparks and fire
+    pub additive: bool,
+    /// Live particles at most, reserved in the shared pool when the emitter is added.
+    /// `spawn_rate * lifetime` keeps a steady stream going
+    pub capacity: u32,
+}
+
+impl Default for Emitter {
+    fn default() -> Self {
+        Emitter {
+            node: None,
+            offset: Vec3::zero(),
+            spawn_rate: 50.0,
+            lifetime: 2.0,
+            velocity: Vec3::unit_y(),
+            spread: 0.5,
+            gravity: Vec3::new(0.0, -9.81, 0.0),
+            color_start: [1.0, 1.0, 1.0, 1.0],
+            color_end: [1.0, 1.0, 1.0, 0.0],
+            size_start: 0.1,
+            size_end: 0.1,
+            additive: false,
+            capacity: 128,
+        }
+    }
+}
+
+impl Emitter {
+    /// Short lived bright streaks thrown upwards
+    pub fn sparks() -> Self {
+        Emitter {
+            spawn_rate: 400.0,
+            lifetime: 0.6,
+            velocity: Vec3::new(0.0, 4.0, 0.0),
+            spread: 2.5,
+            color_start: [4.0, 2.4, 0.8, 1.0],
+            color_end: [1.0, 0.2, 0.0, 0.0],
+            size_start: 0.04,
+            size_end: 0.01,
+            additive: true,
+            capacity: 256,
+            ..Default::default()
+        }
+    }
+
+    /// Slow rising puffs growing over time
+    pub fn smoke() -> Self {
+        Emitter {
+            spawn_rate: 20.0,
+            lifetime: 5.0,
+            velocity: Vec3::new(0.0, 0.6, 0.0),
+            spread: 0.2,
+            gravity: Vec3::new(0.0, 0.1, 0.0),
+            color_start: [0.3, 0.3, 0.3, 0.6],
+            color_end: [0.5, 0.5, 0.5, 0.0],
+            size_start: 0.3,
+            size_end: 1.5,
+            capacity: 128,
+            ..Default::default()
+        }
+    }
+
+    /// Falling drops spread over `area` around the emitter
+    pub fn rain(area: f32) -> Self {
+        Emitter {
+            spawn_rate: 2000.0,
+            lifetime: 1.5,
+            velocity: Vec3::new(0.0, -8.0, 0.0),
+            spread: area,
+            gravity: Vec3::new(0.0, -4.0, 0.0),
+            color_start: [0.6, 0.7, 0.8, 0.5],
+            color_end: [0.6, 0.7, 0.8, 0.5],
+            size_start: 0.02,
+            size_end: 0.02,
+            capacity: 4096,
+            ..Default::default()
+        }
+    }
+}
+
+struct EmitterSlot {
+    emitter: Emitter,
+    first: u32,
+    capacity: u32,
+    accumulator: f32,
+    burst: u32,
+    params: EmitterParams,
+}
+
+/// Compute simulated particles drawn as camera facing billboards with one indirect draw.
+/// All emitters share one pool, each simulates its own slice of it
+#[derive(Default)]
+pub struct ParticleSystem {
+    pub enabled: bool,
+    /// View space distance over which particles fade into geometry behind them, zero clips them hard
+    pub softness: f32,
+
+    emitters: Vec<Option<EmitterSlot>>,
+    capacity: u32,
+    frame: u32,
+
+    particles: Buffer,
+    alive: Buffer,
+    counters: Buffer,
+
+    reset_pass: ComputePass,
+    simulate_pass: ComputePass,
+    multisampled_depth: bool,
+    pipeline: VkDestroy<VkPipeline>,
+    layout: VkDestroy<VkPipelineLayout>,
+    descriptor_set: VkDescriptorSet,
+    descriptor_pool: VkDestroy<VkDescriptorPool>,
+    descriptor_layout: VkDestroy<VkDescriptorSetLayout>,
+}
+
+impl ParticleSystem {
+    /// `template` is the base pipeline, particles draw into the resolved HDR target and sample the depth target with `samples`
+    pub fn new(vulkan: &Vulkan, template: GraphicsPipelineCreateInfo, samples: VkSampleCountFlags, capacity: u32) -> Self {
+        let storage = |binding: u32, stage_flags: VkShaderStageFlags| VkDescriptorSetLayoutBinding {
+            binding,
+            descriptorType: VkDescriptorType::STORAGE_BUFFER,
+            descriptorCount: 1,
+            stageFlags: stage_flags,
+            pImmutableSamplers: null_mut(),
+        };
+        let bindings = [
+            storage(0, VkShaderStageFlags::COMPUTE_BIT | VkShaderStageFlags::VERTEX_BIT),
+            storage(1, VkShaderStageFlags::COMPUTE_BIT | VkShaderStageFlags::VERTEX_BIT),
+            storage(2, VkShaderStageFlags::COMPUTE_BIT),
+            // scene depth, written by `update_depth` whenever the render graph is rebuilt
+            VkDescriptorSetLayoutBinding {
+                binding: 3,
+                descriptorType: VkDescriptorType::SAMPLED_IMAGE,
+                descriptorCount: 1,
+                stageFlags: VkShaderStageFlags::FRAGMENT_BIT,
+                pImmutableSamplers: null_mut(),
+            },
+        ];
+        let descriptor_layout = vulkan.create_descriptor_set_layout(&bindings);
+        let descriptor_pool = vulkan.create_descriptor_pool(&[
+            VkDescriptorPoolSize {
+                descriptorType: VkDescriptorType::STORAGE_BUFFER,
+                descriptorCount: 3,
+            },
+            VkDescriptorPoolSize {
+                descriptorType: VkDescriptorType::SAMPLED_IMAGE,
+                descriptorCount: 1,
+            },
+        ], 1, false);
+        let descriptor_set = vulkan.allocate_descriptor_sets(descriptor_pool, &[descriptor_layout])[0];
+
+        let alloc_info = VmaAllocationCreateInfo {
+            usage: VmaMemoryUsage::AUTO_PREFER_DEVICE,
+            ..Default::default()
+        };
+        let particles_size = capacity as u64 * size_of::<Particle>() as u64;
+        let counter_words = PARTICLE_DRAW_WORDS + MAX_EMITTERS as u32;
+        let particles = vulkan.pool().allocate_buffer(particles_size, BufferUsage::default().storage_buffer(true), alloc_info);
+        let alive = vulkan.pool().allocate_buffer(capacity as u64 * size_of::<u32>() as u64, BufferUsage::default().storage_buffer(true), alloc_info);
+        let counters = vulkan.pool().allocate_buffer(counter_words as u64 * size_of::<u32>() as u64,
+                                                     BufferUsage::default().storage_buffer(true).indirect_buffer(true), alloc_info);
+
+        let buffers = [(0, *particles), (1, *alive), (2, *counters)];
+        vulkan.update_descriptor_sets(vec![], buffers.into_iter().map(|(binding, buffer)| BufferDescriptorInfo {
+            target_descriptor: DescriptorSetInfo {
+                descriptor_set,
+                descriptor_binding: binding,
+                array_element: 0,
+            },
+            target_descriptor_type: VkDescriptorType::STORAGE_BUFFER,
+            buffer_infos: vec![VkDescriptorBufferInfo {
+                buffer,
+                offset: 0,
+                range: VK_WHOLE_SIZE,
+            }],
+        }).collect(), vec![], vec![]);
+
+        let fill_pass = ComputePass::new::<FillParams>(vulkan, "fill", &[descriptor_layout], [FILL_WORKGROUP_SIZE, 1, 1]);
+        let reset_pass = ComputePass::new::<FillParams>(vulkan, "particles_reset", &[descriptor_layout], [PARTICLE_WORKGROUP_SIZE, 1, 1]);
+        let simulate_pass = ComputePass::new::<EmitterParams>(vulkan, "particles_simulate", &[descriptor_layout], [PARTICLE_WORKGROUP_SIZE, 1, 1]);
+
+        // zero age and lifetime marks every slot as dead
+        let words = (particles_size / size_of::<u32>() as u64) as u32;
+        vulkan.immediate_submit(|command_buffer| {
+            fill_pass.bind(vulkan, command_buffer, &[descriptor_set]);
+            fill_pass.push_constants(vulkan, command_buffer, &FillParams { value: 0, count: words });
+            fill_pass.dispatch_threads(vulkan, command_buffer, [words, 1, 1]);
+        });
+
+        let push_constant_ranges = [push_constant_range::<ParticleDrawParams>()];
+        let fragment_entry = if samples != VkSampleCountFlags::SC_1_BIT { "particles_msaa" } else { "particles" };
+        graphics_interface("particles", fragment_entry).check_layout("particles", &[&bindings], &push_constant_ranges);
+        let layout = vulkan.create_pipeline_layout_with::<ParticleDrawParams>(&[descriptor_layout]);
+        let multisampled_depth = samples != VkSampleCountFlags::SC_1_BIT;
+        let pipeline = create_pipelines_multithreaded(true, vec![preset_particles(template, layout, HDR_FORMAT, multisampled_depth)], vulkan)[0];
+
+        ParticleSystem {
+            enabled: true,
+            softness: 0.5,
+            emitters: vec![],
+            capacity,
+            frame: 0,
+            particles,
+            alive,
+            counters,
+            reset_pass,
+            simulate_pass,
+            multisampled_depth,
+            pipeline: VkDestroy::new(pipeline, vulkan),
+            layout: VkDestroy::new(layout, vulkan),
+            descriptor_set,
+            descriptor_pool: VkDestroy::new(descriptor_pool, vulkan),
+            descriptor_layout: VkDestroy::new(descriptor_layout, vulkan),
+        }
+    }
+
+    /// Recreates the billboard pipeline from a `template` with new shader modules
+    pub fn rebuild_pipeline(&mut self, vulkan: &Vulkan, template: GraphicsPipelineCreateInfo) {
+        let info = preset_particles(template, *self.layout, HDR_FORMAT, self.multisampled_depth);
+        let pipeline = create_pipelines_multithreaded(true, vec![info], vulkan)[0];
+        self.pipeline = VkDestroy::new(pipeline, vulkan);
+    }
+
+    /// Recreates the simulation kernels from a rebuilt `compute` crate
+    pub fn reload_compute(&mut self, vulkan: &Vulkan, spirv: &[u8]) {
+        self.reset_pass.reload(vulkan, spirv);
+        self.simulate_pass.reload(vulkan, spirv);
+    }
+
+    /// Particle pool, alive list and counters with the indirect draw command, for render graph imports
+    pub fn buffers(&self) -> [VkBuffer; 3] {
+        [*self.particles, *self.alive, *self.counters]
+    }
+
+    /// Reserves `emitter.capacity` slots of the pool, `None` when no gap is large enough or all `MAX_EMITTERS` are in use
+    pub fn add_emitter(&mut self, emitter: Emitter) -> Option<usize> {
+        let mut ranges = self.emitters.iter().flatten()
+            .map(|slot| (slot.first, slot.first + slot.capacity))
+            .collect::<Vec<_>>();
+        ranges.sort_unstable();
+
+        let mut first = 0;
+        for (start, end) in ranges {
+            if start - first >= emitter.capacity {
+                break;
+            }
+            first = end;
+        }
+        if first + emitter.capacity > self.capacity {
+            return None;
+        }
+
+        let slot = EmitterSlot {
+            emitter,
+            first,
+            capacity: emitter.capacity,
+            accumulator: 0.0,
+            burst: 0,
+            params: EmitterParams::default(),
+        };
+        match self.emitters.iter().position(|slot| slot.is_none()) {
+            Some(index) => {
+                self.emitters[index] = Some(slot);
+                Some(index)
+            }
+            None if self.emitters.len() < MAX_EMITTERS => {
+                self.emitters.push(Some(slot));
+                Some(self.emitters.len() - 1)
+            }
+            None => None,
+        }
+    }
+
+    /// Live particles of the emitter vanish, its slice is reused by later emitters
+    pub fn remove_emitter(&mut self, id: usize) {
+        self.emitters[id] = None;
+    }
+
+    /// Changes of `capacity` are ignored, it is fixed once the emitter is added
+    pub fn emitter_mut(&mut self, id: usize) -> &mut Emitter {
+        &mut self.emitters[id].as_mut().expect("Tried to get removed emitter").emitter
+    }
+
+    /// Spawns `count` particles on the next update on top of the spawn rate
+    pub fn burst(&mut self, id: usize, count: u32) {
+        self.emitters[id].as_mut().expect("Tried to burst removed emitter").burst += count;
+    }
+
+    /// Resolves node transforms and spawn counts for the next simulation
+    pub fn update(&mut self, delta_time: f32, scene: &Scene) {
+        self.frame = self.frame.wrapping_add(1);
+        for (index, slot) in self.emitters.iter_mut().enumerate() {
+            let Some(slot) = slot else { continue };
+            let emitter = &slot.emitter;
+
+            let transform = emitter.node
+                .and_then(|node| scene.node_matrices.get(node).copied())
+                .unwrap_or(Mat4::identity());
+            let position = transform.transform_point3(emitter.offset);
+            let velocity = transform.transform_vec3(emitter.velocity);
+
+            slot.accumulator += emitter.spawn_rate * delta_time;
+            let spawn_count = slot.accumulator as u32 + std::mem::take(&mut slot.burst);
+            slot.accumulator = slot.accumulator.fract();
+
+            slot.params = EmitterParams {
+                position: [position.x, position.y, position.z],
+                spawn_count,
+                velocity: [velocity.x, velocity.y, velocity.z],
+                spread: emitter.spread,
+                gravity: [emitter.gravity.x, emitter.gravity.y, emitter.gravity.z],
+                delta_time,
+                color_start: emitter.color_start,
+                color_end: emitter.color_end,
+                size_start: emitter.size_start,
+                size_end: emitter.size_end,
+                lifetime: emitter.lifetime,
+                seed: self.frame.wrapping_mul(0x9E37_79B9) ^ index as u32,
+                first: slot.first,
+                capacity: slot.capacity,
+                emitter: index as u32,
+                additive: emitter.additive as u32,
+            };
+        }
+    }
+
+    /// Points the fragment stage at the depth target of the scene pass, device must be idle
+    pub fn update_depth(&self, vulkan: &Vulkan, depth_view: VkImageView) {
+        vulkan.update_descriptor_sets(vec![ImageDescriptorInfo {
+            target_descriptor: DescriptorSetInfo {
+                descriptor_set: self.descriptor_set,
+                descriptor_binding: 3,
+                array_element: 0,
+            },
+            target_descriptor_type: VkDescriptorType::SAMPLED_IMAGE,
+            image_infos: vec![VkDescriptorImageInfo {
+                sampler: VkSampler::none(),
+                imageView: depth_view,
+                imageLayout: VkImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            }],
+        }], vec![], vec![], vec![]);
+    }
+
+    /// Resets the draw command and runs one dispatch per emitter. Dispatches only share counters, which they update atomically
+    pub fn record_simulation(&self, vulkan: &Vulkan, command_buffer: VkCommandBuffer) {
+        let counter_words = PARTICLE_DRAW_WORDS + self.emitters.len() as u32;
+        self.reset_pass.bind(vulkan, command_buffer, &[self.descriptor_set]);
+        self.reset_pass.push_constants(vulkan, command_buffer, &FillParams { value: 0, count: counter_words });
+        self.reset_pass.dispatch_threads(vulkan, command_buffer, [counter_words, 1, 1]);
+        if !self.enabled {
+            return;
+        }
+
+        let compute = ResourceUsage::StorageWrite(VkPipelineStageFlags2::COMPUTE_SHADER_BIT);
+        self.reset_pass.barrier(vulkan, command_buffer, vec![buffer_barrier(*self.counters, compute, compute)], vec![]);
+
+        self.simulate_pass.bind(vulkan, command_buffer, &[self.descriptor_set]);
+        for slot in self.emitters.iter().flatten() {
+            self.simulate_pass.push_constants(vulkan, command_buffer, &slot.params);
+            self.simulate_pass.dispatch_threads(vulkan, command_buffer, [slot.capacity, 1, 1]);
+        }
+    }
+
+    /// Draws every live particle into the bound HDR target, `viewport` has to be the one of the scene pass
+    pub fn record_draw(&self, vulkan: &Vulkan, command_buffer: VkCommandBuffer, camera: &mut Camera, viewport: VkViewport, scissor: VkRect2D) {
+        if !self.enabled {
+            return;
+        }
+
+        let projection = camera.projection_matrix();
+        let view_proj: Mat4 = projection * camera.view_matrix();
+        let right = camera.right_direction();
+        let up = camera.up_direction();
+        let params = ParticleDrawParams {
+            view_proj: view_proj.cols.map(|col| [col.x, col.y, col.z, col.w]),
+            camera_right: [right.x, right.y, right.z],
+            softness: self.softness,
+            camera_up: [up.x, up.y, up.z],
+            _pad: 0.0,
+            depth_params: [projection.cols[2].z, projection.cols[3].z, viewport.minDepth, viewport.maxDepth],
+        };
+
+        vulkan.bind_pipeline(command_buffer, VkPipelineBindPoint::GRAPHICS, *self.pipeline);
+        vulkan.bind_descriptor_sets(command_buffer, VkPipelineBindPoint::GRAPHICS, *self.layout, 0, &[self.descriptor_set], &[]);
+        unsafe {
+            vkCmdSetViewport(command_buffer, 0, 1, &viewport);
+            vkCmdSetScissor(command_buffer, 0, 1, &scissor);
+        }
+        vulkan.push_constants(command_buffer, *self.layout, &params);
+        vulkan.draw_indirect(command_buffer, *self.counters, 0, 1, size_of::<VkDrawIndirectCommand>() as u32);
+    }
+}
@@ -0,0 +1,151 @@
+use crate::engine::pipeline_registry::{PipelineKey, PipelineRegistry, SharedPipeline};
+use crate::prelude::*;
+use crate::vulkan::func::Vulkan;
+use shaders::common::{FEATURE_ALPHA_TEST, FEATURE_COUNT, FEATURE_NORMAL_MAP};
+use std::collections::HashMap;
+
+/// Feature set of a scene draw, bits are the `FEATURE_*` constants of the common crate
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ShaderFeatures(pub u32);
+
+impl ShaderFeatures {
+    pub const NONE: ShaderFeatures = ShaderFeatures(0);
+
+    pub fn contains(self, feature: u32) -> bool {
+        self.0 & feature == feature
+    }
+
+    pub fn with(self, feature: u32, enabled: bool) -> ShaderFeatures {
+        if enabled { ShaderFeatures(self.0 | feature) } else { self }
+    }
+
+    /// One 32-bit boolean constant per feature, the constant id is the bit index
+    pub fn specialization_info(self) -> SpecializationInfo {
+        let constants = (0..FEATURE_COUNT).map(|id| (self.0 >> id) & 1).collect::<Vec<_>>();
+        SpecializationInfo::from_u32s(&constants)
+    }
+
+    pub fn label(self) -> String {
+        let names = [
+            (FEATURE_ALPHA_TEST, "alpha test"),
+            (FEATURE_NORMAL_MAP, "normal map"),
+        ];
+        let enabled = names.iter()
+            .filter(|(feature, _)| self.contains(*feature))
+            .map(|(_, name)| *name)
+            .collect::<Vec<_>>();
+        if enabled.is_empty() { "base".to_string() } else { enabled.join(", ") }
+    }
+}
+
+/// Scene pipelines, one per requested feature set, all specialized from the same template.
+/// Creation goes through the pipeline registry, identical permutations of other caches share their pipelines.
+/// Permutations prepared with `prepare_async` are drawn with the base permutation until they are ready
+#[derive(Default)]
+pub struct PermutationCache {
+    template: Option<GraphicsPipelineCreateInfo>,
+    pipelines: HashMap<ShaderFeatures, SharedPipeline>,
+    pending: HashMap<ShaderFeatures, PipelineKey>,
+}
+
+impl PermutationCache {
+    pub fn new(template: GraphicsPipelineCreateInfo) -> Self {
+        PermutationCache {
+            template: Some(template),
+            ..Default::default()
+        }
+    }
+
+    /// Drops every permutation, the next `prepare` creates them from `template`
+    pub fn set_template(&mut self, template: GraphicsPipelineCreateInfo) {
+        self.pipelines.clear();
+        self.pending.clear();
+        self.template = Some(template);
+    }
+
+    /// Creates the missing permutations in one batch
+    pub fn prepare(&mut self, vulkan: &Vulkan, registry: &mut PipelineRegistry, requested: impl IntoIterator<Item = ShaderFeatures>) {
+        let missing = self.missing(requested);
+        if missing.is_empty() {
+            return;
+        }
+
+        let infos = missing.iter().map(|features| self.create_info(*features)).collect();
+        let pipelines = registry.get_or_create(vulkan, infos);
+        for (features, pipeline) in missing.into_iter().zip(pipelines) {
+            self.pending.remove(&features);
+            self.pipelines.insert(features, pipeline);
+        }
+    }
+
+    /// Compiles the missing permutations in the background, `poll` picks them up
+    pub fn prepare_async(&mut self, vulkan: &Vulkan, registry: &mut PipelineRegistry, requested: impl IntoIterator<Item = ShaderFeatures>) {
+        let missing = self.missing(requested)
+            .into_iter()
+            .filter(|features| !self.pending.contains_key(features))
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return;
+        }
+
+        let infos = missing.iter().map(|features| self.create_info(*features)).collect();
+        let keys = registry.request(vulkan, infos);
+        self.pending.extend(missing.into_iter().zip(keys));
+        self.poll(registry);
+    }
+
+    /// Takes the permutations the registry finished since the last call, `registry` has to be polled before
+    pub fn poll(&mut self, registry: &PipelineRegistry) {
+        self.pending.retain(|features, key| match registry.get(key) {
+            Some(pipeline) => {
+                self.pipelines.insert(*features, pipeline);
+                false
+            }
+            None => true,
+        });
+    }
+
+    /// Panics when `features` was not prepared
+    pub fn get(&self, features: ShaderFeatures) -> VkPipeline {
+        match self.pipelines.get(&features) {
+            Some(pipeline) => ***pipeline,
+            None => panic!("Scene pipeline for {} was not prepared", features.label()),
+        }
+    }
+
+    /// Pipeline for `features`, the base permutation while it still compiles. `None` when neither is ready, the draw is skipped then
+    pub fn resolve(&self, features: ShaderFeatures) -> Option<VkPipeline> {
+        self.pipelines.get(&features)
+            .or_else(|| self.pipelines.get(&ShaderFeatures::NONE))
+            .map(|pipeline| ***pipeline)
+    }
+
+    pub fn is_pending(&self, features: ShaderFeatures) -> bool {
+        self.pending.contains_key(&features)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pipelines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pipelines.is_empty()
+    }
+
+    fn missing(&self, requested: impl IntoIterator<Item = ShaderFeatures>) -> Vec<ShaderFeatures> {
+        let mut missing = requested.into_iter()
+            .filter(|features| !self.pipelines.contains_key(features))
+            .collect::<Vec<_>>();
+        missing.sort();
+        missing.dedup();
+        missing
+    }
+
+    fn create_info(&self, features: ShaderFeatures) -> GraphicsPipelineCreateInfo {
+        let mut info = self.template.clone().expect("Permutation cache has no template");
+        for stage in &mut info.stages {
+            stage.specialization_info = Some(features.specialization_info());
+        }
+        info
+    }
+}
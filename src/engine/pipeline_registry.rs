@@ -0,0 +1,324 @@
+use crate::engine::pipelines::{create_pipelines_async, create_pipelines_multithreaded, PendingPipelines};
+use crate::prelude::*;
+use crate::vulkan::func::Vulkan;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Pipeline handed out by the registry, destroyed once the registry evicts it and the last clone is dropped
+pub type SharedPipeline = Arc<VkDestroy<VkPipeline>>;
+
+/// Identifies a create info in the registry, see `PipelineRegistry::key`
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineKey(Vec<u8>);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PipelineRegistryStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub live: usize,
+    /// Pipelines still compiling in the background
+    pub pending: usize,
+}
+
+/// Graphics pipelines deduplicated by their full create info: shader modules and entries, specialization data, fixed function states,
+/// layout and render pass or attachment formats. Identical infos share one `VkPipeline`.
+/// `request` compiles in the background instead, the pipelines show up after a later `poll`. Create with `new`, the default has no device
+#[derive(Default)]
+pub struct PipelineRegistry {
+    pipelines: HashMap<PipelineKey, SharedPipeline>,
+    pending: Vec<(Vec<PipelineKey>, PendingPipelines)>,
+    stats: PipelineRegistryStats,
+    vulkan: Vulkan,
+}
+
+impl Drop for PipelineRegistry {
+    fn drop(&mut self) {
+        for (_, pending) in self.pending.drain(..) {
+            pending.wait().into_iter().for_each(|pipeline| pipeline.destroy(&self.vulkan));
+        }
+    }
+}
+
+impl PipelineRegistry {
+    pub fn new(vulkan: &Vulkan) -> Self {
+        PipelineRegistry {
+            vulkan: vulkan.clone(),
+            ..Default::default()
+        }
+    }
+
+    pub fn key(info: &GraphicsPipelineCreateInfo) -> PipelineKey {
+        PipelineKey(pipeline_key(info))
+    }
+
+    /// Pipelines for `infos` in order, the ones not seen before are created in one batch
+    pub fn get_or_create(&mut self, vulkan: &Vulkan, infos: Vec<GraphicsPipelineCreateInfo>) -> Vec<SharedPipeline> {
+        self.wait_pending();
+        let (keys, missing_keys, missing_infos) = self.split_missing(infos);
+        if !missing_infos.is_empty() {
+            let pipelines = create_pipelines_multithreaded(true, missing_infos, vulkan);
+            self.insert(missing_keys, pipelines);
+        }
+
+        keys.iter().map(|key| self.pipelines[key].clone()).collect()
+    }
+
+    /// Starts compiling the pipelines of `infos` that are neither created nor pending on a background thread.
+    /// Returns the keys to look them up with `get` once `poll` picked them up
+    pub fn request(&mut self, vulkan: &Vulkan, infos: Vec<GraphicsPipelineCreateInfo>) -> Vec<PipelineKey> {
+        let (keys, missing_keys, missing_infos) = self.split_missing(infos);
+        if !missing_infos.is_empty() {
+            self.stats.pending += missing_keys.len();
+            self.pending.push((missing_keys, create_pipelines_async(true, missing_infos, vulkan)));
+        }
+        keys
+    }
+
+    /// Moves finished background compilations into the registry, returns how many pipelines arrived
+    pub fn poll(&mut self) -> usize {
+        let mut arrived = 0;
+        let mut index = 0;
+        while index < self.pending.len() {
+            match self.pending[index].1.take() {
+                Some(pipelines) => {
+                    let (keys, _) = self.pending.swap_remove(index);
+                    arrived += keys.len();
+                    self.stats.pending -= keys.len();
+                    self.insert(keys, pipelines);
+                }
+                None => index += 1,
+            }
+        }
+        arrived
+    }
+
+    /// `None` while the pipeline is pending or was never requested
+    pub fn get(&self, key: &PipelineKey) -> Option<SharedPipeline> {
+        self.pipelines.get(key).cloned()
+    }
+
+    pub fn is_pending(&self, key: &PipelineKey) -> bool {
+        self.pending.iter().any(|(keys, _)| keys.contains(key))
+    }
+
+    /// Blocks until every background compilation is done
+    pub fn wait_pending(&mut self) {
+        for (keys, pending) in std::mem::take(&mut self.pending) {
+            self.stats.pending -= keys.len();
+            self.insert(keys, pending.wait());
+        }
+    }
+
+    pub fn get_or_create_one(&mut self, vulkan: &Vulkan, info: GraphicsPipelineCreateInfo) -> SharedPipeline {
+        self.get_or_create(vulkan, vec![info]).pop().unwrap()
+    }
+
+    /// Drops the pipelines nobody holds a handle to anymore. Device must not use them in frames still in flight
+    pub fn evict_unused(&mut self) -> usize {
+        let before = self.pipelines.len();
+        self.pipelines.retain(|_, pipeline| Arc::strong_count(pipeline) > 1);
+        let evicted = before - self.pipelines.len();
+        self.stats.evictions += evicted as u64;
+        self.stats.live = self.pipelines.len();
+        evicted
+    }
+
+    /// Forgets the pipeline of `info`, holders keep it alive until they drop their handles
+    pub fn evict(&mut self, info: &GraphicsPipelineCreateInfo) -> bool {
+        let evicted = self.pipelines.remove(&PipelineRegistry::key(info)).is_some();
+        if evicted {
+            self.stats.evictions += 1;
+            self.stats.live = self.pipelines.len();
+        }
+        evicted
+    }
+
+    pub fn clear(&mut self) {
+        self.stats.evictions += self.pipelines.len() as u64;
+        self.pipelines.clear();
+        self.stats.live = 0;
+    }
+
+    pub fn stats(&self) -> PipelineRegistryStats {
+        self.stats
+    }
+
+    pub fn len(&self) -> usize {
+        self.pipelines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pipelines.is_empty()
+    }
+
+    /// Keys of `infos` and the infos neither created nor pending, duplicates within `infos` count once
+    fn split_missing(&mut self, infos: Vec<GraphicsPipelineCreateInfo>) -> (Vec<PipelineKey>, Vec<PipelineKey>, Vec<GraphicsPipelineCreateInfo>) {
+        let keys = infos.iter().map(PipelineRegistry::key).collect::<Vec<_>>();
+
+        let mut missing_keys: Vec<PipelineKey> = Vec::new();
+        let mut missing_infos = Vec::new();
+        for (key, info) in keys.iter().zip(infos) {
+            if self.pipelines.contains_key(key) || self.is_pending(key) || missing_keys.contains(key) {
+                self.stats.hits += 1;
+            } else {
+                self.stats.misses += 1;
+                missing_keys.push(key.clone());
+                missing_infos.push(info);
+            }
+        }
+        (keys, missing_keys, missing_infos)
+    }
+
+    fn insert(&mut self, keys: Vec<PipelineKey>, pipelines: Vec<VkPipeline>) {
+        for (key, pipeline) in keys.into_iter().zip(pipelines) {
+            self.pipelines.insert(key, Arc::new(VkDestroy::new(pipeline, &self.vulkan)));
+        }
+        self.stats.live = self.pipelines.len();
+    }
+}
+
+/// Byte image of everything that ends up in `VkGraphicsPipelineCreateInfo`, equal keys create equal pipelines
+fn pipeline_key(info: &GraphicsPipelineCreateInfo) -> Vec<u8> {
+    let mut key = KeyWriter(Vec::with_capacity(512));
+    key.raw(&info.flags);
+
+    key.len(info.stages.len());
+    for stage in &info.stages {
+        key.raw(&stage.flags);
+        key.raw(&stage.stage);
+        key.raw(&stage.module);
+        key.bytes(stage.name.as_bytes());
+        key.option(&stage.specialization_info, |key, specialization| {
+            key.len(specialization.map_entries.len());
+            for entry in &specialization.map_entries {
+                // fields one by one, the struct has padding
+                key.raw(&entry.constantID);
+                key.raw(&entry.offset);
+                key.raw(&entry.size);
+            }
+            key.bytes(&specialization.data);
+        });
+    }
+    key.option(&info.vertex_input_state, |key, state| {
+        key.raw(&state.flags);
+        key.slice(&state.vertex_binding_descriptions);
+        key.slice(&state.vertex_attribute_descriptions);
+    });
+    key.option(&info.input_assembly_state, |key, state| {
+        key.raw(&state.flags);
+        key.raw(&state.topology);
+        key.raw(&state.primitive_restart_enable);
+    });
+    key.option(&info.tessellation_state, |key, state| {
+        key.raw(&state.flags);
+        key.raw(&state.patch_control_points);
+    });
+    key.option(&info.viewport_state, |key, state| {
+        key.raw(&state.flags);
+        key.slice(&state.viewports);
+        key.slice(&state.scissors);
+    });
+    key.option(&info.rasterization_state, |key, state| {
+        key.raw(&state.flags);
+        key.raw(&state.depth_clamp_enable);
+        key.raw(&state.rasterizer_discard_enable);
+        key.raw(&state.polygon_mode);
+        key.raw(&state.cull_mode);
+        key.raw(&state.front_face);
+        key.raw(&state.depth_bias_enable);
+        key.raw(&state.depth_bias_constant_factor);
+        key.raw(&state.depth_bias_clamp);
+        key.raw(&state.depth_bias_slope_factor);
+        key.raw(&state.line_width);
+    });
+    key.option(&info.multisample_state, |key, state| {
+        key.raw(&state.flags);
+        key.raw(&state.rasterization_samples);
+        key.raw(&state.sample_shading_enable);
+        key.raw(&state.min_sample_shading);
+        key.slice(&state.sample_mask);
+        key.raw(&state.alpha_to_coverage_enable);
+        key.raw(&state.alpha_to_one_enable);
+    });
+    key.option(&info.depth_stencil_state, |key, state| {
+        key.raw(&state.flags);
+        key.raw(&state.depth_test_enable);
+        key.raw(&state.depth_write_enable);
+        key.raw(&state.depth_compare_op);
+        key.raw(&state.depth_bounds_test_enable);
+        key.raw(&state.stencil_test_enable);
+        key.raw(&state.front);
+        key.raw(&state.back);
+        key.raw(&state.min_depth_bounds);
+        key.raw(&state.max_depth_bounds);
+    });
+    key.option(&info.color_blend_state, |key, state| {
+        key.raw(&state.flags);
+        key.raw(&state.logic_op_enable);
+        key.raw(&state.logic_op);
+        key.len(state.attachments.len());
+        for attachment in &state.attachments {
+            key.raw(&attachment.blend_enable);
+            key.raw(&attachment.src_color_blend_factor);
+            key.raw(&attachment.dst_color_blend_factor);
+            key.raw(&attachment.color_blend_op);
+            key.raw(&attachment.src_alpha_blend_factor);
+            key.raw(&attachment.dst_alpha_blend_factor);
+            key.raw(&attachment.alpha_blend_op);
+            key.raw(&attachment.color_write_mask);
+        }
+        key.raw(&state.blend_constants);
+    });
+    key.option(&info.dynamic_state, |key, state| {
+        key.raw(&state.flags);
+        key.slice(&state.dynamic_states);
+    });
+    key.option(&info.rendering_info, |key, rendering| {
+        key.raw(&rendering.view_mask);
+        key.slice(&rendering.color_attachment_formats);
+        key.raw(&rendering.depth_attachment_format);
+        key.raw(&rendering.stencil_attachment_format);
+    });
+    key.raw(&info.layout);
+    key.raw(&info.render_pass);
+    key.raw(&info.subpass);
+    key.raw(&info.base_pipeline_handle);
+    key.raw(&info.base_pipeline_index);
+
+    key.0
+}
+
+struct KeyWriter(Vec<u8>);
+
+impl KeyWriter {
+    /// `T` must not contain padding, Vulkan handles, enums, flags and the plain structs used here don't
+    fn raw<T: Copy>(&mut self, value: &T) {
+        let bytes = unsafe { std::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn slice<T: Copy>(&mut self, values: &[T]) {
+        self.len(values.len());
+        values.iter().for_each(|value| self.raw(value));
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.len(bytes.len());
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn len(&mut self, len: usize) {
+        self.0.extend_from_slice(&(len as u64).to_ne_bytes());
+    }
+
+    fn option<T>(&mut self, value: &Option<T>, write: impl FnOnce(&mut KeyWriter, &T)) {
+        match value {
+            Some(value) => {
+                self.0.push(1);
+                write(self, value);
+            }
+            None => self.0.push(0),
+        }
+    }
+}
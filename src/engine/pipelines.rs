@@ -1,31 +1,68 @@
-use crate::engine::caches::{build_device_info, Cache};
+use crate::engine::caches::{build_device_info, Cache, CacheStorage};
 use crate::prelude::*;
 use crate::vulkan::func::{Destructible, Vulkan};
 use prost::Message;
-use std::cmp::max;
 use std::fs;
-use std::fs::File;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
 use std::thread::available_parallelism;
 
-const FILE_PATH: &str = "cache.storage";
-const VERSION: u32 = 0;
+const FILE_NAME: &str = "cache.storage";
+const VERSION: u32 = 1;
+/// Size of `VkPipelineCacheHeaderVersionOne`
+const CACHE_HEADER_SIZE: usize = 32;
+
+static CACHE_PATH: RwLock<Option<PathBuf>> = RwLock::new(None);
+/// Keeps temporary files of concurrent cache writes apart
+static WRITE_COUNTER: AtomicU64 = AtomicU64::new(0);
+/// Serializes read, merge and write of the cache file between compiles of this process
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+/// File the pipeline cache is read from and written to, `None` goes back to `default_cache_path`
+pub fn set_pipeline_cache_path(path: Option<PathBuf>) {
+    *CACHE_PATH.write().unwrap() = path;
+}
+
+pub fn pipeline_cache_path() -> Option<PathBuf> {
+    CACHE_PATH.read().unwrap().clone().or_else(default_cache_path)
+}
+
+/// `cache.storage` in the platform cache directory of the application, `None` where there is none.
+/// Android has no such convention, the app data directory is passed in with `set_pipeline_cache_path`
+pub fn default_cache_path() -> Option<PathBuf> {
+    let env_path = |name: &str| std::env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+    let base = if cfg!(target_os = "windows") {
+        env_path("LOCALAPPDATA")
+    } else if cfg!(target_os = "macos") {
+        env_path("HOME").map(|home| home.join("Library").join("Caches"))
+    } else if cfg!(target_os = "android") {
+        None
+    } else {
+        env_path("XDG_CACHE_HOME").or_else(|| env_path("HOME").map(|home| home.join(".cache")))
+    };
+    base.map(|base| base.join(env!("CARGO_PKG_NAME")).join(FILE_NAME))
+}
 
 pub fn create_pipelines_multithreaded(use_caches: bool, pipeline_infos: Vec<GraphicsPipelineCreateInfo>, vulkan: &Vulkan) -> Vec<VkPipeline> {
-    let mut cache_data: Cache = match use_caches {
-        true => validate_caches(vulkan),
-        false => Cache::default()
+    let cache_path = if use_caches { pipeline_cache_path() } else { None };
+    let cache_data: Cache = match &cache_path {
+        Some(path) => validate_caches(vulkan, path),
+        None => create_new_cache(vulkan),
     };
 
     let thread_count = available_parallelism()
         .map(|x| x.get())
-        .map(|x| max(x, pipeline_infos.len()))
-        .unwrap_or(1);
+        .unwrap_or(1)
+        .min(pipeline_infos.len())
+        .max(1);
+    let chunk_size = pipeline_infos.len().div_ceil(thread_count).max(1);
 
     let mut main_pool = CachePool::new(&[], vulkan.clone());
     let pipelines = std::thread::scope(|s| {
         let handles: Vec<_> = pipeline_infos.as_slice()
-            .chunks(thread_count)
+            .chunks(chunk_size)
             .map(|info_chunk| {
                 let mut cache_pool = CachePool::new(&cache_data.cache_blob, vulkan.clone());
                 s.spawn(move || {
@@ -38,7 +75,7 @@ pub fn create_pipelines_multithreaded(use_caches: bool, pipeline_infos: Vec<Grap
             })
             .collect();
 
-        let mut pipelines = Vec::with_capacity(handles.len());
+        let mut pipelines = Vec::with_capacity(pipeline_infos.len());
         for handle in handles {
             let (mut pipeline_vec, cache_pool) = handle.join().unwrap();
             main_pool.merge(cache_pool);
@@ -47,16 +84,13 @@ pub fn create_pipelines_multithreaded(use_caches: bool, pipeline_infos: Vec<Grap
         pipelines
     });
 
+    if main_pool.is_empty() {
+        return pipelines;
+    }
     let final_cache = main_pool.yield_result();
-    if use_caches {
-        cache_data.cache_blob = vulkan.get_data_from_pipeline_cache(final_cache);
-        let proto_bytes = cache_data.encode_to_vec();
-        let compressed_data = compression_algo(&proto_bytes);
-
-        if cfg!(target_arch = "x86_64") {
-            fs::write(FILE_PATH, compressed_data.as_slice()).expect("Unable to write file");
-        } else if cfg!(target_arch = "aarch64") {
-            //fs::write(FILE_PATH, compressed_data.as_slice()).expect("Unable to write file"); TODO add cache writing to internal app dir
+    if let Some(path) = &cache_path {
+        if let Err(err) = write_cache(vulkan, path, cache_data, final_cache) {
+            eprintln!("Unable to write pipeline cache {}: {}", path.display(), err);
         }
     }
     final_cache.destroy(vulkan);
@@ -64,28 +98,145 @@ pub fn create_pipelines_multithreaded(use_caches: bool, pipeline_infos: Vec<Grap
     pipelines
 }
 
-fn validate_caches(vulkan: &Vulkan) -> Cache {
-    match File::open(FILE_PATH) {
-        Ok(_) => {
-            let raw_bytes = fs::read(FILE_PATH).expect("Unable to read file");
-            let decoded_bytes = decompression_algo(&raw_bytes);
-            let cache_data = Cache::decode(decoded_bytes.as_slice()).expect("Unable to decode cache");
+/// Pipelines compiled by `create_pipelines_async`, in the order of their infos
+pub struct PendingPipelines {
+    receiver: Receiver<Vec<VkPipeline>>,
+    result: Option<Vec<VkPipeline>>,
+}
 
-            if cache_data.device.is_none() || cache_data.cache_blob.is_empty() || cache_data.version != VERSION {
-                return devalidate(vulkan);
+impl PendingPipelines {
+    /// Non-blocking, true once the pipelines can be taken
+    pub fn is_ready(&mut self) -> bool {
+        if self.result.is_none() {
+            match self.receiver.try_recv() {
+                Ok(pipelines) => self.result = Some(pipelines),
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => panic!("Pipeline compilation thread panicked"),
             }
+        }
+        self.result.is_some()
+    }
+
+    /// Pipelines if they are ready, the caller owns them afterwards
+    pub fn take(&mut self) -> Option<Vec<VkPipeline>> {
+        if self.is_ready() { self.result.take() } else { None }
+    }
+
+    /// Blocks until compilation is done
+    pub fn wait(mut self) -> Vec<VkPipeline> {
+        match self.result.take() {
+            Some(pipelines) => pipelines,
+            None => self.receiver.recv().expect("Pipeline compilation thread panicked"),
+        }
+    }
+}
 
-            if cache_data.device.unwrap() != build_device_info(vulkan.get_loaded_device()) {
-                return devalidate(vulkan);
+/// Same as `create_pipelines_multithreaded` on a background thread, the frame loop keeps going meanwhile
+pub fn create_pipelines_async(use_caches: bool, pipeline_infos: Vec<GraphicsPipelineCreateInfo>, vulkan: &Vulkan) -> PendingPipelines {
+    let (sender, receiver) = channel();
+    let vulkan = vulkan.clone();
+    std::thread::Builder::new()
+        .name("pipeline compiler".to_string())
+        .spawn(move || {
+            let pipelines = create_pipelines_multithreaded(use_caches, pipeline_infos, &vulkan);
+            // the receiver is gone when the requester was dropped, nobody destroys the pipelines then
+            if let Err(unsent) = sender.send(pipelines) {
+                unsent.0.into_iter().for_each(|pipeline| pipeline.destroy(&vulkan));
             }
+        })
+        .expect("Unable to spawn pipeline compiler thread");
+
+    PendingPipelines { receiver, result: None }
+}
+
+/// Entry of the current device, an empty one if the file is missing, damaged or written by another device or driver
+fn validate_caches(vulkan: &Vulkan, path: &Path) -> Cache {
+    let Some(storage) = read_storage(path) else {
+        return create_new_cache(vulkan);
+    };
+
+    let current = create_new_cache(vulkan);
+    match storage.entries.into_iter().find(|entry| same_device(entry, &current)) {
+        Some(entry) if valid_device_blob(vulkan, &entry.cache_blob) => entry,
+        Some(_) => devalidate(vulkan),
+        None => current,
+    }
+}
+
+/// `None` if the file does not exist or can't be decoded
+fn read_storage(path: &Path) -> Option<CacheStorage> {
+    let raw_bytes = fs::read(path).ok()?;
+    let storage = decode_storage(&raw_bytes);
+    if storage.is_none() {
+        eprintln!("Pipeline cache {} is damaged or outdated, starting with an empty one", path.display());
+    }
+    storage
+}
+
+/// `None` if `bytes` are not a compressed storage of the current `VERSION`
+fn decode_storage(bytes: &[u8]) -> Option<CacheStorage> {
+    decompression_algo(bytes)
+        .and_then(|bytes| CacheStorage::decode(bytes.as_slice()).ok())
+        .filter(|storage| storage.version == VERSION)
+}
+
+fn encode_storage(storage: &CacheStorage) -> Vec<u8> {
+    compression_algo(&storage.encode_to_vec())
+}
+
+/// Replaces the entry of the device in the file with `pipeline_cache` merged with what other compiles wrote meanwhile,
+/// entries of other devices are kept. Writes into a temporary file first so a crash never leaves a half written cache behind
+fn write_cache(vulkan: &Vulkan, path: &Path, mut cache: Cache, pipeline_cache: VkPipelineCache) -> std::io::Result<()> {
+    let _guard = WRITE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut storage = read_storage(path).unwrap_or(CacheStorage { version: VERSION, entries: vec![] });
+    let written = storage.entries.iter()
+        .find(|entry| same_device(entry, &cache))
+        .filter(|entry| valid_device_blob(vulkan, &entry.cache_blob));
+    if let Some(written) = written {
+        let written_cache = vulkan.create_pipeline_cache(&written.cache_blob);
+        vulkan.merge_pipeline_caches(vec![written_cache], &pipeline_cache);
+        written_cache.destroy(vulkan);
+    }
+    cache.cache_blob = vulkan.get_data_from_pipeline_cache(pipeline_cache);
+
+    storage.entries.retain(|entry| !same_device(entry, &cache));
+    storage.entries.push(cache);
+
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    let temp_path = path.with_extension(format!("{}-{}.tmp", std::process::id(), WRITE_COUNTER.fetch_add(1, Ordering::Relaxed)));
+    fs::write(&temp_path, encode_storage(&storage))?;
+    fs::rename(&temp_path, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temp_path);
+    })
+}
 
-            cache_data
-        },
-        Err(_) => {
-            create_new_cache(vulkan)
-        },
+fn same_device(a: &Cache, b: &Cache) -> bool {
+    a.device == b.device && a.pipeline_cache_uuid == b.pipeline_cache_uuid
+}
+
+/// Drivers may reject or misread foreign blobs, see `valid_blob`
+fn valid_device_blob(vulkan: &Vulkan, blob: &[u8]) -> bool {
+    let properties = &vulkan.get_loaded_device().device_info.properties;
+    valid_blob(blob, properties.vendorID, properties.deviceID, &properties.pipelineCacheUUID)
+}
+
+/// Checks `VkPipelineCacheHeaderVersionOne` against the properties of a device
+fn valid_blob(blob: &[u8], vendor_id: u32, device_id: u32, pipeline_cache_uuid: &[u8]) -> bool {
+    if blob.len() < CACHE_HEADER_SIZE {
+        return false;
     }
+    let word = |index: usize| u32::from_le_bytes(blob[index * 4..index * 4 + 4].try_into().unwrap());
+
+    word(0) as usize >= CACHE_HEADER_SIZE
+        // VK_PIPELINE_CACHE_HEADER_VERSION_ONE
+        && word(1) == 1
+        && word(2) == vendor_id
+        && word(3) == device_id
+        && blob[16..CACHE_HEADER_SIZE] == *pipeline_cache_uuid
 }
+
 #[inline(always)]
 fn devalidate(vulkan: &Vulkan) -> Cache {
     #[cfg(debug_assertions)] println!("Cache devalidated");
@@ -98,12 +249,18 @@ pub fn compression_algo(bytes: &[u8]) -> Vec<u8> {
 }
 
 #[inline(always)]
-pub fn decompression_algo(bytes: &[u8]) -> Vec<u8> {
-    lz4_flex::decompress_size_prepended(bytes).unwrap_or_else(|err|  panic!("{}", err))
+pub fn decompression_algo(bytes: &[u8]) -> Option<Vec<u8>> {
+    lz4_flex::decompress_size_prepended(bytes).ok()
 }
 
 pub fn create_new_cache(vulkan: &Vulkan) -> Cache {
-    Cache { version: VERSION, device: Some(build_device_info(vulkan.get_loaded_device())), ..Default::default() }
+    let device = vulkan.get_loaded_device();
+    Cache {
+        version: VERSION,
+        device: Some(build_device_info(device)),
+        pipeline_cache_uuid: device.device_info.properties.pipelineCacheUUID.to_vec(),
+        ..Default::default()
+    }
 }
 
 struct CachePool {
@@ -121,6 +278,10 @@ impl CachePool {
         }
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.caches.is_empty()
+    }
+
     pub fn merge(&mut self, other: CachePool) {
         self.caches.extend(other.caches);
     }
@@ -141,3 +302,82 @@ impl CachePool {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::caches::cache::DeviceInfo;
+
+    const UUID: [u8; 16] = [7; 16];
+
+    fn blob(vendor_id: u32, device_id: u32, uuid: &[u8]) -> Vec<u8> {
+        let mut blob = [CACHE_HEADER_SIZE as u32, 1, vendor_id, device_id].iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect::<Vec<_>>();
+        blob.extend_from_slice(uuid);
+        blob.extend_from_slice(b"pipeline data");
+        blob
+    }
+
+    fn entry(device_id: u32, uuid: [u8; 16]) -> Cache {
+        Cache {
+            version: VERSION,
+            device: Some(DeviceInfo { vendor_id: 0x1002, device_id, driver_version: 3 }),
+            pipeline_cache_uuid: uuid.to_vec(),
+            cache_blob: blob(0x1002, device_id, &uuid),
+        }
+    }
+
+    #[test]
+    fn accepts_blob_of_device() {
+        assert!(valid_blob(&blob(0x1002, 0x73bf, &UUID), 0x1002, 0x73bf, &UUID));
+    }
+
+    #[test]
+    fn rejects_truncated_blob() {
+        let blob = blob(0x1002, 0x73bf, &UUID);
+        for len in 0..CACHE_HEADER_SIZE {
+            assert!(!valid_blob(&blob[..len], 0x1002, 0x73bf, &UUID), "{len} byte header accepted");
+        }
+    }
+
+    #[test]
+    fn rejects_foreign_blob() {
+        let blob = blob(0x1002, 0x73bf, &UUID);
+        assert!(!valid_blob(&blob, 0x10de, 0x73bf, &UUID));
+        assert!(!valid_blob(&blob, 0x1002, 0x2684, &UUID));
+        assert!(!valid_blob(&blob, 0x1002, 0x73bf, &[8; 16]));
+    }
+
+    #[test]
+    fn rejects_unknown_header_version() {
+        let mut blob = blob(0x1002, 0x73bf, &UUID);
+        blob[4] = 2;
+        assert!(!valid_blob(&blob, 0x1002, 0x73bf, &UUID));
+    }
+
+    #[test]
+    fn storage_round_trips_entries_of_two_devices() {
+        let storage = CacheStorage { version: VERSION, entries: vec![entry(0x73bf, UUID), entry(0x744c, [9; 16])] };
+        let decoded = decode_storage(&encode_storage(&storage)).unwrap();
+        assert_eq!(decoded, storage);
+
+        let current = Cache { cache_blob: vec![], ..entry(0x744c, [9; 16]) };
+        let found = decoded.entries.iter().find(|entry| same_device(entry, &current)).unwrap();
+        assert!(valid_blob(&found.cache_blob, 0x1002, 0x744c, &[9; 16]));
+    }
+
+    #[test]
+    fn rejects_truncated_storage() {
+        let bytes = encode_storage(&CacheStorage { version: VERSION, entries: vec![entry(0x73bf, UUID)] });
+        for len in [0, 3, bytes.len() / 2, bytes.len() - 1] {
+            assert!(decode_storage(&bytes[..len]).is_none(), "{len} of {} bytes decoded", bytes.len());
+        }
+    }
+
+    #[test]
+    fn rejects_other_storage_version() {
+        let bytes = encode_storage(&CacheStorage { version: VERSION + 1, entries: vec![entry(0x73bf, UUID)] });
+        assert!(decode_storage(&bytes).is_none());
+    }
+}
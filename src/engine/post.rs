@@ -0,0 +1,380 @@
+use crate::engine::pipelines::create_pipelines_multithreaded;
+use crate::engine::render_graph::{GraphExtent, ImageDesc, ImageHandle, PassCallback, PassContext, PassDesc, RenderGraph, ResourceUsage};
+use crate::prelude::*;
+use crate::vulkan::func::Vulkan;
+use shaders::common::{PostParams, POST_FLAG_AGX, POST_FLAG_ENCODE_PQ, POST_FLAG_ENCODE_SRGB};
+use std::ptr::null_mut;
+
+pub const HDR_FORMAT: VkFormat = VkFormat::R16G16B16A16_SFLOAT;
+
+const SRGB_FORMATS: &[VkFormat] = &[
+    VkFormat::B8G8R8A8_SRGB,
+    VkFormat::R8G8B8A8_SRGB,
+    VkFormat::A8B8G8R8_SRGB_PACK32,
+];
+
+/// Transfer function the last pass applies for `format`, sRGB formats and scRGB take linear values
+fn output_encoding(format: SurfaceFormat) -> u32 {
+    match format.colorSpace {
+        VkColorSpaceKHR::HDR10_ST2084_EXT => POST_FLAG_ENCODE_PQ,
+        VkColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => 0,
+        _ if SRGB_FORMATS.contains(&format.format) => 0,
+        _ => POST_FLAG_ENCODE_SRGB,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostEffect {
+    Bloom,
+    Tonemap,
+    Fxaa,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Tonemapper {
+    #[default]
+    Aces,
+    AgX,
+}
+
+/// Post-processing options, effects run in `effects` order.
+/// Scalar values are read every frame, changing the active chain rebuilds the render graph
+#[derive(Debug, Clone)]
+pub struct PostSettings {
+    pub exposure: f32,
+    pub tonemapper: Tonemapper,
+    pub bloom_threshold: f32,
+    pub bloom_intensity: f32,
+    pub effects: Vec<(PostEffect, bool)>,
+}
+
+impl Default for PostSettings {
+    fn default() -> Self {
+        PostSettings {
+            exposure: 1.0,
+            tonemapper: Tonemapper::Aces,
+            bloom_threshold: 1.0,
+            bloom_intensity: 0.05,
+            effects: vec![
+                (PostEffect::Bloom, true),
+                (PostEffect::Tonemap, true),
+                (PostEffect::Fxaa, true),
+            ],
+        }
+    }
+}
+
+impl PostSettings {
+    pub fn set_enabled(&mut self, effect: PostEffect, enabled: bool) {
+        match self.effects.iter_mut().find(|(e, _)| *e == effect) {
+            Some((_, state)) => *state = enabled,
+            None => self.effects.push((effect, enabled)),
+        }
+    }
+
+    pub fn toggle(&mut self, effect: PostEffect) {
+        let enabled = self.is_enabled(effect);
+        self.set_enabled(effect, !enabled);
+    }
+
+    pub fn is_enabled(&self, effect: PostEffect) -> bool {
+        self.effects.iter().any(|(e, enabled)| *e == effect && *enabled)
+    }
+
+    /// Moves effect to `position` in the chain, clamped to chain length
+    pub fn move_to(&mut self, effect: PostEffect, position: usize) {
+        if let Some(index) = self.effects.iter().position(|(e, _)| *e == effect) {
+            let entry = self.effects.remove(index);
+            let position = position.min(self.effects.len());
+            self.effects.insert(position, entry);
+        }
+    }
+
+    pub fn active(&self) -> Vec<PostEffect> {
+        self.effects.iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(effect, _)| *effect)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kernel {
+    BloomPrefilter,
+    BloomBlur,
+    BloomComposite,
+    Tonemap,
+    Fxaa,
+    Blit,
+}
+
+impl Kernel {
+    const ALL: [Kernel; 6] = [Kernel::BloomPrefilter, Kernel::BloomBlur, Kernel::BloomComposite, Kernel::Tonemap, Kernel::Fxaa, Kernel::Blit];
+
+    fn entry(&self) -> &'static str {
+        match self {
+            Kernel::BloomPrefilter => "bloom_prefilter",
+            Kernel::BloomBlur => "bloom_blur",
+            Kernel::BloomComposite => "bloom_composite",
+            Kernel::Tonemap => "tonemap",
+            Kernel::Fxaa => "fxaa",
+            Kernel::Blit => "blit",
+        }
+    }
+}
+
+struct PostStep {
+    pass: usize,
+    kernel: Kernel,
+    input: ImageHandle,
+    secondary: Option<ImageHandle>,
+    output_format: VkFormat,
+    direction: [f32; 2],
+    /// `POST_FLAG_ENCODE_*` of the pass writing into the output, 0 otherwise
+    encoding: u32,
+    pipeline: VkPipeline,
+    descriptor_set: VkDescriptorSet,
+}
+
+/// Fullscreen passes between the HDR scene target and the swapchain
+#[derive(Default)]
+pub struct PostChain {
+    pipelines: Vec<(Kernel, VkFormat, VkDestroy<VkPipeline>)>,
+    steps: Vec<PostStep>,
+    effects: Vec<PostEffect>,
+    template: GraphicsPipelineCreateInfo,
+
+    descriptor_pool: VkDestroy<VkDescriptorPool>,
+    descriptor_layout: VkDestroy<VkDescriptorSetLayout>,
+    layout: VkDestroy<VkPipelineLayout>,
+    sampler: VkDestroy<VkSampler>,
+}
+
+impl PostChain {
+    /// `template` provides shader modules and fixed states, see `preset_fullscreen`
+    pub fn new(vulkan: &Vulkan, template: GraphicsPipelineCreateInfo) -> Self {
+        let sampler = vulkan.create_sampler(SamplerInfo {
+            min_filter: VkFilter::LINEAR,
+            mag_filter: VkFilter::LINEAR,
+            address_mode_u: VkSamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_v: VkSamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_w: VkSamplerAddressMode::CLAMP_TO_EDGE,
+            ..Default::default()
+        });
+
+        let bindings = [0, 1].map(|binding| VkDescriptorSetLayoutBinding {
+            binding,
+            descriptorType: VkDescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptorCount: 1,
+            stageFlags: VkShaderStageFlags::FRAGMENT_BIT,
+            pImmutableSamplers: null_mut(),
+        });
+        let descriptor_layout = vulkan.create_descriptor_set_layout(&bindings);
+
+        let push_constant_ranges = [push_constant_range::<PostParams>()];
+        for kernel in Kernel::ALL {
+            graphics_interface("fullscreen", kernel.entry()).check_layout(kernel.entry(), &[&bindings], &push_constant_ranges);
+        }
+        let layout = vulkan.create_pipeline_layout_with::<PostParams>(&[descriptor_layout]);
+
+        Self {
+            pipelines: vec![],
+            steps: vec![],
+            effects: vec![],
+            template,
+
+            descriptor_pool: VkDestroy::default(),
+            descriptor_layout: VkDestroy::new(descriptor_layout, vulkan),
+            layout: VkDestroy::new(layout, vulkan),
+            sampler: VkDestroy::new(sampler, vulkan),
+        }
+    }
+
+    /// Drops cached pipelines so the next `build` creates them from `template`, the graph has to be rebuilt afterwards
+    pub fn set_template(&mut self, template: GraphicsPipelineCreateInfo) {
+        self.pipelines.clear();
+        self.template = template;
+    }
+
+    /// Effects the chain was last built with
+    pub fn effects(&self) -> &[PostEffect] {
+        &self.effects
+    }
+
+    /// Declares post passes reading `input` and ending in `output`, without effects it is a plain copy
+    pub fn build<T>(&mut self, vulkan: &Vulkan, graph: &mut RenderGraph<T>, settings: &PostSettings,
+                    input: ImageHandle, output: ImageHandle, output_surface_format: SurfaceFormat, callback: PassCallback<T>) {
+        self.steps.clear();
+        self.effects = settings.active();
+        let output_format = output_surface_format.format;
+        let output_encoding = output_encoding(output_surface_format);
+
+        let intermediate = |graph: &mut RenderGraph<T>, name: &'static str, extent: GraphExtent| {
+            graph.create_image(name, ImageDesc {
+                format: HDR_FORMAT,
+                extent,
+                samples: VkSampleCountFlags::SC_1_BIT,
+                aspect: VkImageAspectFlags::COLOR_BIT,
+            })
+        };
+
+        let mut current = input;
+        let effects = self.effects.clone();
+        for (index, effect) in effects.iter().enumerate() {
+            let last = index == effects.len() - 1;
+            let (target, target_format) = match last {
+                true => (output, output_format),
+                false => (intermediate(graph, "post_target", GraphExtent::Swapchain), HDR_FORMAT),
+            };
+            let encode = if last { output_encoding } else { 0 };
+
+            match effect {
+                PostEffect::Bloom => {
+                    let bright = intermediate(graph, "bloom_prefilter", GraphExtent::Scaled(0.5));
+                    let blur_horizontal = intermediate(graph, "bloom_blur_horizontal", GraphExtent::Scaled(0.5));
+                    let blur_vertical = intermediate(graph, "bloom_blur_vertical", GraphExtent::Scaled(0.5));
+
+                    self.add_step(graph, "bloom_prefilter", Kernel::BloomPrefilter, current, None, bright, HDR_FORMAT, [0.0, 0.0], 0, callback);
+                    self.add_step(graph, "bloom_blur_horizontal", Kernel::BloomBlur, bright, None, blur_horizontal, HDR_FORMAT, [1.0, 0.0], 0, callback);
+                    self.add_step(graph, "bloom_blur_vertical", Kernel::BloomBlur, blur_horizontal, None, blur_vertical, HDR_FORMAT, [0.0, 1.0], 0, callback);
+                    self.add_step(graph, "bloom_composite", Kernel::BloomComposite, current, Some(blur_vertical), target, target_format, [0.0, 0.0], encode, callback);
+                }
+                PostEffect::Tonemap => self.add_step(graph, "tonemap", Kernel::Tonemap, current, None, target, target_format, [0.0, 0.0], encode, callback),
+                PostEffect::Fxaa => self.add_step(graph, "fxaa", Kernel::Fxaa, current, None, target, target_format, [0.0, 0.0], encode, callback),
+            }
+            current = target;
+        }
+        if effects.is_empty() {
+            self.add_step(graph, "blit", Kernel::Blit, input, None, output, output_format, [0.0, 0.0], output_encoding, callback);
+        }
+
+        self.create_pipelines(vulkan);
+
+        let set_count = self.steps.len() as u32;
+        let pool_sizes = [VkDescriptorPoolSize {
+            descriptorType: VkDescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptorCount: set_count * 2,
+        }];
+        self.descriptor_pool = VkDestroy::new(vulkan.create_descriptor_pool(&pool_sizes, set_count, false), vulkan);
+        let layouts = vec![*self.descriptor_layout; self.steps.len()];
+        let sets = vulkan.allocate_descriptor_sets(*self.descriptor_pool, &layouts);
+        for (step, set) in self.steps.iter_mut().zip(sets) {
+            step.descriptor_set = set;
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn add_step<T>(&mut self, graph: &mut RenderGraph<T>, name: &'static str, kernel: Kernel, input: ImageHandle, secondary: Option<ImageHandle>,
+                   output: ImageHandle, output_format: VkFormat, direction: [f32; 2], encoding: u32, callback: PassCallback<T>) {
+        let sampled = ResourceUsage::Sampled(VkPipelineStageFlags2::FRAGMENT_SHADER_BIT);
+        let mut desc = PassDesc::new().image(input, sampled);
+        if let Some(secondary) = secondary {
+            desc = desc.image(secondary, sampled);
+        }
+        desc = desc.color_attachment(output, VkAttachmentLoadOp::DONT_CARE, VkClearValue::default());
+
+        let pass = graph.add_pass(name, desc, callback);
+        self.steps.push(PostStep {
+            pass,
+            kernel,
+            input,
+            secondary,
+            output_format,
+            direction,
+            encoding,
+            pipeline: VkPipeline::none(),
+            descriptor_set: VkDescriptorSet::none(),
+        });
+    }
+
+    fn create_pipelines(&mut self, vulkan: &Vulkan) {
+        let mut missing: Vec<(Kernel, VkFormat)> = vec![];
+        for step in &self.steps {
+            let key = (step.kernel, step.output_format);
+            let exists = self.pipelines.iter().any(|(kernel, format, _)| (*kernel, *format) == key);
+            if !exists && !missing.contains(&key) {
+                missing.push(key);
+            }
+        }
+
+        if !missing.is_empty() {
+            let infos = missing.iter()
+                .map(|(kernel, format)| preset_fullscreen(self.template.clone(), kernel.entry(), *self.layout, *format))
+                .collect();
+            let pipelines = create_pipelines_multithreaded(true, infos, vulkan);
+            for ((kernel, format), pipeline) in missing.into_iter().zip(pipelines) {
+                self.pipelines.push((kernel, format, VkDestroy::new(pipeline, vulkan)));
+            }
+        }
+
+        for step in &mut self.steps {
+            step.pipeline = self.pipelines.iter()
+                .find(|(kernel, format, _)| *kernel == step.kernel && *format == step.output_format)
+                .map(|(_, _, pipeline)| **pipeline)
+                .unwrap();
+        }
+    }
+
+    /// Points descriptor sets to current transient views, call after every graph compile
+    pub fn update_descriptors<T>(&self, vulkan: &Vulkan, graph: &RenderGraph<T>) {
+        let mut image_infos = Vec::with_capacity(self.steps.len() * 2);
+        for step in &self.steps {
+            let input_view = graph.image(step.input).view;
+            let secondary_view = step.secondary.map(|secondary| graph.image(secondary).view).unwrap_or(input_view);
+
+            for (binding, view) in [(0, input_view), (1, secondary_view)] {
+                image_infos.push(ImageDescriptorInfo {
+                    target_descriptor: DescriptorSetInfo {
+                        descriptor_set: step.descriptor_set,
+                        descriptor_binding: binding,
+                        array_element: 0,
+                    },
+                    target_descriptor_type: VkDescriptorType::COMBINED_IMAGE_SAMPLER,
+                    image_infos: vec![VkDescriptorImageInfo {
+                        sampler: *self.sampler,
+                        imageView: view,
+                        imageLayout: VkImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    }],
+                });
+            }
+        }
+        vulkan.update_descriptor_sets(image_infos, vec![], vec![], vec![]);
+    }
+
+    pub fn record(&self, vulkan: &Vulkan, pass: &PassContext, settings: &PostSettings) {
+        let Some(step) = self.steps.iter().find(|step| step.pass == pass.pass_index) else {
+            return;
+        };
+        let command_buffer = pass.command_buffer;
+        let input = pass.image(step.input);
+
+        let mut flags = step.encoding;
+        if settings.tonemapper == Tonemapper::AgX {
+            flags |= POST_FLAG_AGX;
+        }
+        let params = PostParams {
+            texel_size: [1.0 / input.extent.width as f32, 1.0 / input.extent.height as f32],
+            direction: step.direction,
+            exposure: settings.exposure,
+            threshold: settings.bloom_threshold,
+            intensity: settings.bloom_intensity,
+            flags,
+        };
+
+        vulkan.bind_pipeline(command_buffer, VkPipelineBindPoint::GRAPHICS, step.pipeline);
+        vulkan.bind_descriptor_sets(command_buffer, VkPipelineBindPoint::GRAPHICS, *self.layout, 0, &[step.descriptor_set], &[]);
+        vulkan.push_constants(command_buffer, *self.layout, &params);
+
+        let extent = pass.render_area.extent;
+        vulkan.set_viewport(command_buffer, 0, &[VkViewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            minDepth: 0.0,
+            maxDepth: 1.0,
+        }]);
+        vulkan.set_scissors(command_buffer, 0, &[pass.render_area]);
+        vulkan.draw(command_buffer, 3, 1, 0, 0);
+    }
+}
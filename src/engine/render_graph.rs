@@ -0,0 +1,995 @@
+use crate::prelude::*;
+use crate::vulkan::func::Vulkan;
+use crate::vulkan::utils::ImageUsage;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+// Frame graph: passes declare what they touch, graph decides order, barriers, layouts and
+// memory of transient images. Built once, compiled on init/resize, executed every frame.
+
+pub type PassCallback<T> = fn(&mut T, &Vulkan, &PassContext);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ImageHandle(usize);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct BufferHandle(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum ResourceRef {
+    Image(ImageHandle),
+    Buffer(BufferHandle),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResourceUsage {
+    TransferRead,
+    TransferWrite,
+    VertexBuffer,
+    IndexBuffer,
+    IndirectBuffer,
+    UniformRead(VkPipelineStageFlags2),
+    StorageRead(VkPipelineStageFlags2),
+    StorageWrite(VkPipelineStageFlags2),
+    Sampled(VkPipelineStageFlags2),
+    ColorAttachment,
+    DepthAttachment,
+    DepthRead(VkPipelineStageFlags2),
+    Present,
+}
+
+impl ResourceUsage {
+    pub fn stage(&self) -> VkPipelineStageFlags2 {
+        match self {
+            ResourceUsage::TransferRead | ResourceUsage::TransferWrite => VkPipelineStageFlags2::TRANSFER_BIT,
+            ResourceUsage::VertexBuffer | ResourceUsage::IndexBuffer => VkPipelineStageFlags2::VERTEX_INPUT_BIT,
+            ResourceUsage::IndirectBuffer => VkPipelineStageFlags2::DRAW_INDIRECT_BIT,
+            ResourceUsage::UniformRead(stage) | ResourceUsage::StorageRead(stage)
+            | ResourceUsage::StorageWrite(stage) | ResourceUsage::Sampled(stage) => *stage,
+            ResourceUsage::ColorAttachment => VkPipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT_BIT,
+            ResourceUsage::DepthAttachment => VkPipelineStageFlags2::EARLY_FRAGMENT_TESTS_BIT | VkPipelineStageFlags2::LATE_FRAGMENT_TESTS_BIT,
+            ResourceUsage::DepthRead(stage) => VkPipelineStageFlags2::EARLY_FRAGMENT_TESTS_BIT | VkPipelineStageFlags2::LATE_FRAGMENT_TESTS_BIT | *stage,
+            ResourceUsage::Present => VkPipelineStageFlags2::BOTTOM_OF_PIPE_BIT,
+        }
+    }
+
+    pub fn access(&self) -> VkAccessFlags2 {
+        match self {
+            ResourceUsage::TransferRead => VkAccessFlags2::TRANSFER_READ_BIT,
+            ResourceUsage::TransferWrite => VkAccessFlags2::TRANSFER_WRITE_BIT,
+            ResourceUsage::VertexBuffer => VkAccessFlags2::VERTEX_ATTRIBUTE_READ_BIT,
+            ResourceUsage::IndexBuffer => VkAccessFlags2::INDEX_READ_BIT,
+            ResourceUsage::IndirectBuffer => VkAccessFlags2::INDIRECT_COMMAND_READ_BIT,
+            ResourceUsage::UniformRead(_) => VkAccessFlags2::UNIFORM_READ_BIT,
+            ResourceUsage::StorageRead(_) => VkAccessFlags2::SHADER_STORAGE_READ_BIT,
+            ResourceUsage::StorageWrite(_) => VkAccessFlags2::SHADER_STORAGE_READ_BIT | VkAccessFlags2::SHADER_STORAGE_WRITE_BIT,
+            ResourceUsage::Sampled(_) => VkAccessFlags2::SHADER_SAMPLED_READ_BIT,
+            ResourceUsage::ColorAttachment => VkAccessFlags2::COLOR_ATTACHMENT_READ_BIT | VkAccessFlags2::COLOR_ATTACHMENT_WRITE_BIT,
+            ResourceUsage::DepthAttachment => VkAccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ_BIT | VkAccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE_BIT,
+            ResourceUsage::DepthRead(_) => VkAccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ_BIT | VkAccessFlags2::SHADER_SAMPLED_READ_BIT,
+            ResourceUsage::Present => VkAccessFlags2::empty(),
+        }
+    }
+
+    pub fn layout(&self) -> VkImageLayout {
+        match self {
+            ResourceUsage::TransferRead => VkImageLayout::TRANSFER_SRC_OPTIMAL,
+            ResourceUsage::TransferWrite => VkImageLayout::TRANSFER_DST_OPTIMAL,
+            ResourceUsage::StorageRead(_) | ResourceUsage::StorageWrite(_) => VkImageLayout::GENERAL,
+            ResourceUsage::Sampled(_) => VkImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ResourceUsage::ColorAttachment => VkImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ResourceUsage::DepthAttachment => VkImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ResourceUsage::DepthRead(_) => VkImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            ResourceUsage::Present => VkImageLayout::PRESENT_SRC_KHR,
+            _ => VkImageLayout::UNDEFINED,
+        }
+    }
+
+    pub fn is_write(&self) -> bool {
+        matches!(self, ResourceUsage::TransferWrite | ResourceUsage::StorageWrite(_) | ResourceUsage::ColorAttachment | ResourceUsage::DepthAttachment)
+    }
+
+    fn image_usage(&self, usage: ImageUsage) -> ImageUsage {
+        match self {
+            ResourceUsage::TransferRead => usage.transfer_src(true),
+            ResourceUsage::TransferWrite => usage.transfer_dst(true),
+            ResourceUsage::StorageRead(_) | ResourceUsage::StorageWrite(_) => usage.storage(true),
+            ResourceUsage::Sampled(_) => usage.sampled(true),
+            ResourceUsage::ColorAttachment => usage.color_attachment(true),
+            ResourceUsage::DepthAttachment => usage.depth_stencil_attachment(true),
+            ResourceUsage::DepthRead(_) => usage.depth_stencil_attachment(true).sampled(true),
+            _ => usage,
+        }
+    }
+}
+
+/// Size of a transient image, relative sizes follow the extent passed to `compile`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GraphExtent {
+    Swapchain,
+    Scaled(f32),
+    Fixed(VkExtent2D),
+}
+
+impl GraphExtent {
+    fn resolve(&self, extent: VkExtent2D) -> VkExtent2D {
+        match self {
+            GraphExtent::Swapchain => extent,
+            GraphExtent::Scaled(scale) => VkExtent2D {
+                width: ((extent.width as f32 * scale) as u32).max(1),
+                height: ((extent.height as f32 * scale) as u32).max(1),
+            },
+            GraphExtent::Fixed(fixed) => *fixed,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ImageDesc {
+    pub format: VkFormat,
+    pub extent: GraphExtent,
+    pub samples: VkSampleCountFlags,
+    pub aspect: VkImageAspectFlags,
+}
+
+impl ImageDesc {
+    pub fn attachment(format: VkFormat, aspect: VkImageAspectFlags, samples: VkSampleCountFlags) -> Self {
+        ImageDesc {
+            format,
+            extent: GraphExtent::Swapchain,
+            samples,
+            aspect,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GraphImage {
+    pub image: VkImage,
+    pub view: VkImageView,
+    pub format: VkFormat,
+    pub extent: VkExtent2D,
+    pub aspect: VkImageAspectFlags,
+}
+
+struct ImageResource {
+    name: &'static str,
+    transient: Option<ImageDesc>,
+    initial_layout: VkImageLayout,
+    usage: ImageUsage,
+    physical: GraphImage,
+}
+
+struct BufferResource {
+    name: &'static str,
+    buffer: VkBuffer,
+}
+
+#[derive(Clone)]
+struct Attachment {
+    image: ImageHandle,
+    resolve: Option<ImageHandle>,
+    load_op: VkAttachmentLoadOp,
+    clear_value: VkClearValue,
+}
+
+/// Declaration of everything a pass touches
+#[derive(Clone, Default)]
+pub struct PassDesc {
+    accesses: Vec<(ResourceRef, ResourceUsage)>,
+    color_attachments: Vec<Attachment>,
+    depth_attachment: Option<Attachment>,
+    final_layouts: Vec<(ImageHandle, VkImageLayout)>,
+    manual_rendering: bool,
+    side_effect: bool,
+}
+
+impl PassDesc {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn buffer(mut self, buffer: BufferHandle, usage: ResourceUsage) -> Self {
+        self.accesses.push((ResourceRef::Buffer(buffer), usage));
+        self
+    }
+
+    pub fn image(mut self, image: ImageHandle, usage: ResourceUsage) -> Self {
+        self.accesses.push((ResourceRef::Image(image), usage));
+        self
+    }
+
+    pub fn color_attachment(mut self, image: ImageHandle, load_op: VkAttachmentLoadOp, clear_value: VkClearValue) -> Self {
+        self.accesses.push((ResourceRef::Image(image), ResourceUsage::ColorAttachment));
+        self.color_attachments.push(Attachment { image, resolve: None, load_op, clear_value });
+        self
+    }
+
+    /// Multisampled color attachment resolved into `resolve` at the end of the pass
+    pub fn resolved_color_attachment(mut self, image: ImageHandle, resolve: ImageHandle, load_op: VkAttachmentLoadOp, clear_value: VkClearValue) -> Self {
+        self.accesses.push((ResourceRef::Image(image), ResourceUsage::ColorAttachment));
+        self.accesses.push((ResourceRef::Image(resolve), ResourceUsage::ColorAttachment));
+        self.color_attachments.push(Attachment { image, resolve: Some(resolve), load_op, clear_value });
+        self
+    }
+
+    pub fn depth_attachment(mut self, image: ImageHandle, load_op: VkAttachmentLoadOp, clear_value: VkClearValue) -> Self {
+        self.accesses.push((ResourceRef::Image(image), ResourceUsage::DepthAttachment));
+        self.depth_attachment = Some(Attachment { image, resolve: None, load_op, clear_value });
+        self
+    }
+
+    /// Attachments are only used for synchronization, pass begins rendering by itself (render pass path)
+    pub fn manual_rendering(mut self) -> Self {
+        self.manual_rendering = true;
+        self
+    }
+
+    /// Pass changes layout by itself, e.g. render pass with finalLayout
+    pub fn leaves_in(mut self, image: ImageHandle, layout: VkImageLayout) -> Self {
+        self.final_layouts.push((image, layout));
+        self
+    }
+
+    /// Never cull this pass even if nothing reads its output
+    pub fn side_effect(mut self) -> Self {
+        self.side_effect = true;
+        self
+    }
+
+    fn merged_accesses(&self) -> Vec<(ResourceRef, AccessInfo)> {
+        let mut merged: Vec<(ResourceRef, AccessInfo)> = Vec::with_capacity(self.accesses.len());
+        for (resource, usage) in &self.accesses {
+            let info = AccessInfo::from(*usage);
+            match merged.iter_mut().find(|(r, _)| r == resource) {
+                Some((_, existing)) => {
+                    if existing.layout != info.layout {
+                        panic!("Conflicting image layouts inside one pass: {:?} and {:?}", existing.layout, info.layout);
+                    }
+                    existing.stage |= info.stage;
+                    existing.access |= info.access;
+                    existing.write |= info.write;
+                }
+                None => merged.push((*resource, info)),
+            }
+        }
+        merged
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct AccessInfo {
+    stage: VkPipelineStageFlags2,
+    access: VkAccessFlags2,
+    layout: VkImageLayout,
+    write: bool,
+}
+
+impl From<ResourceUsage> for AccessInfo {
+    fn from(usage: ResourceUsage) -> Self {
+        AccessInfo {
+            stage: usage.stage(),
+            access: usage.access(),
+            layout: usage.layout(),
+            write: usage.is_write(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct ResourceState {
+    write_stage: VkPipelineStageFlags2,
+    write_access: VkAccessFlags2,
+    read_stages: VkPipelineStageFlags2,
+    visible_stages: VkPipelineStageFlags2,
+    layout: VkImageLayout,
+}
+
+impl Default for ResourceState {
+    fn default() -> Self {
+        ResourceState {
+            write_stage: VkPipelineStageFlags2::empty(),
+            write_access: VkAccessFlags2::empty(),
+            read_stages: VkPipelineStageFlags2::empty(),
+            visible_stages: VkPipelineStageFlags2::empty(),
+            layout: VkImageLayout::UNDEFINED,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Barrier {
+    resource: ResourceRef,
+    src_stage: VkPipelineStageFlags2,
+    dst_stage: VkPipelineStageFlags2,
+    src_access: VkAccessFlags2,
+    dst_access: VkAccessFlags2,
+    old_layout: VkImageLayout,
+    new_layout: VkImageLayout,
+}
+
+struct Pass<T> {
+    name: &'static str,
+    desc: PassDesc,
+    callback: PassCallback<T>,
+    enabled: bool,
+}
+
+struct Step {
+    pass: usize,
+    barriers: Vec<Barrier>,
+}
+
+/// Handed to pass callbacks, resolves handles into the current frame's objects
+pub struct PassContext<'a> {
+    pub command_buffer: VkCommandBuffer,
+    pub render_area: VkRect2D,
+    /// Value returned by `add_pass`, lets one callback serve several passes
+    pub pass_index: usize,
+    images: &'a [ImageResource],
+    buffers: &'a [BufferResource],
+}
+
+impl PassContext<'_> {
+    pub fn image(&self, handle: ImageHandle) -> &GraphImage {
+        &self.images[handle.0].physical
+    }
+
+    pub fn buffer(&self, handle: BufferHandle) -> VkBuffer {
+        self.buffers[handle.0].buffer
+    }
+}
+
+pub struct RenderGraph<T> {
+    passes: Vec<Pass<T>>,
+    images: Vec<ImageResource>,
+    buffers: Vec<BufferResource>,
+    exports: Vec<(ImageHandle, ResourceUsage)>,
+
+    steps: Vec<Step>,
+    final_barriers: Vec<Barrier>,
+    alias_slots: Vec<Vec<usize>>,
+    compiled: bool,
+
+    // drop order matters: views, then images, then memory they alias
+    transient_views: Vec<VkDestroy<VkImageView>>,
+    transient_images: Vec<VkDestroy<VkImage>>,
+    transient_memory: Vec<VkDestroy<VkDeviceMemory>>,
+}
+
+impl<T> Default for RenderGraph<T> {
+    fn default() -> Self {
+        RenderGraph {
+            passes: vec![],
+            images: vec![],
+            buffers: vec![],
+            exports: vec![],
+            steps: vec![],
+            final_barriers: vec![],
+            alias_slots: vec![],
+            compiled: false,
+            transient_views: vec![],
+            transient_images: vec![],
+            transient_memory: vec![],
+        }
+    }
+}
+
+impl<T> RenderGraph<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Image owned outside of the graph, `initial_layout` is the layout at frame start (UNDEFINED discards contents)
+    pub fn import_image(&mut self, name: &'static str, initial_layout: VkImageLayout) -> ImageHandle {
+        self.images.push(ImageResource {
+            name,
+            transient: None,
+            initial_layout,
+            usage: ImageUsage::default(),
+            physical: GraphImage::default(),
+        });
+        self.compiled = false;
+        ImageHandle(self.images.len() - 1)
+    }
+
+    pub fn import_buffer(&mut self, name: &'static str) -> BufferHandle {
+        self.buffers.push(BufferResource {
+            name,
+            buffer: VkBuffer::none(),
+        });
+        self.compiled = false;
+        BufferHandle(self.buffers.len() - 1)
+    }
+
+    /// Image living only inside a frame, its memory may be shared with other transients
+    pub fn create_image(&mut self, name: &'static str, desc: ImageDesc) -> ImageHandle {
+        self.images.push(ImageResource {
+            name,
+            transient: Some(desc),
+            initial_layout: VkImageLayout::UNDEFINED,
+            usage: ImageUsage::default(),
+            physical: GraphImage::default(),
+        });
+        self.compiled = false;
+        ImageHandle(self.images.len() - 1)
+    }
+
+    pub fn set_image(&mut self, handle: ImageHandle, image: GraphImage) {
+        let resource = &mut self.images[handle.0];
+        if resource.transient.is_some() {
+            panic!("Tried to set transient image {} from outside of render graph", resource.name);
+        }
+        resource.physical = image;
+    }
+
+    pub fn set_buffer(&mut self, handle: BufferHandle, buffer: VkBuffer) {
+        self.buffers[handle.0].buffer = buffer;
+    }
+
+    pub fn image(&self, handle: ImageHandle) -> &GraphImage {
+        &self.images[handle.0].physical
+    }
+
+    /// Image is transitioned into `usage` after the last pass
+    pub fn export_image(&mut self, handle: ImageHandle, usage: ResourceUsage) {
+        self.exports.push((handle, usage));
+        self.compiled = false;
+    }
+
+    pub fn add_pass(&mut self, name: &'static str, desc: PassDesc, callback: PassCallback<T>) -> usize {
+        for (resource, usage) in &desc.accesses {
+            if let ResourceRef::Image(handle) = resource {
+                let image = &mut self.images[handle.0];
+                image.usage = usage.image_usage(image.usage.clone());
+            }
+        }
+        self.passes.push(Pass { name, desc, callback, enabled: true });
+        self.compiled = false;
+        self.passes.len() - 1
+    }
+
+    /// Skips recording of a pass from the next `execute` on without recompiling. Its barriers are still recorded,
+    /// so images it writes keep their previous contents as long as they don't start the frame `UNDEFINED`
+    pub fn set_pass_enabled(&mut self, pass: usize, enabled: bool) {
+        self.passes[pass].enabled = enabled;
+    }
+
+    /// Orders passes, (re)creates transient images for `extent` and precomputes barriers
+    pub fn compile(&mut self, vulkan: &Vulkan, extent: VkExtent2D) {
+        let order = self.schedule();
+        self.allocate_transients(vulkan, extent, &order);
+
+        // first run only collects end-of-frame states, second one syncs with the previous frame
+        let (_, _, end_states) = self.simulate(&order, None);
+        let (steps, final_barriers, _) = self.simulate(&order, Some(end_states));
+        self.steps = steps;
+        self.final_barriers = final_barriers;
+        self.compiled = true;
+
+        #[cfg(debug_assertions)]
+        {
+            let names = self.steps.iter().map(|step| self.passes[step.pass].name).collect::<Vec<_>>();
+            println!("Render graph order: {:?}", names);
+        }
+    }
+
+    pub fn execute(&self, vulkan: &Vulkan, command_buffer: VkCommandBuffer, context: &mut T) {
+        if !self.compiled {
+            panic!("Render graph executed before compile");
+        }
+        if let Some(image) = self.images.iter().find(|image| image.transient.is_none() && image.physical.image == VkImage::none()) {
+            panic!("Render graph image {} is not set", image.name);
+        }
+        if let Some(buffer) = self.buffers.iter().find(|buffer| buffer.buffer == VkBuffer::none()) {
+            panic!("Render graph buffer {} is not set", buffer.name);
+        }
+
+        for step in &self.steps {
+            self.record_barriers(vulkan, command_buffer, &step.barriers);
+
+            let pass = &self.passes[step.pass];
+            if !pass.enabled {
+                continue;
+            }
+            let render_area = self.render_area(&pass.desc);
+            let dynamic_rendering = !pass.desc.manual_rendering
+                && (!pass.desc.color_attachments.is_empty() || pass.desc.depth_attachment.is_some());
+
+            if dynamic_rendering {
+                let color_attachments = pass.desc.color_attachments.iter()
+                    .map(|attachment| {
+                        let rendering = RenderingAttachment::color(self.images[attachment.image.0].physical.view, attachment.load_op, attachment.clear_value);
+                        match attachment.resolve {
+                            Some(resolve) => rendering.resolve(self.images[resolve.0].physical.view),
+                            None => rendering,
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                let depth_attachment = pass.desc.depth_attachment.as_ref().map(|attachment| {
+                    RenderingAttachment::depth(self.images[attachment.image.0].physical.view, attachment.load_op, attachment.clear_value)
+                });
+                vulkan.begin_rendering(command_buffer, render_area, 1, &color_attachments, depth_attachment);
+            }
+
+            let pass_context = PassContext {
+                command_buffer,
+                render_area,
+                pass_index: step.pass,
+                images: &self.images,
+                buffers: &self.buffers,
+            };
+            (pass.callback)(context, vulkan, &pass_context);
+
+            if dynamic_rendering {
+                vulkan.end_rendering(command_buffer);
+            }
+        }
+
+        self.record_barriers(vulkan, command_buffer, &self.final_barriers);
+    }
+
+    fn render_area(&self, desc: &PassDesc) -> VkRect2D {
+        let attachment = desc.color_attachments.first().or(desc.depth_attachment.as_ref());
+        let extent = match attachment {
+            Some(attachment) => self.images[attachment.image.0].physical.extent,
+            None => VkExtent2D { width: 0, height: 0 },
+        };
+
+        VkRect2D { offset: Default::default(), extent }
+    }
+
+    fn record_barriers(&self, vulkan: &Vulkan, command_buffer: VkCommandBuffer, barriers: &[Barrier]) {
+        let mut buffer_transitions = Vec::new();
+        let mut image_transitions = Vec::new();
+        for barrier in barriers {
+            match barrier.resource {
+                ResourceRef::Buffer(handle) => buffer_transitions.push(BufferTransition {
+                    buffer: self.buffers[handle.0].buffer,
+                    offset: 0,
+                    size: VK_WHOLE_SIZE,
+                    src_stage: barrier.src_stage,
+                    dst_stage: barrier.dst_stage,
+                    src_access: barrier.src_access,
+                    dst_access: barrier.dst_access,
+                    src_queue_family: VK_QUEUE_FAMILY_IGNORED,
+                    dst_queue_family: VK_QUEUE_FAMILY_IGNORED,
+                }),
+                ResourceRef::Image(handle) => {
+                    let image = &self.images[handle.0].physical;
+                    image_transitions.push(ImageTransition2 {
+                        image: image.image,
+                        src_stage: barrier.src_stage,
+                        dst_stage: barrier.dst_stage,
+                        src_access: barrier.src_access,
+                        dst_access: barrier.dst_access,
+                        old_layout: barrier.old_layout,
+                        new_layout: barrier.new_layout,
+                        aspect: image.aspect,
+                        ..Default::default()
+                    })
+                }
+            }
+        }
+
+        vulkan.transition_resources(buffer_transitions, image_transitions, command_buffer);
+    }
+
+    /// Dependency order of passes, passes not contributing to imported resources or side effects are culled
+    fn schedule(&self) -> Vec<usize> {
+        let pass_count = self.passes.len();
+        let mut dependencies: Vec<Vec<usize>> = vec![vec![]; pass_count];
+        let mut last_writer: HashMap<ResourceRef, usize> = HashMap::new();
+        let mut readers: HashMap<ResourceRef, Vec<usize>> = HashMap::new();
+        let mut roots: Vec<usize> = vec![];
+
+        for (index, pass) in self.passes.iter().enumerate() {
+            let mut is_root = pass.desc.side_effect;
+            for (resource, info) in pass.desc.merged_accesses() {
+                if let Some(&writer) = last_writer.get(&resource) {
+                    dependencies[index].push(writer);
+                }
+                if info.write {
+                    if let Some(previous_readers) = readers.remove(&resource) {
+                        dependencies[index].extend(previous_readers.into_iter().filter(|&reader| reader != index));
+                    }
+                    last_writer.insert(resource, index);
+                    is_root |= self.is_imported(resource);
+                } else {
+                    readers.entry(resource).or_default().push(index);
+                }
+            }
+            if is_root {
+                roots.push(index);
+            }
+        }
+
+        let mut alive = vec![false; pass_count];
+        while let Some(index) = roots.pop() {
+            if alive[index] {
+                continue;
+            }
+            alive[index] = true;
+            roots.extend(dependencies[index].iter().copied());
+        }
+
+        let mut in_degree = vec![0usize; pass_count];
+        let mut dependents: Vec<Vec<usize>> = vec![vec![]; pass_count];
+        for index in (0..pass_count).filter(|&index| alive[index]) {
+            dependencies[index].sort_unstable();
+            dependencies[index].dedup();
+            for &dependency in &dependencies[index] {
+                in_degree[index] += 1;
+                dependents[dependency].push(index);
+            }
+        }
+
+        // declaration order breaks ties
+        let mut ready: BinaryHeap<Reverse<usize>> = (0..pass_count)
+            .filter(|&index| alive[index] && in_degree[index] == 0)
+            .map(Reverse)
+            .collect();
+        let mut order = Vec::with_capacity(pass_count);
+        while let Some(Reverse(index)) = ready.pop() {
+            order.push(index);
+            for &dependent in &dependents[index] {
+                in_degree[dependent] -= 1;
+                if in_degree[dependent] == 0 {
+                    ready.push(Reverse(dependent));
+                }
+            }
+        }
+
+        #[cfg(debug_assertions)]
+        for (index, pass) in self.passes.iter().enumerate() {
+            if !alive[index] {
+                println!("Render graph culled pass {}", pass.name);
+            }
+        }
+
+        order
+    }
+
+    fn is_imported(&self, resource: ResourceRef) -> bool {
+        match resource {
+            ResourceRef::Image(handle) => self.images[handle.0].transient.is_none(),
+            ResourceRef::Buffer(_) => true,
+        }
+    }
+
+    fn simulate(&self, order: &[usize], previous: Option<Vec<ResourceState>>) -> (Vec<Step>, Vec<Barrier>, Vec<ResourceState>) {
+        let image_count = self.images.len();
+        let mut states = match previous {
+            Some(previous) => previous,
+            None => vec![ResourceState::default(); image_count + self.buffers.len()],
+        };
+        for (index, image) in self.images.iter().enumerate() {
+            states[index].layout = image.initial_layout;
+        }
+        let state_index = |resource: ResourceRef| match resource {
+            ResourceRef::Image(handle) => handle.0,
+            ResourceRef::Buffer(handle) => image_count + handle.0,
+        };
+
+        let mut touched = vec![false; image_count];
+        let mut steps = Vec::with_capacity(order.len());
+        for &pass_index in order {
+            let pass = &self.passes[pass_index];
+            let mut barriers = vec![];
+            for (resource, info) in pass.desc.merged_accesses() {
+                let index = state_index(resource);
+                if let ResourceRef::Image(handle) = resource {
+                    if !touched[handle.0] {
+                        touched[handle.0] = true;
+                        // aliased memory was last used by the previous image in the slot
+                        if let Some(predecessor) = self.alias_predecessor(handle.0) {
+                            let predecessor_state = states[predecessor];
+                            states[index].write_stage = predecessor_state.write_stage | predecessor_state.read_stages;
+                            states[index].write_access = predecessor_state.write_access;
+                            states[index].read_stages = VkPipelineStageFlags2::empty();
+                            states[index].visible_stages = VkPipelineStageFlags2::empty();
+                        }
+                    }
+                }
+
+                let is_image = matches!(resource, ResourceRef::Image(_));
+                if let Some(barrier) = transit(&mut states[index], resource, info, is_image) {
+                    barriers.push(barrier);
+                }
+            }
+            for (image, layout) in &pass.desc.final_layouts {
+                states[image.0].layout = *layout;
+            }
+            steps.push(Step { pass: pass_index, barriers });
+        }
+
+        let mut final_barriers = vec![];
+        for (image, usage) in &self.exports {
+            if let Some(barrier) = transit(&mut states[image.0], ResourceRef::Image(*image), AccessInfo::from(*usage), true) {
+                final_barriers.push(barrier);
+            }
+        }
+
+        (steps, final_barriers, states)
+    }
+
+    fn alias_predecessor(&self, image: usize) -> Option<usize> {
+        self.alias_slots.iter()
+            .find_map(|slot| {
+                let position = slot.iter().position(|&member| member == image)?;
+                Some(slot[(position + slot.len() - 1) % slot.len()])
+            })
+            .filter(|&predecessor| predecessor != image)
+    }
+
+    fn allocate_transients(&mut self, vulkan: &Vulkan, extent: VkExtent2D, order: &[usize]) {
+        self.transient_views.clear();
+        self.transient_images.clear();
+        self.transient_memory.clear();
+        self.alias_slots.clear();
+
+        let (first_use, last_use) = self.lifetimes(order);
+        let mut transients: Vec<(usize, VkMemoryRequirements)> = vec![];
+        for (index, resource) in self.images.iter_mut().enumerate() {
+            let Some(desc) = &resource.transient else { continue };
+            if first_use[index] == usize::MAX {
+                continue;
+            }
+
+            let image_extent = desc.extent.resolve(extent);
+            let image = vulkan.create_image(desc.format, VkImageType::IT_2D, false, 1, 1, VkExtent3D {
+                width: image_extent.width,
+                height: image_extent.height,
+                depth: 1,
+            }, desc.samples, resource.usage.clone());
+            // dedicated allocation hints are ignored, aliasing needs shared blocks
+            let (requirements, _) = vulkan.get_image_memory_requirements(&image);
+
+            resource.physical = GraphImage {
+                image,
+                view: VkImageView::none(),
+                format: desc.format,
+                extent: image_extent,
+                aspect: desc.aspect,
+            };
+            self.transient_images.push(VkDestroy::new(image, vulkan));
+            transients.push((index, requirements));
+        }
+        let slots = pack_transients(transients, &first_use, &last_use);
+
+        let mut bind_tasks = Vec::with_capacity(self.transient_images.len());
+        for (members, requirements) in &slots {
+            let size = requirements.iter().map(|req| req.size).max().unwrap_or(0);
+            let memory_type = vulkan.find_memory_type(requirements, VkMemoryPropertyFlags::DEVICE_LOCAL_BIT)
+                .expect("Failed to find memory type for transient images");
+            let memory = vulkan.allocate_memory_object(size, memory_type);
+            for &member in members {
+                bind_tasks.push(self.images[member].physical.image.build_bind_task(memory, 0));
+            }
+            self.transient_memory.push(VkDestroy::new(memory, vulkan));
+        }
+        vulkan.bind_memory_to_image(bind_tasks);
+
+        for (members, _) in &slots {
+            for &member in members {
+                let physical = &mut self.images[member].physical;
+                physical.view = vulkan.create_image_view(&physical.image, VkImageViewType::IVT_2D, physical.format, physical.aspect);
+                self.transient_views.push(VkDestroy::new(physical.view, vulkan));
+            }
+        }
+
+        #[cfg(debug_assertions)]
+        println!("Render graph packed {} transient images into {} memory blocks", self.transient_images.len(), slots.len());
+
+        self.alias_slots = slots.into_iter().map(|(members, _)| members).collect();
+    }
+
+    /// First and last position in `order` each image is used at, first use is `usize::MAX` for unused images
+    fn lifetimes(&self, order: &[usize]) -> (Vec<usize>, Vec<usize>) {
+        let mut first_use = vec![usize::MAX; self.images.len()];
+        let mut last_use = vec![0usize; self.images.len()];
+        for (position, &pass_index) in order.iter().enumerate() {
+            for (resource, _) in &self.passes[pass_index].desc.accesses {
+                if let ResourceRef::Image(handle) = resource {
+                    first_use[handle.0] = first_use[handle.0].min(position);
+                    last_use[handle.0] = last_use[handle.0].max(position);
+                }
+            }
+        }
+        (first_use, last_use)
+    }
+}
+
+/// Greedy interval packing, images with disjoint lifetimes and a common memory type share one memory block
+fn pack_transients(mut transients: Vec<(usize, VkMemoryRequirements)>, first_use: &[usize], last_use: &[usize]) -> Vec<(Vec<usize>, Vec<VkMemoryRequirements>)> {
+    transients.sort_by_key(|(index, _)| first_use[*index]);
+
+    let mut slots: Vec<(Vec<usize>, usize, Vec<VkMemoryRequirements>)> = vec![];
+    for (index, requirements) in transients {
+        let type_bits = |reqs: &[VkMemoryRequirements]| reqs.iter().fold(requirements.memoryTypeBits, |bits, req| bits & req.memoryTypeBits);
+        let slot = slots.iter_mut().find(|(_, slot_last_use, reqs)| {
+            *slot_last_use < first_use[index] && type_bits(reqs) != 0
+        });
+        match slot {
+            Some((members, slot_last_use, reqs)) => {
+                members.push(index);
+                *slot_last_use = last_use[index];
+                reqs.push(requirements);
+            }
+            None => slots.push((vec![index], last_use[index], vec![requirements])),
+        }
+    }
+    slots.into_iter().map(|(members, _, requirements)| (members, requirements)).collect()
+}
+
+/// Moves resource into new access, returns barrier if one is needed
+fn transit(state: &mut ResourceState, resource: ResourceRef, info: AccessInfo, is_image: bool) -> Option<Barrier> {
+    let layout_change = is_image && state.layout != info.layout;
+    let old_layout = state.layout;
+    let barrier = |src_stage, src_access| Barrier {
+        resource,
+        src_stage,
+        dst_stage: info.stage,
+        src_access,
+        dst_access: info.access,
+        old_layout,
+        new_layout: if is_image { info.layout } else { VkImageLayout::UNDEFINED },
+    };
+
+    if info.write || layout_change {
+        let src_stage = state.write_stage | state.read_stages;
+        let result = if layout_change || !src_stage.is_empty() {
+            Some(barrier(src_stage, state.write_access))
+        } else {
+            None
+        };
+
+        if info.write {
+            state.write_stage = info.stage;
+            state.write_access = info.access;
+        } else {
+            // layout transition acts as a write that is already visible to this stage
+            state.write_stage = info.stage;
+            state.write_access = VkAccessFlags2::empty();
+        }
+        state.read_stages = if info.write { VkPipelineStageFlags2::empty() } else { info.stage };
+        // a write is not visible to later reads, not even from its own stage
+        state.visible_stages = if info.write { VkPipelineStageFlags2::empty() } else { info.stage };
+        state.layout = if is_image { info.layout } else { state.layout };
+        return result;
+    }
+
+    state.read_stages |= info.stage;
+    if state.write_stage.is_empty() || state.visible_stages.contains(info.stage) {
+        return None;
+    }
+    state.visible_stages |= info.stage;
+    Some(barrier(state.write_stage, state.write_access))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noop(_: &mut (), _: &Vulkan, _: &PassContext) {}
+
+    fn transient(graph: &mut RenderGraph<()>, name: &'static str) -> ImageHandle {
+        graph.create_image(name, ImageDesc::attachment(VkFormat::R8G8B8A8_UNORM, VkImageAspectFlags::COLOR_BIT, VkSampleCountFlags::SC_1_BIT))
+    }
+
+    fn requirements(memory_type_bits: u32) -> VkMemoryRequirements {
+        VkMemoryRequirements { size: 1024, alignment: 256, memoryTypeBits: memory_type_bits }
+    }
+
+    /// a -> b -> c -> swapchain, each pass samples the previous image
+    fn chain() -> (RenderGraph<()>, [ImageHandle; 4]) {
+        let mut graph = RenderGraph::new();
+        let swapchain = graph.import_image("swapchain", VkImageLayout::UNDEFINED);
+        let a = transient(&mut graph, "a");
+        let b = transient(&mut graph, "b");
+        let c = transient(&mut graph, "c");
+        let fragment = ResourceUsage::Sampled(VkPipelineStageFlags2::FRAGMENT_SHADER_BIT);
+        graph.add_pass("a", PassDesc::new().image(a, ResourceUsage::ColorAttachment), noop);
+        graph.add_pass("b", PassDesc::new().image(a, fragment).image(b, ResourceUsage::ColorAttachment), noop);
+        graph.add_pass("c", PassDesc::new().image(b, fragment).image(c, ResourceUsage::ColorAttachment), noop);
+        graph.add_pass("present", PassDesc::new().image(c, fragment).image(swapchain, ResourceUsage::ColorAttachment), noop);
+        graph.export_image(swapchain, ResourceUsage::Present);
+        (graph, [swapchain, a, b, c])
+    }
+
+    #[test]
+    fn schedules_in_dependency_order() {
+        let (graph, _) = chain();
+        assert_eq!(graph.schedule(), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn reader_runs_before_later_writer() {
+        let mut graph = RenderGraph::<()>::new();
+        let buffer = graph.import_buffer("buffer");
+        let compute = VkPipelineStageFlags2::COMPUTE_SHADER_BIT;
+        graph.add_pass("write", PassDesc::new().buffer(buffer, ResourceUsage::StorageWrite(compute)), noop);
+        graph.add_pass("read", PassDesc::new().buffer(buffer, ResourceUsage::StorageRead(compute)), noop);
+        graph.add_pass("overwrite", PassDesc::new().buffer(buffer, ResourceUsage::StorageWrite(compute)), noop);
+        assert_eq!(graph.schedule(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn culls_passes_without_consumers() {
+        let (mut graph, [swapchain, _, _, c]) = chain();
+        let unused = transient(&mut graph, "unused");
+        let kept = transient(&mut graph, "kept");
+        graph.add_pass("unused", PassDesc::new().image(unused, ResourceUsage::ColorAttachment), noop);
+        graph.add_pass("kept", PassDesc::new().image(kept, ResourceUsage::ColorAttachment).side_effect(), noop);
+        graph.add_pass("overlay", PassDesc::new().image(c, ResourceUsage::ColorAttachment), noop);
+        assert_eq!(graph.schedule(), vec![0, 1, 2, 3, 5]);
+
+        // writing an imported image keeps a pass and everything it reads, overlay included
+        graph.add_pass("blit", PassDesc::new().image(c, ResourceUsage::TransferRead).image(swapchain, ResourceUsage::TransferWrite), noop);
+        assert_eq!(graph.schedule(), vec![0, 1, 2, 3, 5, 6, 7]);
+    }
+
+    #[test]
+    fn disjoint_lifetimes_share_a_slot() {
+        let (graph, [_, a, b, c]) = chain();
+        let (first_use, last_use) = graph.lifetimes(&graph.schedule());
+        let transients = [a, b, c].iter().map(|image| (image.0, requirements(0b11))).collect();
+        let slots = pack_transients(transients, &first_use, &last_use);
+        let members = slots.into_iter().map(|(members, _)| members).collect::<Vec<_>>();
+        assert_eq!(members, vec![vec![a.0, c.0], vec![b.0]]);
+    }
+
+    #[test]
+    fn incompatible_memory_types_do_not_alias() {
+        let (graph, [_, a, b, c]) = chain();
+        let (first_use, last_use) = graph.lifetimes(&graph.schedule());
+        let transients = vec![(a.0, requirements(0b01)), (b.0, requirements(0b01)), (c.0, requirements(0b10))];
+        assert_eq!(pack_transients(transients, &first_use, &last_use).len(), 3);
+    }
+
+    #[test]
+    fn simulate_emits_layout_transitions() {
+        let (graph, [swapchain, a, b, _]) = chain();
+        let (steps, final_barriers, _) = graph.simulate(&graph.schedule(), None);
+
+        let first = &steps[0].barriers;
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].resource, ResourceRef::Image(a));
+        assert_eq!((first[0].old_layout, first[0].new_layout), (VkImageLayout::UNDEFINED, VkImageLayout::COLOR_ATTACHMENT_OPTIMAL));
+
+        let sampled = steps[1].barriers.iter().find(|barrier| barrier.resource == ResourceRef::Image(a)).unwrap();
+        assert_eq!(sampled.new_layout, VkImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        assert_eq!(sampled.src_stage, VkPipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT_BIT);
+        assert_eq!(sampled.dst_stage, VkPipelineStageFlags2::FRAGMENT_SHADER_BIT);
+        assert_eq!(sampled.src_access, ResourceUsage::ColorAttachment.access());
+        assert!(steps[1].barriers.iter().any(|barrier| barrier.resource == ResourceRef::Image(b)));
+
+        assert_eq!(final_barriers.len(), 1);
+        assert_eq!(final_barriers[0].resource, ResourceRef::Image(swapchain));
+        assert_eq!(final_barriers[0].new_layout, VkImageLayout::PRESENT_SRC_KHR);
+    }
+
+    #[test]
+    fn aliased_image_waits_for_predecessor() {
+        let (mut graph, [_, a, _, c]) = chain();
+        graph.alias_slots = vec![vec![a.0, c.0]];
+        let (steps, _, _) = graph.simulate(&graph.schedule(), None);
+
+        // c takes over a's memory after pass b sampled it
+        let takeover = steps[2].barriers.iter().find(|barrier| barrier.resource == ResourceRef::Image(c)).unwrap();
+        assert!(takeover.src_stage.contains(VkPipelineStageFlags2::FRAGMENT_SHADER_BIT));
+        assert_eq!(takeover.old_layout, VkImageLayout::UNDEFINED);
+    }
+
+    #[test]
+    fn read_after_read_needs_no_barrier() {
+        let mut graph = RenderGraph::<()>::new();
+        let buffer = graph.import_buffer("buffer");
+        let compute = VkPipelineStageFlags2::COMPUTE_SHADER_BIT;
+        graph.add_pass("write", PassDesc::new().buffer(buffer, ResourceUsage::StorageWrite(compute)), noop);
+        graph.add_pass("read", PassDesc::new().buffer(buffer, ResourceUsage::StorageRead(compute)), noop);
+        graph.add_pass("read again", PassDesc::new().buffer(buffer, ResourceUsage::StorageRead(compute)).side_effect(), noop);
+        let (steps, _, _) = graph.simulate(&graph.schedule(), None);
+        assert_eq!(steps.iter().map(|step| step.barriers.len()).collect::<Vec<_>>(), vec![0, 1, 0]);
+    }
+}
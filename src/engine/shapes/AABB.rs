@@ -41,9 +41,9 @@ pub struct AABB4(pub Vec3x8);
 impl AABB4 {
     pub fn new<A: AABB>(a: &A, b: &A, c: &A, d: &A) -> Self {
         Self(Vec3x8 {
-                x: f32x8::new([a.box_min().x, a.box_min().x, b.box_min().x, b.box_min().x, c.box_min().x, c.box_min().x, d.box_min().x, d.box_min().x]),
-                y: f32x8::new([a.box_min().y, a.box_min().y, b.box_min().y, b.box_min().y, c.box_min().y, c.box_min().y, d.box_min().y, d.box_min().y]),
-                z: f32x8::new([a.box_min().z, a.box_min().z, b.box_min().z, b.box_min().z, c.box_min().z, c.box_min().z, d.box_min().z, d.box_min().z]),
+                x: f32x8::new([a.box_min().x, a.box_max().x, b.box_min().x, b.box_max().x, c.box_min().x, c.box_max().x, d.box_min().x, d.box_max().x]),
+                y: f32x8::new([a.box_min().y, a.box_max().y, b.box_min().y, b.box_max().y, c.box_min().y, c.box_max().y, d.box_min().y, d.box_max().y]),
+                z: f32x8::new([a.box_min().z, a.box_max().z, b.box_min().z, b.box_max().z, c.box_min().z, c.box_max().z, d.box_min().z, d.box_max().z]),
             })
     }
 
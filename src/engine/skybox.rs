@@ -0,0 +1,460 @@
+use crate::engine::camera::Camera;
+use crate::engine::pipelines::create_pipelines_multithreaded;
+use crate::prelude::pool_alloc::Image;
+use crate::prelude::*;
+use crate::vulkan::func::Vulkan;
+use crate::vulkan::gltf::utils::StagingBuffer;
+use crate::vulkan::utils::ImageUsage;
+use png::{Decoder, Transformations};
+use shaders::common::SkyboxParams;
+use std::f32::consts::PI;
+use std::io::Cursor;
+use std::ptr::null_mut;
+use ultraviolet::{Mat4, Vec3, Vec4};
+
+pub const CUBEMAP_HDR_FORMAT: VkFormat = VkFormat::R16G16B16A16_SFLOAT;
+pub const CUBEMAP_LDR_FORMAT: VkFormat = VkFormat::R8G8B8A8_SRGB;
+
+/// Six layer image with a cube view, faces ordered +X, -X, +Y, -Y, +Z, -Z
+pub struct Cubemap {
+    pub view: VkDestroy<VkImageView>,
+    pub image: Image,
+    pub format: VkFormat,
+    pub size: u32,
+    pub mips: u32,
+    /// FNV-1a of the bytes the cubemap was created from, keys the IBL cache. `None` for contents written on the GPU
+    pub source_hash: Option<u64>,
+}
+
+impl Cubemap {
+    /// Faces are PNG files of equal square size, any color type or bit depth is converted to 8 bit RGBA
+    pub fn from_faces(vulkan: &Vulkan, faces: [&[u8]; 6]) -> Result<Cubemap, String> {
+        let mut size = 0;
+        let mut data = vec![];
+        for (index, face) in faces.into_iter().enumerate() {
+            let mut decoder = Decoder::new(Cursor::new(face));
+            // palette and low bit depths to 8 bits, 16 bit channels stripped to 8
+            decoder.set_transformations(Transformations::normalize_to_color8());
+            let mut reader = decoder.read_info().map_err(|e| format!("Cubemap face {index}: {e}"))?;
+            let mut buf = vec![0; reader.output_buffer_size().ok_or(format!("Cubemap face {index} is too large"))?];
+            let info = reader.next_frame(&mut buf).map_err(|e| format!("Cubemap face {index}: {e}"))?;
+            if info.width != info.height || (size != 0 && info.width != size) {
+                return Err(format!("Cubemap faces must be squares of the same size, face {index} is {}x{}", info.width, info.height));
+            }
+            size = info.width;
+
+            let pixels = &buf[..info.buffer_size()];
+            match info.color_type {
+                png::ColorType::Rgba => data.extend_from_slice(pixels),
+                png::ColorType::Rgb => data.extend(pixels.chunks(3).flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])),
+                png::ColorType::GrayscaleAlpha => data.extend(pixels.chunks(2).flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]])),
+                png::ColorType::Grayscale => data.extend(pixels.iter().flat_map(|&g| [g, g, g, 255])),
+                png::ColorType::Indexed => return Err(format!("Cubemap face {index} palette was not expanded")),
+            }
+        }
+
+        let mut cubemap = Cubemap::from_raw(vulkan, CUBEMAP_LDR_FORMAT, size, 1, &data);
+        cubemap.source_hash = Some(fnv1a(faces.into_iter().flatten().copied()));
+        Ok(cubemap)
+    }
+
+    /// Radiance .hdr (RGBE) in equirectangular projection, resampled into faces of `face_size`.
+    /// Errors on malformed files, see `decode_hdr`
+    pub fn from_equirect_hdr(vulkan: &Vulkan, bytes: &[u8], face_size: u32) -> Result<Cubemap, String> {
+        let (pixels, width, height) = decode_hdr(bytes)?;
+        let data = equirect_to_faces(&pixels, width, height, face_size);
+        let mut cubemap = Cubemap::from_raw(vulkan, CUBEMAP_HDR_FORMAT, face_size, 1, bytemuck::cast_slice(&data));
+        cubemap.source_hash = Some(fnv1a(face_size.to_le_bytes().into_iter().chain(bytes.iter().copied())));
+        Ok(cubemap)
+    }
+
+    /// Raw texels laid out mip by mip, each mip holding all six faces
+    pub fn from_raw(vulkan: &Vulkan, format: VkFormat, size: u32, mips: u32, data: &[u8]) -> Cubemap {
+        let mut cubemap = Cubemap::allocate(vulkan, format, size, mips, ImageUsage::default().sampled(true).transfer_dst(true).transfer_src(true));
+        upload_image(vulkan, cubemap.image.image, size, 6, mips, data);
+        let layout = [data.len() as u32, size, mips];
+        cubemap.source_hash = Some(fnv1a(layout.iter().flat_map(|value| value.to_le_bytes()).chain(data.iter().copied())));
+        cubemap
+    }
+
+    /// Uninitialized cubemap, contents are up to the caller
+    pub fn allocate(vulkan: &Vulkan, format: VkFormat, size: u32, mips: u32, usage: ImageUsage) -> Cubemap {
+        let image = vulkan.pool().allocate_image(format, VkImageType::IT_2D, true, mips, 6, VkExtent3D { width: size, height: size, depth: 1 },
+                                                 VkSampleCountFlags::SC_1_BIT, usage);
+        let view = vulkan.create_image_view(&image.image, VkImageViewType::IVT_CUBE, format, VkImageAspectFlags::COLOR_BIT);
+
+        Cubemap {
+            view: VkDestroy::new(view, vulkan),
+            image,
+            format,
+            size,
+            mips,
+            source_hash: None,
+        }
+    }
+}
+
+fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes.into_iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Copies tightly packed `data` (mip by mip, all layers each) into a square image and leaves it in SHADER_READ_ONLY_OPTIMAL
+pub fn upload_image(vulkan: &Vulkan, image: VkImage, size: u32, layers: u32, mips: u32, data: &[u8]) {
+    let mut staging = StagingBuffer::new();
+    let staging_buffer = staging.pull(data.len() as u64, vulkan);
+    let staging_ptr = staging_buffer.map_memory(vulkan);
+    Vulkan::copy_info(staging_ptr, data.as_ptr(), data.len());
+    staging_buffer.flush_memory(vulkan);
+
+    let texel_size = data.len() / (0..mips).map(|mip| ((size >> mip).max(1) as usize).pow(2) * layers as usize).sum::<usize>();
+    let mut offset = 0;
+    let regions = (0..mips).map(|mip| {
+        let mip_size = (size >> mip).max(1);
+        let region = VkBufferImageCopy {
+            bufferOffset: offset as VkDeviceSize,
+            bufferRowLength: 0,
+            bufferImageHeight: 0,
+            imageSubresource: VkImageSubresourceLayers {
+                aspectMask: VkImageAspectFlags::COLOR_BIT,
+                mipLevel: mip,
+                baseArrayLayer: 0,
+                layerCount: layers,
+            },
+            imageOffset: Default::default(),
+            imageExtent: VkExtent3D { width: mip_size, height: mip_size, depth: 1 },
+        };
+        offset += (mip_size as usize).pow(2) * layers as usize * texel_size;
+        region
+    }).collect::<Vec<_>>();
+
+    vulkan.immediate_submit(|command_buffer| {
+        vulkan.transition_images2(vec![ImageTransition2 {
+            image,
+            src_stage: VkPipelineStageFlags2::NONE,
+            dst_stage: VkPipelineStageFlags2::COPY_BIT,
+            src_access: VkAccessFlags2::NONE,
+            dst_access: VkAccessFlags2::TRANSFER_WRITE_BIT,
+            old_layout: VkImageLayout::UNDEFINED,
+            new_layout: VkImageLayout::TRANSFER_DST_OPTIMAL,
+            ..Default::default()
+        }], command_buffer);
+
+        vulkan.buffer_to_image(regions, command_buffer, **staging_buffer, image, VkImageLayout::TRANSFER_DST_OPTIMAL);
+
+        vulkan.transition_images2(vec![ImageTransition2 {
+            image,
+            src_stage: VkPipelineStageFlags2::COPY_BIT,
+            dst_stage: VkPipelineStageFlags2::FRAGMENT_SHADER_BIT,
+            src_access: VkAccessFlags2::TRANSFER_WRITE_BIT,
+            dst_access: VkAccessFlags2::SHADER_SAMPLED_READ_BIT,
+            old_layout: VkImageLayout::TRANSFER_DST_OPTIMAL,
+            new_layout: VkImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ..Default::default()
+        }], command_buffer);
+    });
+}
+
+/// Environment drawn behind everything, recorded at the end of the scene pass
+#[derive(Default)]
+pub struct Skybox {
+    pub intensity: f32,
+    environment: Option<Cubemap>,
+
+    pipeline: VkDestroy<VkPipeline>,
+    descriptor_set: VkDescriptorSet,
+    descriptor_pool: VkDestroy<VkDescriptorPool>,
+    descriptor_layout: VkDestroy<VkDescriptorSetLayout>,
+    layout: VkDestroy<VkPipelineLayout>,
+    sampler: VkDestroy<VkSampler>,
+}
+
+impl Skybox {
+    /// `template` is the scene pipeline, its render targets and sample count are reused
+    pub fn new(vulkan: &Vulkan, template: GraphicsPipelineCreateInfo) -> Self {
+        let sampler = vulkan.create_sampler(SamplerInfo {
+            min_filter: VkFilter::LINEAR,
+            mag_filter: VkFilter::LINEAR,
+            address_mode_u: VkSamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_v: VkSamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_w: VkSamplerAddressMode::CLAMP_TO_EDGE,
+            ..Default::default()
+        });
+
+        let bindings = [VkDescriptorSetLayoutBinding {
+            binding: 0,
+            descriptorType: VkDescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptorCount: 1,
+            stageFlags: VkShaderStageFlags::FRAGMENT_BIT,
+            pImmutableSamplers: null_mut(),
+        }];
+        let descriptor_layout = vulkan.create_descriptor_set_layout(&bindings);
+        let descriptor_pool = vulkan.create_descriptor_pool(&[VkDescriptorPoolSize {
+            descriptorType: VkDescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptorCount: 1,
+        }], 1, false);
+        let descriptor_set = vulkan.allocate_descriptor_sets(descriptor_pool, &[descriptor_layout])[0];
+
+        let push_constant_ranges = [push_constant_range::<SkyboxParams>()];
+        graphics_interface("skybox", "skybox").check_layout("skybox", &[&bindings], &push_constant_ranges);
+        let layout = vulkan.create_pipeline_layout_with::<SkyboxParams>(&[descriptor_layout]);
+        let pipeline = create_pipelines_multithreaded(true, vec![preset_skybox(template, layout)], vulkan)[0];
+
+        Skybox {
+            intensity: 1.0,
+            environment: None,
+            pipeline: VkDestroy::new(pipeline, vulkan),
+            descriptor_set,
+            descriptor_pool: VkDestroy::new(descriptor_pool, vulkan),
+            descriptor_layout: VkDestroy::new(descriptor_layout, vulkan),
+            layout: VkDestroy::new(layout, vulkan),
+            sampler: VkDestroy::new(sampler, vulkan),
+        }
+    }
+
+    /// Recreates the pipeline from a `template` with new shader modules, layout and descriptors are kept
+    pub fn rebuild_pipeline(&mut self, vulkan: &Vulkan, template: GraphicsPipelineCreateInfo) {
+        let pipeline = create_pipelines_multithreaded(true, vec![preset_skybox(template, *self.layout)], vulkan)[0];
+        self.pipeline = VkDestroy::new(pipeline, vulkan);
+    }
+
+    pub fn environment(&self) -> Option<&Cubemap> {
+        self.environment.as_ref()
+    }
+
+    /// Replaces the environment, `None` disables the skybox. Waits for the device, the old cubemap may still be in use
+    pub fn set_environment(&mut self, vulkan: &Vulkan, environment: Option<Cubemap>) {
+        vulkan.device_wait();
+        if let Some(cubemap) = &environment {
+            vulkan.update_descriptor_sets(vec![ImageDescriptorInfo {
+                target_descriptor: DescriptorSetInfo {
+                    descriptor_set: self.descriptor_set,
+                    descriptor_binding: 0,
+                    array_element: 0,
+                },
+                target_descriptor_type: VkDescriptorType::COMBINED_IMAGE_SAMPLER,
+                image_infos: vec![VkDescriptorImageInfo {
+                    sampler: *self.sampler,
+                    imageView: *cubemap.view,
+                    imageLayout: VkImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                }],
+            }], vec![], vec![], vec![]);
+        }
+        self.environment = environment;
+    }
+
+    /// Expects viewport and scissor of the scene pass to be set
+    pub fn record(&self, vulkan: &Vulkan, command_buffer: VkCommandBuffer, camera: &mut Camera) {
+        if self.environment.is_none() {
+            return;
+        }
+
+        let mut view = camera.view_matrix();
+        view.cols[3] = Vec4::new(0.0, 0.0, 0.0, 1.0);
+        let inv_view_proj: Mat4 = (camera.projection_matrix() * view).inversed();
+        let params = SkyboxParams {
+            inv_view_proj: inv_view_proj.cols.map(|col| [col.x, col.y, col.z, col.w]),
+            intensity: self.intensity,
+            lod: 0.0,
+            _pad: [0.0; 2],
+        };
+
+        vulkan.bind_pipeline(command_buffer, VkPipelineBindPoint::GRAPHICS, *self.pipeline);
+        vulkan.bind_descriptor_sets(command_buffer, VkPipelineBindPoint::GRAPHICS, *self.layout, 0, &[self.descriptor_set], &[]);
+        vulkan.push_constants(command_buffer, *self.layout, &params);
+        vulkan.draw(command_buffer, 3, 1, 0, 0);
+    }
+}
+
+/// Decodes Radiance RGBE, both flat and run-length encoded scanlines. Malformed or truncated files are an error
+pub fn decode_hdr(bytes: &[u8]) -> Result<(Vec<[f32; 3]>, u32, u32), String> {
+    if !bytes.starts_with(b"#?") {
+        return Err("Invalid HDR magic, corrupt environment file".to_string());
+    }
+
+    // header ends with an empty line, followed by the resolution line
+    let mut cursor = 0;
+    let mut lines = 0;
+    let (width, height) = loop {
+        let end = bytes[cursor..].iter().position(|&b| b == b'\n').map(|p| cursor + p).ok_or("Truncated HDR header")?;
+        let line = std::str::from_utf8(&bytes[cursor..end]).unwrap_or("");
+        cursor = end + 1;
+        lines += 1;
+
+        if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+            return Err(format!("Unsupported HDR format {}", line));
+        }
+        let parts = line.split_whitespace().collect::<Vec<_>>();
+        if parts.len() == 4 && parts[0] == "-Y" && parts[2] == "+X" {
+            let width = parts[3].parse::<u32>().map_err(|_| format!("Invalid HDR width {}", parts[3]))?;
+            let height = parts[1].parse::<u32>().map_err(|_| format!("Invalid HDR height {}", parts[1]))?;
+            break (width, height);
+        }
+        if lines > 64 {
+            return Err("HDR resolution line not found, only -Y +X orientation is supported".to_string());
+        }
+    };
+    if width == 0 || height == 0 {
+        return Err(format!("Empty HDR image {}x{}", width, height));
+    }
+
+    let truncated = || "Truncated HDR pixel data".to_string();
+    let mut data = &bytes[cursor..];
+    // the longest run covers 127 texels of one channel in 2 bytes, wider scanlines can't fit into the file
+    if width as usize / 127 * 8 > data.len() {
+        return Err(truncated());
+    }
+    let mut pixels = Vec::with_capacity((width as usize).saturating_mul(height as usize).min(data.len()));
+    let mut scanline = vec![[0u8; 4]; width as usize];
+    for _ in 0..height {
+        let is_rle = width >= 8 && width < 0x8000 && data.len() >= 4
+            && data[0] == 2 && data[1] == 2 && ((data[2] as u32) << 8 | data[3] as u32) == width;
+        if is_rle {
+            data = &data[4..];
+            for channel in 0..4 {
+                let mut x = 0;
+                while x < width as usize {
+                    let &count = data.first().ok_or_else(truncated)?;
+                    let count = count as usize;
+                    if count > 128 {
+                        let run = count - 128;
+                        if x + run > width as usize {
+                            return Err(format!("HDR run of {} overflows scanline of {}", run, width));
+                        }
+                        let &value = data.get(1).ok_or_else(truncated)?;
+                        scanline[x..x + run].iter_mut().for_each(|texel| texel[channel] = value);
+                        x += run;
+                        data = &data[2..];
+                    } else {
+                        if count == 0 || x + count > width as usize {
+                            return Err(format!("HDR literal of {} overflows scanline of {}", count, width));
+                        }
+                        let values = data.get(1..=count).ok_or_else(truncated)?;
+                        scanline[x..x + count].iter_mut().zip(values).for_each(|(texel, &value)| texel[channel] = value);
+                        x += count;
+                        data = &data[1 + count..];
+                    }
+                }
+            }
+        } else {
+            if data.len() < scanline.len() * 4 {
+                return Err(truncated());
+            }
+            for texel in scanline.iter_mut() {
+                texel.copy_from_slice(&data[..4]);
+                data = &data[4..];
+            }
+        }
+
+        pixels.extend(scanline.iter().map(|&[r, g, b, e]| {
+            if e == 0 {
+                [0.0; 3]
+            } else {
+                let scale = 2f32.powi(e as i32 - 136);
+                [r as f32 * scale, g as f32 * scale, b as f32 * scale]
+            }
+        }));
+    }
+
+    Ok((pixels, width, height))
+}
+
+fn face_direction(face: usize, s: f32, t: f32) -> Vec3 {
+    match face {
+        0 => Vec3::new(1.0, -t, -s),
+        1 => Vec3::new(-1.0, -t, s),
+        2 => Vec3::new(s, 1.0, t),
+        3 => Vec3::new(s, -1.0, -t),
+        4 => Vec3::new(s, -t, 1.0),
+        _ => Vec3::new(-s, -t, -1.0),
+    }.normalized()
+}
+
+/// Bilinear resample into six RGBA16F faces
+fn equirect_to_faces(pixels: &[[f32; 3]], width: u32, height: u32, face_size: u32) -> Vec<u16> {
+    let (width, height) = (width as usize, height as usize);
+    let fetch = |x: usize, y: usize| pixels[y.min(height - 1) * width + x % width];
+
+    let mut data = Vec::with_capacity(face_size as usize * face_size as usize * 6 * 4);
+    for face in 0..6 {
+        for y in 0..face_size {
+            for x in 0..face_size {
+                let s = 2.0 * (x as f32 + 0.5) / face_size as f32 - 1.0;
+                let t = 2.0 * (y as f32 + 0.5) / face_size as f32 - 1.0;
+                let direction = face_direction(face, s, t);
+
+                let u = 0.5 + direction.x.atan2(-direction.z) / (2.0 * PI);
+                let v = direction.y.clamp(-1.0, 1.0).acos() / PI;
+                let fx = u * width as f32 - 0.5;
+                let fy = v * height as f32 - 0.5;
+                let (x0, y0) = (fx.floor(), fy.floor());
+                let (wx, wy) = (fx - x0, fy - y0);
+                let (x0, y0) = (x0.rem_euclid(width as f32) as usize, y0.max(0.0) as usize);
+
+                let top = lerp3(fetch(x0, y0), fetch(x0 + 1, y0), wx);
+                let bottom = lerp3(fetch(x0, y0 + 1), fetch(x0 + 1, y0 + 1), wx);
+                let color = lerp3(top, bottom, wy);
+                data.extend([f32_to_f16(color[0]), f32_to_f16(color[1]), f32_to_f16(color[2]), f32_to_f16(1.0)]);
+            }
+        }
+    }
+    data
+}
+
+fn lerp3(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+    [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t, a[2] + (b[2] - a[2]) * t]
+}
+
+/// Round-to-zero half conversion, out of range values saturate and subnormals flush to zero
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mantissa = bits & 0x7f_ffff;
+
+    if value.is_nan() {
+        sign | 0x7e00
+    } else if exponent >= 0x1f {
+        sign | 0x7bff
+    } else if exponent <= 0 {
+        sign
+    } else {
+        sign | ((exponent as u16) << 10) | (mantissa >> 13) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decode_hdr;
+
+    const HEADER: &[u8] = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 8\n";
+
+    fn rle_scanline() -> Vec<u8> {
+        let mut bytes = vec![2, 2, 0, 8];
+        for value in [128u8, 64, 32, 129] {
+            bytes.extend([128 + 8, value]);
+        }
+        bytes
+    }
+
+    #[test]
+    fn decodes_rle_scanline() {
+        let (pixels, width, height) = decode_hdr(&[HEADER, &rle_scanline()].concat()).unwrap();
+        assert_eq!((width, height), (8, 1));
+        assert_eq!(pixels, vec![[1.0, 0.5, 0.25]; 8]);
+    }
+
+    #[test]
+    fn truncated_file_is_an_error() {
+        let bytes = [HEADER, &rle_scanline()].concat();
+        for len in 0..bytes.len() {
+            assert!(decode_hdr(&bytes[..len]).is_err(), "{} of {} bytes decoded", len, bytes.len());
+        }
+    }
+
+    #[test]
+    fn overlong_run_is_an_error() {
+        let mut scanline = rle_scanline();
+        scanline[4] = 128 + 9;
+        assert!(decode_hdr(&[HEADER, &scanline].concat()).is_err());
+    }
+}
@@ -1,10 +1,28 @@
 use crate::prelude::*;
 use crate::vulkan::func::{bool_to_vkbool, Destructible, Vulkan};
-use vulkan_raw::{VkBlendFactor, VkBlendOp, VkBool32, VkColorComponentFlags, VkCompareOp, VkCullModeFlags, VkDescriptorSetLayout, VkDynamicState, VkExtent2D, VkFrontFace, VkLogicOp, VkPipelineLayout, VkPipelineShaderStageCreateFlags, VkPolygonMode, VkPrimitiveTopology, VkRenderPass, VkSampleCountFlagBits, VkSampleCountFlags, VkShaderModule, VkShaderStageFlags, VkStencilOp, VkStencilOpState};
+use std::sync::OnceLock;
+use vulkan_raw::{VkBlendFactor, VkBlendOp, VkBool32, VkColorComponentFlags, VkCompareOp, VkCullModeFlags, VkDescriptorSetLayout, VkDynamicState, VkExtent2D, VkFormat, VkFrontFace, VkLogicOp, VkPipelineLayout, VkPipelineShaderStageCreateFlags, VkPolygonMode, VkPrimitiveTopology, VkRenderPass, VkSampleCountFlagBits, VkSampleCountFlags, VkShaderModule, VkShaderStageFlags, VkStencilOp, VkStencilOpState, VkVertexInputAttributeDescription, VkVertexInputBindingDescription, VkVertexInputRate};
 
 const VERTEX_SHADER: &[u8] = include_bytes!(env!("vertex.spv"));
 const FRAGMENT_SHADER: &[u8] = include_bytes!(env!("fragment.spv"));
 
+/// Interface of the embedded `vertex` crate, parsed on first use
+pub fn vertex_reflection() -> &'static ShaderReflection {
+    static REFLECTION: OnceLock<ShaderReflection> = OnceLock::new();
+    REFLECTION.get_or_init(|| ShaderReflection::parse(VERTEX_SHADER))
+}
+
+/// Interface of the embedded `fragment` crate, parsed on first use
+pub fn fragment_reflection() -> &'static ShaderReflection {
+    static REFLECTION: OnceLock<ShaderReflection> = OnceLock::new();
+    REFLECTION.get_or_init(|| ShaderReflection::parse(FRAGMENT_SHADER))
+}
+
+/// Combined interface of a `vertex` and a `fragment` entry point
+pub fn graphics_interface(vertex_entry: &str, fragment_entry: &str) -> ShaderInterface {
+    ShaderInterface::new(&[(vertex_reflection(), vertex_entry), (fragment_reflection(), fragment_entry)])
+}
+
 #[derive(Default)]
 pub struct PipelineContainer {
     pub layout: VkPipelineLayout,
@@ -23,12 +41,31 @@ impl Drop for PipelineContainer {
     }
 }
 
+impl PipelineContainer {
+    /// Points every `stage` entry of `info` to a module created from `spirv` and destroys the old one.
+    /// Pipelines created before keep working, derived templates have to be cloned again
+    pub fn replace_shader(&mut self, vulkan: &Vulkan, stage: VkShaderStageFlags, spirv: &[u8]) {
+        let module = vulkan.create_shader_module(spirv);
+        for info in self.info.stages.iter_mut().filter(|info| info.stage == stage) {
+            let old = std::mem::replace(&mut info.module, module);
+            if let Some(position) = self.shaders.iter().position(|shader| *shader == old) {
+                self.shaders.swap_remove(position).destroy(vulkan);
+            }
+        }
+        self.shaders.push(module);
+    }
+}
+
 pub fn preset_graphic_pipeline(vulkan: &Vulkan, width: u32, height: u32, render_pass: VkRenderPass, subpass: u32, descriptor_set_layouts: &[VkDescriptorSetLayout]) -> PipelineContainer {
-    let push_constant_ranges = &[
+    let interface = graphics_interface("main", "main");
+    assert!(descriptor_set_layouts.len() as u32 >= interface.set_count(), "Scene shaders use {} descriptor sets, {} given",
+            interface.set_count(), descriptor_set_layouts.len());
+    let push_constant_ranges = interface.push_constant_ranges();
 
-    ];
+    let layout = vulkan.create_pipeline_layout(descriptor_set_layouts, &push_constant_ranges);
 
-    let layout = vulkan.create_pipeline_layout(descriptor_set_layouts, push_constant_ranges);
+    let vertex_input = Vulkan::specify_preset_pos_tex_color();
+    interface.check_vertex_input("scene pipeline", &vertex_input);
 
     let vertex_shader_module = vulkan.create_shader_module(VERTEX_SHADER);
     let frag_shader_module = vulkan.create_shader_module(FRAGMENT_SHADER);
@@ -51,7 +88,7 @@ pub fn preset_graphic_pipeline(vulkan: &Vulkan, width: u32, height: u32, render_
                 specialization_info: None,
             },
         ],
-        vertex_input_state: Some(Vulkan::specify_preset_pos_tex_color()),
+        vertex_input_state: Some(vertex_input),
         input_assembly_state: Some(
             PipelineInputAssemblyStateCreateInfo {
                 flags: Default::default(),
@@ -143,6 +180,7 @@ pub fn preset_graphic_pipeline(vulkan: &Vulkan, width: u32, height: u32, render_
                 VkDynamicState::SCISSOR,
             ],
         }),
+        rendering_info: None,
         layout,
         render_pass,
         subpass,
@@ -180,6 +218,7 @@ pub fn preset_multisample(main_pipeline: GraphicsPipelineCreateInfo, samples: Vk
         depth_stencil_state: main_pipeline.depth_stencil_state,
         color_blend_state: main_pipeline.color_blend_state,
         dynamic_state: main_pipeline.dynamic_state,
+        rendering_info: main_pipeline.rendering_info,
         layout: main_pipeline.layout,
         render_pass: main_pipeline.render_pass,
         subpass: main_pipeline.subpass,
@@ -188,6 +227,301 @@ pub fn preset_multisample(main_pipeline: GraphicsPipelineCreateInfo, samples: Vk
     }
 }
 
+pub fn preset_dynamic_rendering(main_pipeline: GraphicsPipelineCreateInfo, color_formats: &[VkFormat], depth_format: VkFormat) -> GraphicsPipelineCreateInfo {
+    GraphicsPipelineCreateInfo {
+        rendering_info: Some(PipelineRenderingCreateInfo {
+            view_mask: 0,
+            color_attachment_formats: color_formats.to_vec(),
+            depth_attachment_format: depth_format,
+            stencil_attachment_format: VkFormat::UNDEFINED,
+        }),
+        render_pass: VkRenderPass::none(),
+        subpass: 0,
+        ..main_pipeline
+    }
+}
+
+/// Fullscreen triangle for post-processing, reuses shader modules of `main_pipeline`
+pub fn preset_fullscreen(main_pipeline: GraphicsPipelineCreateInfo, fragment_entry: &'static str, layout: VkPipelineLayout, color_format: VkFormat) -> GraphicsPipelineCreateInfo {
+    let stages = main_pipeline.stages.iter()
+        .map(|stage| PipelineShaderStageCreateInfo {
+            name: if stage.stage == VkShaderStageFlags::VERTEX_BIT { "fullscreen" } else { fragment_entry },
+            ..stage.clone()
+        })
+        .collect();
+
+    let main_pipeline = preset_dynamic_rendering(main_pipeline, &[color_format], VkFormat::UNDEFINED);
+    GraphicsPipelineCreateInfo {
+        stages,
+        vertex_input_state: Some(PipelineVertexInputStateCreateInfo {
+            flags: Default::default(),
+            vertex_binding_descriptions: vec![],
+            vertex_attribute_descriptions: vec![],
+        }),
+        multisample_state: Some(PipelineMultisampleStateCreateInfo {
+            flags: Default::default(),
+            rasterization_samples: VkSampleCountFlags::SC_1_BIT,
+            sample_shading_enable: Default::default(),
+            min_sample_shading: 0.0,
+            sample_mask: vec![],
+            alpha_to_coverage_enable: Default::default(),
+            alpha_to_one_enable: Default::default(),
+        }),
+        depth_stencil_state: None,
+        layout,
+        ..main_pipeline
+    }
+}
+
+/// Skybox on the far plane, keeps render targets, samples and depth test of `main_pipeline` but never writes depth
+pub fn preset_skybox(main_pipeline: GraphicsPipelineCreateInfo, layout: VkPipelineLayout) -> GraphicsPipelineCreateInfo {
+    let stages = main_pipeline.stages.iter()
+        .map(|stage| PipelineShaderStageCreateInfo {
+            name: "skybox",
+            ..stage.clone()
+        })
+        .collect();
+    let depth_stencil_state = main_pipeline.depth_stencil_state.clone().map(|state| PipelineDepthStencilStateCreateInfo {
+        depth_test_enable: VkBool32::TRUE,
+        depth_write_enable: VkBool32::FALSE,
+        depth_compare_op: VkCompareOp::LESS_OR_EQUAL,
+        ..state
+    });
+
+    GraphicsPipelineCreateInfo {
+        stages,
+        vertex_input_state: Some(PipelineVertexInputStateCreateInfo {
+            flags: Default::default(),
+            vertex_binding_descriptions: vec![],
+            vertex_attribute_descriptions: vec![],
+        }),
+        depth_stencil_state,
+        layout,
+        ..main_pipeline
+    }
+}
+
+/// Line list with `debug_lines` entries inside the scene pass, never writes depth. Without `depth_test` lines draw over everything
+pub fn preset_debug_lines(main_pipeline: GraphicsPipelineCreateInfo, layout: VkPipelineLayout, depth_test: bool) -> GraphicsPipelineCreateInfo {
+    let stages = main_pipeline.stages.iter()
+        .map(|stage| PipelineShaderStageCreateInfo {
+            name: "debug_lines",
+            ..stage.clone()
+        })
+        .collect();
+
+    // position followed by linear RGBA color as floats
+    let vertex_input_state = PipelineVertexInputStateCreateInfo {
+        flags: Default::default(),
+        vertex_binding_descriptions: vec![VkVertexInputBindingDescription {
+            binding: 0,
+            stride: 7 * size_of::<f32>() as u32,
+            inputRate: VkVertexInputRate::VERTEX,
+        }],
+        vertex_attribute_descriptions: vec![
+            VkVertexInputAttributeDescription {
+                location: 0,
+                binding: 0,
+                format: VkFormat::R32G32B32_SFLOAT,
+                offset: 0,
+            },
+            VkVertexInputAttributeDescription {
+                location: 1,
+                binding: 0,
+                format: VkFormat::R32G32B32A32_SFLOAT,
+                offset: 3 * size_of::<f32>() as u32,
+            },
+        ],
+    };
+    graphics_interface("debug_lines", "debug_lines").check_vertex_input("debug lines", &vertex_input_state);
+    let depth_stencil_state = main_pipeline.depth_stencil_state.clone().map(|state| PipelineDepthStencilStateCreateInfo {
+        depth_test_enable: bool_to_vkbool(depth_test),
+        depth_write_enable: VkBool32::FALSE,
+        depth_compare_op: VkCompareOp::LESS_OR_EQUAL,
+        ..state
+    });
+
+    GraphicsPipelineCreateInfo {
+        stages,
+        vertex_input_state: Some(vertex_input_state),
+        input_assembly_state: Some(PipelineInputAssemblyStateCreateInfo {
+            flags: Default::default(),
+            topology: VkPrimitiveTopology::LINE_LIST,
+            primitive_restart_enable: bool_to_vkbool(false),
+        }),
+        depth_stencil_state,
+        layout,
+        ..main_pipeline
+    }
+}
+
+/// Scene geometry with the `debug_view` fragment entry and the scene layout. `additive` sums every layer without depth testing,
+/// `VkPolygonMode::LINE` needs the `fillModeNonSolid` device feature
+pub fn preset_debug_view(main_pipeline: GraphicsPipelineCreateInfo, polygon_mode: VkPolygonMode, additive: bool) -> GraphicsPipelineCreateInfo {
+    let stages = main_pipeline.stages.iter()
+        .map(|stage| PipelineShaderStageCreateInfo {
+            name: if stage.stage == VkShaderStageFlags::FRAGMENT_BIT { "debug_view" } else { stage.name },
+            ..stage.clone()
+        })
+        .collect();
+    let rasterization_state = main_pipeline.rasterization_state.clone().map(|state| PipelineRasterizationStateCreateInfo {
+        polygon_mode,
+        ..state
+    });
+    if !additive {
+        return GraphicsPipelineCreateInfo {
+            stages,
+            rasterization_state,
+            ..main_pipeline
+        };
+    }
+
+    let depth_stencil_state = main_pipeline.depth_stencil_state.clone().map(|state| PipelineDepthStencilStateCreateInfo {
+        depth_test_enable: VkBool32::FALSE,
+        depth_write_enable: VkBool32::FALSE,
+        ..state
+    });
+    let color_blend_state = main_pipeline.color_blend_state.clone().map(|state| PipelineColorBlendStateCreateInfo {
+        attachments: state.attachments.iter().map(|attachment| PipelineColorBlendAttachmentState {
+            blend_enable: VkBool32::TRUE,
+            src_color_blend_factor: VkBlendFactor::ONE,
+            dst_color_blend_factor: VkBlendFactor::ONE,
+            color_blend_op: VkBlendOp::ADD,
+            src_alpha_blend_factor: VkBlendFactor::ONE,
+            dst_alpha_blend_factor: VkBlendFactor::ZERO,
+            alpha_blend_op: VkBlendOp::ADD,
+            ..attachment.clone()
+        }).collect(),
+        ..state
+    });
+
+    GraphicsPipelineCreateInfo {
+        stages,
+        rasterization_state,
+        depth_stencil_state,
+        color_blend_state,
+        ..main_pipeline
+    }
+}
+
+/// egui meshes over the final image: `gui` entries, premultiplied alpha blending, no depth and no culling
+pub fn preset_gui(main_pipeline: GraphicsPipelineCreateInfo, layout: VkPipelineLayout, color_format: VkFormat) -> GraphicsPipelineCreateInfo {
+    let stages = main_pipeline.stages.iter()
+        .map(|stage| PipelineShaderStageCreateInfo {
+            name: "gui",
+            ..stage.clone()
+        })
+        .collect();
+
+    // egui::epaint::Vertex, pos and uv as two floats each followed by Color32
+    let vertex_input_state = PipelineVertexInputStateCreateInfo {
+        flags: Default::default(),
+        vertex_binding_descriptions: vec![VkVertexInputBindingDescription {
+            binding: 0,
+            stride: 5 * size_of::<f32>() as u32,
+            inputRate: VkVertexInputRate::VERTEX,
+        }],
+        vertex_attribute_descriptions: vec![
+            VkVertexInputAttributeDescription {
+                location: 0,
+                binding: 0,
+                format: VkFormat::R32G32_SFLOAT,
+                offset: 0,
+            },
+            VkVertexInputAttributeDescription {
+                location: 1,
+                binding: 0,
+                format: VkFormat::R32G32_SFLOAT,
+                offset: 2 * size_of::<f32>() as u32,
+            },
+            VkVertexInputAttributeDescription {
+                location: 2,
+                binding: 0,
+                format: VkFormat::R8G8B8A8_UNORM,
+                offset: 4 * size_of::<f32>() as u32,
+            },
+        ],
+    };
+    graphics_interface("gui", "gui").check_vertex_input("gui", &vertex_input_state);
+
+    let color_blend_state = main_pipeline.color_blend_state.clone().map(|state| PipelineColorBlendStateCreateInfo {
+        attachments: vec![PipelineColorBlendAttachmentState {
+            blend_enable: VkBool32::TRUE,
+            src_color_blend_factor: VkBlendFactor::ONE,
+            dst_color_blend_factor: VkBlendFactor::ONE_MINUS_SRC_ALPHA,
+            color_blend_op: VkBlendOp::ADD,
+            src_alpha_blend_factor: VkBlendFactor::ONE_MINUS_DST_ALPHA,
+            dst_alpha_blend_factor: VkBlendFactor::ONE,
+            alpha_blend_op: VkBlendOp::ADD,
+            color_write_mask:
+                VkColorComponentFlags::R_BIT |
+                VkColorComponentFlags::G_BIT |
+                VkColorComponentFlags::B_BIT |
+                VkColorComponentFlags::A_BIT,
+        }],
+        ..state
+    });
+    let rasterization_state = main_pipeline.rasterization_state.clone().map(|state| PipelineRasterizationStateCreateInfo {
+        cull_mode: VkCullModeFlags::NONE,
+        polygon_mode: VkPolygonMode::FILL,
+        ..state
+    });
+
+    let main_pipeline = preset_fullscreen(main_pipeline, "gui", layout, color_format);
+    GraphicsPipelineCreateInfo {
+        stages,
+        vertex_input_state: Some(vertex_input_state),
+        color_blend_state,
+        rasterization_state,
+        ..main_pipeline
+    }
+}
+
+/// Indirect drawn particle billboards into the resolved HDR target: `particles` entries, premultiplied blending, no depth attachment.
+/// Depth is sampled instead, `multisampled_depth` picks the fragment entry matching the depth target
+pub fn preset_particles(main_pipeline: GraphicsPipelineCreateInfo, layout: VkPipelineLayout, color_format: VkFormat, multisampled_depth: bool) -> GraphicsPipelineCreateInfo {
+    let stages = main_pipeline.stages.iter()
+        .map(|stage| PipelineShaderStageCreateInfo {
+            name: match (stage.stage == VkShaderStageFlags::VERTEX_BIT, multisampled_depth) {
+                (true, _) | (false, false) => "particles",
+                (false, true) => "particles_msaa",
+            },
+            ..stage.clone()
+        })
+        .collect();
+
+    let color_blend_state = main_pipeline.color_blend_state.clone().map(|state| PipelineColorBlendStateCreateInfo {
+        attachments: vec![PipelineColorBlendAttachmentState {
+            blend_enable: VkBool32::TRUE,
+            src_color_blend_factor: VkBlendFactor::ONE,
+            dst_color_blend_factor: VkBlendFactor::ONE_MINUS_SRC_ALPHA,
+            color_blend_op: VkBlendOp::ADD,
+            src_alpha_blend_factor: VkBlendFactor::ZERO,
+            dst_alpha_blend_factor: VkBlendFactor::ONE,
+            alpha_blend_op: VkBlendOp::ADD,
+            color_write_mask:
+                VkColorComponentFlags::R_BIT |
+                VkColorComponentFlags::G_BIT |
+                VkColorComponentFlags::B_BIT |
+                VkColorComponentFlags::A_BIT,
+        }],
+        ..state
+    });
+    let rasterization_state = main_pipeline.rasterization_state.clone().map(|state| PipelineRasterizationStateCreateInfo {
+        cull_mode: VkCullModeFlags::NONE,
+        polygon_mode: VkPolygonMode::FILL,
+        ..state
+    });
+
+    let main_pipeline = preset_fullscreen(main_pipeline, "particles", layout, color_format);
+    GraphicsPipelineCreateInfo {
+        stages,
+        color_blend_state,
+        rasterization_state,
+        ..main_pipeline
+    }
+}
+
 const SAMPLE_COUNTS: &[VkSampleCountFlags] = &[
     VkSampleCountFlags::SC_2_BIT,
     VkSampleCountFlags::SC_4_BIT,
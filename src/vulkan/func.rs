@@ -11,6 +11,7 @@ pub struct Vulkan {
     pub loaded_device: Option<LoadedDevice>,
     api_version: ApiVersion,
     vma: PoolAllocator,
+    headless: bool,
 }
 
 impl Vulkan {
@@ -26,6 +27,16 @@ impl Vulkan {
         dbg!(self.api_version);
     }
 
+    /// Same as `init`, but without surface and swapchain extensions, for offscreen rendering
+    pub fn init_headless(&mut self) {
+        self.headless = true;
+        self.init();
+    }
+
+    pub fn is_headless(&self) -> bool {
+        self.headless
+    }
+
     pub fn get_instance(&self) -> VkInstance {
         self.instance.expect("Tried to get instance, before initializing it")
     }
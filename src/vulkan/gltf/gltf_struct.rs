@@ -35,6 +35,25 @@ pub struct Node {
     pub translation: Option<[f32; 3]>,
     pub rotation: Option<[f32; 4]>,
     pub scale: Option<[f32; 3]>,
+    pub extensions: Option<NodeExtensions>,
+    pub extras: Option<NodeExtras>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NodeExtensions {
+    pub MSFT_lod: Option<MsftLod>,
+}
+
+/// Nodes holding the lower detail levels of a node, from the most detailed one
+#[derive(Debug, Deserialize)]
+pub struct MsftLod {
+    pub ids: Vec<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NodeExtras {
+    /// Smallest screen coverage of each level of `MSFT_lod` with the base node first, one more entry culls the node
+    pub MSFT_screencoverage: Option<Vec<f32>>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -42,6 +61,9 @@ pub struct Material {
     pub doubleSided: Option<bool>,
     pub name: String,
     pub pbrMetallicRoughness: Option<MetallicRoughness>,
+    pub normalTexture: Option<NormalTexture>,
+    pub alphaMode: Option<String>,
+    pub alphaCutoff: Option<f32>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -57,6 +79,11 @@ pub struct BaseColorTexture {
     pub index: u32,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct NormalTexture {
+    pub index: u32,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Mesh {
     pub name: String,
@@ -1,22 +1,32 @@
-use crate::engine::buffers::ubo::UniformBuffer;
+use crate::engine::bindless::BindlessRegistry;
+use crate::engine::buffers::ubo::{UniformBuffer, MATRICES_SIZE, MAX_CAMERAS};
 use crate::engine::buffers::vbo::VBO;
+use crate::engine::ibl::Ibl;
+use crate::engine::lod::{bounds, simplify_clusters, LodGeneration};
+use crate::engine::permutations::ShaderFeatures;
 use crate::engine::utils::obj_n_size::NSize;
 use crate::prelude::*;
 use crate::vulkan::func::Vulkan;
 use crate::vulkan::gltf::gltf_struct::{Attributes, Gltf};
-use crate::vulkan::gltf::scene::{check_length, check_magic, raw_to_chunks, MaterialID, SIZE_TEXCOORDS};
-use crate::vulkan::gltf::scene::{Image, Mesh, Node, Primitive, Scene};
-use crate::vulkan::gltf::utils::{read_samplers, resolve_amount, resolve_mesh, resolve_offset, resolve_size, resolve_vertex, resolve_vertices, ImageFormat, IndirectParameters, StagingBuffer};
+use crate::vulkan::gltf::scene::{check_length, check_magic, raw_to_chunks, sampled_texture, SIZE_TEXCOORDS};
+use crate::vulkan::gltf::scene::{DrawBatch, Image, Mesh, Node, Primitive, Scene};
+use crate::vulkan::gltf::utils::{read_samplers, resolve_amount, resolve_features, resolve_material, resolve_mesh, resolve_offset, resolve_positions, resolve_size, resolve_vertex, resolve_vertices, ImageFormat, IndirectParameters, StagingBuffer};
 use crate::vulkan::utils::{build_pool_size, BufferUsage, ImageUsage};
 use png::Decoder;
+use shaders::common::{DrawLods, LodLevel, Material, MaterialBinary, TextureMaterial, FEATURE_NORMAL_MAP, MATERIAL_TEXTURE, MAX_LODS};
 use std::collections::{HashMap, HashSet};
 use std::io::Cursor;
-use std::ptr::null_mut;
 use ultraviolet::{Mat3, Mat4, Rotor3, Vec3, Vec4};
-use vulkan_raw::{VkDescriptorBufferInfo, VkDescriptorImageInfo, VkDescriptorSetLayoutBinding, VkDescriptorType, VkExtent3D, VkImageAspectFlags, VkImageLayout, VkImageType, VkImageView, VkImageViewType, VkSampleCountFlagBits, VkSampler, VkShaderStageFlags, VK_WHOLE_SIZE};
+use vulkan_raw::{VkDescriptorBufferInfo, VkDescriptorType, VkExtent3D, VkImageAspectFlags, VkImageType, VkImageViewType, VkSampleCountFlagBits, VkSampler, VK_WHOLE_SIZE};
 
 impl Scene {
-    pub fn from_glb(bytes: &[u8], vulkan: Vulkan, staging: &mut StagingBuffer) -> Scene {
+    /// Textures and samplers are registered in `bindless`, texture materials store the slots it hands out
+    pub fn from_glb(bytes: &[u8], vulkan: Vulkan, staging: &mut StagingBuffer, bindless: &mut BindlessRegistry) -> Scene {
+        Scene::from_glb_with(bytes, vulkan, staging, bindless, &LodGeneration::default())
+    }
+
+    /// Like `from_glb`, meshes without `MSFT_lod` levels get the simplified levels `lods` asks for
+    pub fn from_glb_with(bytes: &[u8], vulkan: Vulkan, staging: &mut StagingBuffer, bindless: &mut BindlessRegistry, lods: &LodGeneration) -> Scene {
         check_magic(bytes);
         check_length(bytes);
 
@@ -45,11 +55,20 @@ impl Scene {
             });
         });
 
-        let idx_buffer = vulkan.create_buffer(idx_size, BufferUsage::preset_index()).unwrap();
+        // meshes of nodes taking part in MSFT_lod come with their own levels
+        let lod_nodes = gltf.nodes.iter()
+            .filter_map(|node| node.extensions.as_ref()?.MSFT_lod.as_ref())
+            .flat_map(|lod| lod.ids.iter().copied())
+            .collect::<HashSet<u32>>();
+        let authored_meshes = gltf.nodes.iter().enumerate()
+            .filter(|(index, node)| lod_nodes.contains(&(*index as u32)) || node.extensions.as_ref().is_some_and(|extensions| extensions.MSFT_lod.is_some()))
+            .flat_map(|(_, node)| resolve_mesh(&gltf, node))
+            .collect::<HashSet<u32>>();
 
         let device_vbo = VBO::new(&vulkan, vbo_size, false);
         let mut staging_vbo = VBO::new(&vulkan, vbo_size, true);
         let mut indices = Vec::with_capacity(idx_size as usize / size_of::<u16>());
+        let mut vertex_count = 0;
         let mut meshes: HashMap<u32, Mesh> = HashMap::with_capacity(gltf.meshes.len());
         gltf.meshes.iter().enumerate().for_each(|(mesh_id, mesh)| {
             let mut primitives: Vec<Primitive> = Vec::with_capacity(mesh.primitives.len());
@@ -64,32 +83,39 @@ impl Scene {
                 let index_size = resolve_size(&gltf, primitive.indices) as usize;
                 let bytes = &bin_chunk.data[index_offset..index_offset + index_size];
                 let u16_slice: &[u16] = bytemuck::cast_slice(bytes);
+                let first_index = indices.len() as u32;
                 indices.extend_from_slice(u16_slice);
 
-                let material = if let Some(material_id) = primitive.material {
-                    let base_index = gltf.materials
-                        .get(material_id as usize)
-                        .and_then(|mat| mat.pbrMetallicRoughness.as_ref())
-                        .and_then(|pbr| pbr.baseColorTexture.as_ref())
-                        .map(|tex| tex.index)
-                        .unwrap_or(0) as usize;
-
-                    let info = &gltf.textures[base_index];
-                    MaterialID {
-                        source_id: info.source,
-                        sampler_id: info.sampler,
+                let positions = resolve_positions(&gltf, attr, &bin_chunk.data);
+                let (min, max) = bounds(&positions);
+                let mut simplified = Vec::new();
+                if !authored_meshes.contains(&(mesh_id as u32)) {
+                    let mut previous = u16_slice.to_vec();
+                    for level in 1..=lods.levels.min(MAX_LODS as u32 - 1) {
+                        let level_indices = simplify_clusters(&positions, &previous, lods.grid(level));
+                        // nothing left to merge, further levels would repeat this one
+                        if level_indices.is_empty() || level_indices.len() == previous.len() {
+                            break;
+                        }
+                        simplified.push((indices.len() as u32, level_indices.len() as u32));
+                        indices.extend_from_slice(&level_indices);
+                        previous = level_indices;
                     }
-                } else {
-                    MaterialID {
-                        source_id: 0,
-                        sampler_id: 0,
-                    }
-                };
+                }
+
+                let material = resolve_material(&gltf, primitive.material).binary();
                 primitives.push(Primitive {
                     indices: resolve_amount(&gltf, primitive.indices),
                     vertices: vertex_amount as u32,
+                    first_index,
+                    vertex_offset: vertex_count,
+                    min,
+                    max,
+                    simplified,
                     material,
+                    features: resolve_features(&gltf, primitive.material),
                 });
+                vertex_count += vertex_amount as i32;
             });
 
             let mesh = Mesh {
@@ -99,6 +125,8 @@ impl Scene {
 
             meshes.insert(mesh_id as u32, mesh);
         });
+        let idx_size = (indices.len() * size_of::<u16>()) as u64;
+        let idx_buffer = vulkan.create_buffer(idx_size, BufferUsage::preset_index()).unwrap();
 
         let mut nodes: Vec<Node> = Vec::with_capacity(gltf.nodes.len());
         gltf.nodes.iter().for_each(|node| {
@@ -113,53 +141,127 @@ impl Scene {
             });
         });
 
-        let mut parameters: Vec<IndirectParameters> = Vec::with_capacity(gltf.meshes.len());
+        let node_matrices = nodes.iter()
+            .map(|node| Mat4::from_translation(node.pos) * mat3_to_mat4(node.rot.into_matrix()) * Mat4::from_nonuniform_scale(node.scale))
+            .collect::<Vec<Mat4>>();
+
+        let mut draws: Vec<(ShaderFeatures, IndirectParameters, DrawLods)> = Vec::with_capacity(gltf.meshes.len());
 
         // Build data structures
         let mut model_matrices: Vec<Mat4> = Vec::with_capacity(gltf.meshes.len());
 
-        let mut index_offset = 0;
-        let mut vertex_offset = 0;
         let mut instance_offset = 0;
 
-        let mut material_ranges = Vec::with_capacity(gltf.meshes.len());
-        nodes.iter().for_each(|node| {
-            let meshes = &node.meshes;
-            meshes.iter().for_each(|mesh| {
-                mesh.primitives.iter().for_each(|primitive| {
-                    let model_matrix = Mat4::from_translation(node.pos) * mat3_to_mat4(node.rot.into_matrix()) * Mat4::from_nonuniform_scale(node.scale);
-                    model_matrices.push(model_matrix);
-
-                    parameters.push(IndirectParameters {
-                        index_count: primitive.indices,
-                        instance_count: nodes.len() as u32,
-                        first_index: index_offset,
+        let mut materials = Vec::with_capacity(gltf.meshes.len());
+        nodes.iter().enumerate().for_each(|(node_index, node)| {
+            // lower levels are drawn through the node listing them
+            if lod_nodes.contains(&(node_index as u32)) {
+                return;
+            }
+            let gltf_node = &gltf.nodes[node_index];
+            let lod_ids = gltf_node.extensions.as_ref()
+                .and_then(|extensions| extensions.MSFT_lod.as_ref())
+                .map_or(&[][..], |lod| lod.ids.as_slice());
+            let coverages = gltf_node.extras.as_ref().and_then(|extras| extras.MSFT_screencoverage.as_deref());
+            let lod_primitives = lod_ids.iter()
+                .map(|&id| nodes[id as usize].meshes.iter().flat_map(|mesh| mesh.primitives.iter()).collect::<Vec<_>>())
+                .collect::<Vec<_>>();
+
+            let model_matrix = Mat4::from_translation(node.pos) * mat3_to_mat4(node.rot.into_matrix()) * Mat4::from_nonuniform_scale(node.scale);
+            let max_scale = node.scale.abs().component_max();
+            let primitives = node.meshes.iter().flat_map(|mesh| mesh.primitives.iter());
+            primitives.enumerate().for_each(|(primitive_index, primitive)| {
+                model_matrices.push(model_matrix);
+
+                let mut levels = vec![(primitive.first_index, primitive.indices, primitive.vertex_offset)];
+                if lod_ids.is_empty() {
+                    levels.extend(primitive.simplified.iter().map(|&(first_index, count)| (first_index, count, primitive.vertex_offset)));
+                } else {
+                    for (level, level_primitives) in lod_primitives.iter().enumerate() {
+                        match level_primitives.get(primitive_index) {
+                            Some(lod) => levels.push((lod.first_index, lod.indices, lod.vertex_offset)),
+                            None => eprintln!("MSFT_lod level {} of node {} has no primitive {primitive_index}, dropped", level + 1, gltf_node.name),
+                        }
+                    }
+                }
+                if levels.len() > MAX_LODS {
+                    eprintln!("Node {} has {} detail levels, only {MAX_LODS} are used", gltf_node.name, levels.len());
+                    levels.truncate(MAX_LODS);
+                }
+
+                let level_count = levels.len() as u32;
+                let mut draw_lods = DrawLods {
+                    level_count,
+                    instance_count: nodes.len() as u32,
+                    first_instance: instance_offset,
+                    ..Default::default()
+                };
+                for (level, (first_index, index_count, vertex_offset)) in levels.into_iter().enumerate() {
+                    let generated = lods.screen_coverage(level as u32, level_count);
+                    draw_lods.levels[level] = LodLevel {
+                        first_index,
+                        index_count,
                         vertex_offset,
-                        first_instance: instance_offset,
-                    });
+                        screen_coverage: coverages.and_then(|coverages| coverages.get(level).copied()).unwrap_or(generated),
+                    };
+                }
+                // an extra MSFT_screencoverage entry is the coverage below which the node is culled
+                let cull_coverage = coverages.and_then(|coverages| coverages.get(level_count as usize)).copied();
+                match cull_coverage {
+                    Some(cull_coverage) if level_count < MAX_LODS as u32 => {
+                        draw_lods.levels[level_count as usize] = LodLevel { screen_coverage: cull_coverage, ..Default::default() };
+                        draw_lods.level_count += 1;
+                    }
+                    _ => draw_lods.levels[level_count as usize - 1].screen_coverage = 0.0,
+                }
 
-                    index_offset += primitive.indices;
-                    vertex_offset += primitive.vertices as i32;
-                    instance_offset += nodes.len() as u32;
+                let min = Vec3::from(primitive.min);
+                let max = Vec3::from(primitive.max);
+                let center = model_matrix.transform_point3((min + max) * 0.5);
+                draw_lods.center = center.into();
+                draw_lods.radius = (max - min).mag() * 0.5 * max_scale;
 
-                    //resolve material
-                    let material = primitive.material;
-                    material_ranges.push(material);
-                });
+                let base = draw_lods.levels[0];
+                draws.push((primitive.features, IndirectParameters {
+                    index_count: base.index_count,
+                    instance_count: nodes.len() as u32,
+                    first_index: base.first_index,
+                    vertex_offset: base.vertex_offset,
+                    first_instance: instance_offset,
+                }, draw_lods));
+
+                instance_offset += nodes.len() as u32;
+
+                materials.push((primitive.material, primitive.features));
             });
         });
+
+        // instances keep their index, so sorting draws leaves the material and model lookups intact
+        draws.sort_by_key(|(features, _, _)| *features);
+        let mut draw_batches: Vec<DrawBatch> = Vec::new();
+        for (index, (features, _, _)) in draws.iter().enumerate() {
+            match draw_batches.last_mut() {
+                Some(batch) if batch.features == *features => batch.draw_count += 1,
+                _ => draw_batches.push(DrawBatch { features: *features, first_draw: index as u32, draw_count: 1 }),
+            }
+        }
+        let (parameters, mut draw_lods): (Vec<_>, Vec<_>) = draws.into_iter().map(|(_, parameters, lods)| (parameters, lods)).unzip();
         let parameters = NSize::from(parameters);
-        let indirect_buffer = vulkan.create_buffer(parameters.size() as u64, BufferUsage::default().transfer_dst(true).indirect_buffer(true)).unwrap();
+        // one slice of commands per camera, the LOD selector writes them from `lod_ssbo`
+        let indirect_size = parameters.size() as u64 * MAX_CAMERAS as u64;
+        let indirect_buffer = vulkan.create_buffer(indirect_size, BufferUsage::default().transfer_dst(true).indirect_buffer(true).storage_buffer(true)).unwrap();
+        let lod_size = (draw_lods.len() * size_of::<DrawLods>()) as u64;
+        let lod_buffer = vulkan.create_buffer(lod_size, BufferUsage::default().storage_buffer(true).transfer_dst(true)).unwrap();
 
         // Create SSBOs
         let model_matrices_size = (model_matrices.len() * size_of::<Mat4>()) as u64;
-        let material_ranges_size = (material_ranges.len() * size_of::<MaterialID>()) as u64;
+        let materials_size = (materials.len() * size_of::<MaterialBinary>()) as u64;
 
         let model_ssbo = vulkan.create_buffer(model_matrices_size, BufferUsage::default().storage_buffer(true).transfer_dst(true)).unwrap();
-        let material_ranges_ssbo = vulkan.create_buffer(material_ranges_size, BufferUsage::default().storage_buffer(true).transfer_dst(true)).unwrap();
+        let material_ssbo_buffer = vulkan.create_buffer(materials_size, BufferUsage::default().storage_buffer(true).transfer_dst(true)).unwrap();
 
 
-        let main_buffers = vec![idx_buffer, indirect_buffer, model_ssbo, material_ranges_ssbo];
+        let main_buffers = vec![idx_buffer, indirect_buffer, model_ssbo, material_ssbo_buffer, lod_buffer];
         let main_buffers_info = vulkan.arena().device(main_buffers, &vulkan);
 
         let samplers: Vec<VkSampler> = read_samplers(&vulkan, &gltf);
@@ -218,67 +320,46 @@ impl Scene {
             }
         }).collect::<Vec<_>>();
 
-        let indirect_description_bindings = [
-            VkDescriptorSetLayoutBinding {
-                binding: 0,
-                descriptorType: VkDescriptorType::SAMPLED_IMAGE,
-                descriptorCount: 2048,
-                stageFlags: VkShaderStageFlags::FRAGMENT_BIT,
-                pImmutableSamplers: null_mut(),
-            },
-            VkDescriptorSetLayoutBinding {
-                binding: 1,
-                descriptorType: VkDescriptorType::SAMPLER,
-                descriptorCount: 16,
-                stageFlags: VkShaderStageFlags::FRAGMENT_BIT,
-                pImmutableSamplers: null_mut(),
-            },
-            VkDescriptorSetLayoutBinding {
-                binding: 3,
-                descriptorType: VkDescriptorType::STORAGE_BUFFER,
-                descriptorCount: 1,
-                stageFlags: VkShaderStageFlags::VERTEX_BIT,
-                pImmutableSamplers: null_mut(),
-            },
-            VkDescriptorSetLayoutBinding {
-                binding: 4,
-                descriptorType: VkDescriptorType::STORAGE_BUFFER,
-                descriptorCount: 1,
-                stageFlags: VkShaderStageFlags::VERTEX_BIT | VkShaderStageFlags::FRAGMENT_BIT,
-                pImmutableSamplers: null_mut(),
-            },
-        ];
+        // set 0 holds the camera UBO, set 1 model matrices and materials, textures and samplers live in the bindless set
+        let interface = graphics_interface("main", "main");
+        let mut indirect_description_bindings = interface.set_layout_bindings(1, &[]);
+        // IBL maps written by `bind_environment`, not sampled yet
+        indirect_description_bindings.extend(Ibl::scene_layout_bindings());
         let indirect_descriptor_layout = vulkan.create_descriptor_set_layout(&indirect_description_bindings);
 
-        let vp_description_bindings = [
-            VkDescriptorSetLayoutBinding {
-                binding: 0,
-                descriptorType: VkDescriptorType::UNIFORM_BUFFER,
-                descriptorCount: 1,
-                stageFlags: VkShaderStageFlags::VERTEX_BIT,
-                pImmutableSamplers: null_mut(),
-            }
-        ];
+        // the camera UBO holds one slice per camera, picked with a dynamic offset
+        let mut vp_description_bindings = interface.set_layout_bindings(0, &[]);
+        vp_description_bindings.iter_mut()
+            .filter(|binding| binding.binding == 0)
+            .for_each(|binding| binding.descriptorType = VkDescriptorType::UNIFORM_BUFFER_DYNAMIC);
+        interface.check_layout("scene", &[&vp_description_bindings, &indirect_description_bindings, bindless.bindings()], &[]);
         let vp_descriptor_layout = vulkan.create_descriptor_set_layout(&vp_description_bindings);
-        let mut descriptor_bindings = vp_description_bindings.into_iter().collect::<Vec<_>>();
+        let mut descriptor_bindings = vp_description_bindings.clone();
         descriptor_bindings.extend_from_slice(&indirect_description_bindings);
 
         let descriptors = PooledDescriptors::new(vec![vp_descriptor_layout, indirect_descriptor_layout], build_pool_size(&descriptor_bindings), &vulkan);
-        let image_infos = gltf.textures.iter().map(|texture| {
-            VkDescriptorImageInfo {
-                sampler: VkSampler::none(),
-                imageView: *texture_images[texture.source as usize].image_view,
-                imageLayout: VkImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        let texture_slots = texture_images.iter()
+            .map(|image| bindless.register_texture(&vulkan, *image.image_view))
+            .collect::<Vec<_>>();
+        let sampler_slots = samplers.iter()
+            .map(|&sampler| bindless.register_sampler(&vulkan, sampler))
+            .collect::<Vec<_>>();
+        // materials were resolved with glTF image and sampler indices
+        let materials = materials.into_iter().map(|(material, features)| {
+            if material.mat_type != MATERIAL_TEXTURE {
+                return material;
             }
-        }).collect::<Vec<_>>();
-
-        let sampler_infos: Vec<_> = samplers.iter().map(|&sampler| {
-            VkDescriptorImageInfo {
-                sampler,
-                imageView: VkImageView::none(),
-                imageLayout: VkImageLayout::UNDEFINED,
+            let mut texture = TextureMaterial::parse(&material.data);
+            texture.source_id = texture_slots[texture.source_id as usize].0;
+            texture.sampler_id = sampler_slots[texture.sampler_id as usize].0;
+            if features.contains(FEATURE_NORMAL_MAP) {
+                texture.normal_id = texture_slots[texture.normal_id as usize].0;
             }
-        }).collect();
+            Material::Texture(texture).binary()
+        }).collect::<Vec<_>>();
+        for lods in &mut draw_lods {
+            lods.texture = sampled_texture(&materials, lods);
+        }
 
         let ubo = UniformBuffer::new(
             Mat4::identity(),
@@ -286,37 +367,18 @@ impl Scene {
             &vulkan,
         );
 
-        vulkan.update_descriptor_sets(vec![
-            ImageDescriptorInfo {
-                target_descriptor: DescriptorSetInfo {
-                    descriptor_set: descriptors.descriptor_sets[1],
-                    descriptor_binding: 0,
-                    array_element: 0,
-                },
-                target_descriptor_type: VkDescriptorType::SAMPLED_IMAGE,
-                image_infos,
-            },
-            ImageDescriptorInfo {
-                target_descriptor: DescriptorSetInfo {
-                    descriptor_set: descriptors.descriptor_sets[1],
-                    descriptor_binding: 1,
-                    array_element: 0,
-                },
-                target_descriptor_type: VkDescriptorType::SAMPLER,
-                image_infos: sampler_infos,
-            },
-        ], vec![
+        vulkan.update_descriptor_sets(vec![], vec![
             BufferDescriptorInfo {
                 target_descriptor: DescriptorSetInfo {
                     descriptor_set: descriptors.descriptor_sets[0],
                     descriptor_binding: 0,
                     array_element: 0,
                 },
-                target_descriptor_type: VkDescriptorType::UNIFORM_BUFFER,
+                target_descriptor_type: VkDescriptorType::UNIFORM_BUFFER_DYNAMIC,
                 buffer_infos: vec![VkDescriptorBufferInfo {
                     buffer: ubo.provide_buffer(),
                     offset: 0,
-                    range: VK_WHOLE_SIZE,
+                    range: MATRICES_SIZE as VkDeviceSize,
                 }],
             },
             // Model matrices SSBO
@@ -333,7 +395,7 @@ impl Scene {
                     range: VK_WHOLE_SIZE,
                 }],
             },
-            // MaterialID ranges SSBO (for texture/sampler lookup)
+            // MaterialBinary SSBO, one per primitive
             BufferDescriptorInfo {
                 target_descriptor: DescriptorSetInfo {
                     descriptor_set: descriptors.descriptor_sets[1],
@@ -342,7 +404,7 @@ impl Scene {
                 },
                 target_descriptor_type: VkDescriptorType::STORAGE_BUFFER,
                 buffer_infos: vec![VkDescriptorBufferInfo {
-                    buffer: material_ranges_ssbo,
+                    buffer: material_ssbo_buffer,
                     offset: 0,
                     range: VK_WHOLE_SIZE,
                 }],
@@ -360,9 +422,10 @@ impl Scene {
 
         //Wrappers
         let idx = NSize::new(VkDestroy::new(idx_buffer, &vulkan), idx_size as usize);
-        let indirect_buffer = NSize::new(VkDestroy::new(indirect_buffer, &vulkan), parameters.size());
+        let indirect_buffer = NSize::new(VkDestroy::new(indirect_buffer, &vulkan), indirect_size as usize);
+        let lod_ssbo = NSize::new(VkDestroy::new(lod_buffer, &vulkan), lod_size as usize);
         let model_ssbo = NSize::new(VkDestroy::new(model_ssbo, &vulkan), model_matrices_size as usize);
-        let material_ssbo = NSize::new(VkDestroy::new(material_ranges_ssbo, &vulkan), material_ranges_size as usize);
+        let material_ssbo = NSize::new(VkDestroy::new(material_ssbo_buffer, &vulkan), materials_size as usize);
 
         let mut scene = Scene {
             ubo,
@@ -370,14 +433,21 @@ impl Scene {
             staging_vbo,
             idx,
             indirect_buffer,
+            lod_ssbo,
             model_ssbo,
             material_ssbo,
             parameters,
+            draw_batches,
+            draw_lods,
             descriptors,
             indices,
             model_matrices,
+            node_matrices,
             texture_images,
-            material_ranges,
+            texture_slots,
+            sampler_slots,
+            bindless_set: bindless.descriptor_set(),
+            materials,
             _samplers,
             _memory,
         };
@@ -1,7 +1,12 @@
+use crate::engine::bindless::BindlessRegistry;
+use crate::engine::ibl::{Ibl, SCENE_BINDINGS};
+use crate::engine::permutations::{PermutationCache, ShaderFeatures};
 use crate::prelude::*;
 use crate::vulkan::func::{Destructible, Vulkan};
-use crate::vulkan::gltf::scene::{MaterialID, Scene};
+use crate::vulkan::gltf::scene::{sampled_texture, DrawBatch, Scene};
 use crate::vulkan::gltf::utils::{IndirectParameters, StagingBuffer};
+use crate::engine::buffers::ubo::MAX_CAMERAS;
+use shaders::common::{DrawLods, MaterialBinary};
 use ultraviolet::Mat4;
 use crate::engine::buffers::vbo::VBO;
 
@@ -11,7 +16,8 @@ impl Scene {
 
         // Add SSBO sizes
         max_staging_size += (self.model_matrices.len() * size_of::<Mat4>()) as u64;
-        max_staging_size += (self.material_ranges.len() * size_of::<MaterialID>()) as u64;
+        max_staging_size += (self.materials.len() * size_of::<MaterialBinary>()) as u64;
+        max_staging_size += (self.draw_lods.len() * size_of::<DrawLods>()) as u64;
         for image in &self.texture_images {
             max_staging_size += image.size as u64;
         }
@@ -39,9 +45,13 @@ impl Scene {
             Vulkan::copy_info(staging_ptr.add(current_offset), self.model_matrices.as_ptr(), self.model_matrices.len());
             current_offset += self.model_matrices.len() * size_of::<Mat4>();
 
-            // Copy material ranges
-            Vulkan::copy_info(staging_ptr.add(current_offset), self.material_ranges.as_ptr(), self.material_ranges.len());
-            current_offset += self.material_ranges.len() * size_of::<MaterialID>();
+            // Copy materials
+            Vulkan::copy_info(staging_ptr.add(current_offset), self.materials.as_ptr(), self.materials.len());
+            current_offset += self.materials.len() * size_of::<MaterialBinary>();
+
+            // Copy LOD records
+            Vulkan::copy_info(staging_ptr.add(current_offset), self.draw_lods.as_ptr(), self.draw_lods.len());
+            current_offset += self.draw_lods.len() * size_of::<DrawLods>();
 
             // Copy images
             for image in &self.texture_images {
@@ -72,12 +82,13 @@ impl Scene {
         }], one_time_command_buffer, **staging_buffer, *self.idx.get());
         offset += self.idx.size() as u64;
 
-        // Copy parameters
-        vulkan.buffer_to_buffer(&[VkBufferCopy {
+        // Copy parameters into every camera slice, so scenes draw at full detail until LODs are selected
+        let parameter_copies = (0..MAX_CAMERAS).map(|slice| VkBufferCopy {
             srcOffset: offset,
-            dstOffset: 0,
+            dstOffset: (slice * self.parameters.size()) as VkDeviceSize,
             size: self.parameters.size() as VkDeviceSize,
-        }], one_time_command_buffer, **staging_buffer, *self.indirect_buffer.get());
+        }).collect::<Vec<_>>();
+        vulkan.buffer_to_buffer(&parameter_copies, one_time_command_buffer, **staging_buffer, *self.indirect_buffer.get());
         offset += self.parameters.size() as u64;
 
         // Copy model ssbo
@@ -92,9 +103,17 @@ impl Scene {
         vulkan.buffer_to_buffer(&[VkBufferCopy {
             srcOffset: offset,
             dstOffset: 0,
-            size: (self.material_ranges.len() * size_of::<MaterialID>()) as VkDeviceSize,
+            size: (self.materials.len() * size_of::<MaterialBinary>()) as VkDeviceSize,
         }], one_time_command_buffer, **staging_buffer, *self.material_ssbo.get());
-        //offset += (self.material_ranges.len() * size_of::<MaterialID>()) as VkDeviceSize; // uncomment to add new strides
+        offset += (self.materials.len() * size_of::<MaterialBinary>()) as VkDeviceSize;
+
+        // Copy LOD ssbo
+        vulkan.buffer_to_buffer(&[VkBufferCopy {
+            srcOffset: offset,
+            dstOffset: 0,
+            size: (self.draw_lods.len() * size_of::<DrawLods>()) as VkDeviceSize,
+        }], one_time_command_buffer, **staging_buffer, *self.lod_ssbo.get());
+        //offset += (self.draw_lods.len() * size_of::<DrawLods>()) as VkDeviceSize; // uncomment to add new strides
 
         // Transition and copy images
         let transitions = self.texture_images.iter().map(|image| {
@@ -156,12 +175,124 @@ impl Scene {
         });
     }
 
-    pub fn render_scene(&self, vulkan: &Vulkan, command_buffer: VkCommandBuffer, pipeline_layout: VkPipelineLayout) {
+    /// Gives the texture and sampler slots back, the scene must not be drawn afterwards
+    pub fn release_bindless(&mut self, bindless: &mut BindlessRegistry) {
+        self.texture_slots.drain(..).for_each(|slot| bindless.free_texture(slot));
+        self.sampler_slots.drain(..).for_each(|slot| bindless.free_sampler(slot));
+    }
+
+    /// Points the material set `SCENE_BINDINGS` at the irradiance, prefiltered and BRDF LUT maps of `ibl`.
+    /// Nothing samples them until the PBR shading lands. The set may be in use by frames in flight, so this waits for the device
+    pub fn bind_environment(&self, vulkan: &Vulkan, ibl: &Ibl) {
+        vulkan.device_wait();
+        let views = [*ibl.irradiance.view, *ibl.prefiltered.view, *ibl.brdf_lut_view];
+        let image_infos = views.into_iter().zip(SCENE_BINDINGS).map(|(view, binding)| ImageDescriptorInfo {
+            target_descriptor: DescriptorSetInfo {
+                descriptor_set: self.descriptors.descriptor_sets[1],
+                descriptor_binding: binding,
+                array_element: 0,
+            },
+            target_descriptor_type: VkDescriptorType::COMBINED_IMAGE_SAMPLER,
+            image_infos: vec![VkDescriptorImageInfo {
+                sampler: *ibl.sampler,
+                imageView: view,
+                imageLayout: VkImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            }],
+        }).collect();
+        vulkan.update_descriptor_sets(image_infos, vec![], vec![], vec![]);
+    }
+
+    /// Replaces the material of draw `draw`, e.g. to show a render-to-texture camera through `RenderLoop::camera_texture`.
+    /// The draw keeps its permutation, so normal map and alpha test only apply if the original material had them.
+    /// Offscreen cameras skip draws showing their own image, see `LodSelector::record`.
+    /// The material buffer may be in use by frames in flight, so this waits for the device
+    pub fn set_material(&mut self, vulkan: &Vulkan, staging: &mut StagingBuffer, draw: usize, material: MaterialBinary) {
+        assert!(draw < self.materials.len(), "Scene has {} draws, material of draw {draw} requested", self.materials.len());
+        vulkan.device_wait();
+        self.materials[draw] = material;
+
+        // LOD records of the draws covering this instance carry the texture it samples
+        let changed_lods = (0..self.draw_lods.len()).filter(|&index| {
+            let lods = &self.draw_lods[index];
+            let instances = lods.first_instance as usize..(lods.first_instance + lods.instance_count) as usize;
+            instances.contains(&draw)
+        }).collect::<Vec<_>>();
+        for &index in &changed_lods {
+            self.draw_lods[index].texture = sampled_texture(&self.materials, &self.draw_lods[index]);
+        }
+
+        let size = size_of::<MaterialBinary>();
+        let lods_size = size_of::<DrawLods>();
+        let staging_buffer = staging.pull((size + changed_lods.len() * lods_size) as u64, vulkan);
+        let staging_ptr = staging_buffer.map_memory(vulkan);
+        Vulkan::copy_info(staging_ptr, &self.materials[draw], 1);
+        for (slot, &index) in changed_lods.iter().enumerate() {
+            unsafe { Vulkan::copy_info(staging_ptr.add(size + slot * lods_size), &self.draw_lods[index], 1) };
+        }
+        staging_buffer.flush_memory(vulkan);
+
+        let lods_copies = changed_lods.iter().enumerate().map(|(slot, &index)| VkBufferCopy {
+            srcOffset: (size + slot * lods_size) as VkDeviceSize,
+            dstOffset: (index * lods_size) as VkDeviceSize,
+            size: lods_size as VkDeviceSize,
+        }).collect::<Vec<_>>();
+        vulkan.immediate_submit(|command_buffer| {
+            vulkan.buffer_to_buffer(&[VkBufferCopy {
+                srcOffset: 0,
+                dstOffset: (draw * size) as VkDeviceSize,
+                size: size as VkDeviceSize,
+            }], command_buffer, **staging_buffer, *self.material_ssbo.get());
+            if !lods_copies.is_empty() {
+                vulkan.buffer_to_buffer(&lods_copies, command_buffer, **staging_buffer, *self.lod_ssbo.get());
+            }
+        });
+    }
+
+    /// Binds the permutation of every draw batch, `pipelines` has to be prepared with `features()`.
+    /// Batches whose permutation is still compiling use the base one or are skipped, see `PermutationCache::resolve`.
+    /// `camera_slice` selects the UBO slice holding the camera matrices and the slice of draw commands
+    pub fn render_scene(&self, vulkan: &Vulkan, command_buffer: VkCommandBuffer, pipeline_layout: VkPipelineLayout, pipelines: &PermutationCache, camera_slice: usize) {
+        self.bind_scene(vulkan, command_buffer, pipeline_layout, camera_slice);
+        for batch in &self.draw_batches {
+            let Some(pipeline) = pipelines.resolve(batch.features) else {
+                continue;
+            };
+            vulkan.bind_pipeline(command_buffer, VkPipelineBindPoint::GRAPHICS, pipeline);
+            self.draw_batch(command_buffer, batch, camera_slice);
+        }
+    }
+
+    /// Same draws as `render_scene` with a single pipeline for every batch, used by the debug views
+    pub fn render_scene_with(&self, vulkan: &Vulkan, command_buffer: VkCommandBuffer, pipeline_layout: VkPipelineLayout, pipeline: VkPipeline, camera_slice: usize) {
+        self.bind_scene(vulkan, command_buffer, pipeline_layout, camera_slice);
+        vulkan.bind_pipeline(command_buffer, VkPipelineBindPoint::GRAPHICS, pipeline);
+        for batch in &self.draw_batches {
+            self.draw_batch(command_buffer, batch, camera_slice);
+        }
+    }
+
+    fn bind_scene(&self, vulkan: &Vulkan, command_buffer: VkCommandBuffer, pipeline_layout: VkPipelineLayout, camera_slice: usize) {
         self.device_vbo.bind(vulkan, command_buffer);
 
         vulkan.bind_index_buffer(command_buffer, *self.idx.get(), 0, VkIndexType::UINT16);
-        vulkan.bind_descriptor_sets(command_buffer, VkPipelineBindPoint::GRAPHICS, pipeline_layout, 0, &self.descriptors.descriptor_sets, &[]);
+        let descriptor_sets = [self.descriptors.descriptor_sets[0], self.descriptors.descriptor_sets[1], self.bindless_set];
+        vulkan.bind_descriptor_sets(command_buffer, VkPipelineBindPoint::GRAPHICS, pipeline_layout, 0, &descriptor_sets, &[self.ubo.slice_offset(camera_slice)]);
+    }
+
+    fn draw_batch(&self, command_buffer: VkCommandBuffer, batch: &DrawBatch, camera_slice: usize) {
+        let stride = size_of::<IndirectParameters>() as u32;
+        let first_draw = camera_slice as u32 * self.draw_count();
+        let offset = ((first_draw + batch.first_draw) * stride) as VkDeviceSize;
+        unsafe { vkCmdDrawIndexedIndirect(command_buffer, *self.indirect_buffer.get(), offset, batch.draw_count, stride) };
+    }
+
+    /// Indirect draws per camera slice
+    pub fn draw_count(&self) -> u32 {
+        self.parameters.len() as u32
+    }
 
-        unsafe { vkCmdDrawIndexedIndirect(command_buffer, *self.indirect_buffer.get(), 0, self.parameters.len() as u32, size_of::<IndirectParameters>() as u32) };
+    /// Shader permutations the draws need
+    pub fn features(&self) -> impl Iterator<Item = ShaderFeatures> + '_ {
+        self.draw_batches.iter().map(|batch| batch.features)
     }
 }
\ No newline at end of file
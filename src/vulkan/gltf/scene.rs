@@ -1,8 +1,11 @@
+use crate::engine::bindless::{SamplerSlot, TextureSlot};
 use crate::engine::buffers::ubo::UniformBuffer;
+use crate::engine::permutations::ShaderFeatures;
 use crate::engine::buffers::vbo::VBO;
 use crate::engine::utils::obj_n_size::NSize;
 use crate::prelude::*;
 use crate::vulkan::gltf::utils::{ChunkType, IndirectParameters};
+use shaders::common::{DrawLods, MaterialBinary, TextureMaterial, MATERIAL_TEXTURE, NO_TEXTURE};
 use ultraviolet::{Mat4, Rotor3, Vec3};
 use vulkan_raw::{VkBuffer, VkDeviceMemory, VkExtent3D, VkImage, VkImageView, VkSampler};
 
@@ -14,18 +17,31 @@ pub struct Scene {
     pub device_vbo: VBO,
     pub staging_vbo: VBO,
     pub idx: SizedBuffer,
+    /// One slice of draw commands per camera, written by `LodSelector` or filled with the base levels by `prepare`
     pub indirect_buffer: SizedBuffer,
+    pub lod_ssbo: SizedBuffer,
     pub material_ssbo: SizedBuffer,
     pub model_ssbo: SizedBuffer,
 
+    /// Sorted by shader features, `draw_batches` covers it in order
     pub parameters: NSize<Vec<IndirectParameters>>,
+    pub draw_batches: Vec<DrawBatch>,
+    /// Detail levels of every draw in draw order, uploaded into `lod_ssbo`
+    pub draw_lods: Vec<DrawLods>,
     pub descriptors: PooledDescriptors,
 
     pub indices: Vec<u16>,
 
     pub texture_images: Vec<Image>,
+    /// Bindless slots of `texture_images` and the glTF samplers, in file order
+    pub texture_slots: Vec<TextureSlot>,
+    pub sampler_slots: Vec<SamplerSlot>,
+    pub bindless_set: VkDescriptorSet,
     pub model_matrices: Vec<Mat4>,
-    pub material_ranges: Vec<MaterialID>,
+    /// Local transform of every glTF node in file order, emitters and other attachments index into it
+    pub node_matrices: Vec<Mat4>,
+    /// One per primitive in draw order, uploaded into `material_ssbo`
+    pub materials: Vec<MaterialBinary>,
 
     pub _samplers: Vec<VkDestroy<VkSampler>>,
     pub _memory: Vec<VkDestroy<VkDeviceMemory>>,
@@ -43,19 +59,29 @@ pub struct Mesh {
     pub primitives: Vec<Primitive>,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub struct Primitive {
     pub indices: u32,
     pub vertices: u32,
-    pub material: MaterialID,
+    pub first_index: u32,
+    pub vertex_offset: i32,
+    /// Model space bounds
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+    /// First index and index count of generated levels, they share the primitive's vertices
+    pub simplified: Vec<(u32, u32)>,
+    pub material: MaterialBinary,
+    pub features: ShaderFeatures,
 }
 
-#[derive(Copy, Clone, Debug)]
-#[repr(C)]
-pub struct MaterialID {
-    pub source_id: u32,
-    pub sampler_id: u32,
+/// Consecutive indirect draws sharing one pipeline permutation
+#[derive(Clone, Copy, Debug)]
+pub struct DrawBatch {
+    pub features: ShaderFeatures,
+    pub first_draw: u32,
+    pub draw_count: u32,
 }
+
 pub const SIZE_TEXCOORDS: usize = size_of::<[f32; 2]>();
 #[derive(Debug)]
 #[repr(C)]
@@ -79,6 +105,15 @@ pub struct Image {
 }
 
 const GLB_MAGIC: &[u8] = b"glTF";
+/// Bindless slot the instances of `lods` sample through a texture material, `NO_TEXTURE` if none does.
+/// Instances index `materials` like the fragment shader, with differing textures the first instance wins
+pub fn sampled_texture(materials: &[MaterialBinary], lods: &DrawLods) -> u32 {
+    let instances = lods.first_instance as usize..(lods.first_instance + lods.instance_count) as usize;
+    materials.get(instances).unwrap_or_default().iter()
+        .find(|material| material.mat_type == MATERIAL_TEXTURE)
+        .map_or(NO_TEXTURE, |material| TextureMaterial::parse(&material.data).source_id)
+}
+
 pub fn check_magic(bytes: &[u8]) {
     if !bytes.starts_with(GLB_MAGIC) {
         panic!("Invalid GLTF magic, corrupt scene file");
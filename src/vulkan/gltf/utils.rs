@@ -1,9 +1,11 @@
 use crate::engine::buffers::vbo::VBO;
+use crate::engine::permutations::ShaderFeatures;
 use crate::prelude::pool_alloc::Buffer;
 use crate::prelude::*;
 use crate::vulkan::func::Vulkan;
 use crate::vulkan::gltf::gltf_struct::{Attributes, Gltf, Node};
 use crate::vulkan::utils::BufferUsage;
+use shaders::common::{DiffuseMaterial, Material, SpecularMaterial, TextureMaterial, DEFAULT_ALPHA_CUTOFF, FEATURE_ALPHA_TEST, FEATURE_NORMAL_MAP};
 use vulkan_raw::{VkBorderColor, VkCompareOp, VkFilter, VkFormat, VkSampler, VkSamplerAddressMode, VkSamplerMipmapMode};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -110,6 +112,57 @@ pub fn resolve_mesh(gltf: &Gltf, node: &Node) -> Vec<u32> {
     }
 }
 
+/// Textured PBR materials sample their base color, untextured ones approximate metallic-roughness with a highlight.
+/// Primitives without a material are plain white
+pub fn resolve_material(gltf: &Gltf, material: Option<u32>) -> Material {
+    let gltf_material = material.and_then(|id| gltf.materials.get(id as usize));
+    let Some(pbr) = gltf_material.and_then(|material| material.pbrMetallicRoughness.as_ref()) else {
+        return Material::Diffuse(DiffuseMaterial { base_color: [1.0; 4] });
+    };
+    let base_color = pbr.baseColorFactor.unwrap_or([1.0; 4]);
+
+    if let (Some(texture), Some(gltf_material)) = (&pbr.baseColorTexture, gltf_material) {
+        let info = &gltf.textures[texture.index as usize];
+        let normal_id = gltf_material.normalTexture.as_ref()
+            .map_or(0, |normal| gltf.textures[normal.index as usize].source);
+        return Material::Texture(TextureMaterial {
+            base_color,
+            source_id: info.source,
+            sampler_id: info.sampler,
+            normal_id,
+            alpha_cutoff: gltf_material.alphaCutoff.unwrap_or(DEFAULT_ALPHA_CUTOFF),
+        });
+    }
+    if pbr.metallicFactor.is_none() && pbr.roughnessFactor.is_none() {
+        return Material::Diffuse(DiffuseMaterial { base_color });
+    }
+
+    // glTF defaults both factors to one
+    let metallic = pbr.metallicFactor.unwrap_or(1.0);
+    let roughness = pbr.roughnessFactor.unwrap_or(1.0).max(0.05);
+    let [r, g, b, a] = base_color;
+    Material::Specular(SpecularMaterial {
+        base_color: [r * (1.0 - metallic), g * (1.0 - metallic), b * (1.0 - metallic), a],
+        specular_color: [r, g, b].map(|channel| 0.04 + (channel - 0.04) * metallic),
+        shininess: (2.0 / roughness.powi(4) - 2.0).max(1.0),
+    })
+}
+
+/// Shader permutation of a primitive. Only textured materials have alpha tested and normal mapped paths
+pub fn resolve_features(gltf: &Gltf, material: Option<u32>) -> ShaderFeatures {
+    let Some(material) = material.and_then(|id| gltf.materials.get(id as usize)) else {
+        return ShaderFeatures::NONE;
+    };
+    let textured = material.pbrMetallicRoughness.as_ref().is_some_and(|pbr| pbr.baseColorTexture.is_some());
+    if !textured {
+        return ShaderFeatures::NONE;
+    }
+
+    ShaderFeatures::NONE
+        .with(FEATURE_ALPHA_TEST, material.alphaMode.as_deref() == Some("MASK"))
+        .with(FEATURE_NORMAL_MAP, material.normalTexture.is_some())
+}
+
 const GL_NEAREST: u32 = 0x2600;
 const GL_LINEAR: u32 = 0x2601;
 const GL_NEAREST_MIPMAP_NEAREST: u32 = 0x2700;
@@ -185,6 +238,16 @@ pub fn resolve_vertex(gltf: &Gltf, attr: Attributes, id: usize, data: &[u8], vbo
     vbo.build_vertex_inplace(position, normal, texcoords);
 }
 
+/// Model space positions of a primitive in vertex order
+pub fn resolve_positions(gltf: &Gltf, attr: Attributes, data: &[u8]) -> Vec<[f32; 3]> {
+    let offset = resolve_offset(gltf, attr.POSITION) as usize;
+    let count = resolve_vertices(gltf, attr) as usize;
+    data[offset..offset + count * size_of::<[f32; 3]>()]
+        .chunks_exact(size_of::<[f32; 3]>())
+        .map(bytemuck::pod_read_unaligned::<[f32; 3]>)
+        .collect()
+}
+
 pub fn read_samplers(vulkan: &Vulkan, gltf: &Gltf) -> Vec<VkSampler> {
     gltf.samplers.iter().map(|sampler| {
         let mut sampler_info = SamplerInfo {
@@ -114,6 +114,30 @@ impl Vulkan {
         let result = unsafe { vkQueueSubmit(queue, 1, &submit_info, fence) };
         assert!(result.is_ok());
     }
+
+    /// Records commands into a throwaway buffer, submits it on the first queue and waits for completion
+    pub fn immediate_submit(&self, record: impl FnOnce(VkCommandBuffer)) {
+        let command_pool = self.create_command_pool(self.get_loaded_device().queue_info[0].family_index, VkCommandPoolCreateFlags::TRANSIENT_BIT);
+        let command_buffer = self.alloc_command_buffers(command_pool, VkCommandBufferLevel::PRIMARY, 1)[0];
+
+        self.start_recording(command_buffer, VkCommandBufferUsageFlags::ONE_TIME_SUBMIT_BIT, RecordingInfo {
+            renderPass: VkRenderPass::none(),
+            subpass: 0,
+            framebuffer: VkFramebuffer::none(),
+            occlusionQueryEnable: false,
+            queryFlags: Default::default(),
+            pipelineStatistics: Default::default(),
+        });
+        record(command_buffer);
+        self.end_recording(command_buffer);
+
+        let queue = self.get_queues()[0];
+        self.submit_buffer(queue, VkFence::none(), &[command_buffer], &[], &[]);
+        self.wait_for_queue(queue);
+
+        self.free_buffers(command_pool, &[command_buffer]);
+        self.destroy_pool(command_pool);
+    }
 }
 
 #[allow(non_snake_case)]
@@ -1,8 +1,9 @@
 use crate::safe_ptr;
 use crate::vulkan::func::{Destructible, Vulkan};
 use std::any::Any;
+use std::ffi::c_void;
 use std::ptr::null_mut;
-use vulkan_raw::{vkAllocateDescriptorSets, vkCmdBindDescriptorSets, vkCreateDescriptorPool, vkCreateDescriptorSetLayout, vkDestroyDescriptorPool, vkDestroyDescriptorSetLayout, vkFreeDescriptorSets, vkResetDescriptorPool, vkUpdateDescriptorSets, VkBufferView, VkCommandBuffer, VkCopyDescriptorSet, VkDescriptorBufferInfo, VkDescriptorImageInfo, VkDescriptorPool, VkDescriptorPoolCreateFlags, VkDescriptorPoolCreateInfo, VkDescriptorPoolResetFlagBits, VkDescriptorPoolSize, VkDescriptorSet, VkDescriptorSetAllocateInfo, VkDescriptorSetLayout, VkDescriptorSetLayoutBinding, VkDescriptorSetLayoutCreateInfo, VkDescriptorType, VkPipelineBindPoint, VkPipelineLayout, VkWriteDescriptorSet};
+use vulkan_raw::{vkAllocateDescriptorSets, vkCmdBindDescriptorSets, vkCreateDescriptorPool, vkCreateDescriptorSetLayout, vkDestroyDescriptorPool, vkDestroyDescriptorSetLayout, vkFreeDescriptorSets, vkResetDescriptorPool, vkUpdateDescriptorSets, VkBufferView, VkCommandBuffer, VkCopyDescriptorSet, VkDescriptorBindingFlags, VkDescriptorBufferInfo, VkDescriptorImageInfo, VkDescriptorPool, VkDescriptorPoolCreateFlags, VkDescriptorPoolCreateInfo, VkDescriptorPoolResetFlagBits, VkDescriptorPoolSize, VkDescriptorSet, VkDescriptorSetAllocateInfo, VkDescriptorSetLayout, VkDescriptorSetLayoutBinding, VkDescriptorSetLayoutBindingFlagsCreateInfo, VkDescriptorSetLayoutCreateFlags, VkDescriptorSetLayoutCreateInfo, VkDescriptorType, VkPipelineBindPoint, VkPipelineLayout, VkWriteDescriptorSet};
 
 impl Vulkan {
     pub fn create_descriptor_set_layout(&self, bindings: &[VkDescriptorSetLayoutBinding]) -> VkDescriptorSetLayout {
@@ -19,6 +20,47 @@ impl Vulkan {
         descriptor_set_layout
     }
 
+    /// `binding_flags` has one entry per binding, update after bind bindings need a layout with `UPDATE_AFTER_BIND_POOL_BIT`
+    pub fn create_descriptor_set_layout_with_flags(&self, bindings: &[VkDescriptorSetLayoutBinding], binding_flags: &[VkDescriptorBindingFlags],
+                                                   flags: VkDescriptorSetLayoutCreateFlags) -> VkDescriptorSetLayout {
+        assert_eq!(bindings.len(), binding_flags.len(), "Every binding needs its flags");
+        let binding_flags_info = VkDescriptorSetLayoutBindingFlagsCreateInfo {
+            bindingCount: binding_flags.len() as u32,
+            pBindingFlags: binding_flags.as_ptr(),
+            ..Default::default()
+        };
+        let descriptor_set_layout_create_info = VkDescriptorSetLayoutCreateInfo {
+            pNext: &binding_flags_info as *const _ as *const c_void,
+            flags,
+            bindingCount: bindings.len() as u32,
+            pBindings: bindings.as_ptr(),
+            ..Default::default()
+        };
+
+        let mut descriptor_set_layout = VkDescriptorSetLayout::none();
+        let result = unsafe { vkCreateDescriptorSetLayout(self.get_loaded_device().logical_device, &descriptor_set_layout_create_info, null_mut(), &mut descriptor_set_layout) };
+        assert!(result.is_ok());
+
+        descriptor_set_layout
+    }
+
+    /// Pool for sets whose layouts were created with `UPDATE_AFTER_BIND_POOL_BIT`
+    pub fn create_update_after_bind_descriptor_pool(&self, descriptor_types: &[VkDescriptorPoolSize], max_sets_count: u32) -> VkDescriptorPool {
+        let descriptor_pool_create_info = VkDescriptorPoolCreateInfo {
+            flags: VkDescriptorPoolCreateFlags::UPDATE_AFTER_BIND_BIT,
+            maxSets: max_sets_count,
+            poolSizeCount: descriptor_types.len() as u32,
+            pPoolSizes: descriptor_types.as_ptr(),
+            ..Default::default()
+        };
+
+        let mut descriptor_pool = VkDescriptorPool::none();
+        let result = unsafe { vkCreateDescriptorPool(self.get_loaded_device().logical_device, &descriptor_pool_create_info, null_mut(), &mut descriptor_pool) };
+        assert!(result.is_ok());
+
+        descriptor_pool
+    }
+
     pub fn create_descriptor_pool(&self, descriptor_types: &[VkDescriptorPoolSize], max_sets_count: u32, free: bool) -> VkDescriptorPool {
         let descriptor_pool_create_info = VkDescriptorPoolCreateInfo {
             flags: {
@@ -88,7 +130,7 @@ impl Vulkan {
         unsafe { vkCmdBindDescriptorSets(command_buffer, pipeline_bind_point, layout, first_set, descriptor_sets.len() as u32, descriptor_sets.as_ptr(), dynamic_offsets.len() as u32, dynamic_offsets.as_ptr()) }
     }
 
-    pub fn free_descriptor_sets(&mut self, descriptor_pool: VkDescriptorPool, descriptor_sets: &[VkDescriptorSet]) {
+    pub fn free_descriptor_sets(&self, descriptor_pool: VkDescriptorPool, descriptor_sets: &[VkDescriptorSet]) {
         if let Ok(loaded_device) = self.safe_get_loaded_device() {
             let result = unsafe { vkFreeDescriptorSets(loaded_device.logical_device, descriptor_pool, descriptor_sets.len() as u32, safe_ptr!(descriptor_sets)) };
             assert!(result.is_ok());
@@ -86,7 +86,7 @@ impl Vulkan {
 
     pub fn create_logical_device(&mut self) {
         let devices = self.get_devices();
-        let desired_extensions: HashSet<String> = device_extensions()
+        let desired_extensions: HashSet<String> = if self.is_headless() { vec![] } else { device_extensions() }
             .into_iter()
             .map(null_terminated_str)
             .collect();
@@ -124,8 +124,11 @@ impl Vulkan {
                 continue;
             }
             else {
+                let fill_mode_non_solid = device_info.features.fillModeNonSolid;
                 device_info.features = VkPhysicalDeviceFeatures::default();
 
+                device_info.features.fillModeNonSolid = fill_mode_non_solid;
+
                 device_info.features.geometryShader = VkBool32::TRUE;
                 device_info.features.multiDrawIndirect = VkBool32::TRUE;
             };
@@ -154,10 +157,22 @@ impl Vulkan {
                 println!("Device does not support Vulkan memory model/Runtime Descriptor Array");
                 continue;
             }
+            let features12 = &device_info.features12;
+            let bindless = [features12.descriptorBindingPartiallyBound, features12.descriptorBindingSampledImageUpdateAfterBind,
+                features12.descriptorBindingStorageBufferUpdateAfterBind, features12.descriptorBindingUpdateUnusedWhilePending];
+            if bindless.iter().any(|&feature| feature != VkBool32::TRUE) {
+                println!("Device does not support partially bound/update after bind descriptors");
+                continue;
+            }
             let vulkan_12_features = VkPhysicalDeviceVulkan12Features {
                 pNext: &mut coherent_features as *mut _ as *mut c_void,
                 vulkanMemoryModel: VkBool32::TRUE,
                 runtimeDescriptorArray: VkBool32::TRUE,
+                descriptorIndexing: features12.descriptorIndexing,
+                descriptorBindingPartiallyBound: VkBool32::TRUE,
+                descriptorBindingSampledImageUpdateAfterBind: VkBool32::TRUE,
+                descriptorBindingStorageBufferUpdateAfterBind: VkBool32::TRUE,
+                descriptorBindingUpdateUnusedWhilePending: VkBool32::TRUE,
                 bufferDeviceAddress: device_info.features12.bufferDeviceAddress,
                 ..Default::default()
             };
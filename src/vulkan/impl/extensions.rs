@@ -1,6 +1,6 @@
 use crate::vulkan::func::Vulkan;
 use std::ptr::{null, null_mut};
-use vulkan_raw::{vkEnumerateInstanceExtensionProperties, VkExtensionProperties};
+use vulkan_raw::{vkEnumerateInstanceExtensionProperties, vkEnumerateInstanceLayerProperties, VkExtensionProperties, VkLayerProperties};
 
 impl Vulkan {
     pub fn get_extensions() -> Vec<VkExtensionProperties> {
@@ -25,4 +25,20 @@ impl Vulkan {
         }
         extensions
     }
+
+    pub fn get_layers() -> Vec<VkLayerProperties> {
+        let mut layer_count: u32 = 0;
+        let result = unsafe { vkEnumerateInstanceLayerProperties(&mut layer_count, null_mut()) };
+        assert!(result.is_ok());
+
+        let mut layers: Vec<VkLayerProperties> = Vec::with_capacity(layer_count as usize);
+        let spare = layers.spare_capacity_mut();
+        let result = unsafe { vkEnumerateInstanceLayerProperties(&mut layer_count, spare.as_mut_ptr() as *mut VkLayerProperties) };
+        assert!(result.is_ok());
+
+        unsafe {
+            layers.set_len(layer_count as usize);
+        }
+        layers
+    }
 }
\ No newline at end of file
@@ -1,9 +1,10 @@
 use crate::vulkan::func::{Destructible, Vulkan};
+use crate::vulkan::r#impl::BufferTransition;
 use crate::vulkan::utils::ImageUsage;
 use std::ffi::c_void;
 use std::mem::MaybeUninit;
 use std::ptr::null_mut;
-use vulkan_raw::{vkBindImageMemory, vkBindImageMemory2, vkCmdClearColorImage, vkCmdClearDepthStencilImage, vkCmdCopyImageToBuffer, vkCmdPipelineBarrier, vkCreateImage, vkCreateImageView, vkDestroyImage, vkDestroyImageView, vkGetImageMemoryRequirements, vkGetImageMemoryRequirements2, VkAccessFlags, VkBindImageMemoryInfo, VkBuffer, VkBufferImageCopy, VkClearColorValue, VkClearDepthStencilValue, VkCommandBuffer, VkDependencyFlags, VkExtent2D, VkExtent3D, VkFormat, VkFormatFeatureFlagBits, VkImage, VkImageAspectFlags, VkImageCreateFlagBits, VkImageCreateInfo, VkImageLayout, VkImageMemoryBarrier, VkImageMemoryRequirementsInfo2, VkImageSubresourceRange, VkImageTiling, VkImageType, VkImageView, VkImageViewCreateInfo, VkImageViewType, VkMemoryDedicatedRequirements, VkMemoryRequirements, VkMemoryRequirements2, VkPipelineStageFlags, VkResult, VkSampleCountFlagBits, VkSampleCountFlags, VkSharingMode, VkVersion, VK_REMAINING_ARRAY_LAYERS, VK_REMAINING_MIP_LEVELS};
+use vulkan_raw::{vkBindImageMemory, vkBindImageMemory2, vkCmdClearColorImage, vkCmdClearDepthStencilImage, vkCmdCopyImageToBuffer, vkCmdPipelineBarrier, vkCmdPipelineBarrier2, vkCreateImage, vkCreateImageView, vkDestroyImage, vkDestroyImageView, vkGetImageMemoryRequirements, vkGetImageMemoryRequirements2, VkAccessFlags, VkAccessFlags2, VkBindImageMemoryInfo, VkBuffer, VkBufferImageCopy, VkBufferMemoryBarrier2, VkClearColorValue, VkClearDepthStencilValue, VkCommandBuffer, VkDependencyFlags, VkDependencyInfo, VkExtent2D, VkExtent3D, VkFormat, VkFormatFeatureFlagBits, VkImage, VkImageAspectFlags, VkImageCreateFlagBits, VkImageCreateInfo, VkImageLayout, VkImageMemoryBarrier, VkImageMemoryBarrier2, VkImageMemoryRequirementsInfo2, VkImageSubresourceRange, VkImageTiling, VkImageType, VkImageView, VkImageViewCreateInfo, VkImageViewType, VkMemoryDedicatedRequirements, VkMemoryRequirements, VkMemoryRequirements2, VkPipelineStageFlags, VkPipelineStageFlags2, VkResult, VkSampleCountFlagBits, VkSampleCountFlags, VkSharingMode, VkVersion, VK_QUEUE_FAMILY_IGNORED, VK_REMAINING_ARRAY_LAYERS, VK_REMAINING_MIP_LEVELS};
 
 impl Vulkan {
     pub fn create_image(&self, format: VkFormat, image_type: VkImageType, is_cubemap: bool, mipmaps: u32, layers: u32, size: VkExtent3D, samples: VkSampleCountFlags, usage: ImageUsage) -> VkImage {
@@ -43,16 +44,66 @@ impl Vulkan {
         unsafe { vkCmdPipelineBarrier(command_buffer, generating_stages, consuming_stages, VkDependencyFlags::empty(), 0, null_mut(), 0, null_mut(), image_barriers.len() as u32, image_barriers.as_ptr()); };
     }
 
+    pub fn transition_images2(&self, transition_infos: Vec<ImageTransition2>, command_buffer: VkCommandBuffer) {
+        if transition_infos.is_empty() { return; }
+
+        let image_barriers: Vec<VkImageMemoryBarrier2> = transition_infos
+            .into_iter()
+            .map(|t| t.into())
+            .collect();
+
+        let dependency_info = VkDependencyInfo {
+            dependencyFlags: VkDependencyFlags::empty(),
+            imageMemoryBarrierCount: image_barriers.len() as u32,
+            pImageMemoryBarriers: image_barriers.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe { vkCmdPipelineBarrier2(command_buffer, &dependency_info); }
+    }
+
+    /// Buffer and image barriers in one dependency
+    pub fn transition_resources(&self, buffer_transitions: Vec<BufferTransition>, image_transitions: Vec<ImageTransition2>, command_buffer: VkCommandBuffer) {
+        if buffer_transitions.is_empty() && image_transitions.is_empty() { return; }
+
+        let buffer_barriers: Vec<VkBufferMemoryBarrier2> = buffer_transitions
+            .into_iter()
+            .map(|t| t.into())
+            .collect();
+        let image_barriers: Vec<VkImageMemoryBarrier2> = image_transitions
+            .into_iter()
+            .map(|t| t.into())
+            .collect();
+
+        let dependency_info = VkDependencyInfo {
+            dependencyFlags: VkDependencyFlags::empty(),
+            bufferMemoryBarrierCount: buffer_barriers.len() as u32,
+            pBufferMemoryBarriers: buffer_barriers.as_ptr(),
+            imageMemoryBarrierCount: image_barriers.len() as u32,
+            pImageMemoryBarriers: image_barriers.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe { vkCmdPipelineBarrier2(command_buffer, &dependency_info); }
+    }
+
     pub fn create_image_view(&self, image: &VkImage, view_type: VkImageViewType, format: VkFormat, aspect: VkImageAspectFlags) -> VkImageView {
+        self.create_image_view_range(image, view_type, format, aspect, 0, VK_REMAINING_MIP_LEVELS, 0, VK_REMAINING_ARRAY_LAYERS)
+    }
+
+    /// View over part of the image, e.g. a single cubemap face of one mip level
+    pub fn create_image_view_range(&self, image: &VkImage, view_type: VkImageViewType, format: VkFormat, aspect: VkImageAspectFlags,
+                                   base_mip_level: u32, level_count: u32, base_array_layer: u32, layer_count: u32) -> VkImageView {
         let image_view_create_info = VkImageViewCreateInfo {
             image: *image,
             viewType: view_type,
             format,
             subresourceRange: VkImageSubresourceRange {
                 aspectMask: aspect,
-                levelCount: VK_REMAINING_MIP_LEVELS,
-                layerCount: VK_REMAINING_ARRAY_LAYERS,
-                ..Default::default()
+                baseMipLevel: base_mip_level,
+                levelCount: level_count,
+                baseArrayLayer: base_array_layer,
+                layerCount: layer_count,
             },
             ..Default::default()
         };
@@ -212,6 +263,68 @@ impl Into<VkImageMemoryBarrier> for ImageTransition {
     }
 }
 
+pub struct ImageTransition2 {
+    pub image: VkImage,
+    pub src_stage: VkPipelineStageFlags2,
+    pub dst_stage: VkPipelineStageFlags2,
+    pub src_access: VkAccessFlags2,
+    pub dst_access: VkAccessFlags2,
+    pub old_layout: VkImageLayout,
+    pub new_layout: VkImageLayout,
+    pub src_queue_family: u32,
+    pub dst_queue_family: u32,
+    pub aspect: VkImageAspectFlags,
+    pub base_mip_level: u32,
+    pub level_count: u32,
+    pub base_array_layer: u32,
+    pub layer_count: u32,
+}
+
+impl Default for ImageTransition2 {
+    fn default() -> Self {
+        ImageTransition2 {
+            image: VkImage::none(),
+            src_stage: Default::default(),
+            dst_stage: Default::default(),
+            src_access: Default::default(),
+            dst_access: Default::default(),
+            old_layout: VkImageLayout::UNDEFINED,
+            new_layout: VkImageLayout::UNDEFINED,
+            src_queue_family: VK_QUEUE_FAMILY_IGNORED,
+            dst_queue_family: VK_QUEUE_FAMILY_IGNORED,
+            aspect: VkImageAspectFlags::COLOR_BIT,
+            base_mip_level: 0,
+            level_count: VK_REMAINING_MIP_LEVELS,
+            base_array_layer: 0,
+            layer_count: VK_REMAINING_ARRAY_LAYERS,
+        }
+    }
+}
+
+impl Into<VkImageMemoryBarrier2> for ImageTransition2 {
+    fn into(self) -> VkImageMemoryBarrier2 {
+        VkImageMemoryBarrier2 {
+            srcStageMask: self.src_stage,
+            srcAccessMask: self.src_access,
+            dstStageMask: self.dst_stage,
+            dstAccessMask: self.dst_access,
+            oldLayout: self.old_layout,
+            newLayout: self.new_layout,
+            srcQueueFamilyIndex: self.src_queue_family,
+            dstQueueFamilyIndex: self.dst_queue_family,
+            image: self.image,
+            subresourceRange: VkImageSubresourceRange {
+                aspectMask: self.aspect,
+                baseMipLevel: self.base_mip_level,
+                levelCount: self.level_count,
+                baseArrayLayer: self.base_array_layer,
+                layerCount: self.layer_count,
+            },
+            ..Default::default()
+        }
+    }
+}
+
 impl Destructible for VkImageView {
     fn destroy(&self, vulkan: &Vulkan) {
         vulkan.destroy_image_view(*self);
@@ -1,7 +1,7 @@
 use crate::application::{APPLICATION_NAME, ENGINE_MAJOR_VERSION, ENGINE_MINOR_VERSION, ENGINE_NAME, ENGINE_PATCH_VERSION};
 use crate::safe_ptr;
 use crate::vulkan::func::Vulkan;
-use crate::vulkan::platform::{extensions, platform_extensions};
+use crate::vulkan::platform::{extensions, optional_extensions, platform_extensions};
 use crate::vulkan::utils::{null_terminated_str, null_terminated_string};
 use std::collections::HashSet;
 use std::ffi::c_char;
@@ -15,11 +15,24 @@ impl Vulkan {
             .iter()
             .map(|x| null_terminated_string(&x.extensionName))
             .collect();
-        let desired_extensions: HashSet<String> = [extensions().as_slice(), platform_extensions(&supported).as_slice()]
-            .concat()
+        let desired_extensions: HashSet<String> = if self.is_headless() {
+            vec![]
+        } else {
+            [extensions().as_slice(), platform_extensions(&supported).as_slice()].concat()
+        }
             .into_iter()
             .map(|str| null_terminated_str(str))
             .collect();
+        let desired_extensions: HashSet<String> = if self.is_headless() {
+            desired_extensions
+        } else {
+            optional_extensions()
+                .into_iter()
+                .map(null_terminated_str)
+                .filter(|ext| supported.contains(ext))
+                .chain(desired_extensions)
+                .collect()
+        };
 
         if !desired_extensions.iter().all(|x| supported.contains(x)) {
             panic!("The Vulkan API does not support specified extensions");
@@ -43,7 +56,13 @@ impl Vulkan {
         let mut layers: Vec<*const c_char> = vec![];
         #[cfg(target_os="linux")]
         {
-            #[cfg(debug_assertions)] layers.push("VK_LAYER_KHRONOS_validation\0".as_ptr() as *const c_char);
+            // CI runners (lavapipe) usually come without the SDK layers
+            #[cfg(debug_assertions)]
+            if Vulkan::get_layers().iter().any(|layer| null_terminated_string(&layer.layerName) == "VK_LAYER_KHRONOS_validation\0") {
+                layers.push("VK_LAYER_KHRONOS_validation\0".as_ptr() as *const c_char);
+            } else {
+                eprintln!("Validation layer is not installed, running without it");
+            }
         }
         let instance_info = VkInstanceCreateInfo {
             pApplicationInfo: &application_info,
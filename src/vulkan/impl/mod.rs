@@ -13,8 +13,10 @@ mod sampler;
 mod descriptors;
 mod extensions;
 mod renderpass;
+mod rendering;
 mod shaders;
 mod pipelines;
+mod reflection;
 
 pub use buffer::*;
 pub use command_buffer::*;
@@ -26,7 +28,9 @@ pub use instance::*;
 pub use memory::*;
 pub use pipelines::*;
 pub use queues::*;
+pub use reflection::*;
 pub use renderpass::*;
+pub use rendering::*;
 pub use sampler::*;
 pub use shaders::*;
 pub use surface::*;
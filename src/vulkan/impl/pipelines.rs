@@ -1,10 +1,12 @@
 use crate::vulkan::func::{Destructible, Vulkan};
+use crate::vulkan::r#impl::PipelineRenderingCreateInfo;
 use crate::vulkan::gltf::scene::Vertex;
 use crate::{null_if_none, safe_ptr};
-use std::any::Any;
+use shaders::common::{PushConstants, PushStages};
+use std::any::{type_name, Any};
 use std::ffi::c_void;
 use std::ptr::{null, null_mut};
-use vulkan_raw::{vkCmdBindIndexBuffer, vkCmdBindPipeline, vkCmdBindVertexBuffers, vkCmdPushConstants, vkCmdSetScissor, vkCmdSetViewport, vkCreateComputePipelines, vkCreateGraphicsPipelines, vkCreatePipelineCache, vkCreatePipelineLayout, vkDestroyPipeline, vkDestroyPipelineCache, vkDestroyPipelineLayout, vkGetPipelineCacheData, vkMergePipelineCaches, VkBlendFactor, VkBlendOp, VkBool32, VkBuffer, VkColorComponentFlags, VkCommandBuffer, VkCompareOp, VkComputePipelineCreateInfo, VkCullModeFlags, VkDescriptorSetLayout, VkDeviceSize, VkDynamicState, VkExtent2D, VkFormat, VkFrontFace, VkGraphicsPipelineCreateInfo, VkIndexType, VkLogicOp, VkOffset2D, VkPipeline, VkPipelineBindPoint, VkPipelineCache, VkPipelineCacheCreateInfo, VkPipelineColorBlendAttachmentState, VkPipelineColorBlendStateCreateFlags, VkPipelineColorBlendStateCreateInfo, VkPipelineCreateFlags, VkPipelineDepthStencilStateCreateFlags, VkPipelineDepthStencilStateCreateInfo, VkPipelineDynamicStateCreateFlags, VkPipelineDynamicStateCreateInfo, VkPipelineInputAssemblyStateCreateFlags, VkPipelineInputAssemblyStateCreateInfo, VkPipelineLayout, VkPipelineLayoutCreateInfo, VkPipelineMultisampleStateCreateFlags, VkPipelineMultisampleStateCreateInfo, VkPipelineRasterizationStateCreateFlags, VkPipelineRasterizationStateCreateInfo, VkPipelineShaderStageCreateFlags, VkPipelineShaderStageCreateInfo, VkPipelineTessellationStateCreateFlags, VkPipelineTessellationStateCreateInfo, VkPipelineVertexInputStateCreateFlags, VkPipelineVertexInputStateCreateInfo, VkPipelineViewportStateCreateFlags, VkPipelineViewportStateCreateInfo, VkPolygonMode, VkPrimitiveTopology, VkPushConstantRange, VkRect2D, VkRenderPass, VkSampleCountFlagBits, VkSampleMask, VkShaderModule, VkShaderStageFlagBits, VkShaderStageFlags, VkSpecializationInfo, VkSpecializationMapEntry, VkStencilOpState, VkVertexInputAttributeDescription, VkVertexInputBindingDescription, VkVertexInputRate, VkViewport};
+use vulkan_raw::{vkCmdBindIndexBuffer, vkCmdBindPipeline, vkCmdBindVertexBuffers, vkCmdDispatch, vkCmdDispatchIndirect, vkCmdDraw, vkCmdDrawIndexed, vkCmdDrawIndirect, vkCmdPushConstants, vkCmdSetScissor, vkCmdSetViewport, vkCreateComputePipelines, vkCreateGraphicsPipelines, vkCreatePipelineCache, vkCreatePipelineLayout, vkDestroyPipeline, vkDestroyPipelineCache, vkDestroyPipelineLayout, vkGetPipelineCacheData, vkMergePipelineCaches, VkBlendFactor, VkBlendOp, VkBool32, VkBuffer, VkColorComponentFlags, VkCommandBuffer, VkCompareOp, VkComputePipelineCreateInfo, VkCullModeFlags, VkDescriptorSetLayout, VkDeviceSize, VkDynamicState, VkExtent2D, VkFormat, VkFrontFace, VkGraphicsPipelineCreateInfo, VkIndexType, VkLogicOp, VkOffset2D, VkPipeline, VkPipelineBindPoint, VkPipelineCache, VkPipelineCacheCreateInfo, VkPipelineColorBlendAttachmentState, VkPipelineColorBlendStateCreateFlags, VkPipelineColorBlendStateCreateInfo, VkPipelineCreateFlags, VkPipelineDepthStencilStateCreateFlags, VkPipelineDepthStencilStateCreateInfo, VkPipelineDynamicStateCreateFlags, VkPipelineDynamicStateCreateInfo, VkPipelineInputAssemblyStateCreateFlags, VkPipelineInputAssemblyStateCreateInfo, VkPipelineLayout, VkPipelineLayoutCreateInfo, VkPipelineMultisampleStateCreateFlags, VkPipelineMultisampleStateCreateInfo, VkPipelineRasterizationStateCreateFlags, VkPipelineRasterizationStateCreateInfo, VkPipelineRenderingCreateInfo, VkPipelineShaderStageCreateFlags, VkPipelineShaderStageCreateInfo, VkPipelineTessellationStateCreateFlags, VkPipelineTessellationStateCreateInfo, VkPipelineVertexInputStateCreateFlags, VkPipelineVertexInputStateCreateInfo, VkPipelineViewportStateCreateFlags, VkPipelineViewportStateCreateInfo, VkPolygonMode, VkPrimitiveTopology, VkPushConstantRange, VkRect2D, VkRenderPass, VkSampleCountFlagBits, VkSampleMask, VkShaderModule, VkShaderStageFlagBits, VkShaderStageFlags, VkSpecializationInfo, VkSpecializationMapEntry, VkStencilOpState, VkVertexInputAttributeDescription, VkVertexInputBindingDescription, VkVertexInputRate, VkViewport};
 
 impl Vulkan {
     #[inline]
@@ -86,6 +88,15 @@ impl Vulkan {
         pipeline_layout
     }
 
+    /// Layout whose only push constant range is the block `T`
+    pub fn create_pipeline_layout_with<T: PushConstants>(&self, descriptor_set_layouts: &[VkDescriptorSetLayout]) -> VkPipelineLayout {
+        let range = push_constant_range::<T>();
+        let limit = self.get_loaded_device().device_info.properties.limits.maxPushConstantsSize;
+        assert!(range.size <= limit, "{} is {} bytes, the device allows {limit} bytes of push constants", type_name::<T>(), range.size);
+
+        self.create_pipeline_layout(descriptor_set_layouts, &[range])
+    }
+
     pub fn create_pipeline_creation_parameters(&self, options: VkPipelineCreateFlags, shader_info: ShaderInfo,
                                                viewport_state: Option<VkPipelineViewportStateCreateInfo>, rasterization_state: Option<VkPipelineRasterizationStateCreateInfo>,
                                                multisample_state: Option<VkPipelineMultisampleStateCreateInfo>, depth_stencil_state: Option<VkPipelineDepthStencilStateCreateInfo>,
@@ -218,6 +229,12 @@ impl Vulkan {
         unsafe { vkCmdPushConstants(command_buffer, layout, stage_flags, offset, size, data); }
     }
 
+    /// Pushes the whole block with the stages of `T`, `layout` has to contain `push_constant_range::<T>()`
+    pub fn push_constants<T: PushConstants>(&self, command_buffer: VkCommandBuffer, layout: VkPipelineLayout, value: &T) {
+        let range = push_constant_range::<T>();
+        unsafe { self.set_push_constants(command_buffer, layout, range.stageFlags, range.offset, range.size, value as *const T as *const c_void); }
+    }
+
     pub fn set_viewport(&self, command_buffer: VkCommandBuffer, offset: u32, viewports: &[VkViewport]) {
         unsafe { vkCmdSetViewport(command_buffer, offset, viewports.len() as u32, viewports.as_ptr()); }
     }
@@ -230,6 +247,26 @@ impl Vulkan {
         unsafe { vkCmdBindPipeline(command_buffer, pipeline_type, pipeline) };
     }
 
+    pub fn draw(&self, command_buffer: VkCommandBuffer, vertex_count: u32, instance_count: u32, first_vertex: u32, first_instance: u32) {
+        unsafe { vkCmdDraw(command_buffer, vertex_count, instance_count, first_vertex, first_instance) };
+    }
+
+    pub fn draw_indexed(&self, command_buffer: VkCommandBuffer, index_count: u32, instance_count: u32, first_index: u32, vertex_offset: i32, first_instance: u32) {
+        unsafe { vkCmdDrawIndexed(command_buffer, index_count, instance_count, first_index, vertex_offset, first_instance) };
+    }
+
+    pub fn draw_indirect(&self, command_buffer: VkCommandBuffer, buffer: VkBuffer, offset: VkDeviceSize, draw_count: u32, stride: u32) {
+        unsafe { vkCmdDrawIndirect(command_buffer, buffer, offset, draw_count, stride) };
+    }
+
+    pub fn dispatch(&self, command_buffer: VkCommandBuffer, group_count_x: u32, group_count_y: u32, group_count_z: u32) {
+        unsafe { vkCmdDispatch(command_buffer, group_count_x, group_count_y, group_count_z) };
+    }
+
+    pub fn dispatch_indirect(&self, command_buffer: VkCommandBuffer, buffer: VkBuffer, offset: VkDeviceSize) {
+        unsafe { vkCmdDispatchIndirect(command_buffer, buffer, offset) };
+    }
+
     fn destroy_pipeline(&self, pipeline: VkPipeline) {
         unsafe { vkDestroyPipeline(self.get_loaded_device().logical_device, pipeline, null_mut()) };
     }
@@ -245,6 +282,29 @@ impl Vulkan {
     }
 }
 
+/// Range of the push constant block `T`, for layouts shared with other blocks or for reflection checks
+pub fn push_constant_range<T: PushConstants>() -> VkPushConstantRange {
+    let size = size_of::<T>() as u32;
+    assert!(size > 0 && size % 4 == 0, "{} is {size} bytes, push constant blocks have to be a non-zero multiple of 4", type_name::<T>());
+    VkPushConstantRange {
+        stageFlags: T::STAGES.into(),
+        offset: 0,
+        size,
+    }
+}
+
+impl From<PushStages> for VkShaderStageFlags {
+    fn from(stages: PushStages) -> Self {
+        let mut flags = VkShaderStageFlags::empty();
+
+        if stages.vertex { flags |= VkShaderStageFlags::VERTEX_BIT; }
+        if stages.fragment { flags |= VkShaderStageFlags::FRAGMENT_BIT; }
+        if stages.compute { flags |= VkShaderStageFlags::COMPUTE_BIT; }
+
+        flags
+    }
+}
+
 impl Destructible for VkPipeline {
     fn destroy(&self, vulkan: &Vulkan) {
         vulkan.destroy_pipeline(*self);
@@ -303,6 +363,7 @@ pub struct GraphicsPipelineCreateInfo {
     pub depth_stencil_state: Option<PipelineDepthStencilStateCreateInfo>,
     pub color_blend_state: Option<PipelineColorBlendStateCreateInfo>,
     pub dynamic_state: Option<PipelineDynamicStateCreateInfo>,
+    pub rendering_info: Option<PipelineRenderingCreateInfo>,
     pub layout: VkPipelineLayout,
     pub render_pass: VkRenderPass,
     pub subpass: u32,
@@ -325,6 +386,20 @@ pub struct SpecializationInfo {
     pub data: Vec<u8>,
 }
 
+impl SpecializationInfo {
+    /// One 32-bit constant per value, the constant id is the index. Float constants are passed as `f32::to_bits`
+    pub fn from_u32s(constants: &[u32]) -> SpecializationInfo {
+        SpecializationInfo {
+            map_entries: (0..constants.len() as u32).map(|id| VkSpecializationMapEntry {
+                constantID: id,
+                offset: id * size_of::<u32>() as u32,
+                size: size_of::<u32>(),
+            }).collect(),
+            data: constants.iter().flat_map(|constant| constant.to_ne_bytes()).collect(),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct PipelineVertexInputStateCreateInfo {
     pub flags: VkPipelineVertexInputStateCreateFlags,
@@ -522,7 +597,19 @@ impl GraphicsPipelineCreateInfo {
             None => dynamic_ptr = null()
         };
 
+        // Dynamic rendering pipelines describe attachments through pNext instead of render pass
+        let rendering_ptr: *const c_void;
+        match &self.rendering_info {
+            Some(rendering_info) => {
+                let boxed = Box::new(rendering_info.to_vulkan(&mut keep_alive));
+                rendering_ptr = boxed.as_ref() as *const VkPipelineRenderingCreateInfo as *const c_void;
+                keep_alive.push(boxed);
+            },
+            None => rendering_ptr = null()
+        };
+
         let info = VkGraphicsPipelineCreateInfo {
+            pNext: rendering_ptr,
             flags: self.flags,
             stageCount: stages.len() as u32,
             pStages: stages.as_ptr(),
@@ -0,0 +1,627 @@
+use crate::vulkan::r#impl::PipelineVertexInputStateCreateInfo;
+use std::collections::HashMap;
+use std::ptr::null_mut;
+use vulkan_raw::{VkDescriptorSetLayoutBinding, VkDescriptorType, VkFormat, VkPushConstantRange, VkShaderStageFlags};
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_FUNCTION: u32 = 54;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BUILT_IN: u32 = 11;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+const STORAGE_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_INPUT: u32 = 1;
+const STORAGE_UNIFORM: u32 = 2;
+const STORAGE_PUSH_CONSTANT: u32 = 9;
+const STORAGE_STORAGE_BUFFER: u32 = 12;
+
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+
+/// Descriptor used by an entry point, `count` is 0 for runtime arrays whose size the host picks
+#[derive(Clone, Copy, Debug)]
+pub struct ReflectedBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: VkDescriptorType,
+    pub count: u32,
+    pub stages: VkShaderStageFlags,
+}
+
+/// User defined vertex shader input, built-ins are skipped
+#[derive(Clone, Copy, Debug)]
+pub struct ReflectedInput {
+    pub location: u32,
+    pub format: VkFormat,
+}
+
+#[derive(Clone, Debug)]
+pub struct EntryPointReflection {
+    pub name: String,
+    pub stage: VkShaderStageFlags,
+    pub bindings: Vec<ReflectedBinding>,
+    /// Size of the push constant block in bytes, zero without one
+    pub push_constant_size: u32,
+    pub inputs: Vec<ReflectedInput>,
+}
+
+/// Resource interface of every entry point of one SPIR-V module
+#[derive(Clone, Debug, Default)]
+pub struct ShaderReflection {
+    pub entry_points: Vec<EntryPointReflection>,
+}
+
+#[derive(Clone, Debug)]
+enum SpirvType {
+    Int { width: u32, signed: bool },
+    Float { width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    Image { dim: u32, sampled: u32 },
+    Sampler,
+    SampledImage,
+    Array { element: u32, length: u32 },
+    RuntimeArray { element: u32 },
+    Struct { members: Vec<u32> },
+    Pointer { pointee: u32 },
+}
+
+#[derive(Default)]
+struct Decorations {
+    set: Option<u32>,
+    binding: Option<u32>,
+    location: Option<u32>,
+    built_in: bool,
+    buffer_block: bool,
+    array_stride: Option<u32>,
+}
+
+/// Declarations before the first function, which is all reflection needs
+#[derive(Default)]
+struct Module {
+    types: HashMap<u32, SpirvType>,
+    constants: HashMap<u32, u32>,
+    decorations: HashMap<u32, Decorations>,
+    member_offsets: HashMap<(u32, u32), u32>,
+    matrix_strides: HashMap<(u32, u32), u32>,
+    /// Pointer type and storage class per global variable
+    variables: HashMap<u32, (u32, u32)>,
+    entry_points: Vec<(u32, String, Vec<u32>)>,
+}
+
+impl ShaderReflection {
+    /// Panics on anything that is not a little endian SPIR-V module
+    pub fn parse(spirv: &[u8]) -> ShaderReflection {
+        assert_eq!(spirv.len() % 4, 0, "SPIR-V size is not a multiple of 4");
+        let words = spirv.chunks_exact(4).map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]])).collect::<Vec<_>>();
+        assert!(words.len() >= 5 && words[0] == SPIRV_MAGIC, "Not a SPIR-V module");
+
+        let module = Module::parse(&words[5..]);
+        let entry_points = module.entry_points.iter()
+            .filter_map(|(model, name, interface)| {
+                let stage = match model {
+                    0 => VkShaderStageFlags::VERTEX_BIT,
+                    4 => VkShaderStageFlags::FRAGMENT_BIT,
+                    5 => VkShaderStageFlags::COMPUTE_BIT,
+                    _ => return None,
+                };
+                Some(module.entry_point(name.clone(), stage, interface))
+            })
+            .collect();
+
+        ShaderReflection { entry_points }
+    }
+
+    pub fn entry_point(&self, name: &str) -> Option<&EntryPointReflection> {
+        self.entry_points.iter().find(|entry| entry.name == name)
+    }
+}
+
+impl Module {
+    fn parse(mut words: &[u32]) -> Module {
+        let mut module = Module::default();
+        while !words.is_empty() {
+            let count = (words[0] >> 16) as usize;
+            let opcode = words[0] & 0xffff;
+            assert!(count > 0 && count <= words.len(), "Truncated SPIR-V instruction");
+            let operands = &words[1..count];
+            words = &words[count..];
+
+            match opcode {
+                OP_ENTRY_POINT => {
+                    let (name, used) = literal_string(&operands[2..]);
+                    module.entry_points.push((operands[0], name, operands[2 + used..].to_vec()));
+                }
+                OP_DECORATE => {
+                    let decorations = module.decorations.entry(operands[0]).or_default();
+                    let literal = operands.get(2).copied();
+                    match operands[1] {
+                        DECORATION_DESCRIPTOR_SET => decorations.set = literal,
+                        DECORATION_BINDING => decorations.binding = literal,
+                        DECORATION_LOCATION => decorations.location = literal,
+                        DECORATION_ARRAY_STRIDE => decorations.array_stride = literal,
+                        DECORATION_BUILT_IN => decorations.built_in = true,
+                        DECORATION_BUFFER_BLOCK => decorations.buffer_block = true,
+                        _ => {}
+                    }
+                }
+                OP_MEMBER_DECORATE => match operands[2] {
+                    DECORATION_OFFSET => { module.member_offsets.insert((operands[0], operands[1]), operands[3]); }
+                    DECORATION_MATRIX_STRIDE => { module.matrix_strides.insert((operands[0], operands[1]), operands[3]); }
+                    _ => {}
+                },
+                OP_TYPE_INT => { module.types.insert(operands[0], SpirvType::Int { width: operands[1], signed: operands[2] == 1 }); }
+                OP_TYPE_FLOAT => { module.types.insert(operands[0], SpirvType::Float { width: operands[1] }); }
+                OP_TYPE_VECTOR => { module.types.insert(operands[0], SpirvType::Vector { component: operands[1], count: operands[2] }); }
+                OP_TYPE_MATRIX => { module.types.insert(operands[0], SpirvType::Matrix { column: operands[1], count: operands[2] }); }
+                OP_TYPE_IMAGE => { module.types.insert(operands[0], SpirvType::Image { dim: operands[2], sampled: operands[6] }); }
+                OP_TYPE_SAMPLER => { module.types.insert(operands[0], SpirvType::Sampler); }
+                OP_TYPE_SAMPLED_IMAGE => { module.types.insert(operands[0], SpirvType::SampledImage); }
+                OP_TYPE_ARRAY => { module.types.insert(operands[0], SpirvType::Array { element: operands[1], length: operands[2] }); }
+                OP_TYPE_RUNTIME_ARRAY => { module.types.insert(operands[0], SpirvType::RuntimeArray { element: operands[1] }); }
+                OP_TYPE_STRUCT => { module.types.insert(operands[0], SpirvType::Struct { members: operands[1..].to_vec() }); }
+                OP_TYPE_POINTER => { module.types.insert(operands[0], SpirvType::Pointer { pointee: operands[2] }); }
+                OP_CONSTANT => { module.constants.insert(operands[1], operands[2]); }
+                OP_VARIABLE => { module.variables.insert(operands[1], (operands[0], operands[2])); }
+                OP_FUNCTION => break,
+                _ => {}
+            }
+        }
+        module
+    }
+
+    /// Since SPIR-V 1.4 the interface lists every global an entry point uses, not only inputs and outputs
+    fn entry_point(&self, name: String, stage: VkShaderStageFlags, interface: &[u32]) -> EntryPointReflection {
+        let mut bindings = vec![];
+        let mut inputs = vec![];
+        let mut push_constant_size = 0;
+
+        for id in interface {
+            let Some(&(pointer, storage_class)) = self.variables.get(id) else {
+                continue;
+            };
+            let Some(SpirvType::Pointer { pointee }) = self.types.get(&pointer) else {
+                continue;
+            };
+            let decorations = self.decorations.get(id);
+
+            match storage_class {
+                STORAGE_PUSH_CONSTANT => push_constant_size = self.size_of(*pointee),
+                STORAGE_INPUT if stage == VkShaderStageFlags::VERTEX_BIT => {
+                    let Some(location) = decorations.filter(|decorations| !decorations.built_in).and_then(|decorations| decorations.location) else {
+                        continue;
+                    };
+                    match self.vertex_format(*pointee) {
+                        Some(format) => inputs.push(ReflectedInput { location, format }),
+                        None => eprintln!("Vertex input at location {location} of `{name}` has no matching format"),
+                    }
+                }
+                STORAGE_UNIFORM_CONSTANT | STORAGE_UNIFORM | STORAGE_STORAGE_BUFFER => {
+                    let (Some(set), Some(binding)) = (decorations.and_then(|d| d.set), decorations.and_then(|d| d.binding)) else {
+                        continue;
+                    };
+                    let Some((descriptor_type, count)) = self.descriptor(*pointee, storage_class) else {
+                        eprintln!("Unknown descriptor type at set {set} binding {binding} of `{name}`");
+                        continue;
+                    };
+                    bindings.push(ReflectedBinding { set, binding, descriptor_type, count, stages: stage });
+                }
+                _ => {}
+            }
+        }
+        bindings.sort_by_key(|binding| (binding.set, binding.binding));
+        inputs.sort_by_key(|input| input.location);
+
+        EntryPointReflection {
+            name,
+            stage,
+            bindings,
+            push_constant_size,
+            inputs,
+        }
+    }
+
+    fn descriptor(&self, pointee: u32, storage_class: u32) -> Option<(VkDescriptorType, u32)> {
+        let (element, count) = match self.types.get(&pointee)? {
+            SpirvType::Array { element, length } => (*element, *self.constants.get(length)?),
+            SpirvType::RuntimeArray { element } => (*element, 0),
+            _ => (pointee, 1),
+        };
+
+        let descriptor_type = match storage_class {
+            STORAGE_STORAGE_BUFFER => VkDescriptorType::STORAGE_BUFFER,
+            STORAGE_UNIFORM if self.decorations.get(&element).is_some_and(|d| d.buffer_block) => VkDescriptorType::STORAGE_BUFFER,
+            STORAGE_UNIFORM => VkDescriptorType::UNIFORM_BUFFER,
+            _ => match self.types.get(&element)? {
+                SpirvType::Sampler => VkDescriptorType::SAMPLER,
+                SpirvType::SampledImage => VkDescriptorType::COMBINED_IMAGE_SAMPLER,
+                SpirvType::Image { dim: DIM_BUFFER, sampled: 2 } => VkDescriptorType::STORAGE_TEXEL_BUFFER,
+                SpirvType::Image { dim: DIM_BUFFER, .. } => VkDescriptorType::UNIFORM_TEXEL_BUFFER,
+                SpirvType::Image { dim: DIM_SUBPASS_DATA, .. } => VkDescriptorType::INPUT_ATTACHMENT,
+                SpirvType::Image { sampled: 2, .. } => VkDescriptorType::STORAGE_IMAGE,
+                SpirvType::Image { .. } => VkDescriptorType::SAMPLED_IMAGE,
+                _ => return None,
+            },
+        };
+        Some((descriptor_type, count))
+    }
+
+    fn vertex_format(&self, id: u32) -> Option<VkFormat> {
+        let (component, count) = match self.types.get(&id)? {
+            SpirvType::Vector { component, count } => (*component, *count),
+            _ => (id, 1),
+        };
+        let format = match (self.types.get(&component)?, count) {
+            (SpirvType::Float { width: 32 }, 1) => VkFormat::R32_SFLOAT,
+            (SpirvType::Float { width: 32 }, 2) => VkFormat::R32G32_SFLOAT,
+            (SpirvType::Float { width: 32 }, 3) => VkFormat::R32G32B32_SFLOAT,
+            (SpirvType::Float { width: 32 }, 4) => VkFormat::R32G32B32A32_SFLOAT,
+            (SpirvType::Int { width: 32, signed: true }, 1) => VkFormat::R32_SINT,
+            (SpirvType::Int { width: 32, signed: true }, 2) => VkFormat::R32G32_SINT,
+            (SpirvType::Int { width: 32, signed: true }, 3) => VkFormat::R32G32B32_SINT,
+            (SpirvType::Int { width: 32, signed: true }, 4) => VkFormat::R32G32B32A32_SINT,
+            (SpirvType::Int { width: 32, signed: false }, 1) => VkFormat::R32_UINT,
+            (SpirvType::Int { width: 32, signed: false }, 2) => VkFormat::R32G32_UINT,
+            (SpirvType::Int { width: 32, signed: false }, 3) => VkFormat::R32G32B32_UINT,
+            (SpirvType::Int { width: 32, signed: false }, 4) => VkFormat::R32G32B32A32_UINT,
+            _ => return None,
+        };
+        Some(format)
+    }
+
+    /// Byte size following explicit offsets and strides, runtime arrays count as empty
+    fn size_of(&self, id: u32) -> u32 {
+        match self.types.get(&id) {
+            Some(SpirvType::Int { width, .. }) | Some(SpirvType::Float { width }) => width / 8,
+            Some(SpirvType::Vector { component, count }) => self.size_of(*component) * count,
+            Some(SpirvType::Matrix { column, count }) => self.size_of(*column) * count,
+            Some(SpirvType::Array { element, length }) => {
+                let stride = self.decorations.get(&id).and_then(|d| d.array_stride).unwrap_or_else(|| self.size_of(*element));
+                stride * self.constants.get(length).copied().unwrap_or(0)
+            }
+            Some(SpirvType::Struct { members }) => {
+                let mut end = 0;
+                for (index, &member) in members.iter().enumerate() {
+                    let offset = self.member_offsets.get(&(id, index as u32)).copied().unwrap_or(end);
+                    let size = match (self.types.get(&member), self.matrix_strides.get(&(id, index as u32))) {
+                        (Some(SpirvType::Matrix { count, .. }), Some(stride)) => stride * count,
+                        _ => self.size_of(member),
+                    };
+                    end = end.max(offset + size);
+                }
+                end
+            }
+            _ => 0,
+        }
+    }
+}
+
+fn literal_string(words: &[u32]) -> (String, usize) {
+    let mut bytes = vec![];
+    for (index, word) in words.iter().enumerate() {
+        for byte in word.to_le_bytes() {
+            if byte == 0 {
+                return (String::from_utf8_lossy(&bytes).into_owned(), index + 1);
+            }
+            bytes.push(byte);
+        }
+    }
+    (String::from_utf8_lossy(&bytes).into_owned(), words.len())
+}
+
+/// Float, signed or unsigned integer. Normalized and scaled formats read as floats, component counts may differ
+fn numeric_type(format: VkFormat) -> &'static str {
+    match format {
+        VkFormat::R8_SINT | VkFormat::R8G8_SINT | VkFormat::R8G8B8_SINT | VkFormat::B8G8R8_SINT
+        | VkFormat::R8G8B8A8_SINT | VkFormat::B8G8R8A8_SINT
+        | VkFormat::R16_SINT | VkFormat::R16G16_SINT | VkFormat::R16G16B16_SINT | VkFormat::R16G16B16A16_SINT
+        | VkFormat::R32_SINT | VkFormat::R32G32_SINT | VkFormat::R32G32B32_SINT | VkFormat::R32G32B32A32_SINT
+        | VkFormat::R64_SINT | VkFormat::R64G64_SINT | VkFormat::R64G64B64_SINT | VkFormat::R64G64B64A64_SINT => "int",
+        VkFormat::R8_UINT | VkFormat::R8G8_UINT | VkFormat::R8G8B8_UINT | VkFormat::B8G8R8_UINT
+        | VkFormat::R8G8B8A8_UINT | VkFormat::B8G8R8A8_UINT
+        | VkFormat::R16_UINT | VkFormat::R16G16_UINT | VkFormat::R16G16B16_UINT | VkFormat::R16G16B16A16_UINT
+        | VkFormat::R32_UINT | VkFormat::R32G32_UINT | VkFormat::R32G32B32_UINT | VkFormat::R32G32B32A32_UINT
+        | VkFormat::R64_UINT | VkFormat::R64G64_UINT | VkFormat::R64G64B64_UINT | VkFormat::R64G64B64A64_UINT => "uint",
+        _ => "float",
+    }
+}
+
+/// Combined interface of the entry points one pipeline is built from
+#[derive(Clone, Debug, Default)]
+pub struct ShaderInterface {
+    pub bindings: Vec<ReflectedBinding>,
+    /// Stages reading push constants and the largest block size among them
+    pub push_constants: Option<(VkShaderStageFlags, u32)>,
+    pub vertex_inputs: Vec<ReflectedInput>,
+}
+
+impl ShaderInterface {
+    /// Panics when an entry point does not exist in its module
+    pub fn new(stages: &[(&ShaderReflection, &str)]) -> ShaderInterface {
+        let mut interface = ShaderInterface::default();
+        for (reflection, name) in stages {
+            let entry = reflection.entry_point(name)
+                .unwrap_or_else(|| panic!("Entry point `{name}` not found in shader module"));
+
+            for binding in &entry.bindings {
+                match interface.bindings.iter_mut().find(|b| (b.set, b.binding) == (binding.set, binding.binding)) {
+                    Some(existing) if existing.descriptor_type != binding.descriptor_type || existing.count != binding.count => {
+                        eprintln!("Stages disagree on set {} binding {}: {:?}[{}] and {:?}[{}]", binding.set, binding.binding,
+                                  existing.descriptor_type, existing.count, binding.descriptor_type, binding.count);
+                    }
+                    Some(existing) => existing.stages = existing.stages | binding.stages,
+                    None => interface.bindings.push(*binding),
+                }
+            }
+
+            if entry.push_constant_size > 0 {
+                interface.push_constants = Some(match interface.push_constants {
+                    Some((stages, size)) => (stages | entry.stage, size.max(entry.push_constant_size)),
+                    None => (entry.stage, entry.push_constant_size),
+                });
+            }
+            if entry.stage == VkShaderStageFlags::VERTEX_BIT {
+                interface.vertex_inputs = entry.inputs.clone();
+            }
+        }
+        interface.bindings.sort_by_key(|binding| (binding.set, binding.binding));
+        interface
+    }
+
+    /// Number of descriptor sets the pipeline layout needs
+    pub fn set_count(&self) -> u32 {
+        self.bindings.iter().map(|binding| binding.set + 1).max().unwrap_or(0)
+    }
+
+    /// Layout bindings of `set`, runtime arrays take their size from `variable_counts` as (binding, count)
+    pub fn set_layout_bindings(&self, set: u32, variable_counts: &[(u32, u32)]) -> Vec<VkDescriptorSetLayoutBinding> {
+        self.bindings.iter()
+            .filter(|binding| binding.set == set)
+            .map(|binding| {
+                let count = match binding.count {
+                    0 => variable_counts.iter().find(|(b, _)| *b == binding.binding).map(|(_, count)| *count)
+                        .unwrap_or_else(|| panic!("No size given for runtime array at set {set} binding {}", binding.binding)),
+                    count => count,
+                };
+                VkDescriptorSetLayoutBinding {
+                    binding: binding.binding,
+                    descriptorType: binding.descriptor_type,
+                    descriptorCount: count,
+                    stageFlags: binding.stages,
+                    pImmutableSamplers: null_mut(),
+                }
+            })
+            .collect()
+    }
+
+    /// One range from offset 0 visible to every stage that declares a push constant block
+    pub fn push_constant_ranges(&self) -> Vec<VkPushConstantRange> {
+        self.push_constants.iter().map(|&(stages, size)| VkPushConstantRange {
+            stageFlags: stages,
+            offset: 0,
+            size,
+        }).collect()
+    }
+
+    /// Warns about every descriptor or push constant the shaders use that `sets` and `push_constant_ranges` do not provide.
+    /// Host bindings the shaders ignore are fine
+    pub fn check_layout(&self, label: &str, sets: &[&[VkDescriptorSetLayoutBinding]], push_constant_ranges: &[VkPushConstantRange]) -> bool {
+        let mut compatible = true;
+        for used in &self.bindings {
+            let host = sets.get(used.set as usize).and_then(|set| set.iter().find(|b| b.binding == used.binding));
+            let Some(host) = host else {
+                eprintln!("{label}: shaders use set {} binding {} which the layout does not declare", used.set, used.binding);
+                compatible = false;
+                continue;
+            };
+            if !descriptor_types_match(host.descriptorType, used.descriptor_type) {
+                eprintln!("{label}: set {} binding {} is {:?} in the layout but {:?} in the shaders",
+                          used.set, used.binding, host.descriptorType, used.descriptor_type);
+                compatible = false;
+            }
+            if used.count != 0 && host.descriptorCount < used.count {
+                eprintln!("{label}: set {} binding {} has {} descriptors, shaders index {}",
+                          used.set, used.binding, host.descriptorCount, used.count);
+                compatible = false;
+            }
+            if !host.stageFlags.contains(used.stages) {
+                eprintln!("{label}: set {} binding {} is not visible to every stage using it", used.set, used.binding);
+                compatible = false;
+            }
+        }
+
+        if let Some((stages, size)) = self.push_constants {
+            let covered = push_constant_ranges.iter()
+                .any(|range| range.offset == 0 && range.size >= size && range.stageFlags.contains(stages));
+            if !covered {
+                eprintln!("{label}: no push constant range covers {size} bytes for every stage using them");
+                compatible = false;
+            }
+        }
+        compatible
+    }
+
+    /// Warns about vertex shader inputs without an attribute of the same numeric type
+    pub fn check_vertex_input(&self, label: &str, vertex_input: &PipelineVertexInputStateCreateInfo) -> bool {
+        let mut compatible = true;
+        for input in &self.vertex_inputs {
+            match vertex_input.vertex_attribute_descriptions.iter().find(|attribute| attribute.location == input.location) {
+                None => {
+                    eprintln!("{label}: vertex input at location {} has no attribute", input.location);
+                    compatible = false;
+                }
+                Some(attribute) if numeric_type(attribute.format) != numeric_type(input.format) => {
+                    eprintln!("{label}: attribute at location {} is {:?} but the shader reads {:?}", input.location, attribute.format, input.format);
+                    compatible = false;
+                }
+                Some(_) => {}
+            }
+        }
+        compatible
+    }
+}
+
+/// Shaders cannot tell dynamic buffers apart, the host chooses how offsets are given
+fn descriptor_types_match(host: VkDescriptorType, used: VkDescriptorType) -> bool {
+    host == used
+        || (host == VkDescriptorType::UNIFORM_BUFFER_DYNAMIC && used == VkDescriptorType::UNIFORM_BUFFER)
+        || (host == VkDescriptorType::STORAGE_BUFFER_DYNAMIC && used == VkDescriptorType::STORAGE_BUFFER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline_presets::{fragment_reflection, graphics_interface, vertex_reflection};
+    use shaders::common::{GuiParams, PostParams, SkyboxParams};
+    use std::mem::size_of;
+
+    fn binding(interface: &ShaderInterface, set: u32, binding: u32) -> ReflectedBinding {
+        *interface.bindings.iter().find(|b| (b.set, b.binding) == (set, binding))
+            .unwrap_or_else(|| panic!("set {set} binding {binding} not reflected"))
+    }
+
+    #[test]
+    fn scene_interface() {
+        let interface = graphics_interface("main", "main");
+        assert_eq!(interface.set_count(), 3);
+
+        let camera = binding(&interface, 0, 0);
+        assert_eq!((camera.descriptor_type, camera.count, camera.stages), (VkDescriptorType::UNIFORM_BUFFER, 1, VkShaderStageFlags::VERTEX_BIT));
+        let models = binding(&interface, 1, 3);
+        assert_eq!((models.descriptor_type, models.stages), (VkDescriptorType::STORAGE_BUFFER, VkShaderStageFlags::VERTEX_BIT));
+        let materials = binding(&interface, 1, 4);
+        assert_eq!((materials.descriptor_type, materials.stages), (VkDescriptorType::STORAGE_BUFFER, VkShaderStageFlags::FRAGMENT_BIT));
+
+        // bindless arrays are sized by the host
+        let textures = binding(&interface, 2, 0);
+        assert_eq!((textures.descriptor_type, textures.count), (VkDescriptorType::SAMPLED_IMAGE, 0));
+        let samplers = binding(&interface, 2, 1);
+        assert_eq!((samplers.descriptor_type, samplers.count), (VkDescriptorType::SAMPLER, 0));
+
+        assert!(interface.push_constants.is_none());
+    }
+
+    #[test]
+    fn vertex_inputs() {
+        let main = vertex_reflection().entry_point("main").unwrap();
+        let mut inputs = main.inputs.clone();
+        inputs.sort_by_key(|input| input.location);
+        let formats = inputs.iter().map(|input| input.format).collect::<Vec<_>>();
+        assert_eq!(formats, vec![VkFormat::R32G32B32_SFLOAT, VkFormat::R32G32B32_SFLOAT, VkFormat::R32G32_SFLOAT]);
+    }
+
+    #[test]
+    fn push_constant_sizes() {
+        let fragment = fragment_reflection();
+        assert_eq!(fragment.entry_point("skybox").unwrap().push_constant_size, size_of::<SkyboxParams>() as u32);
+        assert_eq!(fragment.entry_point("bloom_blur").unwrap().push_constant_size, size_of::<PostParams>() as u32);
+        assert_eq!(fragment.entry_point("main").unwrap().push_constant_size, 0);
+
+        let gui = graphics_interface("gui", "gui");
+        assert_eq!(gui.push_constants, Some((VkShaderStageFlags::VERTEX_BIT | VkShaderStageFlags::FRAGMENT_BIT, size_of::<GuiParams>() as u32)));
+    }
+
+    #[test]
+    fn check_layout_accepts_reflected_layout() {
+        let interface = graphics_interface("main", "main");
+        let mut set0 = interface.set_layout_bindings(0, &[]);
+        set0[0].descriptorType = VkDescriptorType::UNIFORM_BUFFER_DYNAMIC;
+        let set1 = interface.set_layout_bindings(1, &[]);
+        let set2 = interface.set_layout_bindings(2, &[(0, 16), (1, 4)]);
+        assert!(interface.check_layout("scene", &[&set0, &set1, &set2], &[]));
+    }
+
+    #[test]
+    fn check_layout_rejects_mismatches() {
+        let interface = graphics_interface("main", "main");
+        let set0 = interface.set_layout_bindings(0, &[]);
+        let set2 = interface.set_layout_bindings(2, &[(0, 16), (1, 4)]);
+        let mut set1 = interface.set_layout_bindings(1, &[]);
+        set1.retain(|binding| binding.binding != 4);
+        assert!(!interface.check_layout("missing", &[&set0, &set1, &set2], &[]));
+
+        let mut set1 = interface.set_layout_bindings(1, &[]);
+        set1.iter_mut().for_each(|binding| binding.descriptorType = VkDescriptorType::UNIFORM_BUFFER);
+        assert!(!interface.check_layout("wrong type", &[&set0, &set1, &set2], &[]));
+
+        let skybox = graphics_interface("skybox", "skybox");
+        let sets = [skybox.set_layout_bindings(0, &[])];
+        let sets = sets.iter().map(|set| set.as_slice()).collect::<Vec<_>>();
+        assert!(!skybox.check_layout("no push constants", &sets, &[]));
+        assert!(skybox.check_layout("skybox", &sets, &skybox.push_constant_ranges()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Not a SPIR-V module")]
+    fn parse_rejects_other_bytes() {
+        ShaderReflection::parse(&[0; 20]);
+    }
+
+    #[test]
+    fn descriptor_types() {
+        let mut module = Module::default();
+        module.types.insert(1, SpirvType::SampledImage);
+        module.types.insert(2, SpirvType::Array { element: 1, length: 3 });
+        module.constants.insert(3, 4);
+        module.types.insert(4, SpirvType::Struct { members: vec![] });
+        module.decorations.insert(4, Decorations { buffer_block: true, ..Default::default() });
+        module.types.insert(5, SpirvType::Image { dim: 1, sampled: 2 });
+        module.types.insert(6, SpirvType::RuntimeArray { element: 5 });
+
+        assert_eq!(module.descriptor(2, STORAGE_UNIFORM_CONSTANT), Some((VkDescriptorType::COMBINED_IMAGE_SAMPLER, 4)));
+        assert_eq!(module.descriptor(4, STORAGE_UNIFORM), Some((VkDescriptorType::STORAGE_BUFFER, 1)));
+        assert_eq!(module.descriptor(4, STORAGE_STORAGE_BUFFER), Some((VkDescriptorType::STORAGE_BUFFER, 1)));
+        assert_eq!(module.descriptor(6, STORAGE_UNIFORM_CONSTANT), Some((VkDescriptorType::STORAGE_IMAGE, 0)));
+    }
+
+    #[test]
+    fn sizes_follow_offsets_and_strides() {
+        let mut module = Module::default();
+        module.types.insert(1, SpirvType::Float { width: 32 });
+        module.types.insert(2, SpirvType::Vector { component: 1, count: 4 });
+        module.types.insert(3, SpirvType::Matrix { column: 2, count: 4 });
+        module.constants.insert(4, 3);
+        module.types.insert(5, SpirvType::Array { element: 1, length: 4 });
+        module.decorations.insert(5, Decorations { array_stride: Some(16), ..Default::default() });
+        module.types.insert(6, SpirvType::Struct { members: vec![1, 3, 5] });
+        module.member_offsets.insert((6, 0), 0);
+        module.member_offsets.insert((6, 1), 16);
+        module.matrix_strides.insert((6, 1), 16);
+        module.member_offsets.insert((6, 2), 80);
+
+        assert_eq!(module.size_of(3), 64);
+        assert_eq!(module.size_of(5), 48);
+        assert_eq!(module.size_of(6), 128);
+    }
+
+    #[test]
+    fn numeric_types() {
+        assert_eq!(numeric_type(VkFormat::R32G32B32_SFLOAT), "float");
+        assert_eq!(numeric_type(VkFormat::R8G8B8A8_UNORM), "float");
+        assert_eq!(numeric_type(VkFormat::R32_SINT), "int");
+        assert_eq!(numeric_type(VkFormat::R16G16_UINT), "uint");
+    }
+}
@@ -0,0 +1,139 @@
+use crate::safe_ptr;
+use crate::vulkan::func::Vulkan;
+use std::any::Any;
+use std::ptr::{null, null_mut};
+use vulkan_raw::{vkCmdBeginRendering, vkCmdEndRendering, VkAttachmentLoadOp, VkAttachmentStoreOp, VkClearValue, VkCommandBuffer, VkFormat, VkImageLayout, VkImageView, VkPipelineRenderingCreateInfo, VkRect2D, VkRenderingAttachmentInfo, VkRenderingFlags, VkRenderingInfo, VkResolveModeFlagBits};
+
+impl Vulkan {
+    pub fn begin_rendering(&self, command_buffer: VkCommandBuffer, render_area: VkRect2D, layers: u32, color_attachments: &[RenderingAttachment], depth_attachment: Option<RenderingAttachment>) {
+        let color_attachments: Vec<VkRenderingAttachmentInfo> = color_attachments.iter()
+            .map(|attachment| attachment.clone().into())
+            .collect();
+        let depth_attachment: Option<VkRenderingAttachmentInfo> = depth_attachment.map(|attachment| attachment.into());
+
+        let rendering_info = VkRenderingInfo {
+            flags: VkRenderingFlags::empty(),
+            renderArea: render_area,
+            layerCount: layers,
+            viewMask: 0,
+            colorAttachmentCount: color_attachments.len() as u32,
+            pColorAttachments: safe_ptr!(color_attachments),
+            pDepthAttachment: match &depth_attachment {
+                Some(attachment) => attachment,
+                None => null(),
+            },
+            pStencilAttachment: null(),
+            ..Default::default()
+        };
+
+        unsafe { vkCmdBeginRendering(command_buffer, &rendering_info) };
+    }
+
+    pub fn end_rendering(&self, command_buffer: VkCommandBuffer) {
+        unsafe { vkCmdEndRendering(command_buffer) };
+    }
+}
+
+#[derive(Clone)]
+pub struct RenderingAttachment {
+    pub image_view: VkImageView,
+    pub image_layout: VkImageLayout,
+    pub resolve_mode: VkResolveModeFlagBits,
+    pub resolve_image_view: VkImageView,
+    pub resolve_image_layout: VkImageLayout,
+    pub load_op: VkAttachmentLoadOp,
+    pub store_op: VkAttachmentStoreOp,
+    pub clear_value: VkClearValue,
+}
+
+impl RenderingAttachment {
+    pub fn color(image_view: VkImageView, load_op: VkAttachmentLoadOp, clear_value: VkClearValue) -> Self {
+        RenderingAttachment {
+            image_view,
+            image_layout: VkImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            load_op,
+            clear_value,
+            ..Default::default()
+        }
+    }
+
+    pub fn depth(image_view: VkImageView, load_op: VkAttachmentLoadOp, clear_value: VkClearValue) -> Self {
+        RenderingAttachment {
+            image_view,
+            image_layout: VkImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            load_op,
+            clear_value,
+            ..Default::default()
+        }
+    }
+
+    /// Resolves multisampled `image_view` into `resolve_image_view` at the end of rendering
+    pub fn resolve(mut self, resolve_image_view: VkImageView) -> Self {
+        self.resolve_mode = VkResolveModeFlagBits::AVERAGE_BIT;
+        self.resolve_image_view = resolve_image_view;
+        self.resolve_image_layout = VkImageLayout::COLOR_ATTACHMENT_OPTIMAL;
+        self.store_op = VkAttachmentStoreOp::DONT_CARE;
+        self
+    }
+}
+
+impl Default for RenderingAttachment {
+    fn default() -> Self {
+        RenderingAttachment {
+            image_view: VkImageView::none(),
+            image_layout: VkImageLayout::UNDEFINED,
+            resolve_mode: VkResolveModeFlagBits::empty(),
+            resolve_image_view: VkImageView::none(),
+            resolve_image_layout: VkImageLayout::UNDEFINED,
+            load_op: VkAttachmentLoadOp::DONT_CARE,
+            store_op: VkAttachmentStoreOp::STORE,
+            clear_value: Default::default(),
+        }
+    }
+}
+
+impl Into<VkRenderingAttachmentInfo> for RenderingAttachment {
+    fn into(self) -> VkRenderingAttachmentInfo {
+        VkRenderingAttachmentInfo {
+            imageView: self.image_view,
+            imageLayout: self.image_layout,
+            resolveMode: self.resolve_mode,
+            resolveImageView: self.resolve_image_view,
+            resolveImageLayout: self.resolve_image_layout,
+            loadOp: self.load_op,
+            storeOp: self.store_op,
+            clearValue: self.clear_value,
+            ..Default::default()
+        }
+    }
+}
+
+/// Attachment formats for pipelines used inside `begin_rendering`, replaces render pass + subpass
+#[derive(Clone, Debug, Default)]
+pub struct PipelineRenderingCreateInfo {
+    pub view_mask: u32,
+    pub color_attachment_formats: Vec<VkFormat>,
+    pub depth_attachment_format: VkFormat,
+    pub stencil_attachment_format: VkFormat,
+}
+
+impl PipelineRenderingCreateInfo {
+    pub fn to_vulkan(&self, keep_alive: &mut Vec<Box<dyn Any>>) -> VkPipelineRenderingCreateInfo {
+        let formats = Box::new(self.color_attachment_formats.clone());
+
+        let info = VkPipelineRenderingCreateInfo {
+            viewMask: self.view_mask,
+            colorAttachmentCount: formats.len() as u32,
+            pColorAttachmentFormats: safe_ptr!(formats),
+            depthAttachmentFormat: self.depth_attachment_format,
+            stencilAttachmentFormat: self.stencil_attachment_format,
+            ..Default::default()
+        };
+
+        keep_alive.push(formats);
+        info
+    }
+}
+
+unsafe impl Send for PipelineRenderingCreateInfo {}
+unsafe impl Sync for PipelineRenderingCreateInfo {}
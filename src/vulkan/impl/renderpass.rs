@@ -97,7 +97,7 @@ impl Vulkan {
 
     pub fn preset_renderpass_color_depth(&self, samples: VkSampleCountFlags, format: VkFormat, initial_layout: VkImageLayout, final_layout: VkImageLayout) -> VkRenderPass {
         let color_layout = if samples == VkSampleCountFlags::SC_1_BIT {
-            VkImageLayout::PRESENT_SRC_KHR
+            final_layout
         } else {
             VkImageLayout::COLOR_ATTACHMENT_OPTIMAL
         };
@@ -5,7 +5,7 @@ use std::ptr::null_mut;
 use vulkan_raw::{vkCreateAndroidSurfaceKHR, vkCreateWaylandSurfaceKHR, vkCreateWin32SurfaceKHR, vkCreateXcbSurfaceKHR, vkCreateXlibSurfaceKHR, vkDestroySurfaceKHR, vkGetPhysicalDeviceSurfaceCapabilitiesKHR, vkGetPhysicalDeviceSurfacePresentModesKHR, VkAndroidSurfaceCreateInfoKHR, VkColorSpaceKHR, VkFormat, VkPhysicalDevice, VkPresentModeKHR, VkSurfaceCapabilitiesKHR, VkSurfaceFormatKHR, VkSurfaceKHR, VkWaylandSurfaceCreateInfoKHR, VkWin32SurfaceCreateInfoKHR, VkXcbSurfaceCreateInfoKHR, VkXlibSurfaceCreateInfoKHR, HINSTANCE, HWND};
 
 impl Vulkan {
-    pub fn get_presentation_mode(&self, desired_mode: VkPresentModeKHR, device: VkPhysicalDevice, surface: VkSurfaceKHR) -> VkPresentModeKHR {
+    pub fn get_present_modes(&self, device: VkPhysicalDevice, surface: VkSurfaceKHR) -> Vec<VkPresentModeKHR> {
         let mut present_mode_count = 0;
         let mut result = unsafe {
             vkGetPhysicalDeviceSurfacePresentModesKHR(device, surface, &mut present_mode_count, null_mut())
@@ -24,16 +24,18 @@ impl Vulkan {
         unsafe {
             present_modes.set_len(present_mode_count as usize);
         }
-        for mode in &present_modes {
-            if *mode == desired_mode {
-                return desired_mode;
-            }
-        }
-        println!("Desired present mode is unsupported, falling back to FIFO");
-        
-        for mode in &present_modes {
-            if *mode == VkPresentModeKHR::FIFO_KHR {
-                return VkPresentModeKHR::FIFO_KHR;
+        present_modes
+    }
+
+    /// First mode of `desired.fallbacks()` the surface supports, FIFO is always available
+    pub fn get_presentation_mode(&self, desired: PresentMode, device: VkPhysicalDevice, surface: VkSurfaceKHR) -> VkPresentModeKHR {
+        let present_modes = self.get_present_modes(device, surface);
+        for mode in desired.fallbacks() {
+            if present_modes.contains(&mode.vk()) {
+                if *mode != desired {
+                    println!("Present mode {:?} is unsupported, falling back to {:?}", desired, mode);
+                }
+                return mode.vk();
             }
         }
         panic!("FIFO is unsupported, is vulkan hardware supports graphic?");
@@ -134,12 +136,81 @@ impl Vulkan {
     }
 }
 
-#[derive(Copy, Clone)]
+/// How finished frames are handed to the display
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PresentMode {
+    /// Waits for vertical blank, never tears
+    #[default]
+    Fifo,
+    /// FIFO that presents late frames right away, tears only when behind
+    FifoRelaxed,
+    /// Replaces the queued frame with the newest one, no tearing and low latency
+    Mailbox,
+    /// No waiting at all, tears
+    Immediate,
+}
+
+impl PresentMode {
+    pub const ALL: [PresentMode; 4] = [PresentMode::Fifo, PresentMode::FifoRelaxed, PresentMode::Mailbox, PresentMode::Immediate];
+
+    pub fn vk(self) -> VkPresentModeKHR {
+        match self {
+            PresentMode::Fifo => VkPresentModeKHR::FIFO_KHR,
+            PresentMode::FifoRelaxed => VkPresentModeKHR::FIFO_RELAXED_KHR,
+            PresentMode::Mailbox => VkPresentModeKHR::MAILBOX_KHR,
+            PresentMode::Immediate => VkPresentModeKHR::IMMEDIATE_KHR,
+        }
+    }
+
+    /// `self` followed by the modes closest to it, ends with FIFO
+    pub fn fallbacks(self) -> &'static [PresentMode] {
+        match self {
+            PresentMode::Fifo => &[PresentMode::Fifo],
+            PresentMode::FifoRelaxed => &[PresentMode::FifoRelaxed, PresentMode::Fifo],
+            PresentMode::Mailbox => &[PresentMode::Mailbox, PresentMode::Fifo],
+            PresentMode::Immediate => &[PresentMode::Immediate, PresentMode::Mailbox, PresentMode::Fifo],
+        }
+    }
+
+    pub fn next(self) -> PresentMode {
+        let index = PresentMode::ALL.iter().position(|mode| *mode == self).unwrap();
+        PresentMode::ALL[(index + 1) % PresentMode::ALL.len()]
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[allow(non_snake_case)]
 pub struct SurfaceFormat {
     pub format: VkFormat,
     pub colorSpace: VkColorSpaceKHR,
 }
+
+impl SurfaceFormat {
+    /// 8-bit output, the post chain encodes gamma itself
+    pub const SDR_BGRA: SurfaceFormat = SurfaceFormat { format: VkFormat::B8G8R8A8_UNORM, colorSpace: VkColorSpaceKHR::SRGB_NONLINEAR_KHR };
+    pub const SDR_RGBA: SurfaceFormat = SurfaceFormat { format: VkFormat::R8G8B8A8_UNORM, colorSpace: VkColorSpaceKHR::SRGB_NONLINEAR_KHR };
+    /// Hardware gamma encoding
+    pub const SRGB_BGRA: SurfaceFormat = SurfaceFormat { format: VkFormat::B8G8R8A8_SRGB, colorSpace: VkColorSpaceKHR::SRGB_NONLINEAR_KHR };
+    /// BT.2020 primaries with the PQ curve, needs `VK_EXT_swapchain_colorspace`
+    pub const HDR10: SurfaceFormat = SurfaceFormat { format: VkFormat::A2B10G10R10_UNORM_PACK32, colorSpace: VkColorSpaceKHR::HDR10_ST2084_EXT };
+    /// Linear BT.709 floats where 1.0 is SDR white, needs `VK_EXT_swapchain_colorspace`
+    pub const SCRGB: SurfaceFormat = SurfaceFormat { format: VkFormat::R16G16B16A16_SFLOAT, colorSpace: VkColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT };
+
+    /// Default SDR formats in order of preference
+    pub fn sdr_preferences() -> Vec<SurfaceFormat> {
+        vec![SurfaceFormat::default(), SurfaceFormat::SDR_BGRA, SurfaceFormat::SDR_RGBA, SurfaceFormat::SRGB_BGRA]
+    }
+
+    /// HDR10 or scRGB where the display offers them, SDR otherwise
+    pub fn hdr_preferences() -> Vec<SurfaceFormat> {
+        [vec![SurfaceFormat::HDR10, SurfaceFormat::SCRGB], SurfaceFormat::sdr_preferences()].concat()
+    }
+
+    pub fn is_hdr(&self) -> bool {
+        self.colorSpace == VkColorSpaceKHR::HDR10_ST2084_EXT || self.colorSpace == VkColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT
+    }
+}
+
 impl Default for SurfaceFormat {
     fn default() -> Self {
         if cfg!(target_arch = "x86_64") {
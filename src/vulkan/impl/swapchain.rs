@@ -1,6 +1,6 @@
 use crate::safe_ptr;
 use crate::vulkan::func::Vulkan;
-use crate::vulkan::r#impl::surface::SurfaceFormat;
+use crate::vulkan::r#impl::surface::{PresentMode, SurfaceFormat};
 use crate::vulkan::utils::clamp;
 use std::ptr::null_mut;
 use vulkan_raw::{vkAcquireNextImageKHR, vkCreateSwapchainKHR, vkDestroySwapchainKHR, vkGetPhysicalDeviceSurfaceFormatsKHR, vkGetSwapchainImagesKHR, vkQueuePresentKHR, VkBool32, VkColorSpaceKHR, VkExtent2D, VkFence, VkFormat, VkImage, VkImageUsageFlagBits, VkImageUsageFlags, VkPhysicalDevice, VkPresentInfoKHR, VkPresentModeKHR, VkQueue, VkResult, VkSemaphore, VkSurfaceFormatKHR, VkSurfaceKHR, VkSurfaceTransformFlagsKHR, VkSwapchainCreateInfoKHR, VkSwapchainKHR};
@@ -28,11 +28,9 @@ impl Vulkan {
         }
     }
     
-    //TODO: move flags to args
-    fn get_desired_usages(&self, device: VkPhysicalDevice, surface: VkSurfaceKHR) -> VkImageUsageFlags {
+    fn get_desired_usages(&self, device: VkPhysicalDevice, surface: VkSurfaceKHR, desired_usage: VkImageUsageFlags) -> VkImageUsageFlags {
         let surface_capabilities = self.get_surface_capabilities(device, surface);
 
-        let desired_usage = VkImageUsageFlagBits::COLOR_ATTACHMENT_BIT;
         if surface_capabilities.supportedUsageFlags.contains(desired_usage) {
             desired_usage
         } else {
@@ -40,11 +38,9 @@ impl Vulkan {
         }
     }
 
-    fn get_desired_transformation(&self, device: VkPhysicalDevice, surface: VkSurfaceKHR) -> VkSurfaceTransformFlagsKHR {
+    fn get_desired_transformation(&self, device: VkPhysicalDevice, surface: VkSurfaceKHR, desired_transforms: VkSurfaceTransformFlagsKHR) -> VkSurfaceTransformFlagsKHR {
         let surface_capabilities = self.get_surface_capabilities(device, surface);
 
-        //TODO: change this to settings contained variable
-        let desired_transforms = VkSurfaceTransformFlagsKHR::IDENTITY_BIT_KHR;
         if surface_capabilities.supportedTransforms.contains(desired_transforms) {
             desired_transforms
         } else {
@@ -52,7 +48,7 @@ impl Vulkan {
         }
     }
 
-    fn get_image_format(&self, device: VkPhysicalDevice, surface: VkSurfaceKHR, desired_image_format: VkSurfaceFormatKHR) -> VkSurfaceFormatKHR {
+    pub fn get_surface_formats(&self, device: VkPhysicalDevice, surface: VkSurfaceKHR) -> Vec<VkSurfaceFormatKHR> {
         let mut format_count: u32 = 0;
 
         let mut result = unsafe { vkGetPhysicalDeviceSurfaceFormatsKHR(device, surface, &mut format_count, null_mut()) };
@@ -70,35 +66,41 @@ impl Vulkan {
         unsafe {
             surface_formats.set_len(format_count as usize);
         }
-        if surface_formats.len() == 1 && surface_formats.get(0).unwrap().format == VkFormat::UNDEFINED {
-            return desired_image_format;
+        surface_formats
+    }
+
+    /// First of `preferences` the surface supports. Without a match any sRGB nonlinear format is taken, the first supported one as last resort
+    fn get_image_format(&self, device: VkPhysicalDevice, surface: VkSurfaceKHR, preferences: &[SurfaceFormat]) -> SurfaceFormat {
+        assert!(!preferences.is_empty(), "Swapchain needs at least one preferred surface format");
+        let surface_formats = self.get_surface_formats(device, surface)
+            .into_iter()
+            .map(|format| SurfaceFormat { format: format.format, colorSpace: format.colorSpace })
+            .collect::<Vec<_>>();
+        if surface_formats.len() == 1 && surface_formats[0].format == VkFormat::UNDEFINED {
+            return preferences[0];
         }
 
-        for format in &surface_formats {
-            if desired_image_format.format == format.format && desired_image_format.colorSpace == format.colorSpace {
-                return desired_image_format;
+        if let Some(format) = preferences.iter().find(|preferred| surface_formats.contains(preferred)) {
+            return *format;
+        }
+        match surface_formats.iter().find(|format| format.colorSpace == VkColorSpaceKHR::SRGB_NONLINEAR_KHR) {
+            Some(format) => {
+                println!("No preferred surface format is supported, falling back to {:?}", format);
+                *format
             }
-        };
-        dbg!(&surface_formats);
-        dbg!(&desired_image_format);
-        println!("There is no supported image format, that exactly equals to desired one, falling back to another colorspace");
-
-        for format in &surface_formats {
-            if desired_image_format.format == format.format {
-                return VkSurfaceFormatKHR {
-                    format: desired_image_format.format,
-                    colorSpace: format.colorSpace,
-                }
+            None => {
+                eprintln!("There is no sRGB surface format, falling back to {:?}, expect failure", surface_formats[0]);
+                surface_formats[0]
             }
-        };
-        eprintln!("There is no supported image format, falling back to first supported, expect failure");
-
-        surface_formats[0].clone()
+        }
     }
     
+    /// Negotiates format and present mode against the surface, `info.format` and `info.active_present_mode` hold the result.
+    /// The old swapchain is retired, the device must not use its images anymore
     pub fn create_swapchain(&self, info: &mut SwapchainInfo) {
         let device = self.get_loaded_device().device;
-        let format = self.get_image_format(device, info.surface, info.format.into());
+        let format = self.get_image_format(device, info.surface, &info.preferred_formats);
+        let present_mode = self.get_presentation_mode(info.present_mode, device, info.surface);
         let image_size = self.get_swapchain_image_size(device, info.surface, VkExtent2D {width: info.width, height: info.height});
 
         let swapchain_create_info = VkSwapchainCreateInfoKHR {
@@ -108,13 +110,9 @@ impl Vulkan {
             imageColorSpace: format.colorSpace,
             imageExtent: image_size,
             imageArrayLayers: 1,
-            imageUsage: self.get_desired_usages(device, info.surface),
-            preTransform: self.get_desired_transformation(device, info.surface),
-            presentMode: self.get_presentation_mode({ if info.vsync {
-                VkPresentModeKHR::MAILBOX_KHR
-            } else {
-                VkPresentModeKHR::IMMEDIATE_KHR
-            }}, device, info.surface),
+            imageUsage: self.get_desired_usages(device, info.surface, info.usage),
+            preTransform: self.get_desired_transformation(device, info.surface, info.transform),
+            presentMode: present_mode,
             clipped: VkBool32::TRUE,
             oldSwapchain: info.swapchain,
             ..Default::default()
@@ -128,6 +126,8 @@ impl Vulkan {
         //destroy old one
         self.destroy_swapchain(info.swapchain);
         info.swapchain = swapchain;
+        info.format = format;
+        info.active_present_mode = present_mode;
 
         #[cfg(debug_assertions)] println!("Swapchain created with size: {}, {}", image_size.width, image_size.height);
     }
@@ -197,9 +197,16 @@ pub struct SwapchainInfo {
     pub width: u32,
     pub height: u32,
     pub surface: VkSurfaceKHR,
+    /// Format the swapchain was created with, picked from `preferred_formats`
     pub format: SurfaceFormat,
+    pub preferred_formats: Vec<SurfaceFormat>,
     pub swapchain: VkSwapchainKHR,
-    pub vsync: bool,
+    /// Requested mode, falls back along `PresentMode::fallbacks`
+    pub present_mode: PresentMode,
+    pub active_present_mode: VkPresentModeKHR,
+    pub usage: VkImageUsageFlags,
+    /// Used when supported, the current transform of the surface otherwise
+    pub transform: VkSurfaceTransformFlagsKHR,
 }
 
 impl Default for SwapchainInfo {
@@ -208,12 +215,13 @@ impl Default for SwapchainInfo {
             width: 640,
             height: 480,
             surface: Default::default(),
-            format: SurfaceFormat {
-                format: VkFormat::B8G8R8A8_UNORM,
-                colorSpace: VkColorSpaceKHR::SRGB_NONLINEAR_KHR,
-            },
+            format: SurfaceFormat::SDR_BGRA,
+            preferred_formats: SurfaceFormat::sdr_preferences(),
             swapchain: VkSwapchainKHR::none(),
-            vsync: false,
+            present_mode: PresentMode::default(),
+            active_present_mode: VkPresentModeKHR::FIFO_KHR,
+            usage: VkImageUsageFlagBits::COLOR_ATTACHMENT_BIT,
+            transform: VkSurfaceTransformFlagsKHR::IDENTITY_BIT_KHR,
         }
     }
 }
@@ -229,8 +237,8 @@ impl SwapchainInfo {
         self
     }
     
-    pub fn set_format(&mut self, format: SurfaceFormat) -> &mut Self{
-        self.format = format;
+    pub fn set_formats(&mut self, preferred_formats: Vec<SurfaceFormat>) -> &mut Self{
+        self.preferred_formats = preferred_formats;
         self
     }
     
@@ -239,8 +247,8 @@ impl SwapchainInfo {
         self
     }
     
-    pub fn set_vsync(&mut self, vsync: bool) -> &mut Self{
-        self.vsync = vsync;
+    pub fn set_present_mode(&mut self, present_mode: PresentMode) -> &mut Self{
+        self.present_mode = present_mode;
         self
     }
     
@@ -4,6 +4,13 @@ pub fn extensions() -> Vec<&'static str> {
     ]
 }
 
+/// Enabled when the loader supports them
+pub fn optional_extensions() -> Vec<&'static str> {
+    vec![
+        "VK_EXT_swapchain_colorspace"
+    ]
+}
+
 pub fn device_extensions() -> Vec<&'static str> {
     vec![
         "VK_KHR_swapchain"
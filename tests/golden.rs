@@ -0,0 +1,307 @@
+//! Golden-image regression tests, renders scenes headless and compares them with tests/golden/*.png
+//!
+//! `GOLDEN_REGENERATE=1 cargo test --test golden` rewrites references, commit them together with the change.
+//! References are produced by lavapipe, it is picked automatically when `VK_DRIVER_FILES` is not set.
+//! The test is skipped without a lavapipe ICD or without references, cases missing a reference are skipped one by one.
+use amalgam::engine::camera::Camera;
+use amalgam::engine::headless::{save_png, HeadlessRenderer};
+use amalgam::engine::post::PostSettings;
+use amalgam::vulkan::func::Vulkan;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use ultraviolet::Vec3;
+
+const REFERENCE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden");
+const OUTPUT_DIR: &str = env!("CARGO_TARGET_TMPDIR");
+const LAVAPIPE_ICDS: &[&str] = &[
+    "/usr/share/vulkan/icd.d/lvp_icd.x86_64.json",
+    "/usr/share/vulkan/icd.d/lvp_icd.aarch64.json",
+    "/usr/share/vulkan/icd.d/lvp_icd.json",
+];
+
+#[derive(Debug, Clone, Copy)]
+struct Tolerance {
+    /// Max absolute difference per channel before a pixel counts as changed
+    channel: u8,
+    /// Fraction of changed pixels still accepted
+    changed_pixels: f64,
+    /// Lowest accepted mean SSIM over luma
+    ssim: f64,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Tolerance {
+            channel: 2,
+            changed_pixels: 0.001,
+            ssim: 0.98,
+        }
+    }
+}
+
+struct Case {
+    name: &'static str,
+    glb: Vec<u8>,
+    camera: Camera,
+    width: u32,
+    height: u32,
+    post: PostSettings,
+    tolerance: Tolerance,
+}
+
+struct Comparison {
+    changed_pixels: f64,
+    ssim: f64,
+    diff: Vec<u8>,
+}
+
+#[test]
+fn golden_images() {
+    let regenerate = std::env::var("GOLDEN_REGENERATE").is_ok_and(|value| value != "0");
+    if !use_lavapipe() {
+        eprintln!("Skipping golden images, no lavapipe ICD found and VK_DRIVER_FILES is not set");
+        return;
+    }
+    if !regenerate && !Path::new(REFERENCE_DIR).is_dir() {
+        eprintln!("Skipping golden images, no references in {}, run with GOLDEN_REGENERATE=1", REFERENCE_DIR);
+        return;
+    }
+
+    let mut vulkan = Vulkan::default();
+    vulkan.init_headless();
+
+    if regenerate {
+        std::fs::create_dir_all(REFERENCE_DIR).unwrap();
+    }
+    let mut failures = vec![];
+    for case in cases() {
+        let mut renderer = HeadlessRenderer::new(&vulkan, &case.glb, case.width, case.height, case.post.clone());
+        let actual = renderer.render(&vulkan, &case.camera);
+        let reference_path = Path::new(REFERENCE_DIR).join(format!("{}.png", case.name));
+
+        if regenerate {
+            save_png(&reference_path, case.width, case.height, &actual).unwrap();
+            println!("Regenerated {}", reference_path.display());
+            continue;
+        }
+        let Some((reference, width, height)) = load_png(&reference_path) else {
+            eprintln!("Skipping {}, no reference at {}, run with GOLDEN_REGENERATE=1", case.name, reference_path.display());
+            continue;
+        };
+        if (width, height) != (case.width, case.height) {
+            failures.push(format!("{}: reference is {}x{}, rendered {}x{}", case.name, width, height, case.width, case.height));
+            continue;
+        }
+
+        let comparison = compare(&reference, &actual, width, height, case.tolerance);
+        if comparison.changed_pixels > case.tolerance.changed_pixels || comparison.ssim < case.tolerance.ssim {
+            let actual_path = output_path(case.name, "actual");
+            let diff_path = output_path(case.name, "diff");
+            save_png(&actual_path, width, height, &actual).unwrap();
+            save_png(&diff_path, width, height, &comparison.diff).unwrap();
+            failures.push(format!("{}: {:.4}% pixels changed, ssim {:.4}, see {} and {}", case.name,
+                                  comparison.changed_pixels * 100.0, comparison.ssim, actual_path.display(), diff_path.display()));
+        }
+    }
+
+    if !failures.is_empty() {
+        panic!("Golden images differ:\n{}", failures.join("\n"));
+    }
+}
+
+fn cases() -> Vec<Case> {
+    let camera = Camera::new(Vec3::new(1.5, 1.5, 4.0), -0.3, 0.35, 1.0, 0.0);
+    let mut no_post = PostSettings::default();
+    no_post.effects.iter_mut().for_each(|(_, enabled)| *enabled = false);
+
+    vec![
+        Case {
+            name: "cube",
+            glb: cube_glb(),
+            camera,
+            width: 256,
+            height: 256,
+            post: PostSettings::default(),
+            tolerance: Tolerance::default(),
+        },
+        Case {
+            name: "cube_no_post",
+            glb: cube_glb(),
+            camera,
+            width: 256,
+            height: 256,
+            post: no_post,
+            tolerance: Tolerance::default(),
+        },
+    ]
+}
+
+/// Points the loader at lavapipe unless a driver was chosen explicitly
+/// False when neither a driver is chosen through the environment nor lavapipe is installed
+fn use_lavapipe() -> bool {
+    if std::env::var_os("VK_DRIVER_FILES").is_some() || std::env::var_os("VK_ICD_FILENAMES").is_some() {
+        return true;
+    }
+    match LAVAPIPE_ICDS.iter().find(|path| Path::new(path).exists()) {
+        // only one test touches the environment, before any Vulkan call
+        Some(icd) => {
+            unsafe { std::env::set_var("VK_DRIVER_FILES", icd) };
+            true
+        }
+        None => false,
+    }
+}
+
+fn output_path(name: &str, kind: &str) -> PathBuf {
+    Path::new(OUTPUT_DIR).join(format!("{}.{}.png", name, kind))
+}
+
+fn load_png(path: &Path) -> Option<(Vec<u8>, u32, u32)> {
+    let file = File::open(path).ok()?;
+    let mut reader = png::Decoder::new(BufReader::new(file)).read_info().unwrap();
+    let mut buf = vec![0; reader.output_buffer_size().unwrap()];
+    let info = reader.next_frame(&mut buf).unwrap();
+    assert_eq!(info.color_type, png::ColorType::Rgba, "Reference {} is not RGBA", path.display());
+    buf.truncate(info.buffer_size());
+    Some((buf, info.width, info.height))
+}
+
+fn compare(reference: &[u8], actual: &[u8], width: u32, height: u32, tolerance: Tolerance) -> Comparison {
+    let mut changed = 0usize;
+    let mut diff = Vec::with_capacity(reference.len());
+    for (expected, got) in reference.chunks(4).zip(actual.chunks(4)) {
+        let delta = expected.iter().zip(got).map(|(a, b)| a.abs_diff(*b)).max().unwrap();
+        if delta > tolerance.channel {
+            changed += 1;
+            diff.extend_from_slice(&[255, 0, 255 - delta, 255]);
+        } else {
+            // dimmed reference keeps the diff readable
+            let gray = (luma(expected) * 0.3) as u8;
+            diff.extend_from_slice(&[gray, gray, gray, 255]);
+        }
+    }
+
+    Comparison {
+        changed_pixels: changed as f64 / (width * height) as f64,
+        ssim: ssim(reference, actual, width, height),
+        diff,
+    }
+}
+
+fn luma(pixel: &[u8]) -> f64 {
+    0.2126 * pixel[0] as f64 + 0.7152 * pixel[1] as f64 + 0.0722 * pixel[2] as f64
+}
+
+/// Mean structural similarity of luma over 8x8 windows with stride 4
+fn ssim(reference: &[u8], actual: &[u8], width: u32, height: u32) -> f64 {
+    const WINDOW: usize = 8;
+    const STRIDE: usize = 4;
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    let (width, height) = (width as usize, height as usize);
+    let reference = reference.chunks(4).map(luma).collect::<Vec<_>>();
+    let actual = actual.chunks(4).map(luma).collect::<Vec<_>>();
+
+    let mut total = 0.0;
+    let mut windows = 0;
+    for y in (0..=height.saturating_sub(WINDOW)).step_by(STRIDE) {
+        for x in (0..=width.saturating_sub(WINDOW)).step_by(STRIDE) {
+            let samples = (y..(y + WINDOW).min(height))
+                .flat_map(|row| (x..(x + WINDOW).min(width)).map(move |column| row * width + column))
+                .map(|index| (reference[index], actual[index]))
+                .collect::<Vec<_>>();
+            let n = samples.len() as f64;
+            let mean_a = samples.iter().map(|s| s.0).sum::<f64>() / n;
+            let mean_b = samples.iter().map(|s| s.1).sum::<f64>() / n;
+            let var_a = samples.iter().map(|s| (s.0 - mean_a).powi(2)).sum::<f64>() / n;
+            let var_b = samples.iter().map(|s| (s.1 - mean_b).powi(2)).sum::<f64>() / n;
+            let covariance = samples.iter().map(|s| (s.0 - mean_a) * (s.1 - mean_b)).sum::<f64>() / n;
+
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+    total / windows as f64
+}
+
+/// Unit cube with per-face normals and a 4x4 checker texture
+fn cube_glb() -> Vec<u8> {
+    let faces: [([f32; 3], [f32; 3], [f32; 3]); 6] = [
+        ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+        ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+        ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+        ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+        ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+        ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ];
+    let mut positions: Vec<f32> = vec![];
+    let mut normals: Vec<f32> = vec![];
+    let mut uvs: Vec<f32> = vec![];
+    let mut indices: Vec<u16> = vec![];
+    for (face, (normal, u, v)) in faces.iter().enumerate() {
+        for (su, sv) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+            (0..3).for_each(|i| positions.push(0.5 * (normal[i] + su * u[i] + sv * v[i])));
+            normals.extend_from_slice(normal);
+            uvs.extend_from_slice(&[(su + 1.0) * 0.5, (1.0 - sv) * 0.5]);
+        }
+        let base = face as u16 * 4;
+        indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+    }
+
+    let mut texture = vec![];
+    {
+        let mut encoder = png::Encoder::new(&mut texture, 4, 4);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let pixels = (0..16).flat_map(|i| if (i % 4 + i / 4) % 2 == 0 { [230, 120, 40, 255] } else { [40, 90, 200, 255] }).collect::<Vec<u8>>();
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&pixels).unwrap();
+    }
+
+    let mut bin: Vec<u8> = vec![];
+    let mut views = vec![];
+    for chunk in [bytemuck::cast_slice::<f32, u8>(&positions), bytemuck::cast_slice(&normals), bytemuck::cast_slice(&uvs),
+                  bytemuck::cast_slice::<u16, u8>(&indices), &texture] {
+        views.push(format!(r#"{{"buffer":0,"byteOffset":{},"byteLength":{}}}"#, bin.len(), chunk.len()));
+        bin.extend_from_slice(chunk);
+        bin.resize(bin.len().next_multiple_of(4), 0);
+    }
+
+    let json = format!(r#"{{
+        "asset":{{"generator":"amalgam golden tests","version":"2.0"}},
+        "scene":0,
+        "scenes":[{{"name":"cube","nodes":[0]}}],
+        "nodes":[{{"mesh":0,"name":"cube"}}],
+        "materials":[{{"name":"checker","pbrMetallicRoughness":{{"baseColorTexture":{{"index":0}}}}}}],
+        "meshes":[{{"name":"cube","primitives":[{{"attributes":{{"POSITION":0,"NORMAL":1,"TEXCOORD_0":2}},"indices":3,"material":0}}]}}],
+        "textures":[{{"source":0,"sampler":0}}],
+        "images":[{{"bufferView":4,"mimeType":"image/png"}}],
+        "accessors":[
+            {{"bufferView":0,"componentType":5126,"count":24,"type":"VEC3","min":[-0.5,-0.5,-0.5],"max":[0.5,0.5,0.5]}},
+            {{"bufferView":1,"componentType":5126,"count":24,"type":"VEC3"}},
+            {{"bufferView":2,"componentType":5126,"count":24,"type":"VEC2"}},
+            {{"bufferView":3,"componentType":5123,"count":{},"type":"SCALAR"}}
+        ],
+        "bufferViews":[{}],
+        "buffers":[{{"byteLength":{}}}],
+        "samplers":[{{"magFilter":9728,"minFilter":9728}}]
+    }}"#, indices.len(), views.join(","), bin.len());
+    let mut json = json.into_bytes();
+    json.resize(json.len().next_multiple_of(4), b' ');
+
+    let total = 12 + 8 + json.len() + 8 + bin.len();
+    let mut glb = Vec::with_capacity(total);
+    glb.extend_from_slice(b"glTF");
+    glb.extend_from_slice(&2u32.to_le_bytes());
+    glb.extend_from_slice(&(total as u32).to_le_bytes());
+    glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    glb.extend_from_slice(b"JSON");
+    glb.extend_from_slice(&json);
+    glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+    glb.extend_from_slice(b"BIN\0");
+    glb.extend_from_slice(&bin);
+    glb
+}